@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// The binaries share modules with `mod`, so pull the protocol module in the same way. It is wrapped so its own
+// submodules are found under src/protocol/.
+#[path = "../../src"]
+#[allow(dead_code)]
+mod shared {
+    pub mod protocol;
+}
+use shared::protocol::Frame;
+
+fuzz_target!(|data: &[u8]| {
+    // Parsing must never panic, and anything that parses must survive being re-encoded.
+    if let Ok(frame) = Frame::parse(data) {
+        assert_eq!(Frame::parse(&frame.to_bytes()), Ok(frame));
+    }
+});
@@ -0,0 +1,198 @@
+/// Admin CLI file
+extern crate serde;
+extern crate serde_json;
+use serde_json::{json, Value};
+
+extern crate chrono;
+use chrono::prelude::*;
+
+mod config;
+mod control;
+mod random;
+mod store;
+mod vault;
+use self::config::Args;
+use self::control::{call, PeerInfo, RelayStats};
+use self::store::StoredMessage;
+
+const USAGE: &str = "Error: Usage ./r2wc-admin --control path [--json] [peers | kick <addr> | broadcast <msg> | stats | history [n] | search <text> | invite | purge older-than <age>]";
+
+/// Prints rows as a table with a header, padding each column to its widest cell.
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let header: Vec<String> = header.iter().map(|h| String::from(*h)).collect();
+    for row in Some(header).into_iter().chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:<w$}", cell, w = widths[i]))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Prints a method's result as a table.
+fn print_result(command: &str, result: Value) -> Result<(), serde_json::Error> {
+    match command {
+        "peers" => {
+            let peers: Vec<PeerInfo> = serde_json::from_value(result)?;
+            let rows = peers
+                .into_iter()
+                .map(|p| {
+                    let state = if p.handshake_pending {
+                        "handshaking"
+                    } else {
+                        "connected"
+                    };
+                    vec![
+                        p.addr,
+                        p.nick.unwrap_or_else(|| String::from("-")),
+                        format!("{}s", p.connected_secs),
+                        String::from(state),
+                        p.key.unwrap_or_else(|| String::from("-")),
+                    ]
+                })
+                .collect();
+            print_table(&["ADDR", "NICK", "CONNECTED", "STATE", "KEY"], rows);
+        }
+        "stats" => {
+            let stats: RelayStats = serde_json::from_value(result)?;
+            print_table(
+                &["STAT", "VALUE"],
+                vec![
+                    vec![String::from("peers"), stats.peers.to_string()],
+                    vec![String::from("handshaking"), stats.handshaking.to_string()],
+                    vec![
+                        String::from("messages_relayed"),
+                        stats.messages_relayed.to_string(),
+                    ],
+                    vec![
+                        String::from("clients_dropped"),
+                        stats.clients_dropped.to_string(),
+                    ],
+                    vec![
+                        String::from("handshake_failures"),
+                        stats.handshake_failures.to_string(),
+                    ],
+                    vec![String::from("reconnects"), stats.reconnects.to_string()],
+                    vec![String::from("bytes_sent"), stats.bytes_sent.to_string()],
+                    vec![
+                        String::from("bytes_received"),
+                        stats.bytes_received.to_string(),
+                    ],
+                    vec![String::from("uptime_secs"), stats.uptime_secs.to_string()],
+                    vec![String::from("motd"), stats.motd.unwrap_or_default()],
+                ],
+            );
+        }
+        "history" | "search" => {
+            let messages: Vec<StoredMessage> = serde_json::from_value(result)?;
+            let rows = messages
+                .into_iter()
+                .map(|m| {
+                    let time = Local
+                        .timestamp_opt(m.time, 0)
+                        .single()
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    let mut body = m.body;
+                    if m.deleted {
+                        body = format!("~~{}~~ (deleted)", body);
+                    } else if m.edited {
+                        body = format!("{} (edited)", body);
+                    }
+                    vec![time, m.sender, body]
+                })
+                .collect();
+            print_table(&["TIME", "SENDER", "MESSAGE"], rows);
+        }
+        "kick" => println!("Kicked"),
+        "purge" => {
+            let purged = result.get("purged").and_then(|n| n.as_u64()).unwrap_or(0);
+            println!("Purged {} messages", purged);
+        }
+        "invite" => {
+            let token = result.get("token").and_then(|t| t.as_str()).unwrap_or("");
+            let expires = result
+                .get("expires_secs")
+                .and_then(|n| n.as_u64())
+                .unwrap_or(0);
+            println!(
+                "Invite token: {} (single use, expires in {}s)",
+                token, expires
+            );
+        }
+        _ => {
+            let sent_to = result.get("sent_to").and_then(|n| n.as_u64()).unwrap_or(0);
+            println!("Sent to {} peers", sent_to);
+        }
+    }
+
+    return Ok(());
+}
+
+fn main() {
+    let args = Args::from_env();
+    let path = match args.flag("control") {
+        Some(path) => path.clone(),
+        None => {
+            println!("{}", USAGE);
+            ::std::process::exit(0x0100);
+        }
+    };
+
+    let command = args.positional.get(0).map(|c| c.as_str()).unwrap_or("");
+    let rest = args.positional[1.min(args.positional.len())..].join(" ");
+    let (method, params) = match (command, rest.is_empty()) {
+        ("peers", true) => ("list-peers", Value::Null),
+        ("stats", true) => ("stats", Value::Null),
+        ("kick", false) => ("kick", json!({ "addr": rest })),
+        ("broadcast", false) => ("broadcast", json!({ "text": rest })),
+        ("history", true) => ("history", Value::Null),
+        ("history", false) => match rest.parse::<u64>() {
+            Ok(limit) => ("history", json!({ "limit": limit })),
+            Err(_) => {
+                println!("{}", USAGE);
+                ::std::process::exit(0x0100);
+            }
+        },
+        ("search", false) => ("search", json!({ "text": rest })),
+        ("invite", true) => ("invite", Value::Null),
+        ("purge", false) => match rest.strip_prefix("older-than ") {
+            Some(age) => ("purge", json!({ "older_than": age.trim() })),
+            None => {
+                println!("{}", USAGE);
+                ::std::process::exit(0x0100);
+            }
+        },
+        _ => {
+            println!("{}", USAGE);
+            ::std::process::exit(0x0100);
+        }
+    };
+
+    let result = match call(&path, method, params) {
+        Ok(Ok(result)) => result,
+        Ok(Err(error)) => {
+            println!("Error: {} ({})", error.message, error.code);
+            ::std::process::exit(0x0100);
+        }
+        Err(err) => {
+            println!("Error: Could not reach {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    };
+
+    if args.has_flag("json") {
+        println!("{}", result);
+    } else if let Err(err) = print_result(command, result) {
+        println!("Error: Unexpected response, {}", err);
+        ::std::process::exit(0x0100);
+    }
+}
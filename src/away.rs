@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use crate::protocol::Frame;
+
+/// AutoAway which marks us as away once no key has been pressed for a while, and back on the next key.
+///
+/// # Fields
+/// `after` - How long without a key before we are away, None never.
+/// `last_key` - When a key was last pressed.
+/// `away` - Whether the peer was told we are away.
+pub struct AutoAway {
+    after: Option<Duration>,
+    last_key: Instant,
+    away: bool,
+}
+
+impl AutoAway {
+    /// Creates a new AutoAway, counting from now.
+    ///
+    /// # Arguments
+    /// * `after` - A Option<Duration> of how long without a key before we are away, None never.
+    ///
+    /// # Returns
+    /// `AutoAway` - the tracker, not away.
+    pub fn new(after: Option<Duration>) -> AutoAway {
+        return AutoAway {
+            after: after,
+            last_key: Instant::now(),
+            away: false,
+        };
+    }
+
+    /// Changes how long without a key before we are away, for when the config file changes.
+    ///
+    /// # Arguments
+    /// * `after` - A Option<Duration> of the new idle time, None never.
+    pub fn set_after(&mut self, after: Option<Duration>) {
+        self.after = after;
+    }
+
+    /// Notes a key was pressed.
+    ///
+    /// # Returns
+    /// `Option<Frame>` - the Away frame telling the peer we are back, None if we weren't away.
+    pub fn key(&mut self) -> Option<Frame> {
+        self.last_key = Instant::now();
+        if !self.away {
+            return None;
+        }
+        self.away = false;
+        return Some(Frame::Away { away: false });
+    }
+
+    /// Checks whether we have now been idle long enough to be away.
+    ///
+    /// # Returns
+    /// `Option<Frame>` - the Away frame telling the peer we are away, None if nothing changed.
+    pub fn check(&mut self) -> Option<Frame> {
+        match self.after {
+            Some(after) if !self.away && self.last_key.elapsed() >= after => {
+                self.away = true;
+                return Some(Frame::Away { away: true });
+            }
+            _ => return None,
+        }
+    }
+
+    /// How long until we will be away if no key is pressed, so the event loop wakes up in time.
+    ///
+    /// # Returns
+    /// `Option<Duration>` - the time left, None if we are away already or never will be.
+    pub fn due(&self) -> Option<Duration> {
+        match self.after {
+            Some(after) if !self.away => {
+                return Some(after.saturating_sub(self.last_key.elapsed()))
+            }
+            _ => return None,
+        }
+    }
+}
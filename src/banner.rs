@@ -0,0 +1,95 @@
+/// How many rows tall each letter of the font is.
+const HEIGHT: usize = 3;
+
+/// The widest a banner can be, so each of its lines fits in a frame.
+pub const MAX_WIDTH: usize = 200;
+
+/// A small font drawn with `_|/\()`, each letter three rows of the same width. Lower case letters are drawn as
+/// upper case ones.
+const FONT: &[(char, [&str; HEIGHT])] = &[
+    ('A', [" _ ", "|_|", "| |"]),
+    ('B', [" _ ", "|_)", "|_)"]),
+    ('C', [" _ ", "|  ", "|_ "]),
+    ('D', [" _ ", "| \\", "|_/"]),
+    ('E', [" _ ", "|_ ", "|_ "]),
+    ('F', [" _ ", "|_ ", "|  "]),
+    ('G', [" _ ", "| _", "|_|"]),
+    ('H', ["   ", "|_|", "| |"]),
+    ('I', ["___", " | ", "_|_"]),
+    ('J', ["   ", "  |", "|_|"]),
+    ('K', ["   ", "|_/", "| \\"]),
+    ('L', ["   ", "|  ", "|_ "]),
+    ('M', ["    ", "|\\/|", "|  |"]),
+    ('N', ["    ", "|\\ |", "| \\|"]),
+    ('O', [" _ ", "| |", "|_|"]),
+    ('P', [" _ ", "|_)", "|  "]),
+    ('Q', [" _ ", "| |", "|_\\"]),
+    ('R', [" _ ", "|_)", "| \\"]),
+    ('S', [" _ ", "(_ ", " _)"]),
+    ('T', ["___", " | ", " | "]),
+    ('U', ["   ", "| |", "|_|"]),
+    ('V', ["   ", "\\ /", " V "]),
+    ('W', ["    ", "|  |", "|/\\|"]),
+    ('X', ["   ", "\\_/", "/ \\"]),
+    ('Y', ["   ", "\\_/", " | "]),
+    ('Z', ["__ ", " / ", "/_ "]),
+    ('0', [" _ ", "|/|", "|_|"]),
+    ('1', ["   ", " /|", "  |"]),
+    ('2', [" _ ", " _)", "/_ "]),
+    ('3', ["_ ", "_)", "_)"]),
+    ('4', ["   ", "|_|", "  |"]),
+    ('5', [" _ ", "|_ ", " _)"]),
+    ('6', [" _ ", "|_ ", "|_)"]),
+    ('7', ["__ ", "  /", " / "]),
+    ('8', [" _ ", "(_)", "(_)"]),
+    ('9', [" _ ", "(_|", "  |"]),
+    (' ', ["  ", "  ", "  "]),
+    ('!', [" ", "|", "."]),
+    ('?', ["_ ", " )", " ."]),
+    ('.', [" ", " ", "."]),
+    (',', [" ", " ", ","]),
+    (':', [" ", ".", "."]),
+    ('-', ["  ", "__", "  "]),
+    ('\'', ["|", " ", " "]),
+];
+
+/// Finds how a character is drawn, `?` for one the font doesn't have.
+fn glyph(c: char) -> &'static [&'static str; HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    return FONT
+        .iter()
+        .find(|&&(g, _)| g == c)
+        .or_else(|| FONT.iter().find(|&&(g, _)| g == '?'))
+        .map(|(_, rows)| rows)
+        .unwrap();
+}
+
+/// Draws text as a banner in a small FIGlet style font, a space between each letter.
+///
+/// # Arguments
+/// * `text` - A &str of the text to draw.
+///
+/// # Returns
+/// `Result<Vec<String>, String>` - the banner's lines, or why it can't be drawn, like being too wide to send.
+pub fn banner(text: &str) -> Result<Vec<String>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(String::from("Usage: /big <text>"));
+    }
+    let mut lines = vec![String::new(); HEIGHT];
+    for (i, c) in text.chars().enumerate() {
+        for (line, row) in lines.iter_mut().zip(glyph(c).iter()) {
+            if i > 0 {
+                line.push(' ');
+            }
+            line.push_str(row);
+        }
+    }
+    if lines[0].len() > MAX_WIDTH {
+        return Err(format!(
+            "Too long for a banner, it can be at most {} columns wide",
+            MAX_WIDTH
+        ));
+    }
+    return Ok(lines.iter().map(|l| String::from(l.trim_end())).collect());
+}
@@ -0,0 +1,1263 @@
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use crate::format::{self, Format};
+use crate::highlight;
+use crate::links::{find_urls, label_urls};
+use crate::settings::DEFAULT_TIME_FORMAT;
+use crate::transcript::Transcript;
+
+/// How often the ui is drawn again while it shows relative times, so they don't go stale.
+pub const REFRESH: Duration = Duration::from_secs(20);
+
+/// A ChatMessage which is a single line of the chat log.
+///
+/// # Fields
+/// `id` - The sender's id for the message, None for local status lines.
+/// `header` - Who sent the message, empty for status lines.
+/// `body` - The text of the message.
+/// `remote` - Whether the message came from the peer.
+/// `edited` - Whether the message has been edited since it was sent.
+/// `deleted` - Whether the message has been deleted by its sender.
+/// `links` - The numbers `/open` knows the message's urls by, empty for our own messages.
+/// `quote` - The message this one replies to, as it was shown when the reply was made.
+/// `highlight` - Whether a notification rule asked for the message to stand out.
+/// `failed` - Whether sending our message failed, until it is sent again with `/resend`.
+/// `private` - Whether the message is a `/msg` only we and one other client can read.
+/// `signed` - How its sender's signature checked out, None if it wasn't signed or has been edited since.
+/// `mention` - Whether the message mentions our nick.
+/// `code` - The code block the message is, if it is one, its body then holds the block's lines.
+/// `time` - When the message was sent in seconds since the unix epoch, the sender's time for history and
+/// otherwise when it was added.
+#[derive(Clone)]
+pub struct ChatMessage {
+    pub id: Option<u64>,
+    pub header: String,
+    pub body: String,
+    pub remote: bool,
+    pub edited: bool,
+    pub deleted: bool,
+    pub links: Vec<usize>,
+    pub quote: Option<String>,
+    pub highlight: bool,
+    pub failed: bool,
+    pub private: bool,
+    pub signed: Option<Signed>,
+    pub mention: bool,
+    pub code: Option<Code>,
+    pub time: i64,
+}
+
+/// A Signed which is how a message's signature checked out, drawn after its sender.
+///
+/// # Variants
+/// `Good` - Made with the key of who it should be from, named by the String, empty for our own messages.
+/// `Unknown` - Good, but made with a key no contact is known by and the keyring doesn't trust, only its user id
+/// says who it is.
+/// `Bad` - Didn't check out, or was made with another key than its sender is known by.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signed {
+    Good(String),
+    Unknown(String),
+    Bad,
+}
+
+/// A Code which is a code block sent with `/code`, drawn in a box.
+///
+/// # Fields
+/// `lang` - The language it is written in, empty if not given.
+/// `lines` - Its lines, None for the ones that haven't arrived yet.
+/// `draft` - Whether it is ours and still being written, `/end` sends it.
+#[derive(Clone)]
+pub struct Code {
+    pub lang: String,
+    pub lines: Vec<Option<String>>,
+    pub draft: bool,
+}
+
+impl Code {
+    /// Whether every line of the block has arrived.
+    fn complete(&self) -> bool {
+        return self.lines.iter().all(|l| l.is_some());
+    }
+
+    /// The block's lines one after another, a missing line is left blank.
+    fn text(&self) -> String {
+        let lines: Vec<&str> = self
+            .lines
+            .iter()
+            .map(|l| l.as_deref().unwrap_or(""))
+            .collect();
+        return lines.join("\n");
+    }
+}
+
+/// A Chat which stores the messages shown in the ui.
+///
+/// # Fields
+/// `messages` - The messages in the order they were added.
+/// `next_id` - The id given to the next message we send.
+/// `added` - How many messages have ever been added, counting the ones `truncate_front` dropped since.
+/// `show_ids` - Whether message ids are shown when rendering, toggled by `/ids`.
+/// `show_stats` - Whether the stats panel is shown over the chat, toggled by `/stats`.
+/// `show_peers` - Whether the peers panel is shown, toggled by `/peers` or F2.
+/// `show_help` - Whether the help is shown over the chat, opened by `/help` or F1 and closed by Esc.
+/// `show_chatstats` - Whether the conversation statistics are shown over the chat, opened by `/chatstats` and closed
+/// by Esc.
+/// `show_history` - Whether the end of the last session's transcript is shown over the chat, opened by `/history`
+/// and closed by Esc.
+/// `dnd` - Whether do not disturb is on, which silences bells and notifications, toggled by `/dnd`.
+/// `transcript` - A Option<Transcript> every line is also written to.
+/// `time_format` - How times are shown, a strftime format.
+/// `relative_times` - Whether the ui shows how long ago messages were sent rather than when.
+/// `formatting` - Whether the markup in messages is drawn as the formatting it stands for.
+/// `selected` - Which message is selected, counted like `added` so it stays put as messages come and go, None
+/// outside selection mode.
+/// `mark` - Where the selected range starts, counted like `selected`, None when just one message is selected.
+/// `escaped` - Whether the last key was Esc, so that `v` starts selecting.
+/// `urls` - Every url the peer has sent, `/open <n>` opens `urls[n - 1]`.
+pub struct Chat {
+    messages: Vec<ChatMessage>,
+    urls: Vec<String>,
+    next_id: u64,
+    added: usize,
+    pub show_ids: bool,
+    pub show_stats: bool,
+    pub show_peers: bool,
+    pub show_help: bool,
+    pub show_chatstats: bool,
+    pub show_history: bool,
+    pub dnd: bool,
+    transcript: Option<Transcript>,
+    time_format: String,
+    relative_times: bool,
+    formatting: bool,
+    selected: Option<usize>,
+    mark: Option<usize>,
+    pub escaped: bool,
+}
+
+/// Shows a time in a strftime format.
+///
+/// # Returns
+/// `String` - the time, empty if it is out of range.
+fn format_time(format: &str, time: i64) -> String {
+    return Local
+        .timestamp_opt(time, 0)
+        .single()
+        .map(|t| t.format(format).to_string())
+        .unwrap_or_default();
+}
+
+/// Shows how long ago a time was, roughly, like `5m ago`.
+///
+/// # Arguments
+/// * `time` - A i64 of seconds since the unix epoch.
+/// * `now` - A i64 of the time it is now.
+///
+/// # Returns
+/// `String` - how long ago it was, `just now` for under a minute or a time that is yet to come.
+pub fn time_ago(time: i64, now: i64) -> String {
+    let secs = now - time;
+    if secs < 60 {
+        return String::from("just now");
+    }
+    if secs < 60 * 60 {
+        return format!("{}m ago", secs / 60);
+    }
+    if secs < 24 * 60 * 60 {
+        return format!("{}h ago", secs / (60 * 60));
+    }
+    return format!("{}d ago", secs / (24 * 60 * 60));
+}
+
+impl ChatMessage {
+    /// Formats a message for display.
+    ///
+    /// Called on a ChatMessage.
+    ///
+    /// # Arguments
+    /// * `show_ids` - A bool of whether to prefix the message with its id.
+    /// * `when` - A &str of when the message was sent, shown after the header.
+    ///
+    /// # Returns
+    /// `String` - the line to draw.
+    pub fn display(&self, show_ids: bool, when: &str) -> String {
+        return format!("{}{}", self.lead(show_ids, when), self.shown_body());
+    }
+
+    /// The part of the line before the body, its id, header and time.
+    fn lead(&self, show_ids: bool, when: &str) -> String {
+        let mut line = String::new();
+
+        if let (true, Some(id)) = (show_ids, self.id) {
+            line.push_str(&format!("[{}] ", id));
+        }
+        if !self.header.is_empty() {
+            let mark = match &self.signed {
+                Some(Signed::Good(name)) if name.is_empty() => String::from(" ✓"),
+                Some(Signed::Good(name)) => format!(" ✓ {}", name),
+                Some(Signed::Unknown(uid)) => format!(" ? {}", uid),
+                Some(Signed::Bad) => String::from(" ✗"),
+                None => String::new(),
+            };
+            line.push_str(&format!("{}{} {}: ", self.header, mark, when));
+        }
+        return line;
+    }
+
+    /// The body as the line shows it, with its urls numbered and whether it was changed.
+    fn shown_body(&self) -> String {
+        let mut line = String::new();
+        if let Some(code) = &self.code {
+            line.push_str(&format!("```{}", code.lang));
+            if code.draft {
+                line.push_str(" (draft, /end sends it and /cancel drops it)");
+            } else if self.deleted {
+                line.push_str(" (deleted)");
+            } else if !code.complete() {
+                let got = code.lines.iter().filter(|l| l.is_some()).count();
+                line.push_str(&format!(" ({} of {} lines)", got, code.lines.len()));
+            }
+        } else if self.deleted {
+            line.push_str(&format!("~~{}~~ (deleted)", self.body));
+        } else if self.edited {
+            line.push_str(&format!("{} (edited)", label_urls(&self.body, &self.links)));
+        } else {
+            line.push_str(&label_urls(&self.body, &self.links));
+        }
+
+        return line;
+    }
+
+    /// Whether the message is a status line rather than something someone sent.
+    ///
+    /// # Returns
+    /// `bool` - true for lines added with `push_status`.
+    pub fn is_status(&self) -> bool {
+        return self.header.is_empty();
+    }
+}
+
+impl Chat {
+    /// Creates a new empty Chat.
+    ///
+    /// # Returns
+    ///  `Chat` - the newly created chat.
+    pub fn new() -> Chat {
+        return Chat {
+            messages: Vec::new(),
+            urls: Vec::new(),
+            next_id: 1,
+            added: 0,
+            show_ids: false,
+            show_stats: false,
+            show_peers: false,
+            show_help: false,
+            show_chatstats: false,
+            show_history: false,
+            dnd: false,
+            transcript: None,
+            time_format: String::from(DEFAULT_TIME_FORMAT),
+            relative_times: false,
+            formatting: true,
+            selected: None,
+            mark: None,
+            escaped: false,
+        };
+    }
+
+    /// Logs every line added from now on to a transcript.
+    ///
+    /// # Arguments
+    /// * `transcript` - A Transcript to write lines to.
+    pub fn set_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+    }
+
+    /// Sets how times are shown, for every message and the status lines added from now on.
+    ///
+    /// # Arguments
+    /// * `format` - A &str strftime format, checked when the config file is loaded.
+    pub fn set_time_format(&mut self, format: &str) {
+        self.time_format = String::from(format);
+    }
+
+    /// Sets whether the ui shows how long ago messages were sent, like `5m ago`, rather than when.
+    ///
+    /// # Arguments
+    /// * `relative` - A bool of whether to show relative times.
+    pub fn set_relative_times(&mut self, relative: bool) {
+        self.relative_times = relative;
+    }
+
+    /// Sets whether `*bold*`, `_underline_` and `` `code` `` in messages are drawn that way or shown as typed.
+    ///
+    /// # Arguments
+    /// * `formatting` - A bool of whether to draw formatting.
+    pub fn set_formatting(&mut self, formatting: bool) {
+        self.formatting = formatting;
+    }
+
+    /// Accessor method for whether the ui shows relative times, which need drawing again as they age.
+    ///
+    /// # Returns
+    /// `bool` - true if messages show how long ago they were sent.
+    pub fn relative_times(&self) -> bool {
+        return self.relative_times;
+    }
+
+    /// Shows the current time for a status line.
+    ///
+    /// # Returns
+    /// `String` - now, in the time format.
+    pub fn stamp(&self) -> String {
+        return Local::now().format(&self.time_format).to_string();
+    }
+
+    /// Formats a message the way the ui shows it, with its time in the time format or how long ago it was. The
+    /// selected messages show their full date and time. Unless formatting is off, the markup in what someone
+    /// sent is taken out and the spans it marked are given back to draw.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the message's index in `messages`.
+    ///
+    /// # Returns
+    /// `(String, Vec<(usize, usize, Format)>)` - the line to draw and the byte range of each formatted span in it,
+    /// empty if there is no such message.
+    pub fn line(&self, i: usize) -> (String, Vec<(usize, usize, Format)>) {
+        let message = match self.messages.get(i) {
+            Some(message) => message,
+            None => return (String::new(), Vec::new()),
+        };
+        let when = if self.is_selected(i) {
+            format_time(DEFAULT_TIME_FORMAT, message.time)
+        } else if self.relative_times {
+            time_ago(message.time, Local::now().timestamp())
+        } else {
+            format_time(&self.time_format, message.time)
+        };
+        if !self.formatting || message.deleted || message.is_status() || message.code.is_some() {
+            return (message.display(self.show_ids, &when), Vec::new());
+        }
+        let lead = message.lead(self.show_ids, &when);
+        let (body, spans) = format::parse(&message.shown_body());
+        let spans = spans
+            .into_iter()
+            .map(|(start, end, f)| (lead.len() + start, lead.len() + end, f))
+            .collect();
+        return (lead + &body, spans);
+    }
+
+    /// Moves the selection a message up or down, starting selection mode on the latest message if it isn't on.
+    ///
+    /// # Arguments
+    /// * `up` - A bool of whether to move to the older message.
+    pub fn select(&mut self, up: bool) {
+        if self.messages.is_empty() {
+            return;
+        }
+        let first = self.added - self.messages.len();
+        let last = self.added - 1;
+        self.selected = Some(match self.selected {
+            None => last,
+            Some(n) if up => n.saturating_sub(1).max(first),
+            Some(n) => (n + 1).max(first).min(last),
+        });
+    }
+
+    /// Starts a range at the selected message, so moving the selection selects every message in between, or
+    /// goes back to selecting just one message.
+    pub fn toggle_mark(&mut self) {
+        self.mark = match self.mark {
+            Some(_) => None,
+            None => self.selected,
+        };
+    }
+
+    /// Leaves selection mode.
+    pub fn stop_selecting(&mut self) {
+        self.selected = None;
+        self.mark = None;
+    }
+
+    /// Whether selection mode is on, where the arrow keys move the selection rather than scroll.
+    ///
+    /// # Returns
+    /// `bool` - true while a message is selected.
+    pub fn selecting(&self) -> bool {
+        return self.selected.is_some();
+    }
+
+    /// Whether a message is selected, on its own or as part of the range.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the message's index in `messages`.
+    ///
+    /// # Returns
+    /// `bool` - true if it is selected.
+    pub fn is_selected(&self, i: usize) -> bool {
+        let (from, to) = match self.range() {
+            Some(range) => range,
+            None => return false,
+        };
+        let n = self.added - self.messages.len() + i;
+        return from <= n && n <= to;
+    }
+
+    /// Accessor method for the selected messages.
+    ///
+    /// # Returns
+    /// `Vec<&ChatMessage>` - the messages in the order they were added, empty outside selection mode. Ones that
+    /// have been dropped are left out.
+    pub fn selected(&self) -> Vec<&ChatMessage> {
+        let (from, to) = match self.range() {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+        let first = self.added - self.messages.len();
+        return self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| from <= first + i && first + i <= to)
+            .map(|(_, m)| m)
+            .collect();
+    }
+
+    /// Writes out the selected messages the way the transcript has them.
+    ///
+    /// # Arguments
+    /// * `markdown` - A bool of whether to write them as a Markdown quote, each line starting with `> `.
+    ///
+    /// # Returns
+    /// `String` - the lines, one after another.
+    pub fn export(&self, markdown: bool) -> String {
+        let mut text = String::new();
+        for message in self.selected() {
+            for line in self.transcript_lines(message) {
+                if markdown {
+                    text.push_str("> ");
+                }
+                text.push_str(&line);
+                // Markdown joins quoted lines into one paragraph unless they end in two spaces.
+                text.push_str(if markdown { "  \n" } else { "\n" });
+            }
+        }
+        return text;
+    }
+
+    /// Saves the selected messages to a new file in the current directory, named after the time.
+    ///
+    /// # Arguments
+    /// * `markdown` - A bool of whether to save them as a Markdown quote in a `.md` file rather than a `.txt` one.
+    ///
+    /// # Returns
+    /// `Result<String, String>` - the file's name, or why it couldn't be written.
+    pub fn save_selected(&self, markdown: bool) -> Result<String, String> {
+        let name = format!(
+            "r2wc-{}.{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            if markdown { "md" } else { "txt" }
+        );
+        fs::write(&name, self.export(markdown)).map_err(|e| e.to_string())?;
+        return Ok(name);
+    }
+
+    /// The first and last selected messages, counted like `added`.
+    fn range(&self) -> Option<(usize, usize)> {
+        let selected = self.selected?;
+        let mark = self.mark.unwrap_or(selected);
+        return Some((selected.min(mark), selected.max(mark)));
+    }
+
+    /// The separator to draw above a message sent on another day than the one before it.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the message's index in `messages`.
+    ///
+    /// # Returns
+    /// `Option<String>` - the separator with the message's date, None if the day didn't change.
+    pub fn day_change(&self, i: usize) -> Option<String> {
+        let day = |m: &ChatMessage| {
+            Local
+                .timestamp_opt(m.time, 0)
+                .single()
+                .map(|t| t.naive_local().date())
+        };
+        let before = day(self.messages.get(i.checked_sub(1)?)?)?;
+        let day = day(self.messages.get(i)?)?;
+        if day == before {
+            return None;
+        }
+        return Some(format!("---- {} ----", day.format("%Y-%m-%d")));
+    }
+
+    /// Closes whatever is shown over the chat, the help, `/chatstats` or `/history`, for Esc or before opening
+    /// another one.
+    pub fn close_overlays(&mut self) {
+        self.show_help = false;
+        self.show_chatstats = false;
+        self.show_history = false;
+    }
+
+    /// Accessor method for the Chat's messages.
+    ///
+    /// # Returns
+    ///  `&Vec<ChatMessage>` - the messages in the chat.
+    pub fn messages(&self) -> &Vec<ChatMessage> {
+        return &self.messages;
+    }
+
+    /// Accessor method for how many messages have ever been added.
+    ///
+    /// # Returns
+    /// `usize` - the count, which keeps going up when old messages are dropped.
+    pub fn added(&self) -> usize {
+        return self.added;
+    }
+
+    /// Accessor method for the urls the peer has sent.
+    ///
+    /// # Returns
+    /// `&Vec<String>` - the urls, `/open <n>` opens the n-th.
+    pub fn urls(&self) -> &Vec<String> {
+        return &self.urls;
+    }
+
+    /// Adds a status line that is not tied to any message.
+    ///
+    /// # Arguments
+    /// * `text` - A String of the line.
+    /// * `remote` - A bool of whether to color the line as the peer's.
+    pub fn push_status(&mut self, text: String, remote: bool) {
+        self.push(ChatMessage {
+            id: None,
+            header: String::new(),
+            body: text,
+            remote: remote,
+            edited: false,
+            deleted: false,
+            links: Vec::new(),
+            quote: None,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: Local::now().timestamp(),
+        });
+    }
+
+    /// Accessor method for the id our next message will get.
+    ///
+    /// # Returns
+    /// `u64` - the id `push_local` will give the next message.
+    pub fn next_id(&self) -> u64 {
+        return self.next_id;
+    }
+
+    /// Adds a message we sent and gives it a new id.
+    ///
+    /// # Arguments
+    /// * `header` - A String of who sent the message and when.
+    /// * `body` - A String of the message.
+    ///
+    /// # Returns
+    /// `u64` - The id to send the message with.
+    pub fn push_local(&mut self, header: String, body: String) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.push(ChatMessage {
+            id: Some(id),
+            header: header,
+            body: body,
+            remote: false,
+            edited: false,
+            deleted: false,
+            links: Vec::new(),
+            quote: None,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: Local::now().timestamp(),
+        });
+
+        return id;
+    }
+
+    /// Adds a message received from the peer.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the peer's id for the message.
+    /// * `header` - A String of who sent the message and when.
+    /// * `body` - A String of the message.
+    pub fn push_remote(&mut self, id: u64, header: String, body: String) {
+        let links = self.number_urls(&body);
+        self.push(ChatMessage {
+            id: Some(id),
+            header: header,
+            body: body,
+            remote: true,
+            edited: false,
+            deleted: false,
+            links: links,
+            quote: None,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: Local::now().timestamp(),
+        });
+    }
+
+    /// Starts writing a code block, shown as a draft that every line sent is added to until `finish_code`.
+    ///
+    /// # Arguments
+    /// * `header` - A String of who is writing it.
+    /// * `lang` - A String of the language it is in, empty if not given.
+    ///
+    /// # Returns
+    /// `bool` - false if a block is already being written.
+    pub fn start_code(&mut self, header: String, lang: String) -> bool {
+        if self.drafting() {
+            return false;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.push(ChatMessage {
+            id: Some(id),
+            header: header,
+            body: String::new(),
+            remote: false,
+            edited: false,
+            deleted: false,
+            links: Vec::new(),
+            quote: None,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: Some(Code {
+                lang: lang,
+                lines: Vec::new(),
+                draft: true,
+            }),
+            time: Local::now().timestamp(),
+        });
+        return true;
+    }
+
+    /// Whether a code block is being written, so lines sent go into it.
+    ///
+    /// # Returns
+    /// `bool` - true between `start_code` and `finish_code` or `cancel_code`.
+    pub fn drafting(&self) -> bool {
+        return self
+            .messages
+            .iter()
+            .any(|m| m.code.as_ref().map_or(false, |c| c.draft));
+    }
+
+    /// Adds a line to the code block being written, with its tabs turned into four spaces.
+    ///
+    /// # Arguments
+    /// * `line` - A &str of the line.
+    pub fn add_code_line(&mut self, line: &str) {
+        if let Some(message) = self.draft() {
+            let code = message.code.as_mut().unwrap();
+            code.lines.push(Some(line.replace('\t', "    ")));
+            message.body = code.text();
+        }
+    }
+
+    /// Finishes the code block being written so it can be sent, dropping it if it has no lines.
+    ///
+    /// # Returns
+    /// `Option<(u64, String, Vec<String>)>` - the block's id, language and lines, None if there was nothing to send.
+    pub fn finish_code(&mut self) -> Option<(u64, String, Vec<String>)> {
+        let message = self.draft()?;
+        let code = message.code.as_mut().unwrap();
+        code.draft = false;
+        if code.lines.is_empty() {
+            message.deleted = true;
+            return None;
+        }
+        message.time = Local::now().timestamp();
+        let sent = (
+            message.id.unwrap_or_default(),
+            code.lang.clone(),
+            code.lines
+                .iter()
+                .map(|l| l.clone().unwrap_or_default())
+                .collect(),
+        );
+        let message = message.clone();
+        for line in self.transcript_lines(&message) {
+            self.log(&line);
+        }
+        return Some(sent);
+    }
+
+    /// Adds a whole code block of ours at once, like a banner, so it can be sent.
+    ///
+    /// # Arguments
+    /// * `header` - A String of who wrote it.
+    /// * `lang` - A String of the language it is in, empty if not given.
+    /// * `lines` - A &[String] of its lines.
+    ///
+    /// # Returns
+    /// `Option<(u64, String, Vec<String>)>` - the block's id, language and lines, None if another block is being
+    /// written.
+    pub fn push_code(
+        &mut self,
+        header: String,
+        lang: String,
+        lines: &[String],
+    ) -> Option<(u64, String, Vec<String>)> {
+        if !self.start_code(header, lang) {
+            return None;
+        }
+        for line in lines {
+            self.add_code_line(line);
+        }
+        return self.finish_code();
+    }
+
+    /// Drops the code block being written, it stays in the chat marked as deleted.
+    ///
+    /// # Returns
+    /// `bool` - false if no block was being written.
+    pub fn cancel_code(&mut self) -> bool {
+        match self.draft() {
+            Some(message) => {
+                message.code.as_mut().unwrap().draft = false;
+                message.deleted = true;
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    /// Adds a line of a code block from the peer, starting the block with its first line to arrive.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the peer's id for the block.
+    /// * `header` - A String of who sent it.
+    /// * `line` - A u32 of which line it is, counting from 0.
+    /// * `count` - A u32 of how many lines the block has.
+    /// * `lang` - A String of the language it is in, empty if not given.
+    /// * `text` - A String of the line.
+    pub fn push_code_line(
+        &mut self,
+        id: u64,
+        header: String,
+        line: u32,
+        count: u32,
+        lang: String,
+        text: String,
+    ) {
+        let found = match self.find(id, true) {
+            Some(message) if message.code.is_some() => true,
+            _ => false,
+        };
+        if !found {
+            self.push(ChatMessage {
+                id: Some(id),
+                header: header,
+                body: String::new(),
+                remote: true,
+                edited: false,
+                deleted: false,
+                links: Vec::new(),
+                quote: None,
+                highlight: false,
+                failed: false,
+                private: false,
+                signed: None,
+                mention: false,
+                code: Some(Code {
+                    lang: lang,
+                    lines: vec![None; count as usize],
+                    draft: false,
+                }),
+                time: Local::now().timestamp(),
+            });
+        }
+        let message = match self.find(id, true) {
+            Some(message) => message,
+            None => return,
+        };
+        let code = message.code.as_mut().unwrap();
+        let was_complete = code.complete();
+        if let Some(slot) = code.lines.get_mut(line as usize) {
+            *slot = Some(text);
+        }
+        message.body = code.text();
+        if !was_complete && code.complete() {
+            let message = message.clone();
+            for line in self.transcript_lines(&message) {
+                self.log(&line);
+            }
+        }
+    }
+
+    /// How many rows `code_rows` gives for a message, without colouring them.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the message's index in `messages`.
+    ///
+    /// # Returns
+    /// `usize` - the rows, 0 if the message isn't a code block or was deleted.
+    pub fn code_height(&self, i: usize) -> usize {
+        match self.messages.get(i) {
+            Some(ChatMessage {
+                code: Some(code),
+                deleted: false,
+                ..
+            }) => return code.lines.len() + 2,
+            _ => return 0,
+        }
+    }
+
+    /// The rows of the box a code block is drawn in, under the line with its header. The indentation every line
+    /// shares is taken off, and the ui cuts rows off at its edge rather than wrapping them.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the message's index in `messages`.
+    ///
+    /// # Returns
+    /// `Vec<(String, Vec<(usize, usize, Format)>)>` - each row and the byte ranges to colour in it, empty if the
+    /// message isn't a code block or was deleted.
+    pub fn code_rows(&self, i: usize) -> Vec<(String, Vec<(usize, usize, Format)>)> {
+        let code = match self.messages.get(i) {
+            Some(ChatMessage {
+                code: Some(code),
+                deleted: false,
+                ..
+            }) => code,
+            _ => return Vec::new(),
+        };
+        let indent = code
+            .lines
+            .iter()
+            .flatten()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.len() - l.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(0);
+        let lines: Vec<&str> = code
+            .lines
+            .iter()
+            .map(|l| match l {
+                Some(l) => l.get(indent..).unwrap_or(""),
+                None => "…",
+            })
+            .collect();
+        let width = lines
+            .iter()
+            .map(|l| l.chars().count())
+            .chain(Some(code.lang.chars().count() + 2))
+            .max()
+            .unwrap_or(0);
+        let title = if code.lang.is_empty() {
+            String::new()
+        } else {
+            format!(" {} ", code.lang)
+        };
+        let colours = highlight::highlight(&code.lang, &lines);
+
+        let mut rows = vec![(format!("┌─{:─<w$}─┐", title, w = width), Vec::new())];
+        for (line, spans) in lines.iter().zip(colours) {
+            // The spans are in the line, which starts after the border and a space.
+            let start = "│ ".len();
+            let spans = spans
+                .into_iter()
+                .map(|(from, to, f)| (start + from, start + to, f))
+                .collect();
+            rows.push((format!("│ {:<w$} │", line, w = width), spans));
+        }
+        rows.push((format!("└{}┘", "─".repeat(width + 2)), Vec::new()));
+        return rows;
+    }
+
+    /// Adds a message the peer sent before we connected.
+    ///
+    /// History has no id, so it can't be edited or deleted.
+    ///
+    /// # Arguments
+    /// * `time` - A i64 of when the message was sent, in seconds since the unix epoch.
+    /// * `header` - A String of who sent the message and when.
+    /// * `body` - A String of the message.
+    pub fn push_history(&mut self, time: i64, header: String, body: String) {
+        let links = self.number_urls(&body);
+        self.push(ChatMessage {
+            id: None,
+            header: header,
+            body: body,
+            remote: true,
+            edited: false,
+            deleted: false,
+            links: links,
+            quote: None,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: time,
+        });
+    }
+
+    /// Adds a reply we sent to one of the peer's messages and gives it a new id.
+    ///
+    /// # Arguments
+    /// * `to` - A u64 of the peer's id for the message being replied to.
+    /// * `header` - A String of who sent the reply and when.
+    /// * `body` - A String of the reply.
+    ///
+    /// # Returns
+    /// `Option<u64>` - The id to send the reply with, or None if there is no such message.
+    pub fn push_reply(&mut self, to: u64, header: String, body: String) -> Option<u64> {
+        let quote = self.quote_of(to, true)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.push(ChatMessage {
+            id: Some(id),
+            header: header,
+            body: body,
+            remote: false,
+            edited: false,
+            deleted: false,
+            links: Vec::new(),
+            quote: Some(quote),
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: Local::now().timestamp(),
+        });
+
+        return Some(id);
+    }
+
+    /// Adds a reply received from the peer to one of our messages.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the peer's id for the reply.
+    /// * `to` - A u64 of our id for the message being replied to.
+    /// * `header` - A String of who sent the reply and when.
+    /// * `body` - A String of the reply.
+    pub fn push_remote_reply(&mut self, id: u64, to: u64, header: String, body: String) {
+        let links = self.number_urls(&body);
+        let quote = self
+            .quote_of(to, false)
+            .or_else(|| Some(String::from("(message not found)")));
+        self.push(ChatMessage {
+            id: Some(id),
+            header: header,
+            body: body,
+            remote: true,
+            edited: false,
+            deleted: false,
+            links: links,
+            quote: quote,
+            highlight: false,
+            failed: false,
+            private: false,
+            signed: None,
+            mention: false,
+            code: None,
+            time: Local::now().timestamp(),
+        });
+    }
+
+    /// Replaces the body of a message.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    /// * `remote` - A bool of whether the message came from the peer.
+    /// * `body` - A String of the new message text.
+    ///
+    /// # Returns
+    /// `bool` - Whether the message was found and changed.
+    pub fn edit(&mut self, id: u64, remote: bool, body: String) -> bool {
+        match self.find(id, remote) {
+            Some(msg) if !msg.deleted => {}
+            _ => return false,
+        }
+
+        let links = if remote {
+            self.number_urls(&body)
+        } else {
+            Vec::new()
+        };
+        let format = self.time_format.clone();
+        match self.find(id, remote) {
+            Some(msg) => {
+                msg.body = body;
+                msg.edited = true;
+                msg.signed = None;
+                msg.links = links;
+                let line = format!(
+                    "Edited: {}",
+                    msg.display(false, &format_time(&format, msg.time))
+                );
+                self.log(&line);
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Marks a message as deleted.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    /// * `remote` - A bool of whether the message came from the peer.
+    ///
+    /// # Returns
+    /// `bool` - Whether the message was found and deleted.
+    pub fn delete(&mut self, id: u64, remote: bool) -> bool {
+        let format = self.time_format.clone();
+        match self.find(id, remote) {
+            Some(msg) if !msg.deleted => {
+                msg.deleted = true;
+                let line = format!(
+                    "Deleted: {}",
+                    msg.display(false, &format_time(&format, msg.time))
+                );
+                self.log(&line);
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Marks one of our messages as not sent.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    pub fn mark_failed(&mut self, id: u64) {
+        if let Some(msg) = self.find(id, false) {
+            msg.failed = true;
+        }
+    }
+
+    /// Clears the mark on one of our messages that was not sent, before sending it again.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    ///
+    /// # Returns
+    /// `bool` - Whether the message was found and marked as not sent.
+    pub fn clear_failed(&mut self, id: u64) -> bool {
+        match self.find(id, false) {
+            Some(msg) if msg.failed => {
+                msg.failed = false;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Marks the most recently added message as private, so it is drawn apart from messages everyone sees.
+    pub fn mark_private_latest(&mut self) {
+        if let Some(msg) = self.messages.last_mut() {
+            msg.private = true;
+        }
+    }
+
+    /// Looks up who sent a message that can be signed and its body, to check its signature against.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    /// * `remote` - A bool of whether the message came from the peer.
+    ///
+    /// # Returns
+    /// `Option<(String, String)>` - the sender and the body, None if there is no such message or it was edited,
+    /// deleted or is a code block.
+    pub fn signable(&mut self, id: u64, remote: bool) -> Option<(String, String)> {
+        match self.find(id, remote) {
+            Some(msg) if !msg.edited && !msg.deleted && msg.code.is_none() => {
+                return Some((msg.header.clone(), msg.body.clone()))
+            }
+            _ => return None,
+        }
+    }
+
+    /// Marks a message with how its signature checked out, shown as ✓, ? or ✗ after its sender.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message id.
+    /// * `remote` - A bool of whether the message came from the peer.
+    /// * `signed` - A Signed of how the signature checked out.
+    pub fn mark_signed(&mut self, id: u64, remote: bool, signed: Signed) {
+        if let Some(msg) = self.find(id, remote) {
+            msg.signed = Some(signed);
+        }
+    }
+
+    /// Marks the most recently added message as mentioning our nick, so it is drawn in the mention colour.
+    pub fn mention_latest(&mut self) {
+        if let Some(msg) = self.messages.last_mut() {
+            msg.mention = true;
+        }
+    }
+
+    /// Highlights the most recently added message.
+    pub fn highlight_latest(&mut self) {
+        if let Some(msg) = self.messages.last_mut() {
+            msg.highlight = true;
+        }
+    }
+
+    /// Looks up a url the peer sent.
+    ///
+    /// # Arguments
+    /// * `n` - A Option<usize> of the url's number, None for the latest.
+    ///
+    /// # Returns
+    /// `Option<&String>` - the url, or None if there is no such url.
+    pub fn url(&self, n: Option<usize>) -> Option<&String> {
+        match n {
+            Some(n) if n > 0 => return self.urls.get(n - 1),
+            Some(_) => return None,
+            None => return self.urls.last(),
+        }
+    }
+
+    /// Drops every message and url and wipes the transcript, see `Transcript::wipe`.
+    ///
+    /// # Returns
+    /// `io::Result<()>` - Ok, or why the transcript could not be wiped, the messages are dropped either way.
+    pub fn wipe(&mut self) -> io::Result<()> {
+        self.messages.clear();
+        self.urls.clear();
+        self.selected = None;
+        self.mark = None;
+        return match &self.transcript {
+            Some(transcript) => transcript.wipe(),
+            None => Ok(()),
+        };
+    }
+
+    /// Drops the messages past the retention policy's limits, the oldest first.
+    ///
+    /// # Arguments
+    /// * `max` - A Option<usize> of the most messages to keep, None to keep any number.
+    /// * `before` - A Option<i64> unix time older messages are dropped before, None to keep them however old.
+    ///
+    /// # Returns
+    /// `usize` - how many messages were dropped.
+    pub fn prune(&mut self, max: Option<usize>, before: Option<i64>) -> usize {
+        let len = self.messages.len();
+        if let Some(before) = before {
+            self.messages.retain(|m| m.time >= before);
+        }
+        if let Some(max) = max {
+            self.truncate_front(max);
+        }
+        return len - self.messages.len();
+    }
+
+    /// Drops the oldest messages so that at most `max` remain.
+    ///
+    /// # Arguments
+    /// * `max` - A usize of how many messages to keep.
+    pub fn truncate_front(&mut self, max: usize) {
+        while self.messages.len() > max {
+            self.messages.remove(0);
+        }
+    }
+
+    /// Adds a message and logs it to the transcript, a code block once it is whole.
+    fn push(&mut self, message: ChatMessage) {
+        if message
+            .code
+            .as_ref()
+            .map_or(true, |c| c.complete() && !c.draft)
+        {
+            for line in self.transcript_lines(&message) {
+                self.log(&line);
+            }
+        }
+        self.messages.push(message);
+        self.added += 1;
+    }
+
+    /// The lines the transcript has for a message, the one it replies to first.
+    fn transcript_lines(&self, message: &ChatMessage) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(quote) = &message.quote {
+            lines.push(format!("> {}", quote));
+        }
+        lines.push(message.display(false, &format_time(&self.time_format, message.time)));
+        if let Some(code) = &message.code {
+            lines.extend(code.lines.iter().map(|l| l.clone().unwrap_or_default()));
+            lines.push(String::from("```"));
+        }
+        return lines;
+    }
+
+    /// The code block being written.
+    fn draft(&mut self) -> Option<&mut ChatMessage> {
+        return self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.code.as_ref().map_or(false, |c| c.draft));
+    }
+
+    /// Writes a line to the transcript if there is one.
+    fn log(&self, line: &str) {
+        if let Some(transcript) = &self.transcript {
+            transcript.write_line(line);
+        }
+    }
+
+    /// Remembers the urls in a message from the peer.
+    ///
+    /// # Returns
+    /// `Vec<usize>` - the numbers given to the urls, in order.
+    fn number_urls(&mut self, body: &str) -> Vec<usize> {
+        let mut numbers = Vec::new();
+        for (start, end) in find_urls(body) {
+            self.urls.push(String::from(&body[start..end]));
+            numbers.push(self.urls.len());
+        }
+        return numbers;
+    }
+
+    /// Formats a message for quoting in a reply.
+    fn quote_of(&self, id: u64, remote: bool) -> Option<String> {
+        return self
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.remote == remote && m.id == Some(id))
+            .map(|m| m.display(false, &format_time(&self.time_format, m.time)));
+    }
+
+    /// Finds a message by id and sender.
+    fn find(&mut self, id: u64, remote: bool) -> Option<&mut ChatMessage> {
+        return self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.remote == remote && m.id == Some(id));
+    }
+}
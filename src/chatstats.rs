@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use crate::chat::ChatMessage;
+
+/// How many columns the busiest hour's bar takes, the other hours are drawn in proportion.
+const BAR_WIDTH: usize = 30;
+
+/// Works out the conversation's statistics for `/chatstats`: how much each sender said, when the chat was busiest
+/// and how long this session has run.
+///
+/// Status lines and deleted messages aren't counted. Times are bucketed by the local hour they were sent in.
+///
+/// # Arguments
+/// * `messages` - A &[ChatMessage] of the messages in the chat, including the history it was sent.
+/// * `session` - A Duration of how long this session has run.
+///
+/// # Returns
+/// `Vec<String>` - the overlay's lines.
+pub fn chatstats(messages: &[ChatMessage], session: Duration) -> Vec<String> {
+    let mut senders: HashMap<&str, (usize, usize)> = HashMap::new();
+    let mut hours = [0; 24];
+    for m in messages
+        .iter()
+        .filter(|m| !m.header.is_empty() && !m.deleted)
+    {
+        let sender = senders.entry(m.header.as_str()).or_insert((0, 0));
+        sender.0 += 1;
+        sender.1 += m.body.chars().count();
+        if let Some(time) = Local.timestamp_opt(m.time, 0).single() {
+            hours[time.hour() as usize] += 1;
+        }
+    }
+    let total: usize = senders.values().map(|(count, _)| count).sum();
+
+    let mut lines = vec![
+        String::from("Conversation"),
+        format!("  Session   {}", duration(session)),
+        format!("  Messages  {}", total),
+    ];
+    if total == 0 {
+        return lines;
+    }
+
+    let mut senders: Vec<(&str, usize, usize)> = senders
+        .into_iter()
+        .map(|(name, (count, chars))| (name, count, chars))
+        .collect();
+    senders.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let width = senders
+        .iter()
+        .map(|(name, _, _)| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Sender".len());
+    lines.push(String::new());
+    lines.push(format!(
+        "  {:<w$}  {:>8}  {:>10}",
+        "Sender",
+        "Messages",
+        "Avg length",
+        w = width
+    ));
+    for (name, count, chars) in senders {
+        lines.push(format!(
+            "  {:<w$}  {:>8}  {:>10}",
+            name,
+            count,
+            chars / count,
+            w = width
+        ));
+    }
+
+    let busiest = hours.iter().max().copied().unwrap_or(0);
+    lines.push(String::new());
+    lines.push(String::from("Busiest hours"));
+    for (hour, count) in hours.iter().enumerate() {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(busiest));
+        lines.push(format!(
+            "  {:02}:00  {:<w$}  {}",
+            hour,
+            bar,
+            count,
+            w = BAR_WIDTH
+        ));
+    }
+    return lines;
+}
+
+/// Formats how long the session has run in its two largest units.
+fn duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 * 60 {
+        return format!("{}m {}s", secs / 60, secs % 60);
+    }
+    return format!("{}h {}m", secs / (60 * 60), secs / 60 % 60);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::chatstats;
+    use crate::chat::Chat;
+
+    #[test]
+    fn senders_are_counted_busiest_first() {
+        let mut chat = Chat::new();
+        chat.push_local(String::from("You"), String::from("hi"));
+        chat.push_remote(1, String::from("Alice"), String::from("hello"));
+        chat.push_remote(2, String::from("Alice"), String::from("how are you"));
+        chat.push_status(String::from("Alice is away"), true);
+
+        let lines = chatstats(chat.messages(), Duration::from_secs(3725));
+        assert_eq!(lines[1], "  Session   1h 2m");
+        assert_eq!(lines[2], "  Messages  3");
+        assert_eq!(lines[5], "  Alice          2           8");
+        assert_eq!(lines[6], "  You            1           2");
+        let hours: Vec<&String> = lines[8..].iter().filter(|l| l.ends_with("  3")).collect();
+        assert_eq!(hours.len(), 1);
+        assert!(hours[0].contains(&"#".repeat(30)));
+    }
+
+    #[test]
+    fn an_empty_chat_has_no_table() {
+        let lines = chatstats(Chat::new().messages(), Duration::from_secs(42));
+        assert_eq!(
+            lines,
+            vec!["Conversation", "  Session   0m 42s", "  Messages  0"]
+        );
+    }
+}
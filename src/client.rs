@@ -1,245 +1,1245 @@
 /// Client UI file
-use std::sync::mpsc::{self, RecvTimeoutError};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::Instant;
 
 extern crate chrono;
-use chrono::prelude::*;
 
-extern crate ncurses;
-use ncurses::*;
-use std::char;
+extern crate signal_hook;
 
 extern crate stopwatch;
-use stopwatch::Stopwatch;
 
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+
+mod away;
+mod banner;
+mod chat;
+mod chatstats;
+mod clipboard;
+mod commands;
+mod common;
+mod complete;
+mod config;
 mod connection;
-use self::connection::Connection;
-
-/// Inits ncurses
-fn init_ncurses() {
-    initscr();
-    raw();
-    keypad(stdscr(), true);
-    start_color();
-    init_pair(1, COLOR_GREEN, COLOR_BLACK);
-    init_pair(2, COLOR_BLUE, COLOR_BLACK);
-    init_pair(3, COLOR_WHITE, COLOR_BLACK);
-    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
-}
-
-/// Handles a serve messsage.
-fn handle_server_message(
-    con: &Connection,
-    chat: &mut Vec<(std::string::String, bool)>,
-    msg: String,
-    sent_time: Stopwatch,
-) -> bool {
-    if msg == "Message Received." {
-        let time_in_ms = sent_time.elapsed_ms();
-        chat.push((
-            format!(
-                "Server {}: {} taking {}ms",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                msg,
-                time_in_ms
-            ),
-            true,
-        ));
-    } else if msg == "Disconnected" {
-        chat.push((
-            format!(
-                "Client {}: Disconnected",
-                Local::now().format("%Y-%m-%d %H:%M:%S")
-            ),
-            true,
-        ));
-        return true;
-    } else if msg != "Empty" && msg != "Blocked" {
-        chat.push((
-            format!(
-                "Server {}: {}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                msg
-            ),
-            true,
-        ));
-        con.notify_message_received();
-    }
+mod e2e;
+mod format;
+mod highlight;
+mod keymap;
+mod links;
+mod peers;
+mod protocol;
+mod random;
+mod retention;
+mod rules;
+mod servers;
+mod settings;
+mod sign;
+mod spell;
+mod term;
+mod theme;
+mod transcript;
+mod ui;
+mod vault;
+mod viewer;
+mod watch;
+mod wizard;
+use self::away::AutoAway;
+use self::banner::banner;
+use self::chat::{Chat, REFRESH};
+use self::chatstats::chatstats;
+use self::commands::{contacts, help, outbox, parse_command, Command};
+use self::common::{
+    alert, check_signature, forward_signals, handle_selecting, install_panic_hook,
+    load_spell_checker, not_sent, open_journal, open_transcript, otr_started, save_settings,
+    say_goodbye, screen, send, send_code, send_left, send_signature, watch_settings, wipe_history,
+    UiEvent,
+};
+use self::complete::Completer;
+use self::config::{Args, Uri, URI_SCHEME};
+use self::connection::{
+    set_server_port, Connection, ConnectionBuilder, Event, Journal, NetStatus, OutgoingMsg, Reason,
+};
+use self::e2e::{KeyChange, Keyring};
+use self::keymap::{Action, Keymap};
+use self::links::open_url;
+use self::peers::Peers;
+use self::protocol::Frame;
+use self::retention::{cutoff, Retention};
+use self::rules::Rules;
+use self::servers::RecentServers;
+use self::settings::Settings;
+use self::sign::Signer;
+use self::term::Key;
+use self::transcript::{history, read_lines, LastSession};
+use self::ui::{ChatUi, Scroll, Ui};
+use self::vault::prompt_passphrase;
+use self::viewer::Viewer;
+use self::wizard::Wizard;
 
-    return false;
-}
+/// The most events handled in a row without drawing, so a flood of them can't keep the screen from updating.
+const MAX_BATCH: usize = 64;
 
-/// Prints the chat.
-fn print_chat(chat: &mut Vec<(std::string::String, bool)>, max_y: usize, max_x: usize) {
-    while chat.len() >= (max_y + 1) {
-        chat.remove(0);
-    }
+/// What `/wipe` leaves alone, since it isn't history.
+const KEPT: &str =
+    "contacts and their keys, the other clients' e2e keys for this run and the recent servers";
 
-    let mut chat_iter = chat.iter();
-    let mut ln = 0;
-    loop {
-        match chat_iter.next() {
-            Some((msg, client)) => {
-                mv(ln, 0);
-                clrtoeol();
-                if *client {
-                    attron(COLOR_PAIR(1));
-                } else {
-                    attron(COLOR_PAIR(2));
+/// Handles a network event, returns true once the server disconnected.
+fn handle_network_event<U: Ui>(
+    ui: &mut U,
+    chat: &mut Chat,
+    status: &mut Option<NetStatus>,
+    peers: &mut Peers,
+    rules: &Rules,
+    settings: &Settings,
+    keyring: &mut Option<Keyring>,
+    dialled: Option<&str>,
+    event: Event,
+) -> bool {
+    match event {
+        Event::Reconnecting(secs) => {
+            peers.leave("Server");
+            chat.push_status(
+                format!(
+                    "Server {}: Connection lost, trying to resume the session for {}s",
+                    chat.stamp(),
+                    secs
+                ),
+                true,
+            );
+        }
+        Event::Resumed(_) => {
+            peers.join("Server");
+            chat.push_status(format!("Server {}: Session resumed", chat.stamp()), true);
+        }
+        Event::PeerDisconnected(_, reason) => {
+            let how = if reason == Reason::Left {
+                "Server left"
+            } else {
+                "Disconnected"
+            };
+            chat.push_status(format!("Client {}: {}", chat.stamp(), how), true);
+            return true;
+        }
+        Event::Acknowledged(time_in_ms) => {
+            chat.push_status(
+                format!(
+                    "Server {}: Message Received. taking {}ms",
+                    chat.stamp(),
+                    time_in_ms
+                ),
+                true,
+            );
+        }
+        Event::Status(s) => {
+            otr_started(chat, status, &s);
+            *status = Some(s);
+        }
+        Event::Frame(Frame::Text { id, body }) => {
+            peers.active("Server");
+            if let Some(actions) = screen(settings, rules, "Server", &body) {
+                chat.push_remote(id, String::from("Server"), body.clone());
+                alert(ui, chat, &actions, "Server", &body);
+            }
+        }
+        Event::Frame(Frame::Reply { id, to, body }) => {
+            peers.active("Server");
+            if let Some(actions) = screen(settings, rules, "Server", &body) {
+                chat.push_remote_reply(id, to, String::from("Server"), body.clone());
+                alert(ui, chat, &actions, "Server", &body);
+            }
+        }
+        Event::Frame(Frame::Code {
+            id,
+            line,
+            count,
+            lang,
+            text,
+        }) => {
+            peers.active("Server");
+            if !settings.is_ignored("Server") {
+                chat.push_code_line(id, String::from("Server"), line, count, lang, text);
+            }
+        }
+        Event::Frame(Frame::Edit { id, body }) => {
+            chat.edit(id, true, body);
+        }
+        Event::Frame(Frame::Delete { id }) => {
+            chat.delete(id, true);
+        }
+        Event::Frame(Frame::Signature { id, signature }) => {
+            check_signature(chat, settings, "Server", dialled, id, &signature)
+        }
+        Event::Frame(Frame::Wiped) => {
+            chat.push_status(String::from("The server wiped their history"), true);
+        }
+        Event::NotSent(id, why) => not_sent(chat, id, why),
+        Event::Frame(Frame::Reject { reason }) => {
+            chat.push_status(format!("Peer rejected a message: {}", reason), false);
+        }
+        Event::Frame(Frame::Busy { position }) => {
+            let why = match position {
+                Some(position) => format!(
+                    "The server is busy with another client, you are number {} in line",
+                    position
+                ),
+                None => String::from("The server is busy with another client, try again later"),
+            };
+            chat.push_status(why, false);
+        }
+        Event::Frame(Frame::Relayed { id, sender, body }) => {
+            peers.active(&sender);
+            if let Some(actions) = screen(settings, rules, &sender, &body) {
+                chat.push_remote(id, sender.clone(), body.clone());
+                alert(ui, chat, &actions, &sender, &body);
+            }
+        }
+        Event::Frame(Frame::Nick { nick, key }) => {
+            if let Some(keyring) = keyring {
+                let learned = keyring.learn(&nick, &key);
+                let fingerprint = keyring.peer_fingerprint(&nick).unwrap_or_default();
+                match learned {
+                    Ok(KeyChange::New) => chat.push_status(
+                        format!("{} is here, key fingerprint {}", nick, fingerprint),
+                        true,
+                    ),
+                    Ok(KeyChange::Changed) => {
+                        chat.push_status(
+                            format!("{}'s key changed, fingerprint is now {}", nick, fingerprint),
+                            true,
+                        );
+                        chat.highlight_latest();
+                    }
+                    Ok(KeyChange::Unchanged) => (),
+                    Err(err) => chat.push_status(err, false),
                 }
-                if msg.len() > max_x {
-                    let (mut first, mut next) = msg.split_at(max_x);
-                    printw(first);
-                    while next.len() > max_x {
-                        ln += 1;
-                        mv(ln, 0);
-                        let (f, n) = next.split_at(max_x);
-                        first = f;
-                        next = n;
-                        printw(first);
+            }
+        }
+        Event::Frame(Frame::Presence {
+            peer,
+            online,
+            away,
+            nick,
+        }) => {
+            if !online {
+                peers.leave(&peer);
+                return false;
+            }
+            peers.join(&peer);
+            peers.set_away(&peer, away);
+            if let Some(nick) = nick {
+                peers.set_nick(&peer, &nick);
+            }
+        }
+        Event::Frame(Frame::Away { away }) => peers.set_away("Server", away),
+        Event::Frame(Frame::System { body }) => chat.push_status(body, true),
+        Event::Frame(Frame::Private { id, peer, data }) => {
+            peers.active(&peer);
+            let opened = match keyring {
+                Some(keyring) => keyring.open(&peer, &data),
+                None => Err(String::from("we have no nick")),
+            };
+            match opened {
+                Ok(body) => {
+                    if let Some(actions) = screen(settings, rules, &peer, &body) {
+                        chat.push_remote(id, format!("{} (private)", peer), body.clone());
+                        chat.mark_private_latest();
+                        alert(ui, chat, &actions, &peer, &body);
                     }
-                    ln += 1;
-                    mv(ln, 0);
-                    printw(next);
-                } else {
-                    printw(msg);
                 }
-                refresh();
-                ln += 1;
+                Err(err) => chat.push_status(
+                    format!("Could not read a private message from {}, {}", peer, err),
+                    false,
+                ),
+            }
+        }
+        Event::Frame(Frame::History { time, sender, body }) => {
+            if let Some(actions) = screen(settings, rules, &sender, &body) {
+                chat.push_history(time, sender.clone(), body.clone());
+                alert(ui, chat, &actions, &sender, &body);
             }
-            None => break,
         }
+        _ => (),
     }
 
-    while ln < (max_y as i32) - 1 {
-        mv(ln, 0);
-        clrtoeol();
-        ln += 1;
+    return false;
+}
+
+/// The lines of the stats panel in the top right corner.
+fn stats(status: &Option<NetStatus>) -> Vec<String> {
+    let mut lines = vec![String::from("Stats")];
+    match status.as_ref().and_then(|s| s.throttle.as_ref()) {
+        Some(t) => lines.push(format!(
+            "Throttle: {:.1}/{} kbps ({:.0}%)",
+            t.rate_kbps,
+            t.limit_kbps,
+            t.usage * 100.0
+        )),
+        None => lines.push(String::from("Throttle: off")),
+    }
+    match status.as_ref().and_then(|s| s.clock.as_ref()) {
+        Some(c) => {
+            lines.push(format!(
+                "Latency: {:.1} ms one way ({:.1} ms rtt)",
+                c.one_way_ms, c.rtt_ms
+            ));
+            lines.push(format!("Clock offset: {:+.1} ms", c.offset_ms));
+        }
+        None => lines.push(String::from("Latency: measuring")),
+    }
+    if let Some(fingerprint) = status.as_ref().and_then(|s| s.otr.as_ref()) {
+        lines.push(format!("Session: {}", fingerprint));
     }
+
+    return lines;
+}
+
+/// Seals a private message into the frame the relay passes on.
+///
+/// # Returns
+/// `Result<Frame, String>` - the frame, or why it can't be sent.
+fn seal_private(keyring: &Keyring, id: u64, to: &str, text: &str) -> Result<Frame, String> {
+    let data = keyring.seal(to, text)?;
+    // The relay swaps the recipient's nick for ours, so the frame has to fit with the longer one.
+    let longest = if keyring.nick().len() > to.len() {
+        keyring.nick()
+    } else {
+        to
+    };
+    let fits = Frame::Private {
+        id: id,
+        peer: String::from(longest),
+        data: data.clone(),
+    }
+    .encode()
+    .len()
+        <= 255;
+    if !fits {
+        return Err(String::from("Message is too long for /msg"));
+    }
+
+    return Ok(Frame::Private {
+        id: id,
+        peer: String::from(to),
+        data: data,
+    });
 }
 
 /// handles input
-fn handle_input(
-    con: &Connection,
-    chat: &mut Vec<(std::string::String, bool)>,
-    input: Result<i32, RecvTimeoutError>,
+fn handle_input<U: Ui>(
+    ui: &mut U,
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    key: Key,
+    keymap: &Keymap,
     line: &mut String,
-    mut max_y: i32,
-    mut max_x: i32,
-    sent_time: &mut Stopwatch,
+    completer: &mut Completer,
+    peers: &mut Peers,
+    settings: &mut Settings,
+    keyring: &Option<Keyring>,
+    signer: &Option<Signer>,
+    journal: &Journal,
+    last_session: &mut Option<LastSession>,
 ) -> bool {
-    match input {
-        Ok(c) => {
-            match c {
-                // enter
-                0xA | 13 | KEY_ENTER => {
-                    if line == ":quit" {
-                        return true;
-                    }
-                    let (_, time) = con.send_message(line.clone());
-                    *sent_time = time;
-                    chat.push((
-                        format!(
-                            "You {}: {}",
-                            Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            line.clone()
-                        ),
+    let escaped = ::std::mem::replace(&mut chat.escaped, key == Key::Escape);
+    if escaped && key == Key::Char('v') {
+        chat.select(true);
+        return false;
+    }
+    if chat.selecting() {
+        return handle_selecting(ui, chat, key);
+    }
+    let action = keymap.action(key);
+    if action != Some(Action::Complete) {
+        completer.reset();
+    }
+    if let Some(action) = action {
+        match action {
+            Action::Quit => return true,
+            Action::Clear => line.clear(),
+            Action::Redraw => ui.redraw(),
+            Action::Complete => completer.complete(line, &peers.names()),
+            Action::ScrollUp => ui.scroll(Scroll::Line, true),
+            Action::ScrollDown => ui.scroll(Scroll::Line, false),
+            Action::PageUp => ui.scroll(Scroll::Page, true),
+            Action::PageDown => ui.scroll(Scroll::Page, false),
+            Action::Top => ui.scroll(Scroll::All, true),
+            Action::Bottom => ui.scroll(Scroll::All, false),
+            Action::NextTab => ui.switch_tab(true),
+            Action::PrevTab => ui.switch_tab(false),
+            Action::Help => {
+                chat.close_overlays();
+                chat.show_help = true;
+            }
+            Action::CloseHelp => chat.close_overlays(),
+            Action::TogglePeers => chat.show_peers = !chat.show_peers,
+        }
+        return false;
+    }
+
+    match key {
+        Key::Enter if chat.drafting() => {
+            match parse_command(line) {
+                Some(Ok(Command::End)) => {
+                    if let Some((id, lang, lines)) = chat.finish_code() {
+                        send_code(outgoing, chat, id, &lang, &lines);
+                    }
+                }
+                Some(Ok(Command::Cancel)) => {
+                    chat.cancel_code();
+                }
+                _ => chat.add_code_line(line),
+            }
+            line.clear();
+        }
+        Key::Enter => {
+            match parse_command(line) {
+                Some(Ok(Command::Quit)) => return true,
+                Some(Ok(Command::Edit(id, text))) => {
+                    if chat.edit(id, false, text.clone()) {
+                        send(outgoing, Frame::Edit { id: id, body: text });
+                    } else {
+                        chat.push_status(format!("No message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::Reply(to, text))) => {
+                    let header = String::from("You");
+                    match chat.push_reply(to, header, text.clone()) {
+                        Some(id) => {
+                            send(
+                                outgoing,
+                                Frame::Reply {
+                                    id: id,
+                                    to: to,
+                                    body: text.clone(),
+                                },
+                            );
+                            send_signature(outgoing, chat, signer, id, &text);
+                        }
+                        None => chat.push_status(format!("No message with id {}", to), false),
+                    }
+                }
+                Some(Ok(Command::Resend(id))) => {
+                    if chat.clear_failed(id) {
+                        let _ = outgoing.send(OutgoingMsg::Resend(id));
+                    } else {
+                        chat.push_status(format!("No unsent message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::Delete(id))) => {
+                    if chat.delete(id, false) {
+                        send(outgoing, Frame::Delete { id: id });
+                    } else {
+                        chat.push_status(format!("No message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::ToggleIds)) => chat.show_ids = !chat.show_ids,
+                Some(Ok(Command::ToggleStats)) => chat.show_stats = !chat.show_stats,
+                Some(Ok(Command::TogglePeers)) => chat.show_peers = !chat.show_peers,
+                Some(Ok(Command::Outbox)) => {
+                    for line in outbox(&journal.pending()) {
+                        chat.push_status(line, false);
+                    }
+                }
+                Some(Ok(Command::Help)) => {
+                    chat.close_overlays();
+                    chat.show_help = true;
+                }
+                Some(Ok(Command::Chatstats)) => {
+                    chat.close_overlays();
+                    chat.show_chatstats = true;
+                }
+                Some(Ok(Command::History)) => {
+                    chat.close_overlays();
+                    chat.show_history = true;
+                }
+                Some(Ok(Command::Latest)) => ui.latest(),
+                Some(Ok(Command::Wipe(None))) => chat.push_status(
+                    format!(
+                        "This deletes the chat, the transcript, unsent messages, the peers that left \
+                         and the last session, and keeps {}. /wipe yes to go ahead, /wipe yes notify to \
+                         also tell the peer",
+                        KEPT
+                    ),
+                    false,
+                ),
+                Some(Ok(Command::Wipe(Some(notify)))) => {
+                    wipe_history(
+                        outgoing,
+                        chat,
+                        peers,
+                        journal,
+                        last_session,
+                        KEPT,
+                        notify,
+                    );
+                    ui.latest();
+                }
+                Some(Ok(Command::Purge(age))) => {
+                    let purged = chat.prune(None, Some(cutoff(age)));
+                    chat.push_status(
+                        format!("Dropped {} old messages, the transcript is kept", purged),
                         false,
-                    ));
-                    line.clear();
-                    mv(max_y, 3);
-                    clrtoeol();
+                    );
                 }
-                // backspace
-                0x7f | KEY_BACKSPACE => {
-                    line.pop();
-                    mv(max_y, 3);
-                    clrtoeol();
+                Some(Ok(Command::Code(lang))) => {
+                    chat.start_code(String::from("You"), lang.unwrap_or_default());
+                }
+                Some(Ok(Command::Big(text))) => match banner(&text) {
+                    Ok(lines) => {
+                        let you = String::from("You");
+                        if let Some((id, lang, lines)) = chat.push_code(you, String::new(), &lines)
+                        {
+                            send_code(outgoing, chat, id, &lang, &lines);
+                        }
+                    }
+                    Err(e) => chat.push_status(e, false),
+                },
+                Some(Ok(Command::End)) | Some(Ok(Command::Cancel)) => chat.push_status(
+                    String::from("No code block is being written, /code starts one"),
+                    false,
+                ),
+                Some(Ok(Command::ToggleDnd)) => {
+                    chat.dnd = !chat.dnd;
+                    let state = if chat.dnd { "on" } else { "off" };
+                    chat.push_status(format!("Do not disturb is {}", state), false);
+                }
+                Some(Ok(Command::Ignore(None))) => {
+                    let ignored = if settings.ignore.is_empty() {
+                        String::from("Not ignoring anyone")
+                    } else {
+                        format!("Ignoring {}", settings.ignore.join(", "))
+                    };
+                    chat.push_status(ignored, false);
+                }
+                Some(Ok(Command::Ignore(Some(who)))) => {
+                    if settings.ignore(&who) {
+                        chat.push_status(format!("Ignoring {}", who), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("Already ignoring {}", who), false);
+                    }
+                }
+                Some(Ok(Command::Unignore(who))) => {
+                    if settings.unignore(&who) {
+                        chat.push_status(format!("No longer ignoring {}", who), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("Not ignoring {}", who), false);
+                    }
+                }
+                Some(Ok(Command::Contacts)) => {
+                    for line in contacts(&settings.contacts) {
+                        chat.push_status(line, false);
+                    }
                 }
-                // resize event
-                KEY_RESIZE => {
-                    clear();
-                    getmaxyx(stdscr(), &mut max_y, &mut max_x);
-                    max_y -= 1;
-                    max_x -= 1;
-                    mv(max_y, max_x);
-                    mv(max_y, (3 + line.len()) as i32);
+                Some(Ok(Command::AddContact(name, contact))) => {
+                    match settings.add_contact(&name, contact) {
+                        Ok(true) => chat.push_status(format!("Added @{} to contacts", name), false),
+                        Ok(false) => chat.push_status(format!("Updated @{}", name), false),
+                        Err(err) => chat.push_status(format!("Could not add @{}, {}", name, err), false),
+                    }
+                    save_settings(chat, settings);
+                }
+                Some(Ok(Command::RemoveContact(name))) => {
+                    if settings.remove_contact(&name).is_some() {
+                        chat.push_status(format!("Removed @{} from contacts", name), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("No contact @{}", name), false);
+                    }
+                }
+                Some(Ok(Command::Theme(None))) => {
+                    let names: Vec<String> = settings
+                        .theme_names()
+                        .into_iter()
+                        .map(|name| {
+                            if name == settings.theme_name() {
+                                format!("{} (current)", name)
+                            } else {
+                                String::from(name)
+                            }
+                        })
+                        .collect();
+                    chat.push_status(format!("Themes: {}", names.join(", ")), false);
+                }
+                Some(Ok(Command::Theme(Some(name)))) => match settings.find_theme(&name) {
+                    Some(theme) => {
+                        ui.set_theme(&theme);
+                        chat.push_status(format!("Switched to the {} theme", name), false);
+                        settings.theme = Some(name);
+                        save_settings(chat, settings);
+                    }
+                    None => chat.push_status(
+                        format!("There is no theme {}, /theme lists them", name),
+                        false,
+                    ),
+                },
+                Some(Ok(Command::Invite)) => {
+                    chat.push_status(String::from("Only the server can make invites"), false)
                 }
+                Some(Ok(Command::Msg(to, text))) => {
+                    let sealed = match keyring {
+                        Some(keyring) => seal_private(keyring, chat.next_id(), &to, &text),
+                        None => Err(String::from("Start with --nick name to use /msg")),
+                    };
+                    match sealed {
+                        Ok(frame) => {
+                            chat.push_local(format!("You to {} (private)", to), text);
+                            chat.mark_private_latest();
+                            send(outgoing, frame);
+                        }
+                        Err(err) => chat.push_status(err, false),
+                    }
+                }
+                Some(Ok(Command::Open(n))) => match chat.url(n).cloned() {
+                    Some(url) => {
+                        if let Err(err) = open_url(&url) {
+                            chat.push_status(format!("Could not open {}, {}", url, err), false);
+                        }
+                    }
+                    None => chat.push_status(String::from("No such link"), false),
+                },
+                Some(Err(usage)) => chat.push_status(usage, false),
+                None => {
+                    let id = chat.push_local(String::from("You"), line.clone());
+                    send(
+                        outgoing,
+                        Frame::Text {
+                            id: id,
+                            body: line.clone(),
+                        },
+                    );
+                    send_signature(outgoing, chat, signer, id, line);
+                }
+            }
+            line.clear();
+        }
+        Key::Backspace => {
+            line.pop();
+        }
+        Key::Resize => ui.resize(),
+
+        Key::Quit => return true,
+        Key::Char(c) => line.push(c),
+        _ => (),
+    }
+
+    return false;
+}
+
+/// Makes a keyring for `--nick`, or the link's nick, exiting if no key could be made.
+fn open_keyring(args: &Args, uri: &Option<Uri>) -> Option<Keyring> {
+    let nick = args
+        .flag("nick")
+        .or_else(|| uri.as_ref().and_then(|u| u.nick.as_ref()))?;
+    match Keyring::new(nick) {
+        Ok(keyring) => return Some(keyring),
+        Err(err) => {
+            println!("Error: Could not make a key, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Loads the config file given by `--config` and compiles its notification rules and keys, exiting if it is invalid.
+///
+/// # Arguments
+/// * `args` - A &Args of the command line.
+/// * `nick` - A Option<&str> of our nick, for the rules to tell which messages mention us.
+fn load_settings(args: &Args, nick: Option<&str>) -> (Settings, Rules, Keymap) {
+    let path = match args.flag("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Settings::default_path(),
+    };
+    let shown = path.clone().unwrap_or_default();
+    let loaded = Settings::load(path).and_then(|settings| {
+        let rules = Rules::new(&settings.rules, nick)?;
+        let keymap = Keymap::new(&settings.keys)?;
+        return Ok((settings, rules, keymap));
+    });
+    match loaded {
+        Ok(loaded) => return loaded,
+        Err(err) => {
+            println!("Error: Could not load config {}, {}", shown.display(), err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Loads the config file again after it changed and applies it, telling the user whether that worked.
+///
+/// Our own saves, like `/ignore`, change nothing and are not mentioned.
+fn reload_settings<U: Ui>(
+    ui: &mut U,
+    args: &Args,
+    nick: Option<&str>,
+    chat: &mut Chat,
+    outgoing: &Sender<OutgoingMsg>,
+    settings: &mut Settings,
+    rules: &mut Rules,
+    keymap: &mut Keymap,
+) {
+    let path = settings.path().cloned();
+    let shown = path.clone().unwrap_or_default();
+    let loaded = Settings::load(path).and_then(|settings| {
+        let rules = Rules::new(&settings.rules, nick)?;
+        let keymap = Keymap::new(&settings.keys)?;
+        return Ok((settings, rules, keymap));
+    });
+    match loaded {
+        Ok((loaded, _, _)) if loaded == *settings => (),
+        Ok((loaded, new_rules, new_keymap)) => {
+            chat.set_time_format(loaded.time_format());
+            chat.set_relative_times(loaded.relative_times);
+            chat.set_formatting(!loaded.plain_text);
+            if loaded.theme() != settings.theme() {
+                ui.set_theme(&loaded.theme());
+            }
+            if loaded.max_kbps != settings.max_kbps {
+                let max_kbps = loaded
+                    .max_kbps
+                    .unwrap_or(args.usize_flag("max-kbps", 0) as u32);
+                let _ = outgoing.send(OutgoingMsg::Limit(max_kbps));
+            }
+            *settings = loaded;
+            *rules = new_rules;
+            *keymap = new_keymap;
+            chat.push_status(format!("Reloaded config {}", shown.display()), false);
+        }
+        Err(err) => chat.push_status(
+            format!(
+                "Could not reload config {}, kept the old settings, {}",
+                shown.display(),
+                err
+            ),
+            false,
+        ),
+    }
+}
+
+/// Asks for the passphrase `--encrypt-history` encrypts the transcript and journal with, before the ui starts,
+/// exiting if it can't be read.
+fn history_passphrase(args: &Args) -> Option<String> {
+    if !args.has_flag("encrypt-history") || !(args.has_flag("log") || args.has_flag("journal")) {
+        return None;
+    }
+    match prompt_passphrase("the history") {
+        Ok(passphrase) => return Some(passphrase),
+        Err(err) => {
+            println!("Error: Could not read the passphrase, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Reads the link given with `--uri` or as the only argument, or looks up the contact given as `@name`, exiting if
+/// it is invalid.
+fn load_uri(args: &Args) -> Option<Uri> {
+    if let Some(name) = dialled(args) {
+        return Some(contact_uri(args, name));
+    }
+    if args.has_flag("last") {
+        match RecentServers::load(RecentServers::default_path()).uri(0) {
+            Ok(uri) => return Some(uri),
+            Err(err) => {
+                println!("Error: Could not reconnect, {}", err);
+                ::std::process::exit(0x0100);
+            }
+        }
+    }
+    let bare = args.positional.is_empty()
+        && !args.has_flag("uri")
+        && !args.has_flag("rendezvous")
+        && !args.has_flag("replay");
+    if bare {
+        return pick_recent().or_else(run_wizard);
+    }
+    match args.uri() {
+        Ok(uri) => return uri,
+        Err(err) => {
+            println!("Error: Bad link, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Offers the servers we connected to lately to a bare `r2wc-client`.
+///
+/// # Returns
+/// `Option<Uri>` - the server picked, None if there are none or none was picked.
+fn pick_recent() -> Option<Uri> {
+    let recent = RecentServers::load(RecentServers::default_path());
+    if recent.servers().is_empty() {
+        return None;
+    }
+    println!("Recent servers:");
+    for line in recent.lines() {
+        println!("{}", line);
+    }
+    print!(
+        "Connect to [1-{}], or Enter for another server: ",
+        recent.servers().len()
+    );
+    io::stdout().flush().ok()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice).ok()?;
+    let n = choice.trim().parse::<usize>().ok().filter(|n| *n > 0)?;
+    match recent.uri(n - 1) {
+        Ok(uri) => return Some(uri),
+        Err(err) => {
+            println!("Error: Could not connect, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
 
-                12 => return true,
-                // any other key
-                _ => {
-                    line.push(char::from_u32(c as u32).unwrap());
-                    mv(max_y, 3);
-                    clrtoeol();
+/// Asks a bare `r2wc-client` for the server with a form drawn by the ui, see `Wizard`.
+///
+/// # Returns
+/// `Option<Uri>` - the server, None if the user gave up, which shows the usage.
+fn run_wizard() -> Option<Uri> {
+    let mut ui = ChatUi::new(false);
+    let mut chat = Chat::new();
+    let mut wizard = Wizard::new();
+    let uri = loop {
+        ui.draw_chat(&mut chat);
+        ui.draw_help(Some(&wizard.lines()));
+        ui.draw_input(wizard.input(), &None);
+        match ui.read_key() {
+            Key::Escape | Key::Quit => break None,
+            Key::Resize => ui.resize(),
+            key => {
+                if let Some(uri) = wizard.key(key) {
+                    break Some(uri);
                 }
             }
         }
-        Err(_) => return false,
+    };
+    ui.end();
+    return uri;
+}
+
+/// Remembers the server we connected to for `--last` and a bare `r2wc-client`, unless we met the peer through a
+/// rendezvous server or are replaying a recording.
+fn remember_server(args: &Args, uri: &Option<Uri>) {
+    if args.has_flag("rendezvous") || args.has_flag("replay") {
+        return;
     }
+    let uri = match (uri, args.positional.as_slice()) {
+        (Some(uri), _) => uri.clone(),
+        (None, [host, port]) => match Uri::parse(&format!("{}{}:{}", URI_SCHEME, host, port)) {
+            Ok(uri) => uri,
+            Err(_) => return,
+        },
+        _ => return,
+    };
+    let mut recent = RecentServers::load(RecentServers::default_path());
+    recent.visited(&uri);
+    // Not remembering the server is no reason to keep the user from chatting.
+    let _ = recent.save();
+}
 
-    if line.len() + 3 > max_x as usize {
-        let (_, l) = line.split_at(line.len() + 3 - (max_x as usize));
-        printw(l);
-        mv(max_y, max_x);
-    } else {
-        printw(&line);
+/// The contact given as `@name` to connect to, if one was.
+fn dialled(args: &Args) -> Option<&str> {
+    match args.positional.as_slice() {
+        [only] => return only.strip_prefix('@'),
+        _ => return None,
     }
+}
 
-    return false;
+/// Looks up where a contact is in the config file's address book, exiting if there is no such contact.
+fn contact_uri(args: &Args, name: &str) -> Uri {
+    let (settings, _, _) = load_settings(args, None);
+    let uri = match settings.contact(name) {
+        Some((_, contact)) => contact.uri(),
+        None => Err(String::from("no such contact, /contacts add saves one")),
+    };
+    match uri {
+        Ok(uri) => return uri,
+        Err(err) => {
+            println!("Error: Could not find @{}, {}", name, err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Connects to the server, or to another client through `--rendezvous`, exiting if it can't.
+///
+/// # Arguments
+/// * `args` - A &Args of the command line.
+/// * `uri` - A &Option<Uri> of the link to connect with instead of host and port.
+/// * `keyring` - A &Option<Keyring> whose nick is announced once connected.
+/// * `journal` - A &Journal the messages not acknowledged yet are kept in.
+///
+/// # Returns
+/// `(Connection, String)` - the connection and how it was made, to show the user.
+fn connect(
+    args: &Args,
+    uri: &Option<Uri>,
+    keyring: &Option<Keyring>,
+    journal: &Journal,
+) -> (Connection, String) {
+    let rendezvous = match (args.flag("rendezvous"), args.flag("room")) {
+        (Some(server), Some(room)) => Some((server, room)),
+        (None, None) => None,
+        _ => {
+            println!("Error: --rendezvous and --room have to be given together");
+            ::std::process::exit(0x0100);
+        }
+    };
+
+    let mut builder = ConnectionBuilder::from_args(255, args)
+        .queue_cap(args.usize_flag("queue-cap", 50))
+        .journal(journal.clone());
+    if let Some(keyring) = keyring {
+        builder = builder.nick(keyring.nick(), keyring.public_key());
+    }
+    if let Some(path) = args.flag("replay") {
+        return replay_session(builder, path, args);
+    }
+    let connected = match rendezvous {
+        Some((server, room)) => {
+            println!("Waiting for a peer in {}...", room);
+            builder
+                .rendezvous(server, room)
+                .map(|(con, peer, relayed)| {
+                    if relayed {
+                        let status = format!("Connected to {} through the rendezvous relay.", peer);
+                        return (con, status);
+                    }
+                    return (con, format!("Connected directly to {}.", peer));
+                })
+        }
+        None => {
+            let (addr, token) = match uri {
+                Some(uri) => (uri.addr(), uri.token.clone()),
+                None => (set_server_port(), None),
+            };
+            builder
+                .invite(args.flag("invite").cloned().or(token))
+                .client(&addr)
+                .map(|con| (con, String::from("Connected.")))
+        }
+    };
+    match connected {
+        Ok(connected) => return connected,
+        Err(err) => {
+            println!("Error: Could not connect, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Connects to a recording played back as if it were the server, exiting if it can't be read.
+///
+/// # Arguments
+/// * `builder` - A ConnectionBuilder with the options to connect with.
+/// * `path` - A &str of the recording given by `--replay`.
+/// * `args` - A &Args of the command line, for `--replay-speed`.
+///
+/// # Returns
+/// `(Connection, String)` - the connection and what is being replayed, to show the user.
+fn replay_session(builder: ConnectionBuilder, path: &str, args: &Args) -> (Connection, String) {
+    let speed = match args.flag("replay-speed").map(|s| s.parse::<f64>()) {
+        Some(Ok(speed)) if speed >= 0.0 => speed,
+        Some(_) => {
+            println!("Error: --replay-speed expects a number, 0 for as fast as possible");
+            ::std::process::exit(0x0100);
+        }
+        None => 1.0,
+    };
+    match builder.replay(path, speed) {
+        Ok(con) => return (con, format!("Replaying {}.", path)),
+        Err(err) => {
+            println!("Error: Could not replay {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Shows the transcript given with `--view` read only, without connecting anywhere, until the user quits.
+///
+/// Typing a search and pressing Enter shows only the lines that contain it, Enter on an empty line shows them all.
+fn view_transcript(args: &Args, path: &str) {
+    let lines = match read_lines(path, || prompt_passphrase("the transcript")) {
+        Ok(lines) => lines,
+        Err(err) => {
+            println!("Error: Could not read transcript {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    };
+    let (settings, _, keymap) = load_settings(args, None);
+    let viewer = Viewer::new(lines, settings.time_format());
+    let mut chat = Chat::new();
+    chat.set_time_format(settings.time_format());
+    chat.set_relative_times(settings.relative_times);
+    chat.set_formatting(!settings.plain_text);
+    let shown = viewer.show(&mut chat, "");
+    chat.push_status(
+        format!(
+            "Viewing {}, {} lines. Type a search and press Enter, Enter on its own shows every line",
+            path, shown
+        ),
+        false,
+    );
+
+    let mut ui = ChatUi::new(false);
+    ui.set_theme(&settings.theme());
+    let mut line = String::new();
+    loop {
+        ui.draw_chat(&mut chat);
+        ui.draw_status(None, None);
+        ui.draw_input(&line, &None);
+        let key = ui.read_key();
+        match keymap.action(key) {
+            Some(Action::Quit) => break,
+            Some(Action::Clear) => line.clear(),
+            Some(Action::Redraw) => ui.redraw(),
+            Some(Action::ScrollUp) => ui.scroll(Scroll::Line, true),
+            Some(Action::ScrollDown) => ui.scroll(Scroll::Line, false),
+            Some(Action::PageUp) => ui.scroll(Scroll::Page, true),
+            Some(Action::PageDown) => ui.scroll(Scroll::Page, false),
+            Some(Action::Top) => ui.scroll(Scroll::All, true),
+            Some(Action::Bottom) => ui.scroll(Scroll::All, false),
+            Some(_) => (),
+            None => match key {
+                Key::Enter => {
+                    let matched = viewer.show(&mut chat, &line);
+                    if !line.is_empty() {
+                        chat.push_status(format!("{} lines contain \"{}\"", matched, line), false);
+                    }
+                    line.clear();
+                    ui.latest();
+                }
+                Key::Backspace => {
+                    line.pop();
+                }
+                Key::Resize => ui.resize(),
+                Key::Quit => break,
+                Key::Char(c) => line.push(c),
+                _ => (),
+            },
+        }
+    }
+    ui.end();
 }
 
 fn main() {
-    let mut con = Connection::new_client_connection(255);
+    let args = Args::from_env();
+    if let Some(path) = args.flag("view") {
+        view_transcript(&args, path);
+        return;
+    }
+    let uri = load_uri(&args);
+    let mut keyring = open_keyring(&args, &uri);
+    let signer = args.flag("sign-key").map(|key| Signer::new(key));
+    let passphrase = history_passphrase(&args);
+    let (journal, left) = open_journal(&args, passphrase.as_deref());
+    let (con, connected) = connect(&args, &uri, &keyring, &journal);
+    remember_server(&args, &uri);
+    let mut last_session = args
+        .flag("log")
+        .and_then(|path| LastSession::read(path, passphrase.as_deref()));
+    let transcript = open_transcript(&args, passphrase.as_deref());
+    let (outgoing, events) = con.spawn_network(None);
 
-    let mut chat: Vec<(String, bool)> = Vec::new();
+    let spell = load_spell_checker(&args);
+    let nick = keyring.as_ref().map(|k| String::from(k.nick()));
+    let (mut settings, mut rules, mut keymap) = load_settings(&args, nick.as_deref());
+    let mut chat = Chat::new();
+    if let Some(transcript) = &transcript {
+        chat.set_transcript(transcript.clone());
+    }
     let mut line = String::new();
+    let mut status: Option<NetStatus> = None;
+    let mut peers = Peers::new();
+    let mut completer = Completer::new();
+    peers.join("Server");
 
-    init_ncurses();
+    // Our handlers have to be in place before initscr, which otherwise installs its own that just exit.
+    let (tx, rx) = mpsc::channel::<UiEvent>();
+    forward_signals(tx.clone());
+    let mut ui = ChatUi::new(false);
+    install_panic_hook(ui.clone(), outgoing.clone(), transcript.clone());
 
-    let mut max_x = 0;
-    let mut max_y = 0;
-    getmaxyx(stdscr(), &mut max_y, &mut max_x);
-    max_y -= 1;
-    max_x -= 1;
+    chat.set_time_format(settings.time_format());
+    chat.set_relative_times(settings.relative_times);
+    chat.set_formatting(!settings.plain_text);
+    ui.set_theme(&settings.theme());
+    if let Some(max_kbps) = settings.max_kbps {
+        let _ = outgoing.send(OutgoingMsg::Limit(max_kbps));
+    }
+    watch_settings(&mut chat, &settings, tx.clone());
+    let started = Instant::now();
+    let mut away = AutoAway::new(settings.away_after());
+    let mut retention = Retention::new(settings.max_messages(), settings.max_age());
 
-    let (tx, rx) = mpsc::channel::<i32>();
+    let key_tx = tx.clone();
+    let mut keys = ui.clone();
     thread::spawn(move || loop {
-        let c = getch();
-        tx.send(c).unwrap();
+        let key = keys.read_key();
+        key_tx.send(UiEvent::Key(key)).unwrap();
+    });
+    thread::spawn(move || {
+        for event in events {
+            if tx.send(UiEvent::Net(event)).is_err() {
+                return;
+            }
+        }
     });
 
-    let mut sent_time = Stopwatch::start_new();
-    chat.push((String::from("Connected."), false));
+    chat.push_status(connected, false);
+    if let Some(last) = &last_session {
+        chat.push_status(last.describe(), false);
+    }
+    if let Some(keyring) = &keyring {
+        chat.push_status(
+            format!(
+                "You are {}, key fingerprint {}",
+                keyring.nick(),
+                keyring.fingerprint()
+            ),
+            false,
+        );
+    }
+    if let Some(signer) = &signer {
+        chat.push_status(
+            format!("Signing messages with the gpg key {}", signer.key()),
+            false,
+        );
+    }
+    if args.has_flag("otr") {
+        chat.push_status(
+            String::from(
+                "Off the record, messages are sealed with keys only this session has once the peer's key arrives",
+            ),
+            false,
+        );
+    }
+    send_left(&outgoing, &mut chat, &journal, left);
+    // An event that was already waiting once the last one was handled, and how many were handled since drawing.
+    let mut next: Option<UiEvent> = None;
+    let mut batched = 0;
     loop {
-        let msg = con.receive_message();
-        if handle_server_message(&con, &mut chat, msg, sent_time) {
-            break;
+        // Events that arrive together, like a burst of frames, are handled before drawing once.
+        if next.is_none() || batched >= MAX_BATCH {
+            let panel = if chat.show_stats {
+                Some(stats(&status))
+            } else {
+                None
+            };
+            ui.draw_chat(&mut chat);
+            let peers_panel = if chat.show_peers {
+                Some(peers.lines())
+            } else {
+                None
+            };
+            ui.draw_status(None, panel.as_deref());
+            ui.draw_peers(peers_panel.as_deref());
+            let help_panel = if chat.show_help {
+                Some(help(&keymap.help()))
+            } else if chat.show_chatstats {
+                Some(chatstats(chat.messages(), started.elapsed()))
+            } else if chat.show_history {
+                Some(history(last_session.as_ref()))
+            } else {
+                None
+            };
+            ui.draw_help(help_panel.as_deref());
+            ui.draw_input(&line, &spell);
+            batched = 0;
         }
-        print_chat(&mut chat, max_y as usize, max_x as usize);
-
-        mv(max_y, 0);
-        attron(COLOR_PAIR(3));
-        printw(">> ");
-        mv(max_y, (3 + line.len()) as i32);
-        refresh();
 
-        let input = rx.recv_timeout(Duration::from_millis(100));
-        if handle_input(
-            &con,
-            &mut chat,
-            input,
-            &mut line,
-            max_y,
-            max_x,
-            &mut sent_time,
-        ) {
-            break;
+        let refresh = if chat.relative_times() {
+            Some(REFRESH)
+        } else {
+            None
+        };
+        let event = match next.take() {
+            Some(event) => Ok(event),
+            None => match [refresh, away.due(), retention.due()]
+                .iter()
+                .flatten()
+                .min()
+            {
+                Some(wait) => rx.recv_timeout(*wait),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            },
+        };
+        batched += 1;
+        match event {
+            // Nothing happened, but the relative times are due to be drawn again, we may be away now or old messages
+            // are due to be dropped.
+            Err(RecvTimeoutError::Timeout) => (),
+            Ok(UiEvent::Key(key)) => {
+                if let Some(frame) = away.key() {
+                    send(&outgoing, frame);
+                    chat.push_status(String::from("You are back"), false);
+                }
+                if handle_input(
+                    &mut ui,
+                    &outgoing,
+                    &mut chat,
+                    key,
+                    &keymap,
+                    &mut line,
+                    &mut completer,
+                    &mut peers,
+                    &mut settings,
+                    &keyring,
+                    &signer,
+                    &journal,
+                    &mut last_session,
+                ) {
+                    break;
+                }
+            }
+            Ok(UiEvent::Net(event)) => {
+                if handle_network_event(
+                    &mut ui,
+                    &mut chat,
+                    &mut status,
+                    &mut peers,
+                    &rules,
+                    &settings,
+                    &mut keyring,
+                    dialled(&args),
+                    event,
+                ) {
+                    break;
+                }
+            }
+            Ok(UiEvent::Reload) => reload_settings(
+                &mut ui,
+                &args,
+                nick.as_deref(),
+                &mut chat,
+                &outgoing,
+                &mut settings,
+                &mut rules,
+                &mut keymap,
+            ),
+            Ok(UiEvent::Quit) | Err(_) => break,
         }
-
-        attron(COLOR_PAIR(3));
+        away.set_after(settings.away_after());
+        if let Some(frame) = away.check() {
+            send(&outgoing, frame);
+            chat.push_status(
+                String::from("You are shown as away, press a key to come back"),
+                false,
+            );
+        }
+        retention.set_limits(settings.max_messages(), settings.max_age());
+        if let Some((max_messages, before)) = retention.check() {
+            chat.prune(max_messages, before);
+        }
+        next = rx.try_recv().ok();
     }
 
-    endwin();
+    say_goodbye(&outgoing, &transcript);
+    ui.end();
 }
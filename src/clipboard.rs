@@ -0,0 +1,25 @@
+use std::sync::Mutex;
+
+use arboard::Clipboard;
+
+/// The clipboard, opened on the first copy and kept open, on X11 what was copied is only there while it is.
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+/// Puts text on the system clipboard.
+///
+/// # Arguments
+/// * `text` - A &str of the text to copy.
+///
+/// # Returns
+/// `Result<(), String>` - Ok once it is copied, or why it couldn't be, like there being no display to copy to.
+pub fn copy(text: &str) -> Result<(), String> {
+    let mut clipboard = CLIPBOARD.lock().map_err(|e| e.to_string())?;
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new().map_err(|e| e.to_string())?);
+    }
+    return clipboard
+        .as_mut()
+        .unwrap()
+        .set_text(text)
+        .map_err(|e| e.to_string());
+}
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::parse_age;
+use crate::protocol::Frame;
+use crate::settings::Contact;
+
+/// A Command which is typed into the input line instead of a message.
+///
+/// # Variants
+/// `Quit` - Leave the chat, `:quit` or `/quit`.
+/// `Edit` - Replace the text of one of our messages, `/edit <id> new text`.
+/// `Delete` - Delete one of our messages, `/delete <id>`.
+/// `Reply` - Reply to one of the peer's messages, quoting it, `/reply <id> text`.
+/// `Resend` - Send one of our messages that could not be sent again, `/resend <id>`.
+/// `ToggleIds` - Show or hide message ids, `/ids`.
+/// `ToggleStats` - Show or hide the stats panel, `/stats`.
+/// `TogglePeers` - Show or hide the peers panel, `/peers`.
+/// `ToggleDnd` - Turn do not disturb on or off, `/dnd`.
+/// `Invite` - Make a single-use token for an invite-only server, `/invite`.
+/// `Ignore` - Stop showing a sender's messages, `/ignore <sender>`, or list the ignored senders, `/ignore`.
+/// `Unignore` - Show a sender's messages again, `/unignore <sender>`.
+/// `Open` - Open a url the peer sent, `/open <n>` or `/open` for the latest.
+/// `Msg` - Send an encrypted message to one client of a headless server, `/msg <nick> text`.
+/// `Theme` - Switch to another theme, `/theme <name>`, or list the themes, `/theme`.
+/// `Code` - Start writing a code block, `/code [lang]`, each line sent after it is added to the block.
+/// `End` - Send the code block being written, `/end`.
+/// `Cancel` - Drop the code block being written, `/cancel`.
+/// `Big` - Send text drawn as a banner, `/big <text>`.
+/// `Outbox` - List our messages the peer hasn't acknowledged yet, `/outbox`.
+/// `Help` - Show the commands and key bindings, `/help`.
+/// `Chatstats` - Show who said how much and when the chat was busiest, `/chatstats`.
+/// `History` - Show the end of the last session's transcript, `/history`.
+/// `Contacts` - List the address book, `/contacts list` or just `/contacts`.
+/// `AddContact` - Add someone to the address book by name, `/contacts add <name> <host:port|link> [key]`.
+/// `RemoveContact` - Drop someone from the address book, `/contacts rm <name>`.
+/// `Latest` - Jump back to the newest messages, `/latest`.
+/// `Wipe` - Delete our local history, `/wipe` says what goes and `/wipe yes` does it, Some(true) with `notify` to
+/// also tell the peer.
+/// `Purge` - Drop the messages older than an age from the chat, `/purge older-than <age>`, the transcript is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Quit,
+    Edit(u64, String),
+    Delete(u64),
+    Reply(u64, String),
+    Resend(u64),
+    ToggleIds,
+    ToggleStats,
+    TogglePeers,
+    ToggleDnd,
+    Invite,
+    Ignore(Option<String>),
+    Unignore(String),
+    Open(Option<usize>),
+    Msg(String, String),
+    Theme(Option<String>),
+    Code(Option<String>),
+    End,
+    Cancel,
+    Big(String),
+    Outbox,
+    Help,
+    Chatstats,
+    History,
+    Contacts,
+    AddContact(String, Contact),
+    RemoveContact(String),
+    Latest,
+    Wipe(Option<bool>),
+    Purge(Duration),
+}
+
+/// Every command's usage and what it does, for the help.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("/edit <id> <text>", "edit one of your messages"),
+    ("/delete <id>", "delete one of your messages"),
+    ("/reply <id> <text>", "reply to one of the peer's messages"),
+    ("/resend <id>", "send a message marked ✗ again"),
+    ("/msg <nick> <text>", "send an encrypted private message"),
+    ("/ids", "show or hide message ids"),
+    ("/stats", "show or hide the stats panel"),
+    ("/peers", "show or hide the peers panel"),
+    (
+        "/ignore [sender]",
+        "ignore a sender, or list who is ignored",
+    ),
+    ("/unignore <sender>", "show a sender's messages again"),
+    ("/dnd", "turn do not disturb on or off"),
+    ("/theme [name]", "switch theme, or list the themes"),
+    ("/code [lang]", "write a code block, /end sends it"),
+    ("/cancel", "drop the code block being written"),
+    ("/big <text>", "send text drawn as a banner"),
+    ("/outbox", "list messages not acknowledged yet"),
+    ("/invite", "make an invite token, server only"),
+    ("/open [n]", "open the n-th link, or the latest"),
+    ("/latest", "jump back to the newest messages"),
+    ("/chatstats", "show message counts and the busiest hours"),
+    ("/history", "show the end of the last session"),
+    ("/contacts [list]", "list the address book"),
+    (
+        "/contacts add <name> <addr> [key]",
+        "save where someone is, for @name",
+    ),
+    ("/contacts rm <name>", "drop someone from the address book"),
+    (
+        "/wipe [yes [notify]]",
+        "delete local history, yes to confirm",
+    ),
+    (
+        "/purge older-than <age>",
+        "drop messages older than an age, like 7d",
+    ),
+    ("/help", "show this help"),
+    ("/quit", "leave the chat"),
+];
+
+/// Makes the help listing every command and key binding.
+///
+/// # Arguments
+/// * `keys` - A &[(String, String)] of the bound keys and what they do, from the Keymap.
+///
+/// # Returns
+/// `Vec<String>` - the help's lines.
+pub fn help(keys: &[(String, String)]) -> Vec<String> {
+    let width = COMMANDS
+        .iter()
+        .map(|(usage, _)| usage.len())
+        .chain(keys.iter().map(|(key, _)| key.len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = vec![String::from("Commands")];
+    for (usage, about) in COMMANDS {
+        lines.push(format!("  {:<w$}  {}", usage, about, w = width));
+    }
+    lines.push(String::new());
+    lines.push(String::from("Keys"));
+    for (key, about) in keys {
+        lines.push(format!("  {:<w$}  {}", key, about, w = width));
+    }
+    return lines;
+}
+
+/// Lists the messages waiting for an ack, for `/outbox`.
+///
+/// # Arguments
+/// * `pending` - A &[Frame] of the messages, oldest first.
+///
+/// # Returns
+/// `Vec<String>` - a line saying how many there are, then one per message with its id.
+pub fn outbox(pending: &[Frame]) -> Vec<String> {
+    if pending.is_empty() {
+        return vec![String::from(
+            "Outbox is empty, every message was acknowledged",
+        )];
+    }
+    let mut lines = vec![format!("Outbox: {} not acknowledged yet", pending.len())];
+    for frame in pending {
+        match frame {
+            Frame::Text { id, body } | Frame::Reply { id, body, .. } => {
+                lines.push(format!("  [{}] {}", id, body))
+            }
+            _ => (),
+        }
+    }
+    return lines;
+}
+
+/// Lists the address book, for `/contacts`.
+///
+/// # Arguments
+/// * `contacts` - A &BTreeMap<String, Contact> of the contacts by name.
+///
+/// # Returns
+/// `Vec<String>` - a line saying how many there are, then one per contact with where they are and their key.
+pub fn contacts(contacts: &BTreeMap<String, Contact>) -> Vec<String> {
+    if contacts.is_empty() {
+        return vec![String::from(
+            "No contacts, /contacts add <name> <host:port> saves one",
+        )];
+    }
+    let mut lines = vec![format!("Contacts: {}", contacts.len())];
+    for (name, contact) in contacts {
+        match &contact.key {
+            Some(key) => lines.push(format!("  @{} {} key {}", name, contact.addr, key)),
+            None => lines.push(format!("  @{} {}", name, contact.addr)),
+        }
+    }
+    return lines;
+}
+
+/// Parses a line of input into a Command.
+///
+/// # Arguments
+/// * `line` - A &str of the input line.
+///
+/// # Returns
+/// `Option<Result<Command, String>>` - None if the line is a normal message, otherwise the command or a usage error.
+pub fn parse_command(line: &str) -> Option<Result<Command, String>> {
+    if line == ":quit" {
+        return Some(Ok(Command::Quit));
+    }
+    if !line.starts_with('/') {
+        return None;
+    }
+
+    let mut parts = line[1..].splitn(3, ' ');
+    let name = parts.next().unwrap_or("");
+    let arg = parts.next();
+    let rest = parts.next();
+
+    match name {
+        "quit" => return Some(Ok(Command::Quit)),
+        "ids" => return Some(Ok(Command::ToggleIds)),
+        "stats" => return Some(Ok(Command::ToggleStats)),
+        "peers" => return Some(Ok(Command::TogglePeers)),
+        "help" => return Some(Ok(Command::Help)),
+        "latest" => return Some(Ok(Command::Latest)),
+        "chatstats" => return Some(Ok(Command::Chatstats)),
+        "history" => return Some(Ok(Command::History)),
+        "contacts" => {
+            let words: Vec<&str> = rest.unwrap_or("").split_whitespace().collect();
+            match (arg, words.as_slice()) {
+                (None, []) | (Some("list"), []) => return Some(Ok(Command::Contacts)),
+                (Some("add"), [name, addr]) | (Some("add"), [name, addr, _]) => {
+                    let contact = Contact {
+                        addr: String::from(*addr),
+                        key: words.get(2).map(|key| String::from(*key)),
+                    };
+                    return Some(Ok(Command::AddContact(String::from(*name), contact)));
+                }
+                (Some("rm"), [name]) => {
+                    return Some(Ok(Command::RemoveContact(String::from(*name))))
+                }
+                _ => {
+                    return Some(Err(String::from(
+                        "Usage: /contacts [list | add <name> <host:port|link> [key] | rm <name>]",
+                    )))
+                }
+            }
+        }
+        "dnd" => return Some(Ok(Command::ToggleDnd)),
+        "invite" => return Some(Ok(Command::Invite)),
+        "end" => return Some(Ok(Command::End)),
+        "cancel" => return Some(Ok(Command::Cancel)),
+        "outbox" => return Some(Ok(Command::Outbox)),
+        "edit" => match (parse_id(arg), rest) {
+            (Some(id), Some(text)) if !text.is_empty() => {
+                return Some(Ok(Command::Edit(id, String::from(text))))
+            }
+            _ => return Some(Err(String::from("Usage: /edit <id> <new text>"))),
+        },
+        "ignore" => match (arg, rest) {
+            (None, None) => return Some(Ok(Command::Ignore(None))),
+            (Some(who), None) if !who.is_empty() => {
+                return Some(Ok(Command::Ignore(Some(String::from(who)))))
+            }
+            _ => return Some(Err(String::from("Usage: /ignore [sender]"))),
+        },
+        "unignore" => match (arg, rest) {
+            (Some(who), None) if !who.is_empty() => {
+                return Some(Ok(Command::Unignore(String::from(who))))
+            }
+            _ => return Some(Err(String::from("Usage: /unignore <sender>"))),
+        },
+        "theme" => match (arg, rest) {
+            (None, None) => return Some(Ok(Command::Theme(None))),
+            (Some(name), None) if !name.is_empty() => {
+                return Some(Ok(Command::Theme(Some(String::from(name)))))
+            }
+            _ => return Some(Err(String::from("Usage: /theme [name]"))),
+        },
+        "code" => match (arg, rest) {
+            (None, None) => return Some(Ok(Command::Code(None))),
+            (Some(lang), None) if !lang.is_empty() => {
+                return Some(Ok(Command::Code(Some(String::from(lang)))))
+            }
+            _ => return Some(Err(String::from("Usage: /code [lang]"))),
+        },
+        "big" => match arg {
+            Some(_) if !line[5..].trim().is_empty() => {
+                return Some(Ok(Command::Big(String::from(&line[5..]))))
+            }
+            _ => return Some(Err(String::from("Usage: /big <text>"))),
+        },
+        "open" => match (arg, rest) {
+            (None, None) => return Some(Ok(Command::Open(None))),
+            (Some(n), None) => match n.parse::<usize>() {
+                Ok(n) => return Some(Ok(Command::Open(Some(n)))),
+                Err(_) => return Some(Err(String::from("Usage: /open [n]"))),
+            },
+            _ => return Some(Err(String::from("Usage: /open [n]"))),
+        },
+        "msg" => match (arg, rest) {
+            (Some(nick), Some(text)) if !nick.is_empty() && !text.is_empty() => {
+                return Some(Ok(Command::Msg(String::from(nick), String::from(text))))
+            }
+            _ => return Some(Err(String::from("Usage: /msg <nick> <text>"))),
+        },
+        "reply" => match (parse_id(arg), rest) {
+            (Some(id), Some(text)) if !text.is_empty() => {
+                return Some(Ok(Command::Reply(id, String::from(text))))
+            }
+            _ => return Some(Err(String::from("Usage: /reply <id> <text>"))),
+        },
+        "delete" => match (parse_id(arg), rest) {
+            (Some(id), None) => return Some(Ok(Command::Delete(id))),
+            _ => return Some(Err(String::from("Usage: /delete <id>"))),
+        },
+        "wipe" => match (arg, rest) {
+            (None, None) => return Some(Ok(Command::Wipe(None))),
+            (Some("yes"), None) => return Some(Ok(Command::Wipe(Some(false)))),
+            (Some("yes"), Some("notify")) => return Some(Ok(Command::Wipe(Some(true)))),
+            _ => return Some(Err(String::from("Usage: /wipe [yes [notify]]"))),
+        },
+        "purge" => match (arg, rest.map(parse_age)) {
+            (Some("older-than"), Some(Ok(age))) => return Some(Ok(Command::Purge(age))),
+            (Some("older-than"), Some(Err(err))) => return Some(Err(err)),
+            _ => return Some(Err(String::from("Usage: /purge older-than <age>"))),
+        },
+        "resend" => match (parse_id(arg), rest) {
+            (Some(id), None) => return Some(Ok(Command::Resend(id))),
+            _ => return Some(Err(String::from("Usage: /resend <id>"))),
+        },
+        _ => return Some(Err(format!("Unknown command /{}", name))),
+    }
+}
+
+/// Parses a message id argument.
+fn parse_id(arg: Option<&str>) -> Option<u64> {
+    return arg.and_then(|a| a.parse::<u64>().ok());
+}
@@ -0,0 +1,519 @@
+use std::panic;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+use crate::chat::{Chat, Signed};
+use crate::clipboard::copy;
+use crate::config::Args;
+use crate::connection::{Event, Journal, NetStatus, OutgoingMsg};
+use crate::peers::Peers;
+use crate::protocol::{code_frames, Frame};
+use crate::rules::{notify_desktop, Actions, Rules};
+use crate::settings::{Contact, Settings};
+use crate::sign::{verify, Signer};
+use crate::spell::{SpellChecker, DEFAULT_DICT};
+use crate::term::Key;
+use crate::transcript::{LastSession, Transcript};
+use crate::ui::Ui;
+use crate::watch::watch;
+
+/// How long to wait for the network thread to tell the peer we are leaving.
+const GOODBYE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A UiEvent which wakes up the ui loop.
+///
+/// # Variants
+/// `Key` - A key was pressed.
+/// `Net` - The network thread sent an event.
+/// `Reload` - The config file changed.
+/// `Quit` - SIGINT or SIGTERM was received.
+pub enum UiEvent {
+    Key(Key),
+    Net(Event),
+    Reload,
+    Quit,
+}
+
+/// Tells the peer we are leaving and flushes the transcript.
+///
+/// Waits at most GOODBYE_TIMEOUT for the network thread, so a stuck socket can't stop us from exiting.
+pub fn say_goodbye(outgoing: &Sender<OutgoingMsg>, transcript: &Option<Transcript>) {
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    if outgoing.send(OutgoingMsg::Shutdown(done_tx)).is_ok() {
+        let _ = done_rx.recv_timeout(GOODBYE_TIMEOUT);
+    }
+    if let Some(transcript) = transcript {
+        transcript.flush();
+    }
+}
+
+/// Installs a panic hook that restores the terminal and says goodbye before the panic message is printed.
+pub fn install_panic_hook<U: Ui>(
+    ui: U,
+    outgoing: Sender<OutgoingMsg>,
+    transcript: Option<Transcript>,
+) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        ui.clone().end();
+        say_goodbye(&outgoing, &transcript);
+        default_hook(info);
+    }));
+}
+
+/// Forwards SIGINT and SIGTERM to the ui loop so it can exit cleanly.
+#[cfg(unix)]
+pub fn forward_signals(tx: Sender<UiEvent>) {
+    let mut signals = match Signals::new(&[SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            println!("Error: Could not install signal handler, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    };
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if tx.send(UiEvent::Quit).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Raw mode hands Ctrl+C to the ui loop as a key on Windows, so there are no signals to forward.
+#[cfg(not(unix))]
+pub fn forward_signals(_tx: Sender<UiEvent>) {}
+
+/// Marks a message that could not be sent and says why.
+pub fn not_sent(chat: &mut Chat, id: Option<u64>, why: String) {
+    match id {
+        Some(id) => {
+            chat.mark_failed(id);
+            chat.push_status(
+                format!(
+                    "{}, message {} did not go through, /resend {} to try again",
+                    why, id, id
+                ),
+                false,
+            );
+        }
+        None => chat.push_status(format!("{}, not sent", why), false),
+    }
+}
+
+/// Shows the off-the-record session's fingerprint once it starts, for the user to compare with the peer's.
+///
+/// # Arguments
+/// * `chat` - A &mut Chat to tell the user in.
+/// * `old` - A &Option<NetStatus> of the status until now.
+/// * `new` - A &NetStatus that just arrived.
+pub fn otr_started(chat: &mut Chat, old: &Option<NetStatus>, new: &NetStatus) {
+    let fingerprint = match &new.otr {
+        Some(fingerprint) => fingerprint,
+        None => return,
+    };
+    if old.as_ref().and_then(|s| s.otr.as_ref()) == Some(fingerprint) {
+        return;
+    }
+    chat.push_status(
+        format!(
+            "Off the record, session fingerprint {}. Compare it with the peer's another way, if they differ \
+             someone in the middle can read everything",
+            fingerprint
+        ),
+        false,
+    );
+}
+
+/// Decides what to do with an incoming message.
+///
+/// # Returns
+/// `Option<Actions>` - what the notification rules ask for, or None if the sender is ignored or a rule hides it.
+pub fn screen(settings: &Settings, rules: &Rules, sender: &str, body: &str) -> Option<Actions> {
+    if settings.is_ignored(sender) {
+        return None;
+    }
+    let actions = rules.check(sender, body);
+    if actions.hide {
+        return None;
+    }
+    return Some(actions);
+}
+
+/// Does what a notification rule asked for with the message just added to the chat.
+///
+/// Bells and desktop notifications are skipped while do not disturb is on, unless the rule is urgent.
+pub fn alert<U: Ui>(ui: &mut U, chat: &mut Chat, actions: &Actions, sender: &str, body: &str) {
+    if actions.mention {
+        chat.mention_latest();
+    }
+    if actions.highlight {
+        chat.highlight_latest();
+    }
+    if chat.dnd && !actions.urgent {
+        return;
+    }
+    if actions.bell {
+        ui.beep();
+    }
+    if actions.notify {
+        notify_desktop(sender, body);
+    }
+}
+
+/// Hands a frame to the network thread to send.
+pub fn send(outgoing: &Sender<OutgoingMsg>, frame: Frame) {
+    // The network thread only stops once we shut it down, so there is nobody to report a failed send to.
+    let _ = outgoing.send(OutgoingMsg::Send(frame));
+}
+
+/// Sends the messages the last run never got acknowledged again, as new messages since their ids belonged to the
+/// last session.
+pub fn send_left(
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    journal: &Journal,
+    left: Vec<Frame>,
+) {
+    if left.is_empty() {
+        return;
+    }
+    chat.push_status(
+        format!("Sending {} messages left over from last time", left.len()),
+        false,
+    );
+    for frame in left {
+        // A reply's quote is gone with the last session, so it is sent as plain text.
+        let body = match frame {
+            Frame::Text { body, .. } | Frame::Reply { body, .. } => body,
+            _ => continue,
+        };
+        let id = chat.push_local(String::from("You"), body.clone());
+        let frame = Frame::Text { id: id, body: body };
+        journal.resend(&frame);
+        send(outgoing, frame);
+    }
+}
+
+/// Sends a signature for one of our messages right after it, if we sign messages, and marks the message signed.
+pub fn send_signature(
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    signer: &Option<Signer>,
+    id: u64,
+    body: &str,
+) {
+    let signer = match signer {
+        Some(signer) => signer,
+        None => return,
+    };
+    let frame = signer.sign(body).map(|signature| Frame::Signature {
+        id: id,
+        signature: signature,
+    });
+    match frame {
+        Ok(frame) if frame.fits() => {
+            send(outgoing, frame);
+            chat.mark_signed(id, false, Signed::Good(String::new()));
+        }
+        Ok(_) => chat.push_status(
+            format!(
+                "Message {} went unsigned, signatures by {} are too long for a frame, an ed25519 key's fit",
+                id,
+                signer.key()
+            ),
+            false,
+        ),
+        Err(err) => chat.push_status(format!("Message {} went unsigned, {}", id, err), false),
+    }
+}
+
+/// Checks the signature sent after one of the peer's messages and marks the message with who made it.
+///
+/// A good signature is only marked ✓ when it was made with a contact's key, whose name follows the ✓, or with a
+/// key the keyring trusts. A message from someone known to be a contact, a sender named like one or the peer we
+/// dialled as one, has to be signed with that contact's key.
+///
+/// # Arguments
+/// * `chat` - A &mut Chat holding the message.
+/// * `settings` - A &Settings of the contacts and the keys they are known by.
+/// * `peer` - A &str of the sender the peer's own messages are shown as.
+/// * `dialled` - A Option<&str> of the contact we reached the peer as, if we did.
+/// * `id` - A u64 of the message id.
+/// * `signature` - A &str of the base64 signature.
+pub fn check_signature(
+    chat: &mut Chat,
+    settings: &Settings,
+    peer: &str,
+    dialled: Option<&str>,
+    id: u64,
+    signature: &str,
+) {
+    let (sender, body) = match chat.signable(id, true) {
+        Some(signable) => signable,
+        None => return,
+    };
+    let signature = match verify(&body, signature) {
+        Ok(signature) => signature,
+        Err(err) => {
+            chat.mark_signed(id, true, Signed::Bad);
+            chat.push_status(
+                format!("Message {}'s signature didn't check out, {}", id, err),
+                false,
+            );
+            return;
+        }
+    };
+
+    let expected = match dialled {
+        Some(dialled) if sender == peer => settings.contact(dialled),
+        _ => settings.contact(&sender),
+    };
+    let signed = match expected {
+        Some((name, Contact { key: Some(key), .. })) if signature.is_by(key) => {
+            Signed::Good(format!("@{}", name))
+        }
+        Some((name, Contact { key: Some(_), .. })) => {
+            chat.push_status(
+                format!(
+                    "Message {} is signed with {}'s key {}, not with @{}'s",
+                    id, signature.uid, signature.fingerprint, name
+                ),
+                false,
+            );
+            Signed::Bad
+        }
+        _ => match settings.contacts.iter().find(|(_, contact)| {
+            contact
+                .key
+                .as_deref()
+                .map_or(false, |key| signature.is_by(key))
+        }) {
+            Some((name, _)) => Signed::Good(format!("@{}", name)),
+            None if signature.trusted => Signed::Good(signature.uid),
+            None => Signed::Unknown(signature.uid),
+        },
+    };
+    chat.mark_signed(id, true, signed);
+}
+
+/// Sends a code block, a frame for each line, saying if any line had to be cut short to fit.
+pub fn send_code(
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    id: u64,
+    lang: &str,
+    lines: &[String],
+) {
+    let (frames, cut) = code_frames(id, lang, lines);
+    for frame in frames {
+        send(outgoing, frame);
+    }
+    if cut > 0 {
+        chat.push_status(
+            format!(
+                "Cut {} long lines of the code block short to fit in a frame",
+                cut
+            ),
+            false,
+        );
+    }
+}
+
+/// Handles a key in selection mode, where the arrow keys pick a message, `v` starts a range, `y` copies and `w`
+/// saves.
+///
+/// # Returns
+/// `bool` - true if the key asks to quit.
+pub fn handle_selecting<U: Ui>(ui: &mut U, chat: &mut Chat, key: Key) -> bool {
+    match key {
+        Key::Up | Key::Char('k') => chat.select(true),
+        Key::Down | Key::Char('j') => chat.select(false),
+        Key::Char('v') => chat.toggle_mark(),
+        Key::Char(c @ 'y') | Key::Char(c @ 'Y') => {
+            let text = match chat.selected().as_slice() {
+                [] => return false,
+                [message] if c == 'y' => message.body.clone(),
+                _ => chat.export(c == 'Y'),
+            };
+            let status = match copy(&text) {
+                Ok(()) => String::from("Copied the selection"),
+                Err(err) => format!("Could not copy the selection, {}", err),
+            };
+            chat.push_status(status, false);
+        }
+        Key::Char(c @ 'w') | Key::Char(c @ 'W') if !chat.selected().is_empty() => {
+            let status = match chat.save_selected(c == 'W') {
+                Ok(name) => format!("Saved the selection to {}", name),
+                Err(err) => format!("Could not save the selection, {}", err),
+            };
+            chat.push_status(status, false);
+        }
+        Key::Escape | Key::Char('q') => chat.stop_selecting(),
+        Key::Resize => ui.resize(),
+        Key::Quit => return true,
+        _ => (),
+    }
+    return false;
+}
+
+/// Loads the word list given by `--dict`, or the system one for `--spellcheck`, exiting if it can't be read.
+pub fn load_spell_checker(args: &Args) -> Option<SpellChecker> {
+    let path = match args.flag("dict") {
+        Some(path) => path.as_str(),
+        None if args.has_flag("spellcheck") => DEFAULT_DICT,
+        None => return None,
+    };
+    match SpellChecker::load(path) {
+        Ok(spell) => return Some(spell),
+        Err(err) => {
+            println!("Error: Could not read word list {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Wipes our local history for `/wipe yes`, the chat and its transcript, the journal, the peers that left and the
+/// last session `/history` shows, telling the user what couldn't be wiped and what was kept.
+///
+/// # Arguments
+/// * `last_session` - A &mut Option<LastSession> of what `/history` shows, None afterwards.
+/// * `kept` - A &str of what this binary keeps that isn't history, so the user knows it is still there.
+/// * `notify` - A bool of whether to tell the peer we wiped our history.
+pub fn wipe_history(
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    peers: &mut Peers,
+    journal: &Journal,
+    last_session: &mut Option<LastSession>,
+    kept: &str,
+    notify: bool,
+) {
+    let wiped = chat.wipe();
+    peers.forget_left();
+    *last_session = None;
+    if let Err(err) = wiped {
+        chat.push_status(format!("Could not wipe the transcript, {}", err), false);
+    }
+    if let Err(err) = journal.wipe() {
+        chat.push_status(format!("Could not wipe the journal, {}", err), false);
+    }
+    if notify {
+        send(outgoing, Frame::Wiped);
+    }
+    chat.push_status(
+        format!(
+            "Wiped the chat, the transcript, unsent messages, the peers that left and the last session, kept {}",
+            kept
+        ),
+        false,
+    );
+}
+
+/// Saves the config file after a change, telling the user if it couldn't be written.
+pub fn save_settings(chat: &mut Chat, settings: &Settings) {
+    if let Err(err) = settings.save() {
+        chat.push_status(format!("Could not save config, {}", err), false);
+    }
+}
+
+/// Watches the config file so changes to it are applied while we run, telling the user if it can't be watched.
+pub fn watch_settings(chat: &mut Chat, settings: &Settings, tx: Sender<UiEvent>) {
+    let path = match settings.path() {
+        Some(path) => path,
+        None => return,
+    };
+    // Without the default config file's directory there is nothing to watch.
+    if !path.parent().map(|dir| dir.is_dir()).unwrap_or(true) {
+        return;
+    }
+    match watch(path) {
+        Ok(changes) => {
+            thread::spawn(move || {
+                for () in changes {
+                    if tx.send(UiEvent::Reload).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Err(err) => chat.push_status(
+            format!("Not watching the config file for changes, {}", err),
+            false,
+        ),
+    }
+}
+
+/// Opens the journal given by `--journal`, exiting if it can't be opened, otherwise unsent messages are only kept
+/// in memory.
+///
+/// # Returns
+/// `(Journal, Vec<Frame>)` - the journal and the messages the last run never got acknowledged.
+pub fn open_journal(args: &Args, passphrase: Option<&str>) -> (Journal, Vec<Frame>) {
+    let path = match args.flag("journal") {
+        Some(path) => path,
+        None => return (Journal::memory(), Vec::new()),
+    };
+    match Journal::open(path, passphrase) {
+        Ok(opened) => return opened,
+        Err(err) => {
+            println!("Error: Could not open journal {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Opens the transcript given by `--log`, exiting if it can't be opened.
+pub fn open_transcript(args: &Args, passphrase: Option<&str>) -> Option<Transcript> {
+    let path = args.flag("log")?;
+    match Transcript::open(path, passphrase) {
+        Ok(transcript) => return Some(transcript),
+        Err(err) => {
+            println!("Error: Could not open log {}, {}", path, err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::wipe_history;
+    use crate::chat::Chat;
+    use crate::connection::Journal;
+    use crate::peers::Peers;
+    use crate::transcript::{history, LastSession};
+
+    #[test]
+    fn history_is_gone_after_a_wipe() {
+        let (outgoing, _sent) = mpsc::channel();
+        let mut chat = Chat::new();
+        let mut last_session = Some(LastSession {
+            ended: 0,
+            lines: vec![String::from("Server: the secret")],
+        });
+        assert!(history(last_session.as_ref())
+            .iter()
+            .any(|l| l.contains("the secret")));
+
+        wipe_history(
+            &outgoing,
+            &mut chat,
+            &mut Peers::new(),
+            &Journal::memory(),
+            &mut last_session,
+            "contacts",
+            false,
+        );
+        assert!(history(last_session.as_ref())
+            .iter()
+            .all(|l| !l.contains("the secret")));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::commands::COMMANDS;
+
+/// A Completer which finishes the word being typed in the input line, a command at the start of the line or
+/// a peer's name anywhere, cycling through the candidates each time it is asked again.
+///
+/// # Fields
+/// `candidates` - What the word can be completed to, empty when not cycling.
+/// `next` - The candidate to put in next.
+/// `start` - Where the word being completed starts in the line.
+pub struct Completer {
+    candidates: Vec<String>,
+    next: usize,
+    start: usize,
+}
+
+/// Finds what a word can be completed to.
+///
+/// # Arguments
+/// * `word` - A &str of the word typed so far.
+/// * `first` - A bool of whether the word starts the line, only then can it be a command.
+/// * `names` - A &[String] of the peers' names.
+///
+/// # Returns
+/// `Vec<String>` - the candidates, commands in the order the help lists them and names in the order given.
+fn candidates(word: &str, first: bool, names: &[String]) -> Vec<String> {
+    if first && word.starts_with('/') {
+        return COMMANDS
+            .iter()
+            .filter_map(|(usage, _)| usage.split(' ').next())
+            .filter(|command| command.starts_with(word))
+            .map(String::from)
+            .collect();
+    }
+
+    let word = word.to_lowercase();
+    return names
+        .iter()
+        .filter(|name| name.to_lowercase().starts_with(&word))
+        .cloned()
+        .collect();
+}
+
+impl Completer {
+    /// Creates a new Completer that isn't cycling.
+    ///
+    /// # Returns
+    /// `Completer` - the completer.
+    pub fn new() -> Completer {
+        return Completer {
+            candidates: Vec::new(),
+            next: 0,
+            start: 0,
+        };
+    }
+
+    /// Completes the last word of the line, or replaces the last completion with the next candidate.
+    ///
+    /// # Arguments
+    /// * `line` - A &mut String of the input line.
+    /// * `names` - A &[String] of the peers' names.
+    pub fn complete(&mut self, line: &mut String, names: &[String]) {
+        if self.candidates.is_empty() {
+            self.start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+            self.candidates = candidates(&line[self.start..], self.start == 0, names);
+            self.next = 0;
+        }
+        if let Some(candidate) = self.candidates.get(self.next) {
+            line.truncate(self.start);
+            line.push_str(candidate);
+            line.push(' ');
+            self.next = (self.next + 1) % self.candidates.len();
+        }
+    }
+
+    /// Stops cycling, so the next `complete` starts from whatever was typed since.
+    pub fn reset(&mut self) {
+        self.candidates.clear();
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::time::Duration;
+
+/// Flags that take a value, either as `--flag value` or `--flag=value`.
+const VALUED_FLAGS: &[&str] = &[
+    "queue-cap",
+    "max-kbps",
+    "ack-timeout",
+    "retries",
+    "nodelay",
+    "keepalive",
+    "keepalive-interval",
+    "send-buffer",
+    "recv-buffer",
+    "simulate",
+    "record",
+    "replay",
+    "replay-speed",
+    "handshake-timeout",
+    "ban-secs",
+    "waiting-room",
+    "max-clients",
+    "log",
+    "journal",
+    "control",
+    "metrics-addr",
+    "db",
+    "history",
+    "dict",
+    "config",
+    "allow",
+    "deny",
+    "invite",
+    "invite-secs",
+    "resume-secs",
+    "rendezvous",
+    "room",
+    "nick",
+    "uri",
+    "sign-key",
+    "view",
+];
+
+/// The scheme of connection links, e.g. r2wc://example.com:4000/token.
+pub const URI_SCHEME: &str = "r2wc://";
+
+/// A Uri which is a server's connection details as a single link, `r2wc://host:port/token?nick=alice&tls=0`.
+///
+/// # Fields
+/// `host` - The hostname or IP, IPv6 addresses are written in brackets in the link.
+/// `port` - The port.
+/// `token` - A Option<String> of the invite token to join with, from the path or `invite=`.
+/// `nick` - A Option<String> of the nick to use on a headless server, from `nick=`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Uri {
+    pub host: String,
+    pub port: u16,
+    pub token: Option<String>,
+    pub nick: Option<String>,
+}
+
+/// The query parameters a Uri understands.
+const URI_PARAMS: &[&str] = &["nick", "invite", "tls"];
+
+/// Args which are the parsed command line arguments of a binary.
+///
+/// # Fields
+/// `positional` - The arguments that are not flags, in order.
+/// `flags` - The `--flag` arguments mapped to their values, "true" for flags without one.
+pub struct Args {
+    pub positional: Vec<String>,
+    flags: HashMap<String, String>,
+}
+
+impl Args {
+    /// Parses the arguments the binary was started with.
+    ///
+    /// # Returns
+    /// `Args` - the parsed arguments.
+    pub fn from_env() -> Args {
+        return Args::parse(env::args().skip(1).collect());
+    }
+
+    /// Parses a list of arguments.
+    ///
+    /// # Arguments
+    /// * `args` - A Vec<String> of arguments not including the binary name.
+    ///
+    /// # Returns
+    /// `Args` - the parsed arguments.
+    pub fn parse(args: Vec<String>) -> Args {
+        let mut positional = Vec::new();
+        let mut flags = HashMap::new();
+        let mut iter = args.into_iter();
+
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+
+            let flag = &arg[2..];
+            if let Some(eq) = flag.find('=') {
+                flags.insert(String::from(&flag[..eq]), String::from(&flag[eq + 1..]));
+            } else if VALUED_FLAGS.contains(&flag) {
+                let value = iter.next().unwrap_or_default();
+                flags.insert(String::from(flag), value);
+            } else {
+                flags.insert(String::from(flag), String::from("true"));
+            }
+        }
+
+        return Args {
+            positional: positional,
+            flags: flags,
+        };
+    }
+
+    /// Accessor method for a flag's raw value.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the flag name without the leading `--`.
+    ///
+    /// # Returns
+    /// `Option<&String>` - the flag's value if it was given.
+    pub fn flag(&self, name: &str) -> Option<&String> {
+        return self.flags.get(name);
+    }
+
+    /// Whether a flag was given.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the flag name without the leading `--`.
+    ///
+    /// # Returns
+    /// `bool` - true if the flag was passed.
+    pub fn has_flag(&self, name: &str) -> bool {
+        return self.flags.contains_key(name);
+    }
+
+    /// Finds the link to connect with, from `--uri` or a lone r2wc:// positional argument.
+    ///
+    /// # Returns
+    /// `Result<Option<Uri>, String>` - the link if one was given, or what is wrong with it.
+    pub fn uri(&self) -> Result<Option<Uri>, String> {
+        let text = match (self.flag("uri"), self.positional.as_slice()) {
+            (Some(uri), _) => uri,
+            (None, [only]) if only.to_ascii_lowercase().starts_with(URI_SCHEME) => only,
+            _ => return Ok(None),
+        };
+        return Uri::parse(text).map(Some);
+    }
+
+    /// Parses a numeric flag, exiting with an error if it is not a number.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the flag name without the leading `--`.
+    /// * `default` - A usize used when the flag is not given.
+    ///
+    /// # Returns
+    /// `usize` - the flag's value.
+    pub fn usize_flag(&self, name: &str, default: usize) -> usize {
+        match self.flag(name) {
+            Some(value) => match value.parse::<usize>() {
+                Ok(n) => return n,
+                Err(_) => {
+                    println!("Error: --{} expects a number, got \"{}\"", name, value);
+                    ::std::process::exit(0x0100);
+                }
+            },
+            None => return default,
+        }
+    }
+}
+
+impl Uri {
+    /// Parses a Uri from a link, with messages that say which part of it is wrong.
+    ///
+    /// # Arguments
+    /// * `text` - A &str like r2wc://192.168.1.5:4000, r2wc://[::1]:4000/token or r2wc://host:4000?nick=alice.
+    ///
+    /// # Returns
+    /// `Result<Uri, String>` - the connection details, or what is wrong with the link.
+    pub fn parse(text: &str) -> Result<Uri, String> {
+        let rest = match text.get(..URI_SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(URI_SCHEME) => &text[URI_SCHEME.len()..],
+            _ => return Err(format!("\"{}\" does not start with {}", text, URI_SCHEME)),
+        };
+        if rest.contains('#') {
+            return Err(format!("\"{}\" can't have a #fragment", text));
+        }
+        let (rest, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let (authority, token) = match rest.find('/') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let (host, port) = if authority.starts_with('[') {
+            match authority.find(']') {
+                Some(end) => (&authority[1..end], authority[end + 1..].strip_prefix(':')),
+                None => return Err(format!("\"{}\" has an unclosed [ around its host", text)),
+            }
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("\"{}\" has no host", text));
+        }
+        let port = match port {
+            Some(port) if !port.is_empty() => port.parse::<u16>().map_err(|_| {
+                format!(
+                    "\"{}\" has a bad port \"{}\", it must be 0-65535",
+                    text, port
+                )
+            })?,
+            _ => return Err(format!("\"{}\" has no port, e.g. {}:4000", text, host)),
+        };
+
+        let mut uri = Uri {
+            host: String::from(host),
+            port: port,
+            token: match token {
+                Some(token) if !token.is_empty() => {
+                    Some(percent_decode(token).map_err(|e| format!("\"{}\" has {}", text, e))?)
+                }
+                _ => None,
+            },
+            nick: None,
+        };
+        let mut seen = Vec::new();
+        for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (
+                    &pair[..i],
+                    percent_decode(&pair[i + 1..])
+                        .map_err(|e| format!("\"{}\" has {}", text, e))?,
+                ),
+                None => (pair, String::new()),
+            };
+            if !URI_PARAMS.contains(&key) {
+                return Err(format!(
+                    "\"{}\" has an unknown parameter \"{}\", expected one of {}",
+                    text,
+                    key,
+                    URI_PARAMS.join(", ")
+                ));
+            }
+            if seen.contains(&key) {
+                return Err(format!("\"{}\" has {} more than once", text, key));
+            }
+            seen.push(key);
+            if value.is_empty() {
+                return Err(format!("\"{}\" has no value for {}", text, key));
+            }
+
+            match key {
+                "nick" => uri.nick = Some(value),
+                "invite" if uri.token.is_some() => {
+                    return Err(format!(
+                        "\"{}\" has an invite in both the path and the query",
+                        text
+                    ))
+                }
+                "invite" => uri.token = Some(value),
+                // Connections can't be encrypted yet, so a link asking for it is refused rather than connected in the
+                // clear.
+                _ => match value.as_str() {
+                    "0" | "false" => (),
+                    "1" | "true" => {
+                        return Err(format!(
+                            "\"{}\" asks for an encrypted connection with tls={}, which isn't supported yet, \
+                             leave it out to connect unencrypted",
+                            text, value
+                        ))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "\"{}\" has tls={}, expected 1, 0, true or false",
+                            text, value
+                        ))
+                    }
+                },
+            }
+        }
+
+        return Ok(uri);
+    }
+
+    /// The address to connect to.
+    ///
+    /// # Returns
+    /// `String` - host:port, with brackets around IPv6 hosts.
+    pub fn addr(&self) -> String {
+        if self.host.contains(':') {
+            return format!("[{}]:{}", self.host, self.port);
+        }
+        return format!("{}:{}", self.host, self.port);
+    }
+}
+
+/// Parses an age like `30m`, `12h`, `7d` or `2w`, a whole number and a unit of seconds, minutes, hours, days or
+/// weeks, for the retention policy and `/purge`.
+///
+/// # Arguments
+/// * `text` - A &str of the age.
+///
+/// # Returns
+/// `Result<Duration, String>` - the age, or why it isn't one.
+pub fn parse_age(text: &str) -> Result<Duration, String> {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (n, unit) = text.split_at(digits);
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("\"{}\" is not an age like 7d", text)),
+    };
+    return n
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("\"{}\" is not an age like 7d", text));
+}
+
+/// Decodes %XX escapes in part of a link.
+fn percent_decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let byte = text
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("a bad % escape in \"{}\"", text))?;
+        decoded.push(byte);
+        i += 3;
+    }
+
+    return String::from_utf8(decoded).map_err(|_| format!("invalid UTF-8 in \"{}\"", text));
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", URI_SCHEME, self.addr())?;
+        if let Some(token) = &self.token {
+            write!(f, "/{}", token)?;
+        }
+        let mut params = Vec::new();
+        if let Some(nick) = &self.nick {
+            params.push(format!("nick={}", nick));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        return Ok(());
+    }
+}
@@ -1,54 +1,152 @@
-use std::env;
-use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
 
 extern crate stopwatch;
 use stopwatch::Stopwatch;
 
+mod bans;
+mod builder;
+mod clock;
+mod filter;
+mod invites;
+mod journal;
+mod network;
+mod otr;
 mod peer;
-pub use self::peer::Peer;
+mod punch;
+mod recent;
+mod record;
+mod resume;
+mod retransmit;
+mod shared;
+mod simulate;
+mod sockopts;
+mod split;
+mod throttle;
+mod traffic;
+mod waiting;
+pub use self::bans::BanList;
+pub use self::builder::ConnectionBuilder;
+use self::clock::Clock;
+pub use self::clock::{now_micros, ClockStats};
+pub use self::filter::AcceptFilter;
+pub use self::invites::Invites;
+pub use self::journal::Journal;
+pub use self::network::{Event, NetStatus, OutgoingMsg, Reason};
+use self::otr::Otr;
+pub use self::peer::{Peer, PeerId};
+pub use self::recent::RecentIds;
+pub use self::record::Recorder;
+use self::resume::Reconnect;
+pub use self::resume::ResumeTokens;
+use self::retransmit::Retransmit;
+pub use self::shared::SharedConnection;
+pub use self::simulate::Simulation;
+pub use self::sockopts::SocketOptions;
+pub use self::split::{ConnReceiver, ConnSender};
+pub use self::throttle::{Throttle, ThrottleStats};
+pub use self::traffic::Traffic;
+use self::waiting::WaitingRoom;
+
+use crate::config::Args;
+use crate::protocol::{codec, Frame, FrameError};
 
 /// A Connection which stores information about a connection through a TcpListener.
 ///
 /// # Fields
-/// `msg_size` - Stores message size for a Conenction, that is how many characters it reads and writes.
-/// `taken` - more for server side, a mutex safe bool so that we can safely check whether a server only has one client.
+/// `msg_size` - Stores message size for a Conenction, that is the most bytes a frame it reads or writes may have.
+/// `state` - Whether a server is waiting for a client or has one, a server only ever has one client.
+/// `waiting` - The clients waiting for their turn while the server has one, server side only.
 /// `peer` - A Option<peer> currently representing the person we are talking to or not.
-/// `sender` - String channel for sending messages.
-/// `receiver` - A mutex safe String channel for receiving messages.
+/// `queue` - Messages waiting to be delivered once a peer connects.
+/// `queue_cap` - The most messages `queue` will hold, 0 disables queueing.
+/// `throttle` - A Option<Throttle> limiting how fast messages are written, None for no limit.
+/// `bans` - IPs that are refused when they connect, server side only.
+/// `filter` - Which IPs may connect at all, server side only.
+/// `invites` - The tokens clients must join with, None unless the server is invite-only.
+/// `resume_tokens` - The tokens clients resume their session with, None unless the server gives them out.
+/// `resume_token` - The token the current or last client can resume its session with, server side.
+/// `resumed` - The token the current client resumed its session with, None if it started a new one.
+/// `reconnect` - What a client needs to resume its session once the connection is lost, None if it can't.
+/// `handshake_timeout` - How long a new client has to send its Hello frame.
+/// `handshake_deadline` - When the current peer's handshake times out, None once it completed.
+/// `strikes` - How many malformed frames the current peer has sent.
+/// `traffic` - How many bytes were sent and received, shared with detached connections.
+/// `retransmit` - The messages waiting for an ack, used by the network thread.
+/// `journal` - The messages not acknowledged yet, kept so they survive a crash, shared with detached connections.
+/// `clock` - The peer's latency and clock offset, measured by the network thread.
+/// `simulation` - The delay and loss received frames go through, None unless testing with `--simulate`.
+/// `recorder` - Where every frame sent and received is recorded, None unless recording with `--record`.
+/// `batch` - The buffer lengths and sealed frames are written into before they are sent, kept between sends so they
+/// don't allocate.
+/// `socket_options` - The TCP options set on every peer's socket.
+/// `otr` - The off-the-record session with the current peer, None unless started with `--otr`.
 pub struct Connection {
     msg_size: usize,
-    pub taken: Option<bool>,
+    state: ServerState,
+    waiting: WaitingRoom,
     peer: Option<Peer>,
+    queue: VecDeque<Vec<u8>>,
+    queue_cap: usize,
+    throttle: Option<Throttle>,
+    bans: BanList,
+    filter: AcceptFilter,
+    invites: Option<Invites>,
+    resume_tokens: Option<ResumeTokens>,
+    resume_token: Option<String>,
+    resumed: Option<String>,
+    reconnect: Option<Reconnect>,
+    handshake_timeout: Duration,
+    handshake_deadline: Option<Instant>,
+    strikes: u32,
+    traffic: Traffic,
+    retransmit: Retransmit,
+    journal: Journal,
+    clock: Clock,
+    simulation: Option<Simulation>,
+    recorder: Option<Recorder>,
+    batch: RefCell<Vec<u8>>,
+    socket_options: SocketOptions,
+    otr: Option<RefCell<Otr>>,
+}
+
+/// The ServerState of a connection, whether it is a server and if so whether it has a client.
+///
+/// # Variants
+/// `Listening` - A server waiting for a client.
+/// `Occupied` - A server with a client as its peer, other clients wait for their turn.
+/// `NotApplicable` - Not a server, e.g. a client or a peer met through a rendezvous server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ServerState {
+    Listening,
+    Occupied,
+    NotApplicable,
 }
 
+/// How many malformed frames a client may send before it is dropped and banned.
+const MAX_STRIKES: u32 = 3;
+
+/// How long `await_client_timeout` sleeps between looking for a client.
+const ACCEPT_POLL: Duration = Duration::from_millis(10);
+
 /// Called by server to arg check for server port.
 ///
 /// # Returns
 /// `String` - the port.
 pub fn set_port() -> String {
-    let args: Vec<String> = env::args().collect();
+    let args = Args::from_env().positional;
 
-    if args.len() != 3 {
-        println!("Error: Usage ./r2wc-server [addr] [port]");
+    if args.len() != 2 {
+        println!("Error: Usage ./r2wc-server [addr] [port] [--queue-cap n] [--max-kbps n] [--ack-timeout secs] [--retries n] [--nodelay 0|1] [--keepalive secs] [--keepalive-interval secs] [--send-buffer bytes] [--recv-buffer bytes] [--simulate spec] [--record path] [--handshake-timeout secs] [--ban-secs secs] [--log path] [--journal path] [--headless] [--control path] [--metrics-addr addr] [--db path] [--history n] [--spellcheck] [--dict path] [--config path] [--allow cidrs] [--deny cidrs] [--invite-only] [--invite-secs secs] [--resume-secs secs] [--map-port] [--qr] [--otr] [--encrypt-history]");
         ::std::process::exit(0x0100);
     }
 
     // Can always call unwrap safely here because otherwise case is handled above.
-    return format!("{}:{}", args.get(1).unwrap(), args.get(2).unwrap());
-}
-
-/// Called by server to create a TcpListener and set nonblocking mode.
-///
-/// # Returns
-/// `TcpListener` - a server side conenction of a TcpListener.
-pub fn create_server() -> TcpListener {
-    let server = TcpListener::bind(&set_port()).expect("Listener failed to bind");
-    server
-        .set_nonblocking(true)
-        .expect("failed to initiate non-blocking");
-
-    return server;
+    return format!("{}:{}", args.get(0).unwrap(), args.get(1).unwrap());
 }
 
 /// Called by client to arg check for server hostname and port.
@@ -56,144 +154,658 @@ pub fn create_server() -> TcpListener {
 /// # Returns
 /// `String` - the hostname and port configured.
 pub fn set_server_port() -> String {
-    let args: Vec<String> = env::args().collect();
+    let args = Args::from_env().positional;
 
-    if args.len() != 3 {
-        println!("Error: Usage ./r2wc-client [host] [port]");
+    if args.len() != 2 {
+        println!("Error: Usage ./r2wc-client [host] [port] [--queue-cap n] [--max-kbps n] [--ack-timeout secs] [--retries n] [--nodelay 0|1] [--keepalive secs] [--keepalive-interval secs] [--send-buffer bytes] [--recv-buffer bytes] [--simulate spec] [--record path] [--replay path [--replay-speed n]] [--log path] [--journal path] [--spellcheck] [--dict path] [--config path] [--invite token] [--rendezvous host:port --room name] [--nick name] [--otr] [--encrypt-history]\nor ./r2wc-client r2wc://host:port[/token][?nick=name] [flags]\nor ./r2wc-client @contact [flags]\nor ./r2wc-client --last [flags]\nor ./r2wc-client --view path");
         ::std::process::exit(0x0100);
     }
 
     // Can always call unwrap safely here because otherwise case is handled above.
-    return format!("{}:{}", args.get(1).unwrap(), args.get(2).unwrap());
+    return format!("{}:{}", args.get(0).unwrap(), args.get(1).unwrap());
 }
 
 /// Called by client to create a TcpStream and set nonblocking mode.
 ///
+/// # Arguments
+/// * `addr` - A &str of the server's host:port.
+///
 /// # Returns
-/// `TcpStream` - a client side connection of a TcpListener.
-pub fn connect_server() -> TcpStream {
-    let stream = TcpStream::connect(&set_server_port()).expect("Stream failed to connect");
-    stream
-        .set_nonblocking(true)
-        .expect("failed to initiate non-blocking");
-
-    return stream;
+/// `io::Result<TcpStream>` - a client side connection of a TcpListener, or why it could not connect.
+pub fn connect_server(addr: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nonblocking(true)?;
+
+    return Ok(stream);
+}
+
+/// Writes a whole message to a nonblocking stream, waiting out WouldBlock instead of losing part of it.
+///
+/// # Arguments
+/// * `stream` - A &TcpStream to write to.
+/// * `buff` - A &[u8] of the prefixed message, or several one after another.
+///
+/// # Returns
+/// `io::Result<()>` - Ok once every byte was written, or the socket error.
+fn write_frame(mut stream: &TcpStream, buff: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+
+    while written < buff.len() {
+        match stream.write(&buff[written..]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::WriteZero,
+                    "peer closed the connection",
+                ))
+            }
+            Ok(n) => written += n,
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    return Ok(());
+}
+
+/// Writes the pieces of several messages to a nonblocking stream with vectored writes, one slice per piece, waiting
+/// out WouldBlock like `write_frame`.
+///
+/// # Arguments
+/// * `stream` - A &TcpStream to write to.
+/// * `parts` - A &[&[u8]] of the pieces in the order they go on the wire, e.g. each length followed by its frame.
+///
+/// # Returns
+/// `io::Result<()>` - Ok once every byte was written, or the socket error.
+fn write_frames(mut stream: &TcpStream, parts: &[&[u8]]) -> io::Result<()> {
+    // The piece written next and how much of it the last write already took.
+    let mut first = 0;
+    let mut offset = 0;
+
+    loop {
+        while first < parts.len() && offset == parts[first].len() {
+            first += 1;
+            offset = 0;
+        }
+        if first == parts.len() {
+            return Ok(());
+        }
+        let mut slices = Vec::with_capacity(parts.len() - first);
+        slices.push(IoSlice::new(&parts[first][offset..]));
+        slices.extend(parts[first + 1..].iter().map(|part| IoSlice::new(part)));
+        match stream.write_vectored(&slices) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::WriteZero,
+                    "peer closed the connection",
+                ))
+            }
+            Ok(mut n) => {
+                // A write may stop anywhere, even partway through a piece.
+                while n > 0 {
+                    let left = parts[first].len() - offset;
+                    if n < left {
+                        offset += n;
+                        break;
+                    }
+                    n -= left;
+                    first += 1;
+                    offset = 0;
+                }
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Sends a client we won't take one last frame, then closes its connection.
+///
+/// # Arguments
+/// * `client` - A Peer of the client, its connection is closed afterwards.
+/// * `frame` - A &Frame telling it why.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+fn turn_away(client: Peer, frame: &Frame, msg_size: usize) {
+    let mut stream = client.stream();
+    // Closing with what it sent still unread would reset the connection, which can lose the frame before the
+    // client reads it.
+    let mut unread = [0; 1024];
+    while let Ok(n) = stream.read(&mut unread) {
+        if n == 0 {
+            break;
+        }
+    }
+    let _ = write_frame(stream, &codec::encode(frame, msg_size));
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+/// Escapes backslashes and newlines so each message takes one line of a journal or recording.
+fn escape(msg: &str) -> String {
+    return msg.replace('\\', "\\\\").replace('\n', "\\n");
+}
+
+/// Undoes `escape`.
+fn unescape(line: &str) -> String {
+    let mut msg = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            msg.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => msg.push('\n'),
+            Some(c) => msg.push(c),
+            None => msg.push('\\'),
+        }
+    }
+    return msg;
 }
 
 impl Connection {
-    pub fn get_peer(&self) -> Option<Peer> {
-        return self.peer.clone();
+    /// Accessor method for the current peer, for its address, nick and key without cloning its socket.
+    ///
+    /// # Returns
+    /// `Option<&Peer>` - the peer, None if there is no current peer.
+    pub fn peer(&self) -> Option<&Peer> {
+        return self.peer.as_ref();
+    }
+
+    /// Records the nick and key the current peer announced, see `Peer::set_identity`.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the nick.
+    /// * `key` - A &str of the base64 public key.
+    pub fn set_peer_identity(&mut self, nick: &str, key: &str) {
+        if let Some(peer) = &mut self.peer {
+            peer.set_identity(nick, key);
+        }
+    }
+
+    /// Changes which IPs may connect while the server runs, see `ConnectionBuilder::accept_filter`.
+    ///
+    /// # Arguments
+    /// * `filter` - A AcceptFilter checked before a client becomes our peer.
+    pub fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.filter = filter;
+    }
+
+    /// Changes how fast messages are sent while the connection runs, see `ConnectionBuilder::max_kbps`.
+    ///
+    /// # Arguments
+    /// * `throttle` - A Option<Throttle> to send through, clones of one Throttle share its limit, None for no limit.
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
     }
 
-    /// Creates a new connection given arguments.
+    /// Creates a new connection given arguments, see `ConnectionBuilder` for making one with options and a peer.
     ///
     /// # Arguments
     /// * `msg_size` - A usize that represents how large the messages can be.
-    /// * `taken` - A option bool to represent a server connection being taken.
+    /// * `state` - A ServerState, Listening for a server or NotApplicable otherwise.
     ///
     /// # Returns
     ///  `Connection` - the newly created connection.
-    pub fn new_connection(msg_size: usize, taken: Option<bool>) -> Connection {
+    fn new(msg_size: usize, state: ServerState) -> Connection {
         return Connection {
             msg_size: msg_size,
-            taken: taken,
+            state: state,
+            waiting: WaitingRoom::new(0),
             peer: None,
+            queue: VecDeque::new(),
+            queue_cap: 0,
+            throttle: None,
+            bans: BanList::new(Duration::from_secs(300)),
+            filter: AcceptFilter::default(),
+            invites: None,
+            resume_tokens: None,
+            resume_token: None,
+            resumed: None,
+            reconnect: None,
+            handshake_timeout: Duration::from_secs(5),
+            handshake_deadline: None,
+            strikes: 0,
+            traffic: Traffic::default(),
+            retransmit: Retransmit::new(Duration::from_secs(0), 0),
+            journal: Journal::memory(),
+            clock: Clock::new(),
+            simulation: None,
+            recorder: None,
+            batch: RefCell::new(Vec::new()),
+            socket_options: SocketOptions::new(),
+            otr: None,
         };
     }
 
-    /// Creates a new pre-configured server connection given an argument.
+    /// Reports how much of the send limit is being used.
     ///
-    /// # Arguments
-    /// * `msg_size` - A usize that represents how large the messages can be.
+    /// # Returns
+    /// `Option<ThrottleStats>` - the throttle's stats, or None if sends are not limited.
+    pub fn throttle_stats(&self) -> Option<ThrottleStats> {
+        return self.throttle.as_ref().map(|t| t.stats());
+    }
+
+    /// Accessor method for the connection's byte counts.
     ///
     /// # Returns
-    ///  `Connection` - the newly created connection.
-    pub fn new_server_connection(msg_size: usize) -> (Connection, TcpListener) {
-        return (
-            Connection {
-                msg_size: msg_size,
-                taken: Some(false),
-                peer: None,
-            },
-            create_server(),
-        );
+    /// `&Traffic` - the bytes sent and received, shared with the connections detached from this one.
+    pub fn traffic(&self) -> &Traffic {
+        return &self.traffic;
     }
 
-    /// Creates a new pre-configured client connection given an argument.
+    /// Accessor method for the message size.
     ///
-    /// # Arguments
-    /// * `msg_size` - A usize that represents how large the messages can be.
+    /// # Returns
+    /// `usize` - the most bytes a frame may have on the wire, not counting its length.
+    pub fn msg_size(&self) -> usize {
+        return self.msg_size;
+    }
+
+    /// Accessor method for whether this is a server's connection.
     ///
     /// # Returns
-    ///  `Connection` - the newly created connection.
-    pub fn new_client_connection(msg_size: usize) -> Connection {
-        return Connection {
-            msg_size: msg_size,
-            taken: None,
-            peer: Some(Peer::new(connect_server(), String::from("Server"))),
-        };
+    /// `bool` - true for a server, false for a client or a peer met through a rendezvous server.
+    pub fn is_server(&self) -> bool {
+        return self.state != ServerState::NotApplicable;
+    }
+
+    /// Accessor method for whether a server has a client.
+    ///
+    /// # Returns
+    /// `bool` - true while a client is the server's peer, always false for a client.
+    pub fn is_occupied(&self) -> bool {
+        return self.state == ServerState::Occupied;
+    }
+
+    /// Accessor method for how many messages are waiting for a peer.
+    ///
+    /// # Returns
+    /// `usize` - the number of queued messages.
+    pub fn queued(&self) -> usize {
+        return self.queue.len();
+    }
+
+    /// Splits the connection into independent writing and reading halves.
+    ///
+    /// Called on a connection, each half holds its own clone of the peer's TcpStream so they can be moved to
+    /// separate threads, e.g. a dedicated reader thread. The halves do not see later peer changes, see `shared` for
+    /// a handle that does and shares the socket instead.
+    ///
+    /// # Returns
+    /// `Option<(ConnSender, ConnReceiver)>` - The two halves, or None if there is no current peer or the
+    /// connection is off the record, whose frames only the Connection can seal and open.
+    pub fn split(&self) -> Option<(ConnSender, ConnReceiver)> {
+        if self.otr.is_some() {
+            return None;
+        }
+        let peer = self.peer.as_ref()?;
+        let write_stream = peer.stream().try_clone().ok()?;
+        let read_stream = peer.stream().try_clone().ok()?;
+
+        return Some((
+            ConnSender::new(self.msg_size, write_stream, self.throttle.clone()),
+            ConnReceiver::new(self.msg_size, read_stream),
+        ));
+    }
+
+    /// Moves the connection behind a SharedConnection.
+    ///
+    /// Called on a connection, consumes it. Unlike `split` no TcpStream is cloned, every clone of the handle uses the
+    /// peer's own.
+    ///
+    /// # Returns
+    /// `SharedConnection` - the handle, clone it for each thread.
+    pub fn shared(self) -> SharedConnection {
+        return SharedConnection::new(self);
+    }
+
+    /// Accessor method for the server's invites.
+    ///
+    /// # Returns
+    /// `Option<&Invites>` - the invites, None unless the server is invite-only.
+    pub fn invites(&self) -> Option<&Invites> {
+        return self.invites.as_ref();
+    }
+
+    /// Accessor method for whether the peer has yet to complete the handshake.
+    ///
+    /// # Returns
+    /// `bool` - true while waiting on the peer's Hello frame, or its Otr frame off the record.
+    pub fn handshake_pending(&self) -> bool {
+        return self.handshake_deadline.is_some() || self.otr_pending();
+    }
+
+    /// Accessor method for whether the off-the-record session with the peer has yet to start.
+    ///
+    /// # Returns
+    /// `bool` - true while there is a peer whose key we don't have, always false without `--otr`.
+    fn otr_pending(&self) -> bool {
+        return self.peer.is_some()
+            && self
+                .otr
+                .as_ref()
+                .map_or(false, |otr| !otr.borrow().established());
     }
 
     /// Turns waiting for a client into a blocking call until a Client connects.
     ///
-    /// Called on a connection and mutates it to have the Client as it's peer.
+    /// Called on a connection and mutates it to have the Client as it's peer, queued messages are delivered once it completes the handshake.
+    /// The listener blocks in accept meanwhile instead of spinning, and is nonblocking again afterwards.
     ///
     /// # Arguments
     /// * `server` - A &TcpListener so we can wait on that server for a client.
+    #[deprecated(note = "use spawn_network, which accepts clients as part of its loop")]
     pub fn await_client(&mut self, server: &TcpListener) {
-        loop {
-            match Peer::get_client(&server) {
-                Some(c) => {
-                    self.peer = Some(c);
-                    self.taken = Some(true);
-                    return;
-                }
-                None => continue,
+        let blocking = server.set_nonblocking(false).is_ok();
+        while !self.accept_client(server) {
+            if !blocking {
+                thread::sleep(ACCEPT_POLL);
             }
         }
+        let _ = server.set_nonblocking(true);
     }
 
-    /// Turns waiting for a client call into a blocking call for 100ms.
+    /// Turns waiting for a client call into a blocking call for at most the given time.
     ///
-    /// Called on a connection and mutates it to have the Client as it's peer.
+    /// Called on a connection and mutates it to have the Client as it's peer, queued messages are delivered once it completes the handshake.
     ///
     /// # Arguments
     /// * `server` - A &TcpListener so we can wait on that server for a client.
-    pub fn await_client_timeout(&mut self, server: &TcpListener) {
-        let start = Stopwatch::start_new();
-
-        while start.elapsed_ms() < 100 {
-            match Peer::get_client(&server) {
-                Some(c) => {
-                    self.peer = Some(c);
-                    self.taken = Some(true);
-                    return;
+    /// * `timeout` - A Duration of how long to wait for a client, it is looked for every ACCEPT_POLL meanwhile.
+    #[deprecated(note = "use spawn_network, which accepts clients as part of its loop")]
+    pub fn await_client_timeout(&mut self, server: &TcpListener, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.accept_client(server) {
+                return;
+            }
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if left > Duration::from_secs(0) => thread::sleep(left.min(ACCEPT_POLL)),
+                _ => return,
+            }
+        }
+    }
+
+    /// Accepts a waiting client as our peer unless its IP is filtered out or banned.
+    ///
+    /// Called on a connection, starts the client's handshake deadline. The client whose turn it is in the waiting
+    /// room goes before any that just connected.
+    ///
+    /// # Arguments
+    /// * `server` - A &TcpListener to accept the client from.
+    ///
+    /// # Returns
+    /// `bool` - true if a client became our peer.
+    fn accept_client(&mut self, server: &TcpListener) -> bool {
+        let next = self.waiting.next(self.msg_size);
+        match next.or_else(|| Peer::get_client(&server, &self.filter)) {
+            Some(c) => {
+                if let Some(ip) = c.ip() {
+                    if self.bans.is_banned(&ip) {
+                        return false;
+                    }
                 }
-                None => continue,
+                // Like a socket that can't be made nonblocking, one without our options isn't accepted.
+                if self.socket_options.apply(c.stream()).is_err() {
+                    return false;
+                }
+
+                self.set_peer(Some(c));
+                self.strikes = 0;
+                self.resume_token = None;
+                self.resumed = None;
+                self.handshake_deadline = Some(Instant::now() + self.handshake_timeout);
+                return true;
             }
+            None => return false,
+        }
+    }
+
+    /// Accepts a waiting client into a new Connection, leaving this one without a peer.
+    ///
+    /// Called on a connection used as a template by the headless relay. The new Connection shares this one's
+    /// settings, throttle and bans, and has its own handshake deadline.
+    ///
+    /// # Arguments
+    /// * `server` - A &TcpListener to accept the client from.
+    ///
+    /// # Returns
+    /// `Option<Connection>` - the client's connection, or None if no client was waiting.
+    pub fn accept_new_client(&mut self, server: &TcpListener) -> Option<Connection> {
+        if !self.accept_client(server) {
+            return None;
+        }
+
+        let peer = self.peer.take();
+        let mut client = self.detached();
+        client.set_peer(peer);
+        self.drop_peer();
+        return Some(client);
+    }
+
+    /// Makes a Connection with this one's settings, throttle and bans but without its peer.
+    ///
+    /// Called on a connection, see `accept_new_client`. The peer's socket isn't duplicated and neither are the
+    /// off-the-record session's keys, see `shared` for several threads using one peer. It starts with an empty queue,
+    /// otherwise every client accepted would be sent what was queued.
+    ///
+    /// # Returns
+    /// `Connection` - the new connection, without a peer.
+    fn detached(&self) -> Connection {
+        return Connection {
+            msg_size: self.msg_size.clone(),
+            state: self.state,
+            // The waiting clients stay in line for this Connection's peer.
+            waiting: WaitingRoom::new(self.waiting.cap()),
+            peer: None,
+            queue: VecDeque::new(),
+            queue_cap: self.queue_cap.clone(),
+            throttle: self.throttle.clone(),
+            bans: self.bans.clone(),
+            filter: self.filter.clone(),
+            invites: self.invites.clone(),
+            resume_tokens: self.resume_tokens.clone(),
+            resume_token: self.resume_token.clone(),
+            resumed: self.resumed.clone(),
+            reconnect: self.reconnect.clone(),
+            handshake_timeout: self.handshake_timeout.clone(),
+            handshake_deadline: self.handshake_deadline.clone(),
+            strikes: self.strikes.clone(),
+            traffic: self.traffic.clone(),
+            retransmit: self.retransmit.clone(),
+            journal: self.journal.clone(),
+            clock: self.clock.clone(),
+            simulation: self.simulation.clone(),
+            recorder: self.recorder.clone(),
+            batch: RefCell::new(Vec::new()),
+            socket_options: self.socket_options.clone(),
+            // A session's keys belong to the one connection, a detached one relays in the clear.
+            otr: None,
+        };
+    }
+
+    /// Changes the peer, the one place `peer` and `state` change so a server is occupied exactly while it has a
+    /// peer and a client never looks like a server.
+    ///
+    /// # Arguments
+    /// * `peer` - A Option<Peer> of the new peer, None to forget the current one and its handshake.
+    fn set_peer(&mut self, peer: Option<Peer>) {
+        if self.is_server() {
+            self.state = if peer.is_some() {
+                ServerState::Occupied
+            } else {
+                ServerState::Listening
+            };
+        }
+        if peer.is_none() {
+            self.handshake_deadline = None;
+        }
+        // Every peer gets a session of its own, and the last one's keys are gone with it.
+        if let Some(otr) = &self.otr {
+            otr.borrow_mut().restart();
         }
+        self.peer = peer;
     }
 
-    /// Rejects other clients from connecting our server.
+    /// Forgets the peer, e.g. after it said it was leaving.
     ///
-    /// Called on a connection, for convience also returns the server taken status and the rejected client if one exists.
+    /// Called on a connection, server side the connection goes back to waiting for a client.
+    fn drop_peer(&mut self) {
+        self.end_session(false);
+        self.set_peer(None);
+    }
+
+    /// Drops the peer and bans its IP.
+    ///
+    /// Called on a connection when the peer breaks the protocol.
+    fn ban_peer(&mut self) {
+        if let Some(ip) = self.peer.as_ref().and_then(|p| p.ip()) {
+            self.bans.ban(ip);
+        }
+        self.end_session(false);
+        self.set_peer(None);
+    }
+
+    /// Checks a message from a peer that has not completed the handshake.
+    ///
+    /// Called on a connection, anything but a Hello frame, or a Join frame with a valid token on an invite-only
+    /// server, gets the peer dropped and banned. A Resume frame with a valid token resumes the client's session
+    /// even on an invite-only server, one with a token that expired counts as Hello.
+    ///
+    /// # Arguments
+    /// * `frame` - A Result<Frame, FrameError> of the parsed message.
+    ///
+    /// # Returns
+    /// `String` - Empty if the handshake completed, otherwise Banned.
+    fn complete_handshake(&mut self, frame: Result<Frame, FrameError>) -> String {
+        let resumed = match &frame {
+            Ok(Frame::Resume { token, .. }) => self.redeem_resume_token(token),
+            _ => false,
+        };
+        let refused = match (&frame, &self.invites) {
+            _ if resumed => None,
+            (Ok(Frame::Hello), None) | (Ok(Frame::Join { .. }), None) => None,
+            (Ok(Frame::Resume { .. }), None) => None,
+            (Ok(Frame::Hello), Some(_)) => Some("This server is invite-only"),
+            (Ok(Frame::Resume { .. }), Some(_)) => Some("Invalid or expired resume token"),
+            (Ok(Frame::Join { token }), Some(invites)) if !invites.redeem(token) => {
+                Some("Invalid or expired invite")
+            }
+            (Ok(Frame::Join { .. }), Some(_)) => None,
+            _ => Some(""),
+        };
+        if let Some(reason) = refused {
+            // Tell a real client why before it is dropped, it can't do anything about it until the ban ends.
+            if !reason.is_empty() {
+                self.send_frame(&Frame::Reject {
+                    reason: String::from(reason),
+                });
+            }
+            self.ban_peer();
+            return String::from("Banned");
+        }
+
+        self.send_frame(&Frame::Hello);
+        if let Some(otr) = &self.otr {
+            let key = otr.borrow_mut().offer();
+            self.send_frame(&Frame::Otr { key: key });
+            // The handshake goes on until the client's key arrives, see `complete_otr`.
+            return String::from("Empty");
+        }
+        self.handshake_deadline = None;
+        self.issue_resume_token();
+        self.flush_queue();
+        return String::from("Empty");
+    }
+
+    /// Checks a message from a peer whose off-the-record key we are waiting for.
+    ///
+    /// Called on a connection once our key was sent, the peer's Otr frame starts the session and completes the
+    /// handshake. A client is also told the server's Hello and whether it has to wait or was refused. Anything else
+    /// means the peer isn't off the record, and it is dropped and banned.
+    ///
+    /// # Arguments
+    /// * `frame` - A Result<Frame, FrameError> of the parsed message.
+    ///
+    /// # Returns
+    /// `Result<Frame, String>` - a Busy or Reject frame for the ui, otherwise Empty if the handshake went on or
+    /// completed, or Banned.
+    fn complete_otr(&mut self, frame: Result<Frame, FrameError>) -> Result<Frame, String> {
+        let accepted = match frame {
+            Ok(Frame::Otr { key }) => match &self.otr {
+                Some(otr) => otr.borrow_mut().accept(&key),
+                None => Err(String::new()),
+            },
+            Ok(Frame::Hello) if !self.is_server() => return Err(String::from("Empty")),
+            Ok(frame @ Frame::Busy { .. }) | Ok(frame @ Frame::Reject { .. })
+                if !self.is_server() =>
+            {
+                return Ok(frame)
+            }
+            _ => Err(String::from(
+                "This peer only talks off the record, start with --otr",
+            )),
+        };
+        if let Err(reason) = accepted {
+            self.send_frame(&Frame::Reject { reason: reason });
+            self.ban_peer();
+            return Err(String::from("Banned"));
+        }
+
+        self.handshake_deadline = None;
+        if self.is_server() {
+            self.issue_resume_token();
+        }
+        self.flush_queue();
+        return Err(String::from("Empty"));
+    }
+
+    /// Turns the peer away, telling it why.
+    ///
+    /// Called on a connection, consumes it. The peer is sent a Reject frame with the reason before its connection is
+    /// closed, e.g. by a headless server that is full.
+    ///
+    /// # Arguments
+    /// * `reason` - A &str of why the peer can't stay, shown by its ui.
+    pub fn refuse(mut self, reason: &str) {
+        if let Some(peer) = self.peer.take() {
+            let frame = Frame::Reject {
+                reason: String::from(reason),
+            };
+            turn_away(peer, &frame, self.msg_size);
+        }
+    }
+
+    /// Puts other clients connecting to our server in the waiting room.
+    ///
+    /// Called on a connection, every client connecting while the server is occupied is sent a Busy frame with its
+    /// place in line, or without one if the waiting room is full and its connection is closed. Clients that left
+    /// while waiting are forgotten.
     ///
     /// # Arguments
     /// * `server` - A &TcpListener so we can wait on that server for a client.
     ///
     /// # Returns
-    /// `(bool, Option<Peer>)` - The server's status of taken by a client, and the possible rejected client.
-    pub fn reject_other_clients(&self, server: &TcpListener) -> (bool, Option<Peer>) {
-        match self.taken {
-            Some(t) => {
-                if t {
-                    return (true, Peer::get_client(server));
-                } else {
-                    return (false, None);
-                }
+    /// `(bool, usize)` - Whether the server is occupied by a client, and how many clients are waiting.
+    pub fn queue_other_clients(&mut self, server: &TcpListener) -> (bool, usize) {
+        if self.state != ServerState::Occupied {
+            return (false, self.waiting.len());
+        }
+
+        self.waiting.prune(self.msg_size);
+        while let Some(client) = Peer::get_client(server, &self.filter) {
+            if client.ip().map_or(false, |ip| self.bans.is_banned(&ip)) {
+                continue;
             }
-            None => return (false, None),
+            self.waiting.join(client, self.msg_size);
         }
+        return (true, self.waiting.len());
     }
 
     /// Sends a message to the peer.
@@ -204,73 +816,408 @@ impl Connection {
     /// * `msg` - A String of the message to send to the peer.
     ///
     /// # Returns
-    /// `(String, Stopwatch)` - Message Sent along with a format or Empty if there was no current peer.
+    /// `(String, Stopwatch)` - Message sent, Empty if there was no current peer, or Failed with the socket error
+    /// or why the frame could not be sealed.
     pub fn send_message(&self, msg: String) -> (String, Stopwatch) {
-        match self.peer.clone() {
-            Some(peer) => {
-                let mut writer = BufWriter::new(peer.stream());
+        return self.send_payload(msg.as_bytes());
+    }
 
-                let mut buff = msg.clone().into_bytes();
-                buff.resize(self.msg_size, 0);
+    /// Sends the bytes of an encoded frame to the peer.
+    ///
+    /// Called on a connection, see `send_message`.
+    ///
+    /// # Arguments
+    /// * `payload` - A &[u8] of the encoded frame, text or not.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Empty if there was no current peer, or Failed with the socket error
+    /// or why the frame could not be sealed.
+    fn send_payload(&self, payload: &[u8]) -> (String, Stopwatch) {
+        match &self.peer {
+            Some(peer) => {
+                let mut buff = self.batch.borrow_mut();
+                buff.clear();
+                match &self.otr {
+                    Some(otr) => match otr.borrow_mut().seal(payload, self.msg_size) {
+                        Ok(sealed) => codec::prefix_into(&sealed, self.msg_size, &mut buff),
+                        Err(err) => return (format!("Failed: {}", err), Stopwatch::start_new()),
+                    },
+                    None => codec::prefix_into(payload, self.msg_size, &mut buff),
+                }
+                if let Some(throttle) = &self.throttle {
+                    throttle.take(buff.len());
+                }
                 let sent_time = Stopwatch::start_new();
-                writer.write_all(&buff).expect("Writing to socket failed.");
-                return (format!("Message sent {:?}", buff), sent_time);
+                if let Err(err) = write_frame(peer.stream(), &buff) {
+                    return (format!("Failed: {}", err), sent_time);
+                }
+                self.traffic.add_sent(buff.len());
+                if let Some(recorder) = &self.recorder {
+                    recorder.sent(&buff[codec::HEADER..]);
+                }
+                return (String::from("Message sent"), sent_time);
             }
             None => return (String::from("Empty"), Stopwatch::start_new()),
         }
     }
 
+    /// Sends several messages to the peer at once.
+    ///
+    /// Called on a connection, the messages go out in as few vectored writes as the socket allows instead of a write
+    /// per message, straight from `msgs` with only their lengths in a buffer reused between batches. Use for bursts
+    /// like history for a reconnecting client or a flushed queue.
+    ///
+    /// # Arguments
+    /// * `msgs` - A &[String] of the messages to send to the peer, in order.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Messages sent with how many, Empty if there was no current peer, or Failed with the
+    /// socket error or why a frame could not be sealed.
+    pub fn send_messages(&self, msgs: &[String]) -> (String, Stopwatch) {
+        return self.send_payloads(msgs);
+    }
+
+    /// Sends the bytes of several encoded frames to the peer at once.
+    ///
+    /// Called on a connection, see `send_messages`.
+    ///
+    /// # Arguments
+    /// * `payloads` - A &[P] of the encoded frames, text or not, in order.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Messages sent with how many, Empty if there was no current peer, or Failed with the
+    /// socket error or why a frame could not be sealed.
+    fn send_payloads<P: AsRef<[u8]>>(&self, payloads: &[P]) -> (String, Stopwatch) {
+        let peer = match &self.peer {
+            Some(peer) => peer,
+            None => return (String::from("Empty"), Stopwatch::start_new()),
+        };
+
+        // Each frame's length, or its length and the frame once sealed, is in `batch`, then how much of the payload
+        // follows it in the clear, None if it was sealed.
+        let mut batch = self.batch.borrow_mut();
+        batch.clear();
+        let mut layout = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let start = batch.len();
+            let clear = match &self.otr {
+                // Nothing is written if one can't be sealed, the others are sent again like lost ones.
+                Some(otr) => match otr.borrow_mut().seal(payload.as_ref(), self.msg_size) {
+                    Ok(sealed) => {
+                        codec::prefix_into(&sealed, self.msg_size, &mut batch);
+                        None
+                    }
+                    Err(err) => return (format!("Failed: {}", err), Stopwatch::start_new()),
+                },
+                None => {
+                    let len = codec::cut(payload.as_ref(), self.msg_size);
+                    batch.extend_from_slice(&(len as u32).to_be_bytes());
+                    Some(len)
+                }
+            };
+            layout.push((start..batch.len(), clear));
+        }
+        let mut parts: Vec<&[u8]> = Vec::with_capacity(payloads.len() * 2);
+        for (payload, (range, clear)) in payloads.iter().zip(&layout) {
+            parts.push(&batch[range.clone()]);
+            if let Some(clear) = clear {
+                parts.push(&payload.as_ref()[..*clear]);
+            }
+        }
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+
+        if let Some(throttle) = &self.throttle {
+            throttle.take(len);
+        }
+        let sent_time = Stopwatch::start_new();
+        if let Err(err) = write_frames(peer.stream(), &parts) {
+            return (format!("Failed: {}", err), sent_time);
+        }
+        self.traffic.add_sent(len);
+        if let Some(recorder) = &self.recorder {
+            for (payload, (range, clear)) in payloads.iter().zip(&layout) {
+                match clear {
+                    Some(clear) => recorder.sent(&payload.as_ref()[..*clear]),
+                    None => recorder.sent(&batch[range.start + codec::HEADER..range.end]),
+                }
+            }
+        }
+        return (format!("Messages sent {}", payloads.len()), sent_time);
+    }
+
+    /// Sends several protocol frames to the peer at once.
+    ///
+    /// Called on a connection, see `send_messages`.
+    ///
+    /// # Arguments
+    /// * `frames` - A &[Frame] to encode and send to the peer, in order.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Messages sent with how many, Empty if there was no current peer, or Failed with the
+    /// socket error.
+    pub fn send_frames(&self, frames: &[Frame]) -> (String, Stopwatch) {
+        let payloads: Vec<Vec<u8>> = frames.iter().map(|f| f.to_bytes()).collect();
+        return self.send_payloads(&payloads);
+    }
+
+    /// Sends a protocol frame to the peer.
+    ///
+    /// Called on a connection, see `send_message`.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame to encode and send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent or Empty if there was no current peer.
+    pub fn send_frame(&self, frame: &Frame) -> (String, Stopwatch) {
+        return self.send_payload(&frame.to_bytes());
+    }
+
+    /// Sends a message to one peer, so a server can answer a single client instead of everyone.
+    ///
+    /// Called on a connection, nothing is sent if the PeerId names a peer that has since left, even if another
+    /// client took its place.
+    ///
+    /// # Arguments
+    /// * `peer` - A PeerId of the peer to send to, from `Event::PeerConnected` or `Peer::id`.
+    /// * `msg` - A String of the message to send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Empty if that peer is not connected, or Failed with
+    /// the socket error.
+    pub fn send_to(&self, peer: PeerId, msg: String) -> (String, Stopwatch) {
+        match &self.peer {
+            Some(current) if current.id() == peer => return self.send_message(msg),
+            _ => return (String::from("Empty"), Stopwatch::start_new()),
+        }
+    }
+
+    /// Sends a message to every peer that completed the handshake.
+    ///
+    /// Called on a connection, a client still handshaking is not sent anything.
+    ///
+    /// # Arguments
+    /// * `msg` - A String of the message to send.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Empty if no peer completed the handshake, or Failed
+    /// with the socket error.
+    pub fn broadcast(&self, msg: String) -> (String, Stopwatch) {
+        if self.handshake_pending() {
+            return (String::from("Empty"), Stopwatch::start_new());
+        }
+        return self.send_message(msg);
+    }
+
+    /// Sends a message to the peer, or queues it if there is no peer.
+    ///
+    /// Called on a connection, the queue is delivered in order when the next peer completes the handshake, so
+    /// messages sent while a client is still handshaking are queued too and can't overtake the queue. A full queue
+    /// drops its oldest message to make room, see `make_room`.
+    ///
+    /// # Arguments
+    /// * `msg` - A String of the message to send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Queued, or Queue full if queueing is disabled.
+    pub fn send_or_queue(&mut self, msg: String) -> (String, Stopwatch) {
+        return self.send_or_queue_payload(msg.into_bytes());
+    }
+
+    /// Sends the bytes of an encoded frame to the peer, or queues them if there is no peer.
+    ///
+    /// Called on a connection, see `send_or_queue`.
+    ///
+    /// # Arguments
+    /// * `payload` - A Vec<u8> of the encoded frame, text or not.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Queued, or Queue full if queueing is disabled.
+    fn send_or_queue_payload(&mut self, payload: Vec<u8>) -> (String, Stopwatch) {
+        if self.peer.is_some() && !self.handshake_pending() {
+            return self.send_payload(&payload);
+        }
+
+        self.make_room();
+        // A handshaking client is about to take the queue, so its cap doesn't apply.
+        if self.queue.len() >= self.queue_cap && self.peer.is_none() {
+            return (String::from("Queue full"), Stopwatch::start_new());
+        }
+        self.queue.push_back(payload);
+        return (String::from("Queued"), Stopwatch::start_new());
+    }
+
+    /// Sends a protocol frame to the peer, or queues it if there is no peer.
+    ///
+    /// Called on a connection, see `send_or_queue`.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame to encode and send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Queued, or Queue full if queueing is disabled.
+    pub fn send_or_queue_frame(&mut self, frame: &Frame) -> (String, Stopwatch) {
+        return self.send_or_queue_payload(frame.to_bytes());
+    }
+
+    /// Drops the oldest queued message if the queue is full, so the next one fits.
+    ///
+    /// Called on a connection, does nothing while there is a peer or if queueing is disabled.
+    ///
+    /// # Returns
+    /// `Option<Frame>` - The dropped message, None if nothing was dropped or it wasn't a frame.
+    pub fn make_room(&mut self) -> Option<Frame> {
+        if self.peer.is_some() || self.queue_cap == 0 || self.queue.len() < self.queue_cap {
+            return None;
+        }
+        return self
+            .queue
+            .pop_front()
+            .and_then(|payload| Frame::parse(&payload).ok());
+    }
+
+    /// Sends every queued message to the peer in the order they were queued.
+    ///
+    /// Called on a connection, does nothing if there is no peer. Messages the last peer never acknowledged were
+    /// sent before anything in the queue, so they go first and message ids stay in order across the reconnect.
+    fn flush_queue(&mut self) {
+        if self.peer.is_none() {
+            return;
+        }
+
+        let mut payloads: Vec<Vec<u8>> = self
+            .retransmit
+            .unacked()
+            .iter()
+            .map(|f| f.to_bytes())
+            .collect();
+        while let Some(payload) = self.queue.pop_front() {
+            if let Ok(frame) = Frame::parse(&payload) {
+                self.retransmit.sent(&frame);
+            }
+            payloads.push(payload);
+        }
+        self.send_payloads(&payloads);
+    }
+
     /// Receives a peer's message.
     ///
-    /// Called on a connection, returns a string message, mutates conenction on client disconnect.
+    /// Called on a connection, see `receive_frame`. A Binary frame's data that isn't UTF-8 is replaced, use
+    /// `receive_frame` for those.
     ///
     /// # Returns
-    /// `String` - The received messaged, blocked, disconencted, or empty depending on the situation.
+    /// `String` - The received messaged, blocked, disconencted, timed out, banned, or empty depending on the situation.
     pub fn receive_message(&mut self) -> String {
-        let mut buff = vec![0; self.msg_size];
-        let pos_peer = &self.peer.clone();
+        match self.receive_frame() {
+            Ok(frame) => return frame.encode(),
+            Err(status) => return status,
+        }
+    }
 
-        match pos_peer {
-            Some(peer) => {
-                let mut reader = BufReader::new(peer.stream());
+    /// Receives a peer's frame.
+    ///
+    /// Called on a connection, mutates conenction on client disconnect.
+    /// Frames are only returned once they parse, malformed ones and ones over `msg_size` bytes are answered with a
+    /// Reject frame.
+    /// Server side, a client that misses the handshake deadline, sends something other than Hello first, or
+    /// keeps sending malformed frames is dropped and banned.
+    ///
+    /// # Returns
+    /// `Result<Frame, String>` - The received frame, or blocked, disconencted, timed out, banned, or empty depending
+    /// on the situation.
+    pub fn receive_frame(&mut self) -> Result<Frame, String> {
+        let read = match &mut self.peer {
+            Some(peer) => match &mut self.simulation {
+                Some(simulation) => simulation.pass(peer.id(), peer.read_frame(self.msg_size)),
+                None => peer.read_frame(self.msg_size),
+            },
+            None => return Err(String::from("Empty")),
+        };
+
+        let frame = match read {
+            Ok(Some(buff)) => {
+                self.traffic.add_received(codec::HEADER + buff.len());
+                if let Some(recorder) = &self.recorder {
+                    recorder.received(&buff);
+                }
+                let frame = Frame::parse(&buff);
+                if let Some(peer) = &mut self.peer {
+                    peer.recycle(buff);
+                }
+                frame
+            }
 
-                match reader.read_exact(&mut buff) {
-                    Ok(_) => {
-                        let msg = buff.into_iter().take_while(|&x| x != 0).collect::<Vec<_>>();
-                        let msg = String::from_utf8(msg).expect("Invalid utf8 message");
+            // The reader skipped the whole frame, so the next one is read from its start.
+            Err(ref err) if err.kind() == ErrorKind::InvalidData => {
+                Err(FrameError::TooLong(self.msg_size))
+            }
 
-                        return msg;
-                    }
+            Ok(None) => match self.handshake_deadline {
+                Some(deadline) if Instant::now() > deadline => {
+                    self.ban_peer();
+                    return Err(String::from("Timed out"));
+                }
+                _ => return Err(String::from("Blocked")),
+            },
 
-                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
-                        return String::from("Blocked")
-                    }
+            Err(_) => {
+                self.end_session(true);
+                self.set_peer(None);
+                return Err(String::from("Disconnected"));
+            }
+        };
+
+        let frame = match (frame, &self.otr) {
+            (Ok(Frame::Binary { data }), Some(otr)) if otr.borrow().established() => {
+                let opened = otr.borrow_mut().open(&data);
+                opened.map_or(Err(FrameError::BadSeal), |buff| Frame::parse(&buff))
+            }
+            // A peer that leaves before the session starts says so in the clear, after that anyone on the path
+            // could have sent it.
+            (Ok(_), Some(otr)) if otr.borrow().established() => Err(FrameError::Unsealed),
+            (frame, _) => frame,
+        };
 
-                    Err(_) => {
-                        self.taken = Some(false);
-                        self.peer = None;
-                        return String::from("Disconnected");
+        if self.otr_pending()
+            && self
+                .otr
+                .as_ref()
+                .map_or(false, |otr| otr.borrow().offered())
+        {
+            return self.complete_otr(frame);
+        }
+        if self.handshake_pending() {
+            return Err(self.complete_handshake(frame));
+        }
+        match frame {
+            Ok(Frame::Disconnect) => {
+                self.end_session(false);
+                return Ok(Frame::Disconnect);
+            }
+            Ok(frame) => return Ok(frame),
+            Err(err) => {
+                self.send_frame(&Frame::Reject {
+                    reason: err.to_string(),
+                });
+                if self.is_server() {
+                    self.strikes += 1;
+                    if self.strikes >= MAX_STRIKES {
+                        self.ban_peer();
+                        return Err(String::from("Banned"));
                     }
                 }
+                return Err(String::from("Empty"));
             }
-            None => return String::from("Empty"),
         }
     }
 
     /// Sends a message to the peer that the peer's message has been received.
     ///
     /// Called on a connection.
-    pub fn notify_message_received(&self) {
-        self.send_message(String::from("Message Received."));
-    }
-}
-
-impl Clone for Connection {
-    fn clone(&self) -> Connection {
-        Connection {
-            msg_size: self.msg_size.clone(),
-            taken: self.taken.clone(),
-            peer: self.peer.clone(),
-        }
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the received message's id.
+    pub fn notify_message_received(&self, id: u64) {
+        self.send_frame(&Frame::Received { id: Some(id) });
     }
 }
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A BanList which temporarily refuses connections from misbehaving IPs.
+///
+/// Clones share the same bans, so a client banned by one relay Connection is refused by all of them.
+#[derive(Clone)]
+pub struct BanList {
+    inner: Arc<Mutex<Bans>>,
+}
+
+/// The shared state of a BanList.
+///
+/// # Fields
+/// `bans` - Banned IPs mapped to when their ban ends.
+/// `duration` - How long a new ban lasts.
+struct Bans {
+    bans: HashMap<IpAddr, Instant>,
+    duration: Duration,
+}
+
+impl BanList {
+    /// Creates a new empty BanList given a ban duration.
+    ///
+    /// # Arguments
+    /// * `duration` - A Duration of how long bans last.
+    ///
+    /// # Returns
+    ///  `BanList` - the newly created ban list.
+    pub fn new(duration: Duration) -> BanList {
+        return BanList {
+            inner: Arc::new(Mutex::new(Bans {
+                bans: HashMap::new(),
+                duration: duration,
+            })),
+        };
+    }
+
+    /// Sets how long future bans last.
+    ///
+    /// # Arguments
+    /// * `duration` - A Duration of how long bans last, zero disables banning.
+    pub fn set_duration(&self, duration: Duration) {
+        self.inner.lock().unwrap().duration = duration;
+    }
+
+    /// Accessor method for how long bans last.
+    ///
+    /// # Returns
+    /// `Duration` - the ban duration.
+    pub fn duration(&self) -> Duration {
+        return self.inner.lock().unwrap().duration;
+    }
+
+    /// Bans an IP for the configured duration.
+    ///
+    /// # Arguments
+    /// * `ip` - A IpAddr to ban.
+    pub fn ban(&self, ip: IpAddr) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.duration > Duration::from_secs(0) {
+            let until = Instant::now() + inner.duration;
+            inner.bans.insert(ip, until);
+        }
+    }
+
+    /// Checks whether an IP is currently banned, forgetting bans that have ended.
+    ///
+    /// # Arguments
+    /// * `ip` - A &IpAddr to check.
+    ///
+    /// # Returns
+    /// `bool` - true if the IP is banned.
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        inner.bans.retain(|_, until| *until > now);
+
+        return inner.bans.contains_key(ip);
+    }
+}
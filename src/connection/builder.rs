@@ -0,0 +1,408 @@
+use std::cell::RefCell;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use super::otr::Otr;
+use super::record::replay;
+use super::resume::Reconnect;
+use super::retransmit::Retransmit;
+use super::waiting::WaitingRoom;
+use super::{
+    connect_server, punch, AcceptFilter, Connection, Invites, Journal, Peer, Recorder,
+    ResumeTokens, ServerState, Simulation, SocketOptions, Throttle,
+};
+use crate::config::Args;
+use crate::protocol::Frame;
+
+/// A ConnectionBuilder which collects a Connection's options and then makes a server, client or rendezvous
+/// connection with them.
+///
+/// # Fields
+/// `msg_size` - The most bytes a message may have, not counting its length.
+/// `queue_cap` - The most messages queued while there is no peer, 0 disables queueing.
+/// `max_kbps` - The most kilobits per second to send, 0 for no limit.
+/// `ack_timeout` - How long to wait for an ack before sending a message again, zero never sends one again.
+/// `retries` - How many times a message is sent again before giving up on it.
+/// `journal` - Where messages not acknowledged yet are kept.
+/// `socket_options` - The TCP options set on every peer's socket.
+/// `simulation` - The delay and loss received frames go through, None for a real network.
+/// `recorder` - Where every frame sent and received is recorded, None to not record.
+/// `handshake_timeout` - How long a new client has to send its Hello frame, server side.
+/// `ban_duration` - How long misbehaving clients are banned for, zero disables banning, server side.
+/// `waiting_room` - The most clients that wait for their turn while the server has one, server side.
+/// `filter` - Which IPs may connect, server side.
+/// `invites` - The tokens clients must join with, None unless the server is invite-only.
+/// `resume_tokens` - The tokens clients resume their session with, None unless the server gives them out.
+/// `invite` - The token a client joins an invite-only server with.
+/// `nick` - The nick and public key a client announces once connected, for private messages.
+/// `otr` - Whether to talk off the record, every frame sealed with keys only this session has.
+pub struct ConnectionBuilder {
+    msg_size: usize,
+    queue_cap: usize,
+    max_kbps: u32,
+    ack_timeout: Duration,
+    retries: u32,
+    journal: Journal,
+    socket_options: SocketOptions,
+    simulation: Option<Simulation>,
+    recorder: Option<Recorder>,
+    handshake_timeout: Duration,
+    ban_duration: Duration,
+    waiting_room: usize,
+    filter: AcceptFilter,
+    invites: Option<Invites>,
+    resume_tokens: Option<ResumeTokens>,
+    invite: Option<String>,
+    nick: Option<(String, String)>,
+    otr: bool,
+}
+
+impl ConnectionBuilder {
+    /// Creates a new ConnectionBuilder with the default options.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize that represents how large the messages can be.
+    ///
+    /// # Returns
+    /// `ConnectionBuilder` - the builder.
+    pub fn new(msg_size: usize) -> ConnectionBuilder {
+        return ConnectionBuilder {
+            msg_size: msg_size,
+            queue_cap: 0,
+            max_kbps: 0,
+            ack_timeout: Duration::from_secs(0),
+            retries: 0,
+            journal: Journal::memory(),
+            socket_options: SocketOptions::new(),
+            simulation: None,
+            recorder: None,
+            handshake_timeout: Duration::from_secs(5),
+            ban_duration: Duration::from_secs(300),
+            waiting_room: 0,
+            filter: AcceptFilter::default(),
+            invites: None,
+            resume_tokens: None,
+            invite: None,
+            nick: None,
+            otr: false,
+        };
+    }
+
+    /// Creates a new ConnectionBuilder with the options both binaries take on the command line, `--max-kbps`,
+    /// `--ack-timeout`, `--retries`, `--simulate`, `--record`, `--otr` and the socket options, exiting with an error if one is not valid.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize that represents how large the messages can be.
+    /// * `args` - A &Args of the command line.
+    ///
+    /// # Returns
+    /// `ConnectionBuilder` - the builder, defaults for any flag not given.
+    pub fn from_args(msg_size: usize, args: &Args) -> ConnectionBuilder {
+        let secs = |name, default| Duration::from_secs(args.usize_flag(name, default) as u64);
+        let mut builder = ConnectionBuilder::new(msg_size)
+            .max_kbps(args.usize_flag("max-kbps", 0) as u32)
+            .retransmit(secs("ack-timeout", 5), args.usize_flag("retries", 3) as u32)
+            .socket_options(SocketOptions::from_args(args))
+            .otr(args.has_flag("otr"));
+        if let Some(spec) = args.flag("simulate") {
+            match Simulation::parse(spec) {
+                Ok(simulation) => builder = builder.simulate(simulation),
+                Err(err) => {
+                    println!("Error: Bad --simulate, {}", err);
+                    ::std::process::exit(0x0100);
+                }
+            }
+        }
+        if let Some(path) = args.flag("record") {
+            match Recorder::create(path) {
+                Ok(recorder) => builder = builder.record(recorder),
+                Err(err) => {
+                    println!("Error: Could not record to {}, {}", path, err);
+                    ::std::process::exit(0x0100);
+                }
+            }
+        }
+        return builder;
+    }
+
+    /// Sets how many messages are queued while there is no peer, once full the oldest is dropped for each new one.
+    ///
+    /// # Arguments
+    /// * `cap` - A usize of the most messages to queue, 0 disables queueing.
+    pub fn queue_cap(mut self, cap: usize) -> ConnectionBuilder {
+        self.queue_cap = cap;
+        return self;
+    }
+
+    /// Limits how fast messages are sent, sends block in `send_message` until the limit allows them.
+    ///
+    /// # Arguments
+    /// * `max_kbps` - A u32 of the most kilobits per second to send, 0 for no limit.
+    pub fn max_kbps(mut self, max_kbps: u32) -> ConnectionBuilder {
+        self.max_kbps = max_kbps;
+        return self;
+    }
+
+    /// Sends messages again when the peer doesn't acknowledge them in time, only the network thread started by
+    /// `spawn_network` does.
+    ///
+    /// # Arguments
+    /// * `timeout` - A Duration to wait for an ack, zero never sends a message again.
+    /// * `retries` - A u32 of how many times to send a message again before giving up on it.
+    pub fn retransmit(mut self, timeout: Duration, retries: u32) -> ConnectionBuilder {
+        self.ack_timeout = timeout;
+        self.retries = retries;
+        return self;
+    }
+
+    /// Keeps the messages the peer hasn't acknowledged in a journal, only the network thread started by
+    /// `spawn_network` does.
+    ///
+    /// # Arguments
+    /// * `journal` - A Journal shared with whoever lists the pending messages.
+    pub fn journal(mut self, journal: Journal) -> ConnectionBuilder {
+        self.journal = journal;
+        return self;
+    }
+
+    /// Sets the TCP options used for every peer's socket.
+    ///
+    /// # Arguments
+    /// * `options` - The SocketOptions to use.
+    pub fn socket_options(mut self, options: SocketOptions) -> ConnectionBuilder {
+        self.socket_options = options;
+        return self;
+    }
+
+    /// Delays and drops received frames like a bad network would, for testing.
+    ///
+    /// # Arguments
+    /// * `simulation` - The Simulation every received frame goes through.
+    pub fn simulate(mut self, simulation: Simulation) -> ConnectionBuilder {
+        self.simulation = Some(simulation);
+        return self;
+    }
+
+    /// Records every frame sent and received, so the session can be played back with `replay`.
+    ///
+    /// # Arguments
+    /// * `recorder` - The Recorder to write to.
+    pub fn record(mut self, recorder: Recorder) -> ConnectionBuilder {
+        self.recorder = Some(recorder);
+        return self;
+    }
+
+    /// Sets how long a new client has to complete the handshake.
+    ///
+    /// # Arguments
+    /// * `timeout` - A Duration a client has to send its Hello frame before it is dropped and banned.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> ConnectionBuilder {
+        self.handshake_timeout = timeout;
+        return self;
+    }
+
+    /// Sets how long misbehaving clients are banned for.
+    ///
+    /// # Arguments
+    /// * `duration` - A Duration of how long bans last, zero disables banning.
+    pub fn ban_duration(mut self, duration: Duration) -> ConnectionBuilder {
+        self.ban_duration = duration;
+        return self;
+    }
+
+    /// Sets how many clients may wait for their turn while the server has a client.
+    ///
+    /// # Arguments
+    /// * `cap` - A usize of the most clients that wait, 0 turns every extra client away.
+    pub fn waiting_room(mut self, cap: usize) -> ConnectionBuilder {
+        self.waiting_room = cap;
+        return self;
+    }
+
+    /// Sets which IPs may connect.
+    ///
+    /// # Arguments
+    /// * `filter` - A AcceptFilter checked before a client becomes our peer.
+    pub fn accept_filter(mut self, filter: AcceptFilter) -> ConnectionBuilder {
+        self.filter = filter;
+        return self;
+    }
+
+    /// Makes the server invite-only, clients then have to join with a token from `invites`.
+    ///
+    /// # Arguments
+    /// * `invites` - A Invites shared with whoever hands out the tokens.
+    pub fn invites(mut self, invites: Invites) -> ConnectionBuilder {
+        self.invites = Some(invites);
+        return self;
+    }
+
+    /// Lets clients that lose their connection resume their session, each is given a token once it completes the
+    /// handshake.
+    ///
+    /// # Arguments
+    /// * `tokens` - A ResumeTokens of how long a client has to come back.
+    pub fn resume(mut self, tokens: ResumeTokens) -> ConnectionBuilder {
+        self.resume_tokens = Some(tokens);
+        return self;
+    }
+
+    /// Joins an invite-only server with a token, a client sends a Join frame instead of Hello.
+    ///
+    /// # Arguments
+    /// * `token` - A Option<String> of the invite token, None to send Hello.
+    pub fn invite(mut self, token: Option<String>) -> ConnectionBuilder {
+        self.invite = token;
+        return self;
+    }
+
+    /// Announces a nick once connected, so a headless server can pass us private messages.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the nick.
+    /// * `key` - A String of the public key messages to us are sealed with.
+    pub fn nick(mut self, nick: &str, key: String) -> ConnectionBuilder {
+        self.nick = Some((String::from(nick), key));
+        return self;
+    }
+
+    /// Talks off the record, see `otr::Otr`. Both sides have to, a peer that doesn't is dropped.
+    ///
+    /// # Arguments
+    /// * `otr` - A bool of whether to seal every frame with per-session keys.
+    pub fn otr(mut self, otr: bool) -> ConnectionBuilder {
+        self.otr = otr;
+        return self;
+    }
+
+    /// Makes the Connection with the options but no peer.
+    ///
+    /// # Arguments
+    /// * `state` - A ServerState, Listening for a server or NotApplicable otherwise.
+    ///
+    /// # Returns
+    /// `io::Result<Connection>` - the connection, or why it can't be made with these options.
+    fn build(self, state: ServerState) -> io::Result<Connection> {
+        let mut con = Connection::new(self.msg_size, state);
+        con.queue_cap = self.queue_cap;
+        if self.max_kbps > 0 {
+            con.throttle = Some(Throttle::new(self.max_kbps));
+        }
+        con.retransmit = Retransmit::new(self.ack_timeout, self.retries);
+        con.journal = self.journal;
+        con.socket_options = self.socket_options;
+        con.simulation = self.simulation;
+        con.recorder = self.recorder;
+        con.handshake_timeout = self.handshake_timeout;
+        con.bans.set_duration(self.ban_duration);
+        con.waiting = WaitingRoom::new(self.waiting_room);
+        con.filter = self.filter;
+        con.invites = self.invites;
+        con.resume_tokens = self.resume_tokens;
+        if self.otr {
+            con.otr = Some(RefCell::new(Otr::new()?));
+        }
+        return Ok(con);
+    }
+
+    /// Sends the frames that start a client's side of the handshake, Hello or Join and then our nick, or our
+    /// key off the record, where the nick would only go out in the clear.
+    fn greet(con: &Connection, invite: Option<String>, nick: Option<(String, String)>) {
+        match invite {
+            Some(token) => con.send_frame(&Frame::Join { token: token }),
+            None => con.send_frame(&Frame::Hello),
+        };
+        if let Some(otr) = &con.otr {
+            let key = otr.borrow_mut().offer();
+            con.send_frame(&Frame::Otr { key: key });
+            return;
+        }
+        if let Some((nick, key)) = nick {
+            con.send_frame(&Frame::Nick {
+                nick: nick,
+                key: key,
+            });
+        }
+    }
+
+    /// Makes a server connection listening on an address, clients are accepted by the loop `spawn_network` runs
+    /// or with `accept_new_client`.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the addr:port to listen on.
+    ///
+    /// # Returns
+    /// `io::Result<(Connection, TcpListener)>` - the connection and its listener, or why it could not listen.
+    pub fn server(self, addr: &str) -> io::Result<(Connection, TcpListener)> {
+        let con = self.build(ServerState::Listening)?;
+        let server = TcpListener::bind(addr)?;
+        server.set_nonblocking(true)?;
+        return Ok((con, server));
+    }
+
+    /// Makes a client connection to a server and sends the Hello frame that completes the handshake, or a Join
+    /// frame with the invite token for an invite-only server. If the server gives us a token the network thread
+    /// connects again with it when the connection is lost.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the server's host:port.
+    ///
+    /// # Returns
+    /// `io::Result<Connection>` - the connection, or why it could not connect.
+    pub fn client(self, addr: &str) -> io::Result<Connection> {
+        let (invite, nick) = (self.invite.clone(), self.nick.clone());
+        let mut con = self.build(ServerState::NotApplicable)?;
+        let stream = connect_server(addr)?;
+        con.socket_options.apply(&stream)?;
+        let peer_addr = stream.peer_addr()?;
+        con.set_peer(Some(Peer::new(stream, peer_addr, String::from("Server"))));
+        con.reconnect = Some(Reconnect::new(addr, nick.clone()));
+        ConnectionBuilder::greet(&con, invite, nick);
+        return Ok(con);
+    }
+
+    /// Makes a client connection to a recording made with `record`, played back as if it were the server, see
+    /// `record::replay`.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the recording.
+    /// * `speed` - A f64 of how many times faster than recorded to play, 0 for as fast as possible.
+    ///
+    /// # Returns
+    /// `io::Result<Connection>` - the connection, or why the recording could not be played.
+    pub fn replay(self, path: &str, speed: f64) -> io::Result<Connection> {
+        let addr = replay(path, speed, self.msg_size)?;
+        return self.client(&addr);
+    }
+
+    /// Makes a client connection to another client, met through a rendezvous server.
+    ///
+    /// Both sides act as clients and send the Hello frame, see `punch::meet` for how they get connected.
+    ///
+    /// # Arguments
+    /// * `server` - A &str of the rendezvous server's host:port.
+    /// * `room` - A &str of the room name both peers use.
+    ///
+    /// # Returns
+    /// `io::Result<(Connection, SocketAddr, bool)>` - the connection, the peer's address and whether the
+    /// rendezvous server relays for us, or why we couldn't meet the peer.
+    pub fn rendezvous(
+        self,
+        server: &str,
+        room: &str,
+    ) -> io::Result<(Connection, SocketAddr, bool)> {
+        let nick = self.nick.clone();
+        let mut con = self.build(ServerState::NotApplicable)?;
+        let meeting = punch::meet(server, room, con.msg_size)?;
+        meeting.stream.set_nonblocking(true)?;
+        con.socket_options.apply(&meeting.stream)?;
+        con.set_peer(Some(Peer::new(
+            meeting.stream,
+            meeting.peer,
+            meeting.peer.to_string(),
+        )));
+        ConnectionBuilder::greet(&con, None, nick);
+        return Ok((con, meeting.peer, meeting.relayed));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::Frame;
+
+/// How often the peer is pinged.
+const PING_EVERY: Duration = Duration::from_secs(5);
+
+/// How many of the latest samples the estimate is picked from.
+const SAMPLES: usize = 8;
+
+/// The ClockStats of the connection, estimated from Ping and Pong timestamps the way NTP does and shown in the stats
+/// panel.
+///
+/// # Fields
+/// `rtt_ms` - The round trip time, without the time the peer took to answer.
+/// `one_way_ms` - Half the round trip, the one way latency if the path is as fast both ways.
+/// `offset_ms` - How far the peer's clock is ahead of ours, negative if it is behind.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockStats {
+    pub rtt_ms: f64,
+    pub one_way_ms: f64,
+    pub offset_ms: f64,
+}
+
+/// A Clock which pings the peer and estimates the latency and clock offset from the answers.
+///
+/// # Fields
+/// `samples` - The latest estimates, oldest first.
+/// `pinged` - When the peer was last pinged, None to ping it straight away.
+/// `enabled` - Whether to ping at all, false once the peer turned out not to know Ping frames.
+#[derive(Clone)]
+pub struct Clock {
+    samples: VecDeque<ClockStats>,
+    pinged: Option<Instant>,
+    enabled: bool,
+}
+
+/// The wall clock time in microseconds since the unix epoch, what Ping and Pong frames carry.
+pub fn now_micros() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0);
+}
+
+impl Clock {
+    /// Creates a new Clock with no samples, which pings as soon as it is asked.
+    ///
+    /// # Returns
+    /// `Clock` - the newly created clock.
+    pub fn new() -> Clock {
+        return Clock {
+            samples: VecDeque::new(),
+            pinged: None,
+            enabled: true,
+        };
+    }
+
+    /// Forgets the samples, for when another peer connected.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.pinged = None;
+    }
+
+    /// Stops pinging, for a peer that rejected our Ping frame.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.samples.clear();
+    }
+
+    /// Makes the next Ping frame once PING_EVERY has passed since the last one.
+    ///
+    /// # Returns
+    /// `Option<Frame>` - the Ping to send, None if it isn't due or pinging is off.
+    pub fn due(&mut self) -> Option<Frame> {
+        if !self.enabled || self.pinged.map_or(false, |p| p.elapsed() < PING_EVERY) {
+            return None;
+        }
+        self.pinged = Some(Instant::now());
+        return Some(Frame::Ping { sent: now_micros() });
+    }
+
+    /// Adds the sample a Pong frame gives, which just arrived.
+    ///
+    /// # Arguments
+    /// * `sent` - A i64 of when we sent the Ping, by our clock.
+    /// * `received` - A i64 of when the peer got it, by its clock.
+    /// * `replied` - A i64 of when the peer sent the Pong, by its clock.
+    pub fn pong(&mut self, sent: i64, received: i64, replied: i64) {
+        let arrived = now_micros();
+        let rtt = (arrived - sent) - (replied - received);
+        // A Pong from before our clock was set back, or a peer that made its times up.
+        if rtt < 0 {
+            return;
+        }
+        let offset = ((received - sent) + (replied - arrived)) as f64 / 2.0;
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ClockStats {
+            rtt_ms: rtt as f64 / 1000.0,
+            one_way_ms: rtt as f64 / 2000.0,
+            offset_ms: offset / 1000.0,
+        });
+    }
+
+    /// The current estimate, from the sample with the shortest round trip since it was delayed the least by
+    /// queueing on the way.
+    ///
+    /// # Returns
+    /// `Option<ClockStats>` - the estimate, None until the peer answered a Ping.
+    pub fn stats(&self) -> Option<ClockStats> {
+        return self
+            .samples
+            .iter()
+            .min_by(|a, b| a.rtt_ms.total_cmp(&b.rtt_ms))
+            .copied();
+    }
+}
@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+
+/// A Cidr which is a range of IPs, e.g. 192.168.1.0/24, or a single IP.
+///
+/// # Fields
+/// `addr` - The first address of the range.
+/// `prefix` - How many leading bits of an IP have to match `addr`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+/// An AcceptFilter which decides which IPs may connect, checked before a client becomes a peer.
+///
+/// # Fields
+/// `allow` - If not empty, only IPs in one of these ranges may connect.
+/// `deny` - IPs in these ranges may never connect, even if they are allowed.
+#[derive(Clone, Debug, Default)]
+pub struct AcceptFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl Cidr {
+    /// Parses a Cidr from an IP with an optional prefix length.
+    ///
+    /// # Arguments
+    /// * `text` - A &str like 10.0.0.0/8, fd00::/8 or 127.0.0.1.
+    ///
+    /// # Returns
+    /// `Result<Cidr, String>` - the range, or why it is not valid.
+    pub fn parse(text: &str) -> Result<Cidr, String> {
+        let text = text.trim();
+        let (addr, prefix) = match text.find('/') {
+            Some(i) => (&text[..i], Some(&text[i + 1..])),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("\"{}\" is not an IP", text))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u32>() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => return Err(format!("\"{}\" has a bad prefix length", text)),
+            },
+            None => bits,
+        };
+
+        return Ok(Cidr {
+            addr: addr,
+            prefix: prefix,
+        });
+    }
+
+    /// Checks whether an IP is in the range, IPv4 addresses mapped into IPv6 count as IPv4.
+    ///
+    /// # Arguments
+    /// * `ip` - A &IpAddr to check.
+    ///
+    /// # Returns
+    /// `bool` - true if the IP is in the range.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            IpAddr::V4(_) => *ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                return u32::from(net) & mask == u32::from(ip) & mask;
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                return u128::from(net) & mask == u128::from(ip) & mask;
+            }
+            _ => return false,
+        }
+    }
+}
+
+impl AcceptFilter {
+    /// Creates a new AcceptFilter from comma separated lists of ranges.
+    ///
+    /// # Arguments
+    /// * `allow` - A Option<&str> of the ranges that may connect, None to allow everyone not denied.
+    /// * `deny` - A Option<&str> of the ranges that may not connect.
+    ///
+    /// # Returns
+    /// `Result<AcceptFilter, String>` - the filter, or which range is not valid.
+    pub fn new(allow: Option<&str>, deny: Option<&str>) -> Result<AcceptFilter, String> {
+        let parse = |list: Option<&str>| -> Result<Vec<Cidr>, String> {
+            return list
+                .unwrap_or("")
+                .split(',')
+                .filter(|c| !c.trim().is_empty())
+                .map(Cidr::parse)
+                .collect();
+        };
+
+        return Ok(AcceptFilter {
+            allow: parse(allow)?,
+            deny: parse(deny)?,
+        });
+    }
+
+    /// Checks whether an IP may connect.
+    ///
+    /// # Arguments
+    /// * `ip` - A &IpAddr of the connecting client.
+    ///
+    /// # Returns
+    /// `bool` - true unless the IP is denied or there is an allowlist it is not on.
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        return self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip));
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::random::random;
+
+/// Makes a random token, 24 hex digits from the OS's randomness.
+///
+/// # Returns
+/// `io::Result<String>` - the token, or why no randomness could be read.
+pub(super) fn random_token() -> io::Result<String> {
+    let bytes = random::<12>()?;
+    return Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect());
+}
+
+/// Invites which are the single-use tokens a client needs to join an invite-only server.
+///
+/// Clones share the same tokens, so the ui or control socket can issue tokens the network thread redeems.
+///
+/// # Fields
+/// `tokens` - Unused tokens mapped to when they expire.
+/// `ttl` - How long a new token is valid for.
+#[derive(Clone)]
+pub struct Invites {
+    tokens: Arc<Mutex<HashMap<String, Instant>>>,
+    ttl: Duration,
+}
+
+impl Invites {
+    /// Creates a new Invites with no tokens.
+    ///
+    /// # Arguments
+    /// * `ttl` - A Duration of how long each token is valid for.
+    ///
+    /// # Returns
+    ///  `Invites` - the newly created invites.
+    pub fn new(ttl: Duration) -> Invites {
+        return Invites {
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            ttl: ttl,
+        };
+    }
+
+    /// Accessor method for how long tokens are valid for.
+    ///
+    /// # Returns
+    /// `Duration` - the token lifetime.
+    pub fn ttl(&self) -> Duration {
+        return self.ttl;
+    }
+
+    /// Makes a new random token.
+    ///
+    /// # Returns
+    /// `io::Result<String>` - the token, or why no randomness could be read.
+    pub fn issue(&self) -> io::Result<String> {
+        let token = random_token()?;
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(token.clone(), Instant::now() + self.ttl);
+        return Ok(token);
+    }
+
+    /// Uses up a token, forgetting tokens that have expired.
+    ///
+    /// # Arguments
+    /// * `token` - A &str of the token the client sent.
+    ///
+    /// # Returns
+    /// `bool` - true if the token was valid, it can't be used again.
+    pub fn redeem(&self, token: &str) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = Instant::now();
+        tokens.retain(|_, expires| *expires > now);
+
+        return tokens.remove(token).is_some();
+    }
+}
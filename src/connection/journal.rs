@@ -0,0 +1,234 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+use super::{escape, unescape};
+use crate::protocol::Frame;
+use crate::transcript::shred;
+use crate::vault::{is_header, Vault};
+
+/// A Journal of the messages we sent that the peer hasn't acknowledged yet, so they can be sent again after a
+/// crash.
+///
+/// The file is appended to, `+` and the encoded frame when a message is sent or queued and `-` and its id once the
+/// peer acknowledged it. When it is opened it is rewritten with just the messages left from earlier runs, `*` and the
+/// encoded frame, and `~` drops the oldest of those once it was sent again as a new message. Clones share the same
+/// messages and file, so the ui can list what is pending while the network thread keeps it up to date.
+///
+/// With `--encrypt-history` the file starts with the vault's header and every line is sealed, like the transcript.
+///
+/// # Fields
+/// `pending` - The messages waiting for an ack, oldest first.
+/// `file` - The journal file, None to only keep the messages in memory.
+/// `vault` - What every line is sealed with, None to write them in the clear.
+#[derive(Clone)]
+pub struct Journal {
+    pending: Arc<Mutex<Vec<Frame>>>,
+    file: Option<Arc<Mutex<File>>>,
+    vault: Option<Arc<Vault>>,
+}
+
+impl Journal {
+    /// Creates a new Journal that doesn't write to a file, its messages are lost if we crash.
+    ///
+    /// # Returns
+    /// `Journal` - the journal, with nothing pending.
+    pub fn memory() -> Journal {
+        return Journal {
+            pending: Arc::new(Mutex::new(Vec::new())),
+            file: None,
+            vault: None,
+        };
+    }
+
+    /// Opens a journal file, creating it if needed, and takes the messages a previous run never got acknowledged.
+    ///
+    /// The file is rewritten with only those messages, so they aren't lost if we crash again before they are sent,
+    /// see `resend`. Their ids belong to the old session, so they should be sent as new messages.
+    ///
+    /// With a passphrase the rewritten file is sealed, see `Vault`, keeping the vault of one that already was, so a
+    /// journal kept in the clear until now is encrypted from here on. Without one, an encrypted file is refused.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the journal file.
+    /// * `passphrase` - A Option<&str> to encrypt the journal with, None to write it in the clear.
+    ///
+    /// # Returns
+    /// `io::Result<(Journal, Vec<Frame>)>` - the journal, with nothing pending, and the messages left from earlier
+    /// runs oldest first, or why the file could not be read or written.
+    pub fn open(path: &str, passphrase: Option<&str>) -> io::Result<(Journal, Vec<Frame>)> {
+        // Messages left from the runs before the last one, which it never sent again, come first.
+        let mut earlier: Vec<Frame> = Vec::new();
+        let mut left: Vec<Frame> = Vec::new();
+        let mut vault = None;
+        match File::open(path) {
+            Ok(file) => {
+                for (i, line) in BufReader::new(file).lines().enumerate() {
+                    let mut line = line?;
+                    if i == 0 && is_header(&line) {
+                        let passphrase = passphrase.ok_or_else(|| {
+                            io::Error::new(
+                                ErrorKind::InvalidData,
+                                "it is encrypted, start with --encrypt-history",
+                            )
+                        })?;
+                        let unlocked = Vault::unlock(passphrase, &line)
+                            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                        vault = Some(unlocked);
+                        continue;
+                    }
+                    if let Some(vault) = &vault {
+                        line = vault.open(&line).map_err(|e| {
+                            io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e))
+                        })?;
+                    }
+                    if let Some(msg) = line.strip_prefix('*') {
+                        earlier.extend(Frame::decode(&unescape(msg)));
+                    } else if line == "~" {
+                        if !earlier.is_empty() {
+                            earlier.remove(0);
+                        }
+                    } else if let Some(msg) = line.strip_prefix('+') {
+                        left.extend(Frame::decode(&unescape(msg)));
+                    } else if let Some(id) = line.strip_prefix('-') {
+                        let id = id.parse::<u64>().ok();
+                        left.retain(|f| f.message_id() != id);
+                    }
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        earlier.append(&mut left);
+        let vault = match (vault, passphrase) {
+            (Some(vault), _) => Some(Arc::new(vault)),
+            (None, Some(passphrase)) => Some(Arc::new(Vault::create(passphrase)?)),
+            (None, None) => None,
+        };
+
+        // The old file is only replaced once the new one is complete, so a crash now loses nothing either.
+        let rewritten = format!("{}.new", path);
+        let mut file = File::create(&rewritten)?;
+        if let Some(vault) = &vault {
+            writeln!(file, "{}", vault.header())?;
+        }
+        for frame in &earlier {
+            writeln!(
+                file,
+                "{}",
+                seal(&vault, &format!("*{}", escape(&frame.encode())))
+            )?;
+        }
+        file.sync_all()?;
+        fs::rename(&rewritten, path)?;
+
+        // Not opened to append, so `wipe` can overwrite it in place.
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::End(0))?;
+        let journal = Journal {
+            pending: Arc::new(Mutex::new(Vec::new())),
+            file: Some(Arc::new(Mutex::new(file))),
+            vault: vault,
+        };
+        return Ok((journal, earlier));
+    }
+
+    /// Accessor method for the messages waiting for an ack.
+    ///
+    /// # Returns
+    /// `Vec<Frame>` - the messages, oldest first.
+    pub fn pending(&self) -> Vec<Frame> {
+        return self.pending.lock().unwrap().clone();
+    }
+
+    /// Journals a message that was just sent or queued, unless it already is.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame that was sent, only Text and Reply frames are acknowledged, others are ignored.
+    pub(super) fn add(&self, frame: &Frame) {
+        match frame {
+            Frame::Text { .. } | Frame::Reply { .. } => {}
+            _ => return,
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.iter().any(|f| f.message_id() == frame.message_id()) {
+            return;
+        }
+        pending.push(frame.clone());
+        self.write(&[&format!("+{}", escape(&frame.encode()))]);
+    }
+
+    /// Journals a message left from an earlier run that is sent again as a new one, in place of the old one.
+    ///
+    /// Called with the messages `open` gave back in the same order, before they are handed to the network thread.
+    /// Both happen in one write, so after a crash the message is there once, either as it was or as the new one.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame of the message with its new id.
+    pub fn resend(&self, frame: &Frame) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(frame.clone());
+        self.write(&["~", &format!("+{}", escape(&frame.encode()))]);
+    }
+
+    /// Forgets a message the peer acknowledged, or that was dropped from a full queue.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message's id.
+    pub(super) fn done(&self, id: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        let before = pending.len();
+        pending.retain(|f| f.message_id() != Some(id));
+        if pending.len() != before {
+            self.write(&[&format!("-{}", id)]);
+        }
+    }
+
+    /// Forgets every message waiting for an ack and shreds the journal file, see `shred`.
+    ///
+    /// Messages already sent are still sent again until acked, they are only no longer kept past a crash. An
+    /// encrypted journal starts again with the vault's header, so it still opens with the same passphrase.
+    ///
+    /// # Returns
+    /// `io::Result<()>` - Ok, or why the file could not be wiped.
+    pub fn wipe(&self) -> io::Result<()> {
+        self.pending.lock().unwrap().clear();
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            shred(&mut file)?;
+            if let Some(vault) = &self.vault {
+                writeln!(file, "{}", vault.header())?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Appends lines to the journal file, in a single write so a crash can't leave half of them.
+    fn write(&self, lines: &[&str]) {
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            let mut written = String::new();
+            for line in lines {
+                written.push_str(&seal(&self.vault, line));
+                written.push('\n');
+            }
+            // A full disk shouldn't take down the chat, the message is only not kept past a crash.
+            let _ = file.write_all(written.as_bytes());
+        }
+    }
+}
+
+/// Seals a journal line with the vault, if there is one.
+///
+/// # Arguments
+/// * `vault` - A &Option<Arc<Vault>> the journal is encrypted with.
+/// * `line` - A &str of the line to write.
+///
+/// # Returns
+/// `String` - the line as it goes in the file.
+fn seal(vault: &Option<Arc<Vault>>, line: &str) -> String {
+    return match vault {
+        Some(vault) => vault.seal(line),
+        None => String::from(line),
+    };
+}
@@ -0,0 +1,959 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{now_micros, ClockStats, Connection, PeerId, RecentIds, Throttle, ThrottleStats};
+use crate::protocol::{Frame, PING_TAG};
+
+/// How long the network thread sleeps when there was nothing to do.
+const IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// How often a Status event is sent even if nothing changed, so throttle usage stays current.
+const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The most frames received in one turn, so a peer sending a flood of them can't hold up our sends and the ui.
+const MAX_RECEIVED: usize = 64;
+
+/// An OutgoingMsg which the ui sends to the network thread.
+///
+/// # Variants
+/// `Send` - Send a frame to the peer, queueing it if there is no peer.
+/// `Resend` - Send a message that could not be sent again, by its id.
+/// `Limit` - Change the most kilobits per second to send, 0 for no limit.
+/// `Shutdown` - Send the peer a Disconnect frame and stop the network thread, replying on the given channel once done.
+pub enum OutgoingMsg {
+    Send(Frame),
+    Resend(u64),
+    Limit(u32),
+    Shutdown(Sender<()>),
+}
+
+/// An Event which the network thread sends to the ui.
+///
+/// # Variants
+/// `PeerConnected` - A client connected, with the PeerId naming it.
+/// `PeerDisconnected` - The peer is gone, with the PeerId it had and why.
+/// `Reconnecting` - The connection to the server was lost, and we try to resume the session for this many seconds.
+/// `Resumed` - A peer that lost its connection is back with its session, with the PeerId it has now.
+/// `Frame` - A frame was received from the peer, received frames are acknowledged automatically.
+/// `Acknowledged` - The peer acknowledged one of our messages, with the round trip time in ms from when it was first
+/// sent.
+/// `Delivered` - Queued messages were delivered after a client completed the handshake, with how many.
+/// `NotSent` - A frame could not be sent, with the id of the message it carried if any and why, "Queue full" if
+/// there is no peer to send it to and queueing is disabled or the oldest queued message was dropped to make room,
+/// "Failed" with the socket error or "Not acknowledged" if the peer didn't acknowledge it after every retry.
+/// Messages are kept so
+/// `OutgoingMsg::Resend` can try again.
+/// `Status` - The current state of the connection.
+pub enum Event {
+    PeerConnected(PeerId),
+    PeerDisconnected(PeerId, Reason),
+    Reconnecting(u64),
+    Resumed(PeerId),
+    Frame(Frame),
+    Acknowledged(i64),
+    Delivered(usize),
+    NotSent(Option<u64>, String),
+    Status(NetStatus),
+}
+
+/// The Reason a peer is gone.
+///
+/// # Variants
+/// `Left` - The peer said it was leaving.
+/// `Closed` - The peer's socket closed.
+/// `TimedOut` - The server dropped and banned a client that did not complete the handshake in time.
+/// `Banned` - The server dropped and banned a client that broke the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Left,
+    Closed,
+    TimedOut,
+    Banned,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Left => return write!(f, "left"),
+            Reason::Closed => return write!(f, "connection lost"),
+            Reason::TimedOut => return write!(f, "timeout"),
+            Reason::Banned => return write!(f, "banned"),
+        }
+    }
+}
+
+/// The NetStatus of the connection, drawn in the status bar and stats panel.
+///
+/// # Fields
+/// `peer` - Who the peer is, None if there is no peer.
+/// `handshake_pending` - Whether the peer has yet to complete the handshake.
+/// `queued` - How many messages are waiting for a peer.
+/// `throttle` - The throttle's stats, None if sends are not limited.
+/// `clock` - The peer's latency and clock offset, None until it answered a Ping.
+/// `otr` - The off-the-record session's fingerprint, None without one.
+pub struct NetStatus {
+    pub peer: Option<String>,
+    pub handshake_pending: bool,
+    pub queued: usize,
+    pub throttle: Option<ThrottleStats>,
+    pub clock: Option<ClockStats>,
+    pub otr: Option<String>,
+}
+
+impl Connection {
+    /// Moves the connection onto a background thread which does all of its socket I/O.
+    ///
+    /// Called on a connection, consumes it. Servers pass their TcpListener so the thread can accept clients.
+    ///
+    /// # Arguments
+    /// * `server` - A Option<TcpListener> to accept clients from, None for clients.
+    ///
+    /// # Returns
+    /// `(Sender<OutgoingMsg>, Receiver<Event>)` - The channels to send messages with and receive events on.
+    pub fn spawn_network(
+        self,
+        server: Option<TcpListener>,
+    ) -> (Sender<OutgoingMsg>, Receiver<Event>) {
+        let (outgoing_tx, outgoing_rx) = mpsc::channel::<OutgoingMsg>();
+        let (event_tx, event_rx) = mpsc::channel::<Event>();
+
+        let network = Network::new(self, server, outgoing_rx, event_tx);
+        thread::spawn(move || run(network));
+
+        return (outgoing_tx, event_rx);
+    }
+
+    /// Reports the current state of the connection.
+    ///
+    /// Called on a connection.
+    ///
+    /// # Returns
+    /// `NetStatus` - the connection's status.
+    pub fn status(&self) -> NetStatus {
+        return NetStatus {
+            peer: self.peer.as_ref().map(|p| p.who().clone()),
+            handshake_pending: self.handshake_pending(),
+            queued: self.queued(),
+            throttle: self.throttle_stats(),
+            clock: self.clock.stats(),
+            otr: self
+                .otr
+                .as_ref()
+                .and_then(|otr| otr.borrow().fingerprint().map(String::from)),
+        };
+    }
+}
+
+/// Sends or queues a frame for the network thread, keeping messages that could not be sent, and journals it until
+/// the peer acknowledges it. A message that was neither sent nor queued, or was dropped from the queue, isn't kept
+/// in the journal, it would be sent again after a crash.
+///
+/// # Arguments
+/// * `con` - A &mut Connection to send with.
+/// * `frame` - A Frame to send.
+/// * `unsent` - A &mut HashMap<u64, Frame> of the messages that could not be sent, by id.
+///
+/// # Returns
+/// `Option<Event>` - NotSent if the frame could not be sent or the oldest queued one was dropped for it, otherwise
+/// None.
+fn send(con: &mut Connection, frame: Frame, unsent: &mut HashMap<u64, Frame>) -> Option<Event> {
+    let dropped = con.make_room();
+    if let Some(id) = dropped.as_ref().and_then(|dropped| dropped.message_id()) {
+        con.journal.done(id);
+    }
+    let (sent, _) = con.send_or_queue_frame(&frame);
+    if sent == "Queue full" || sent.starts_with("Failed") {
+        return Some(not_sent(frame, sent, unsent));
+    }
+    con.journal.add(&frame);
+    if sent.starts_with("Message sent") {
+        con.retransmit.sent(&frame);
+    }
+    return dropped.map(|dropped| {
+        not_sent(
+            dropped,
+            String::from("Queue full, dropped the oldest"),
+            unsent,
+        )
+    });
+}
+
+/// Keeps a message that could not be sent so `OutgoingMsg::Resend` can try again.
+///
+/// # Arguments
+/// * `frame` - A Frame that could not be sent.
+/// * `why` - A String of why it could not be sent.
+/// * `unsent` - A &mut HashMap<u64, Frame> of the messages that could not be sent, by id.
+///
+/// # Returns
+/// `Event` - the NotSent event for the ui.
+fn not_sent(frame: Frame, why: String, unsent: &mut HashMap<u64, Frame>) -> Event {
+    let id = frame.message_id();
+    if let Some(id) = id {
+        unsent.insert(id, frame);
+    }
+    return Event::NotSent(id, why);
+}
+
+/// Forgets the peer that is gone.
+///
+/// # Arguments
+/// * `peer_id` - A &mut Option<PeerId> of the peer, None afterwards.
+/// * `reason` - A Reason of why it is gone.
+///
+/// # Returns
+/// `Option<Event>` - PeerDisconnected naming the peer, None if there was no peer.
+fn gone(peer_id: &mut Option<PeerId>, reason: Reason) -> Option<Event> {
+    return peer_id.take().map(|id| Event::PeerDisconnected(id, reason));
+}
+
+/// The Network thread's state, everything its loop keeps between turns.
+///
+/// # Fields
+/// `con` - The connection, only ever touched by the network thread.
+/// `server` - The listener clients are accepted from, None for clients.
+/// `outgoing` - Where the ui's messages arrive.
+/// `events` - Where the ui's events go.
+/// `unsent` - The messages that could not be sent, by id.
+/// `seen` - The peer's latest message ids.
+/// `relayed_seen` - The latest message ids of each client whose messages a relay passes on.
+/// `peer_id` - The peer, kept past it so its PeerDisconnected event can still name it.
+/// `last_status` - The peer, handshake and queue the last Status event reported.
+/// `status_sent` - When the last Status event was sent.
+struct Network {
+    con: Connection,
+    server: Option<TcpListener>,
+    outgoing: Receiver<OutgoingMsg>,
+    events: Sender<Event>,
+    unsent: HashMap<u64, Frame>,
+    seen: RecentIds,
+    relayed_seen: HashMap<String, RecentIds>,
+    peer_id: Option<PeerId>,
+    last_status: (Option<String>, bool, usize),
+    status_sent: Instant,
+}
+
+impl Network {
+    /// Creates the network thread's state for a connection.
+    ///
+    /// # Arguments
+    /// * `con` - A Connection to do the I/O of.
+    /// * `server` - A Option<TcpListener> to accept clients from, None for clients.
+    /// * `outgoing` - A Receiver<OutgoingMsg> of the ui's messages.
+    /// * `events` - A Sender<Event> for the ui's events.
+    ///
+    /// # Returns
+    /// `Network` - the state, with a Status event due straight away.
+    fn new(
+        con: Connection,
+        server: Option<TcpListener>,
+        outgoing: Receiver<OutgoingMsg>,
+        events: Sender<Event>,
+    ) -> Network {
+        let peer_id = con.peer.as_ref().map(|p| p.id());
+        return Network {
+            con: con,
+            server: server,
+            outgoing: outgoing,
+            events: events,
+            unsent: HashMap::new(),
+            seen: RecentIds::new(),
+            relayed_seen: HashMap::new(),
+            peer_id: peer_id,
+            last_status: (None, false, 0),
+            status_sent: Instant::now() - STATUS_INTERVAL,
+        };
+    }
+
+    /// Runs one turn of the loop: accepts or resumes a peer, reads up to MAX_RECEIVED frames from the peer, handles
+    /// the ui's messages, pings and retransmits, then reports the status if it changed.
+    ///
+    /// Called on the network thread's state. Events for one turn are sent in the order they happened, so a
+    /// PeerConnected or PeerDisconnected event always comes before the Status event showing it.
+    ///
+    /// # Returns
+    /// `Option<bool>` - whether there was anything to do, or None once told to shut down or the ui hung up.
+    fn step(&mut self) -> Option<bool> {
+        let Network {
+            con,
+            server,
+            outgoing,
+            events,
+            unsent,
+            seen,
+            relayed_seen,
+            peer_id,
+            last_status,
+            status_sent,
+        } = self;
+        let mut busy = false;
+
+        if let Some(server) = server {
+            con.queue_other_clients(server);
+            if !con.is_occupied() && con.accept_client(server) {
+                *peer_id = con.peer.as_ref().map(|p| p.id());
+                if let Some(id) = *peer_id {
+                    events.send(Event::PeerConnected(id)).ok()?;
+                }
+                busy = true;
+            }
+        }
+
+        if server.is_none() && con.peer.is_none() {
+            match con.try_resume() {
+                Some(true) => busy = true,
+                Some(false) => {
+                    if let Some(event) = gone(peer_id, Reason::Closed) {
+                        events.send(event).ok()?;
+                    }
+                }
+                None => (),
+            }
+        }
+
+        let mut received_frames = 0;
+        while con.peer.is_some() && received_frames < MAX_RECEIVED {
+            received_frames += 1;
+            let queued = con.queued();
+            let pending = con.handshake_pending();
+            let received = con.receive_frame();
+            // A client that resumed its session may send its last messages again, the rest start their ids over.
+            let started = pending && !con.handshake_pending() && con.peer.is_some();
+            if started {
+                con.clock.reset();
+            }
+            if started && con.resumed().is_none() {
+                seen.clear();
+                relayed_seen.clear();
+            }
+            let event = match received {
+                Err(status) => match status.as_str() {
+                    "Blocked" => break,
+                    "Disconnected" if con.reconnecting() => {
+                        let secs = con.reconnect.as_ref().map_or(0, |r| r.ttl().as_secs());
+                        Some(Event::Reconnecting(secs))
+                    }
+                    "Disconnected" => gone(peer_id, Reason::Closed),
+                    "Timed out" => gone(peer_id, Reason::TimedOut),
+                    "Banned" => gone(peer_id, Reason::Banned),
+                    "Empty" if started && con.resumed().is_some() => (*peer_id).map(Event::Resumed),
+                    "Empty" if queued > 0 && con.queued() == 0 => Some(Event::Delivered(queued)),
+                    _ => None,
+                },
+                Ok(frame) => match frame {
+                    Frame::Received { id } => con.retransmit.acked(id).map(|(id, ms)| {
+                        con.journal.done(id);
+                        Event::Acknowledged(ms)
+                    }),
+                    Frame::Disconnect => {
+                        con.drop_peer();
+                        gone(peer_id, Reason::Left)
+                    }
+                    Frame::Resume {
+                        token,
+                        secs: Some(secs),
+                    } if server.is_none() => {
+                        if con.keep_resume_token(token, secs) {
+                            *peer_id = con.peer.as_ref().map(|p| p.id());
+                            (*peer_id).map(Event::Resumed)
+                        } else {
+                            None
+                        }
+                    }
+                    Frame::Ping { sent } => {
+                        let received = now_micros();
+                        con.send_frame(&Frame::Pong {
+                            sent: sent,
+                            received: received,
+                            replied: now_micros(),
+                        });
+                        None
+                    }
+                    Frame::Pong {
+                        sent,
+                        received,
+                        replied,
+                    } => {
+                        con.clock.pong(sent, received, replied);
+                        None
+                    }
+                    // A peer from before Ping frames says so once, after that it isn't pinged.
+                    ref frame if frame.unknown_tag() == Some(PING_TAG) => {
+                        con.clock.disable();
+                        None
+                    }
+                    Frame::Hello | Frame::Resume { .. } => None,
+                    frame => match frame {
+                        Frame::Text { id, .. } | Frame::Reply { id, .. } => {
+                            // A message sent again is acked again, the peer may have missed the first ack.
+                            con.notify_message_received(id);
+                            if seen.first_seen(id) {
+                                Some(Event::Frame(frame))
+                            } else {
+                                None
+                            }
+                        }
+                        Frame::Relayed { id, ref sender, .. } => {
+                            con.notify_message_received(id);
+                            let first = relayed_seen
+                                .entry(sender.clone())
+                                .or_insert_with(RecentIds::new)
+                                .first_seen(id);
+                            if first {
+                                Some(Event::Frame(frame))
+                            } else {
+                                None
+                            }
+                        }
+                        Frame::Presence {
+                            ref peer,
+                            online: false,
+                            ..
+                        } => {
+                            // Whoever connects from there next starts its ids over.
+                            relayed_seen.remove(peer);
+                            Some(Event::Frame(frame))
+                        }
+                        _ => Some(Event::Frame(frame)),
+                    },
+                },
+            };
+
+            busy = true;
+            if let Some(event) = event {
+                events.send(event).ok()?;
+            }
+        }
+
+        loop {
+            match outgoing.try_recv() {
+                Ok(OutgoingMsg::Send(frame)) => {
+                    busy = true;
+                    if let Some(event) = send(con, frame, unsent) {
+                        events.send(event).ok()?;
+                    }
+                }
+                Ok(OutgoingMsg::Resend(id)) => {
+                    busy = true;
+                    let frame = match unsent.remove(&id) {
+                        Some(frame) => frame,
+                        None => continue,
+                    };
+                    if let Some(event) = send(con, frame, unsent) {
+                        events.send(event).ok()?;
+                    }
+                }
+                Ok(OutgoingMsg::Limit(max_kbps)) => {
+                    let throttle = if max_kbps > 0 {
+                        Some(Throttle::new(max_kbps))
+                    } else {
+                        None
+                    };
+                    con.set_throttle(throttle);
+                }
+                Ok(OutgoingMsg::Shutdown(done)) => {
+                    con.send_frame(&Frame::Disconnect);
+                    let _ = done.send(());
+                    return None;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    con.send_frame(&Frame::Disconnect);
+                    return None;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if con.peer.is_some() && !con.handshake_pending() {
+            if let Some(ping) = con.clock.due() {
+                con.send_frame(&ping);
+            }
+            let (again, given_up) = con.retransmit.due();
+            for frame in again {
+                // A send that fails here is tried again after the next timeout, like a lost one.
+                con.send_frame(&frame);
+            }
+            for frame in given_up {
+                let event = not_sent(frame, String::from("Not acknowledged"), unsent);
+                events.send(event).ok()?;
+            }
+        }
+
+        let status = con.status();
+        let current = (status.peer.clone(), status.handshake_pending, status.queued);
+        if current != *last_status || status_sent.elapsed() >= STATUS_INTERVAL {
+            *last_status = current;
+            *status_sent = Instant::now();
+            events.send(Event::Status(status)).ok()?;
+        }
+
+        return Some(busy);
+    }
+}
+
+/// The network thread's loop, returns once told to shut down or the ui hangs up.
+fn run(mut network: Network) {
+    while let Some(busy) = network.step() {
+        if !busy {
+            thread::sleep(IDLE_SLEEP);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{Event, Network, OutgoingMsg, Reason, MAX_RECEIVED};
+    use crate::connection::otr::Otr;
+    use crate::connection::{write_frames, ConnectionBuilder, PeerId};
+    use crate::protocol::codec::{self, FrameReader};
+    use crate::protocol::Frame;
+
+    const MSG_SIZE: usize = 255;
+
+    /// A Harness which steps the network thread's loop by hand instead of on a thread, so every event can be checked
+    /// against what the connection looked like when it was sent.
+    ///
+    /// `outgoing` is where tests send as the ui, and keeps the loop from seeing the ui hang up.
+    struct Harness {
+        network: Network,
+        outgoing: Sender<OutgoingMsg>,
+        events: Receiver<Event>,
+    }
+
+    impl Harness {
+        fn new(network: impl FnOnce(Receiver<OutgoingMsg>, Sender<Event>) -> Network) -> Harness {
+            let (outgoing_tx, outgoing_rx) = mpsc::channel();
+            let (event_tx, event_rx) = mpsc::channel();
+            return Harness {
+                network: network(outgoing_rx, event_tx),
+                outgoing: outgoing_tx,
+                events: event_rx,
+            };
+        }
+
+        /// A server listening on a free local port, with the address to connect to.
+        fn server() -> (Harness, String) {
+            return Harness::server_with(ConnectionBuilder::new(MSG_SIZE));
+        }
+
+        /// A server like `server` with the builder's options.
+        fn server_with(builder: ConnectionBuilder) -> (Harness, String) {
+            let (con, listener) = builder.server("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let harness = Harness::new(|outgoing, events| {
+                Network::new(con, Some(listener), outgoing, events)
+            });
+            return (harness, addr);
+        }
+
+        /// Steps the loop until an event is `last`, checking a server is occupied exactly while it has a peer after every turn.
+        ///
+        /// Returns every event up to and including that one, in the order they were sent.
+        fn until(&mut self, last: impl Fn(&Event) -> bool) -> Vec<Event> {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut seen = Vec::new();
+            while Instant::now() < deadline {
+                assert!(self.network.step().is_some(), "the loop stopped");
+                let con = &self.network.con;
+                if con.is_server() {
+                    assert_eq!(
+                        con.is_occupied(),
+                        con.peer.is_some(),
+                        "occupied out of step with the peer"
+                    );
+                }
+                while let Ok(event) = self.events.try_recv() {
+                    let done = last(&event);
+                    seen.push(event);
+                    if done {
+                        return seen;
+                    }
+                }
+            }
+            panic!("timed out after {} events", seen.len());
+        }
+
+        /// Steps the loop until a whole frame has arrived on `stream`, None if it closed first.
+        fn read(&mut self, stream: &mut TcpStream) -> Option<Frame> {
+            stream.set_nonblocking(true).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut reader = FrameReader::new();
+            loop {
+                assert!(Instant::now() < deadline, "timed out waiting for a frame");
+                assert!(self.network.step().is_some(), "the loop stopped");
+                match reader.read_frame(&*stream, MSG_SIZE) {
+                    Ok(Some(buff)) => return Some(codec::decode(&buff).unwrap()),
+                    Ok(None) => (),
+                    Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+                    Err(err) => panic!("the connection was reset: {}", err),
+                }
+            }
+        }
+    }
+
+    /// Where the first event `matches` is, panicking if none is.
+    fn position(events: &[Event], what: &str, matches: impl Fn(&Event) -> bool) -> usize {
+        return events
+            .iter()
+            .position(matches)
+            .unwrap_or_else(|| panic!("no {} event", what));
+    }
+
+    fn connected(event: &Event) -> Option<PeerId> {
+        match event {
+            Event::PeerConnected(id) => return Some(*id),
+            _ => return None,
+        }
+    }
+
+    fn shows_peer(event: &Event, peer: bool) -> bool {
+        match event {
+            Event::Status(status) => return status.peer.is_some() == peer,
+            _ => return false,
+        }
+    }
+
+    fn write(stream: &mut TcpStream, frame: &Frame) {
+        stream.write_all(&codec::encode(frame, MSG_SIZE)).unwrap();
+    }
+
+    #[test]
+    fn peer_connected_comes_before_the_status_showing_it() {
+        let (mut harness, addr) = Harness::server();
+        let _client = TcpStream::connect(&addr).unwrap();
+
+        let events = harness.until(|e| shows_peer(e, true));
+        let joined = position(&events, "PeerConnected", |e| connected(e).is_some());
+        assert!(joined < events.len() - 1);
+        assert!(harness.network.con.is_occupied());
+    }
+
+    #[test]
+    fn a_second_client_is_turned_away_while_occupied() {
+        let (mut harness, addr) = Harness::server();
+        let mut first = TcpStream::connect(&addr).unwrap();
+        write(&mut first, &Frame::Hello);
+        let events = harness.until(|e| connected(e).is_some());
+        let first_id = connected(events.last().unwrap());
+
+        // It says Hello like any client, which the server has to read before closing or the Busy frame may be lost.
+        let mut second = TcpStream::connect(&addr).unwrap();
+        write(&mut second, &Frame::Hello);
+        second.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buff = [0; MSG_SIZE];
+        let mut got = Vec::new();
+        loop {
+            assert!(
+                Instant::now() < deadline,
+                "the second client was never turned away"
+            );
+            assert!(harness.network.step().is_some());
+            match second.read(&mut buff) {
+                Ok(0) => break,
+                Ok(n) => got.extend_from_slice(&buff[..n]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+                Err(err) => panic!("the second client's connection was reset: {}", err),
+            }
+        }
+        assert_eq!(
+            got,
+            codec::encode(&Frame::Busy { position: None }, MSG_SIZE)
+        );
+
+        assert!(harness.events.try_iter().all(|e| connected(&e).is_none()));
+        assert!(harness.network.con.is_occupied());
+        assert_eq!(harness.network.con.peer().map(|p| p.id()), first_id);
+    }
+
+    #[test]
+    fn a_waiting_client_is_told_its_place_and_goes_next() {
+        let (mut harness, addr) =
+            Harness::server_with(ConnectionBuilder::new(MSG_SIZE).waiting_room(1));
+        let mut first = TcpStream::connect(&addr).unwrap();
+        write(&mut first, &Frame::Hello);
+        harness.until(|e| connected(e).is_some());
+
+        let mut second = TcpStream::connect(&addr).unwrap();
+        write(&mut second, &Frame::Hello);
+        assert_eq!(
+            harness.read(&mut second),
+            Some(Frame::Busy { position: Some(1) })
+        );
+
+        // The room only has space for one.
+        let mut third = TcpStream::connect(&addr).unwrap();
+        write(&mut third, &Frame::Hello);
+        assert_eq!(
+            harness.read(&mut third),
+            Some(Frame::Busy { position: None })
+        );
+        assert_eq!(harness.read(&mut third), None);
+
+        write(&mut first, &Frame::Disconnect);
+        let events = harness.until(|e| connected(e).is_some());
+        let next = connected(events.last().unwrap()).unwrap();
+        assert_eq!(next.to_string(), second.local_addr().unwrap().to_string());
+        // Its Hello was waiting with it, so the handshake completes straight away.
+        assert_eq!(harness.read(&mut second), Some(Frame::Hello));
+        assert!(!harness.network.con.handshake_pending());
+    }
+
+    #[test]
+    fn a_peer_that_leaves_frees_the_server_for_the_next() {
+        let (mut harness, addr) = Harness::server();
+        let mut first = TcpStream::connect(&addr).unwrap();
+        write(&mut first, &Frame::Hello);
+        write(&mut first, &Frame::Disconnect);
+
+        let events = harness.until(|e| match e {
+            Event::PeerDisconnected(..) => true,
+            _ => false,
+        });
+        let joined = position(&events, "PeerConnected", |e| connected(e).is_some());
+        let first_id = connected(&events[joined]);
+        match events.last() {
+            Some(Event::PeerDisconnected(id, reason)) => {
+                assert_eq!(Some(*id), first_id);
+                assert_eq!(*reason, Reason::Left);
+            }
+            _ => unreachable!(),
+        }
+        // The Status showing the server free comes after the PeerDisconnected event, never before it.
+        assert!(!events[joined..].iter().any(|e| shows_peer(e, false)));
+        harness.until(|e| shows_peer(e, false));
+        assert!(!harness.network.con.is_occupied());
+
+        let _second = TcpStream::connect(&addr).unwrap();
+        let events = harness.until(|e| connected(e).is_some());
+        let second_id = connected(events.last().unwrap());
+        assert!(second_id.is_some());
+        assert_ne!(second_id, first_id);
+    }
+
+    #[test]
+    fn a_client_that_is_not_off_the_record_is_refused() {
+        let (mut harness, addr) = Harness::server_with(ConnectionBuilder::new(MSG_SIZE).otr(true));
+        let mut client = TcpStream::connect(&addr).unwrap();
+        write(&mut client, &Frame::Hello);
+        assert_eq!(harness.read(&mut client), Some(Frame::Hello));
+        match harness.read(&mut client) {
+            Some(Frame::Otr { .. }) => (),
+            other => panic!("expected the server's key, got {:?}", other),
+        }
+        assert!(harness.network.con.handshake_pending());
+
+        // Anything but our key is in the clear, so it's not let through.
+        write(
+            &mut client,
+            &Frame::Text {
+                id: 1,
+                body: String::from("hi"),
+            },
+        );
+        assert_eq!(
+            harness.read(&mut client),
+            Some(Frame::Reject {
+                reason: String::from("This peer only talks off the record, start with --otr"),
+            })
+        );
+        assert_eq!(harness.read(&mut client), None);
+        assert!(harness.events.try_iter().all(|e| match e {
+            Event::Frame(Frame::Text { .. }) => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn a_disconnect_in_the_clear_does_not_end_an_off_the_record_session() {
+        let (mut harness, addr) = Harness::server_with(ConnectionBuilder::new(MSG_SIZE).otr(true));
+        let mut client = TcpStream::connect(&addr).unwrap();
+        write(&mut client, &Frame::Hello);
+        assert_eq!(harness.read(&mut client), Some(Frame::Hello));
+        let mut otr = Otr::new().unwrap();
+        match harness.read(&mut client) {
+            Some(Frame::Otr { key }) => otr.accept(&key).unwrap(),
+            other => panic!("expected the server's key, got {:?}", other),
+        }
+        write(&mut client, &Frame::Otr { key: otr.offer() });
+        harness.until(|e| shows_peer(e, true));
+
+        // Someone on the path forging our goodbye is refused, in a frame sealed for us.
+        write(&mut client, &Frame::Disconnect);
+        let reply = loop {
+            let opened = match harness.read(&mut client) {
+                Some(Frame::Binary { data }) => Frame::parse(&otr.open(&data).unwrap()).unwrap(),
+                other => panic!("expected a sealed frame, got {:?}", other),
+            };
+            match opened {
+                Frame::Ping { .. } => continue,
+                frame => break frame,
+            }
+        };
+        assert_eq!(
+            reply,
+            Frame::Reject {
+                reason: String::from("frame sent in the clear off the record"),
+            }
+        );
+        assert!(harness.network.con.peer().is_some());
+        assert!(harness.events.try_iter().all(|e| match e {
+            Event::PeerDisconnected(..) => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn a_full_queue_drops_its_oldest_message() {
+        let (mut harness, addr) =
+            Harness::server_with(ConnectionBuilder::new(MSG_SIZE).queue_cap(2));
+        let text = |id: u64| Frame::Text {
+            id: id,
+            body: format!("message {}", id),
+        };
+        for id in 1..=3 {
+            harness.outgoing.send(OutgoingMsg::Send(text(id))).unwrap();
+        }
+
+        let events = harness.until(|e| match e {
+            Event::NotSent(..) => true,
+            _ => false,
+        });
+        match events.last() {
+            Some(Event::NotSent(id, why)) => {
+                assert_eq!(*id, Some(1));
+                assert!(why.starts_with("Queue full"));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(harness.network.con.queued(), 2);
+        let journaled: Vec<Option<u64>> = harness
+            .network
+            .con
+            .journal
+            .pending()
+            .iter()
+            .map(|f| f.message_id())
+            .collect();
+        assert_eq!(journaled, vec![Some(2), Some(3)]);
+
+        let mut client = TcpStream::connect(&addr).unwrap();
+        write(&mut client, &Frame::Hello);
+        assert_eq!(harness.read(&mut client), Some(Frame::Hello));
+        assert_eq!(harness.read(&mut client), Some(text(2)));
+        assert_eq!(harness.read(&mut client), Some(text(3)));
+    }
+
+    #[test]
+    fn a_message_that_could_not_be_queued_is_not_journaled() {
+        let (mut harness, _) = Harness::server_with(ConnectionBuilder::new(MSG_SIZE).queue_cap(0));
+        let text = Frame::Text {
+            id: 1,
+            body: String::from("nobody is there"),
+        };
+        harness.outgoing.send(OutgoingMsg::Send(text)).unwrap();
+
+        harness.until(|e| match e {
+            Event::NotSent(Some(1), _) => true,
+            _ => false,
+        });
+        assert!(harness.network.con.journal.pending().is_empty());
+    }
+
+    #[test]
+    fn a_detached_connection_does_not_take_the_queue() {
+        let (mut con, _server) = ConnectionBuilder::new(MSG_SIZE)
+            .queue_cap(2)
+            .server("127.0.0.1:0")
+            .unwrap();
+        let (queued, _) = con.send_or_queue_frame(&Frame::Text {
+            id: 1,
+            body: String::from("for whoever connects"),
+        });
+        assert_eq!(queued, "Queued");
+
+        assert_eq!(con.detached().queued(), 0);
+        assert_eq!(con.queued(), 1);
+    }
+
+    #[test]
+    fn a_flood_of_frames_is_received_a_few_at_a_time() {
+        let (mut harness, addr) = Harness::server();
+        let mut client = TcpStream::connect(&addr).unwrap();
+        write(&mut client, &Frame::Hello);
+        assert_eq!(harness.read(&mut client), Some(Frame::Hello));
+        harness.until(|e| shows_peer(e, true));
+
+        for id in 1..=MAX_RECEIVED as u64 * 2 {
+            write(
+                &mut client,
+                &Frame::Text {
+                    id: id,
+                    body: format!("message {}", id),
+                },
+            );
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert!(harness.network.step().is_some());
+        let received = harness
+            .events
+            .try_iter()
+            .filter(|e| match e {
+                Event::Frame(_) => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(received, MAX_RECEIVED);
+    }
+
+    #[test]
+    fn a_vectored_write_cut_short_picks_up_where_it_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+        sender.set_nonblocking(true).unwrap();
+        // More than the socket buffers hold, so writes block and stop partway through a piece.
+        let pieces: Vec<Vec<u8>> = (0..600u32)
+            .map(|i| (0..i * 7 % 4001).map(|j| (i + j) as u8).collect())
+            .collect();
+        let expected: Vec<u8> = pieces.concat();
+        let reader = thread::spawn(move || {
+            let mut read = Vec::new();
+            receiver.read_to_end(&mut read).unwrap();
+            return read;
+        });
+
+        let parts: Vec<&[u8]> = pieces.iter().map(|p| p.as_slice()).collect();
+        write_frames(&sender, &parts).unwrap();
+        drop(sender);
+        assert!(reader.join().unwrap() == expected);
+    }
+
+    #[test]
+    fn a_client_that_lost_the_server_never_looks_like_one() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let con = ConnectionBuilder::new(MSG_SIZE).client(&addr).unwrap();
+        let mut harness =
+            Harness::new(|outgoing, events| Network::new(con, None, outgoing, events));
+        let (server, _) = listener.accept().unwrap();
+        drop(server);
+
+        let events = harness.until(|e| match e {
+            Event::PeerDisconnected(_, Reason::Closed) => true,
+            _ => false,
+        });
+        assert!(!events.is_empty());
+        assert!(!harness.network.con.is_server());
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+
+extern crate base64;
+extern crate chacha20;
+extern crate hmac;
+extern crate sha2;
+extern crate x25519_dalek;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::random::random;
+
+/// How many bytes a key, a chain's or a message's, has.
+const KEY_LEN: usize = 32;
+
+/// How many bytes of HMAC-SHA256 are kept to check a sealed frame.
+const TAG_LEN: usize = 16;
+
+/// The bytes a sealed frame starts with, its `G ` tag, its counter and how many MAC keys it reveals.
+const SEALED_HEADER: usize = 2 + 8 + 1;
+
+/// How many message keys are kept for frames that haven't arrived yet, e.g. ones a bad network dropped.
+const MAX_SKIPPED: u64 = 64;
+
+/// The Keys of one sealed frame, each used once and then forgotten, apart from the MAC key which is revealed.
+///
+/// # Fields
+/// `enc` - The ChaCha20 key the frame is encrypted with.
+/// `mac` - The HMAC-SHA256 key the frame is checked with.
+struct Keys {
+    enc: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+/// A Chain which is one direction of a session, a hash ratchet that gives every frame its own keys.
+///
+/// Each step hashes the chain key forward and forgets the old one, so keys taken from the chain now can't be worked
+/// out from it later.
+///
+/// # Fields
+/// `key` - The chain key the next frame's keys come from.
+/// `next` - The counter of the next frame.
+/// `skipped` - The keys of frames that were skipped over, by counter, so they can still be opened if they arrive.
+struct Chain {
+    key: [u8; KEY_LEN],
+    next: u64,
+    skipped: HashMap<u64, Keys>,
+}
+
+impl Chain {
+    /// Starts a chain from a key.
+    fn new(key: [u8; KEY_LEN]) -> Chain {
+        return Chain {
+            key: key,
+            next: 0,
+            skipped: HashMap::new(),
+        };
+    }
+
+    /// Takes the next frame's keys and moves the chain on.
+    fn step(&mut self) -> Keys {
+        let keys = Keys {
+            enc: hash(&[&self.key, &[1]]),
+            mac: hash(&[&self.key, &[2]]),
+        };
+        self.key = hash(&[&self.key, &[3]]);
+        self.next += 1;
+        return keys;
+    }
+
+    /// Takes the keys of the frame with the given counter, keeping those of the frames skipped to get there.
+    ///
+    /// # Returns
+    /// `Option<Keys>` - the keys, None for a frame that was already opened or is too far ahead.
+    fn keys_for(&mut self, counter: u64) -> Option<Keys> {
+        if counter < self.next {
+            return self.skipped.remove(&counter);
+        }
+        if counter - self.next > MAX_SKIPPED {
+            return None;
+        }
+        while self.next < counter {
+            let skipped = self.next;
+            let keys = self.step();
+            self.skipped.insert(skipped, keys);
+        }
+        // Frames dropped long ago won't arrive any more, their keys go once there are too many.
+        let oldest = counter.saturating_sub(MAX_SKIPPED);
+        self.skipped.retain(|&c, _| c >= oldest);
+        return Some(self.step());
+    }
+}
+
+/// An Otr which is the off-the-record session with the current peer.
+///
+/// Each side makes a new X25519 key for every peer and sends it with an Otr frame as part of the handshake. Once
+/// both keys are known the shared secret starts a hash ratchet each way and the X25519 secret is forgotten, so
+/// frames sent earlier can't be opened even with everything either side holds later. Every frame after that is
+/// encrypted with ChaCha20 and checked with HMAC-SHA256 under keys of its own, and sent as a Binary frame. The MAC
+/// key of each frame that was opened is revealed with the next frames we send: the peer knew it anyway, and once
+/// anyone could have made the MAC a transcript doesn't prove who wrote what.
+///
+/// The keys aren't signed, so someone in the middle could swap them for their own. Both sides show the session's
+/// fingerprint, made from both keys, for the people on either end to compare over another channel.
+///
+/// # Fields
+/// `secret` - Our X25519 secret for this session, None once the keys are derived.
+/// `public` - Our X25519 public key for this session.
+/// `offered` - Whether our key was sent to the peer.
+/// `sending` - The chain our frames are sealed with, None until the peer's key arrives.
+/// `receiving` - The chain the peer's frames are opened with, None until the peer's key arrives.
+/// `reveal` - The MAC keys of frames we opened that haven't been revealed yet.
+/// `fingerprint` - The session's fingerprint, None until the peer's key arrives.
+pub struct Otr {
+    secret: Option<StaticSecret>,
+    public: PublicKey,
+    offered: bool,
+    sending: Option<Chain>,
+    receiving: Option<Chain>,
+    reveal: Vec<[u8; KEY_LEN]>,
+    fingerprint: Option<String>,
+}
+
+/// SHA-256 of the parts one after another.
+fn hash(parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    return hasher.finalize().into();
+}
+
+/// HMAC-SHA256 of a message, to cut to TAG_LEN bytes or check a tag with, which takes the same time whatever the
+/// tag holds so timing doesn't give away how much of it was right.
+fn mac(key: &[u8; KEY_LEN], msg: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(msg);
+    return mac;
+}
+
+/// Encrypts or decrypts data in place with a key used for nothing else.
+fn apply_keystream(key: &[u8; KEY_LEN], data: &mut [u8]) {
+    let mut cipher = ChaCha20::new(key.into(), &[0; 12].into());
+    cipher.apply_keystream(data);
+}
+
+impl Otr {
+    /// Creates a new Otr session with a fresh key, not yet offered to anyone.
+    ///
+    /// # Returns
+    /// `io::Result<Otr>` - the session, or why no randomness could be read.
+    pub fn new() -> io::Result<Otr> {
+        let secret = StaticSecret::from(random::<KEY_LEN>()?);
+        let public = PublicKey::from(&secret);
+        return Ok(Otr {
+            secret: Some(secret),
+            public: public,
+            offered: false,
+            sending: None,
+            receiving: None,
+            reveal: Vec::new(),
+            fingerprint: None,
+        });
+    }
+
+    /// Forgets the session and starts a new one with a fresh key, for the next peer.
+    ///
+    /// Called on an Otr, if no randomness can be read the session is left without a secret so its handshake fails.
+    pub fn restart(&mut self) {
+        *self = Otr::new().unwrap_or_else(|_| Otr {
+            secret: None,
+            public: self.public,
+            offered: false,
+            sending: None,
+            receiving: None,
+            reveal: Vec::new(),
+            fingerprint: None,
+        });
+    }
+
+    /// Gives our key for this session to send in an Otr frame.
+    ///
+    /// Called on an Otr, marks it as offered.
+    ///
+    /// # Returns
+    /// `String` - the base64 public key.
+    pub fn offer(&mut self) -> String {
+        self.offered = true;
+        return STANDARD.encode(self.public.as_bytes());
+    }
+
+    /// Accessor method for whether our key was sent to the peer.
+    ///
+    /// # Returns
+    /// `bool` - true once `offer` was called for this session.
+    pub fn offered(&self) -> bool {
+        return self.offered;
+    }
+
+    /// Accessor method for whether both keys are known, so frames are sealed.
+    ///
+    /// # Returns
+    /// `bool` - true once `accept` succeeded.
+    pub fn established(&self) -> bool {
+        return self.sending.is_some();
+    }
+
+    /// Accessor method for the session's fingerprint, the same on both sides unless someone swapped the keys.
+    ///
+    /// # Returns
+    /// `Option<&str>` - the fingerprint, e.g. `1a2b 3c4d 5e6f 7a8b`, None until `accept` succeeded.
+    pub fn fingerprint(&self) -> Option<&str> {
+        return self.fingerprint.as_deref();
+    }
+
+    /// Takes the peer's key for this session and starts the chains, forgetting our secret.
+    ///
+    /// # Arguments
+    /// * `key` - A &str of the base64 public key from the peer's Otr frame.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok once frames can be sealed, or why the key was refused.
+    pub fn accept(&mut self, key: &str) -> Result<(), String> {
+        let bytes: [u8; KEY_LEN] = STANDARD
+            .decode(key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| String::from("bad off-the-record key"))?;
+        let theirs = PublicKey::from(bytes);
+        if theirs.as_bytes() == self.public.as_bytes() {
+            return Err(String::from(
+                "the peer sent our own off-the-record key back",
+            ));
+        }
+        let secret = self
+            .secret
+            .take()
+            .ok_or_else(|| String::from("no off-the-record session to accept a key for"))?;
+        let shared = secret.diffie_hellman(&theirs);
+        if !shared.was_contributory() {
+            return Err(String::from("weak off-the-record key"));
+        }
+
+        // The lower key's chain is the first one, so both sides agree which way each chain goes.
+        let ours = *self.public.as_bytes();
+        let (low, high) = if ours < bytes {
+            (ours, bytes)
+        } else {
+            (bytes, ours)
+        };
+        let root = hash(&[b"r2wc otr", shared.as_bytes(), &low, &high]);
+        let digest = hash(&[b"r2wc otr fingerprint", &low, &high]);
+        let groups: Vec<String> = digest[..8]
+            .chunks(2)
+            .map(|c| format!("{:02x}{:02x}", c[0], c[1]))
+            .collect();
+        self.fingerprint = Some(groups.join(" "));
+        let first = Chain::new(hash(&[&root, &[1]]));
+        let second = Chain::new(hash(&[&root, &[2]]));
+        if ours == low {
+            self.sending = Some(first);
+            self.receiving = Some(second);
+        } else {
+            self.sending = Some(second);
+            self.receiving = Some(first);
+        }
+        return Ok(());
+    }
+
+    /// Seals the bytes of an encoded frame into the bytes of the Binary frame that carries it.
+    ///
+    /// Called on an established Otr, as many MAC keys as still fit in `msg_size` bytes are revealed with it. A frame
+    /// that doesn't fit once sealed is refused rather than cut, and doesn't move the chain on.
+    ///
+    /// # Arguments
+    /// * `payload` - A &[u8] of the encoded frame.
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    ///
+    /// # Returns
+    /// `Result<Vec<u8>, String>` - the encoded Binary frame, or the payload as it is if the session isn't
+    /// established, or why it can't be sealed.
+    pub fn seal(&mut self, payload: &[u8], msg_size: usize) -> Result<Vec<u8>, String> {
+        let sending = match &mut self.sending {
+            Some(sending) => sending,
+            None => return Ok(payload.to_vec()),
+        };
+        let room = msg_size.saturating_sub(SEALED_HEADER + TAG_LEN);
+        let len = payload.len();
+        if len > room {
+            return Err(format!(
+                "{} bytes is too long to seal off the record, {} fit",
+                len, room
+            ));
+        }
+        let revealed = ((room - len) / KEY_LEN)
+            .min(self.reveal.len())
+            .min(u8::MAX as usize);
+
+        let counter = sending.next;
+        let keys = sending.step();
+        let mut sealed = Vec::with_capacity(SEALED_HEADER + revealed * KEY_LEN + len + TAG_LEN);
+        sealed.extend_from_slice(b"G ");
+        sealed.extend_from_slice(&counter.to_be_bytes());
+        sealed.push(revealed as u8);
+        for key in self.reveal.drain(..revealed) {
+            sealed.extend_from_slice(&key);
+        }
+        let start = sealed.len();
+        sealed.extend_from_slice(payload);
+        apply_keystream(&keys.enc, &mut sealed[start..]);
+        let tag = mac(&keys.mac, &sealed[2..]).finalize().into_bytes();
+        sealed.extend_from_slice(&tag[..TAG_LEN]);
+        return Ok(sealed);
+    }
+
+    /// Opens the data of a Binary frame the peer sealed.
+    ///
+    /// Called on an established Otr, a frame that was tampered with, replayed or sealed for another session is
+    /// refused and doesn't move the chain on.
+    ///
+    /// # Arguments
+    /// * `data` - A &[u8] of the Binary frame's data.
+    ///
+    /// # Returns
+    /// `Option<Vec<u8>>` - the bytes of the frame that was sealed, or None if it can't be opened.
+    pub fn open(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let receiving = self.receiving.as_mut()?;
+        if data.len() < SEALED_HEADER - 2 + TAG_LEN {
+            return None;
+        }
+        let (body, tag) = data.split_at(data.len() - TAG_LEN);
+        let counter = u64::from_be_bytes(body[..8].try_into().unwrap());
+        let start = 9 + body[8] as usize * KEY_LEN;
+        if start > body.len() {
+            return None;
+        }
+
+        // The keys are only used up once the frame checks out, so a forged frame can't skip the real ones.
+        let mut probe = Chain {
+            key: receiving.key,
+            next: receiving.next,
+            skipped: HashMap::new(),
+        };
+        let keys = match receiving.skipped.get(&counter) {
+            Some(keys) => Keys {
+                enc: keys.enc,
+                mac: keys.mac,
+            },
+            None if counter >= receiving.next => probe.keys_for(counter)?,
+            None => return None,
+        };
+        if mac(&keys.mac, body).verify_truncated_left(tag).is_err() {
+            return None;
+        }
+        receiving.keys_for(counter);
+
+        let mut plain = body[start..].to_vec();
+        apply_keystream(&keys.enc, &mut plain);
+        self.reveal.push(keys.mac);
+        return Some(plain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two sessions that took each other's keys.
+    fn pair() -> (Otr, Otr) {
+        let mut a = Otr::new().unwrap();
+        let mut b = Otr::new().unwrap();
+        let (a_key, b_key) = (a.offer(), b.offer());
+        a.accept(&b_key).unwrap();
+        b.accept(&a_key).unwrap();
+        return (a, b);
+    }
+
+    #[test]
+    fn sealed_frames_open_on_the_other_side_only_once() {
+        let (mut a, mut b) = pair();
+        let first = a.seal(b"T 1 hello", 255).unwrap();
+        let second = a.seal(b"T 2 there", 255).unwrap();
+        assert!(first.starts_with(b"G "));
+        assert_ne!(first[2..], second[2..]);
+
+        // The second arrives first, the first can still be opened with the key kept for it.
+        assert_eq!(b.open(&second[2..]), Some(b"T 2 there".to_vec()));
+        assert_eq!(b.open(&first[2..]), Some(b"T 1 hello".to_vec()));
+        assert_eq!(b.open(&first[2..]), None);
+        assert!(a.fingerprint().is_some());
+        assert_eq!(a.fingerprint(), b.fingerprint());
+
+        // Our own frames don't open with our receiving chain.
+        let back = b.seal(b"T 1 hi", 255).unwrap();
+        assert_eq!(b.open(&back[2..]), None);
+        assert_eq!(a.open(&back[2..]), Some(b"T 1 hi".to_vec()));
+    }
+
+    #[test]
+    fn a_tampered_frame_is_refused_without_losing_the_real_one() {
+        let (mut a, mut b) = pair();
+        let sealed = a.seal(b"T 1 pay alice", 255).unwrap();
+        let mut forged = sealed.clone();
+        let last = forged.len() - TAG_LEN - 1;
+        forged[last] ^= 1;
+        assert_eq!(b.open(&forged[2..]), None);
+        assert_eq!(b.open(&sealed[2..]), Some(b"T 1 pay alice".to_vec()));
+    }
+
+    #[test]
+    fn mac_keys_are_revealed_once_used_and_frames_fit() {
+        let (mut a, mut b) = pair();
+        let sealed = a.seal(b"T 1 hello", 255).unwrap();
+        b.open(&sealed[2..]).unwrap();
+        let mac_key = b.reveal[0];
+
+        let answer = b.seal(&[b'x'; 255 - SEALED_HEADER - TAG_LEN], 255).unwrap();
+        assert_eq!(answer.len(), 255);
+        // A frame that fills the room has none left for the key, the next one carries it.
+        assert_eq!(answer[10], 0);
+        let answer = b.seal(b"R 1", 255).unwrap();
+        assert_eq!(answer[10], 1);
+        assert_eq!(answer[11..11 + KEY_LEN], mac_key);
+        assert_eq!(a.open(&answer[2..]), Some(b"R 1".to_vec()));
+    }
+
+    #[test]
+    fn a_frame_too_long_to_seal_is_refused_not_cut() {
+        let (mut a, mut b) = pair();
+        assert!(a.seal(&[b'x'; 300], 255).is_err());
+        // Nothing was used up, the next frame is the first the peer opens.
+        let sealed = a.seal(b"T 1 hello", 255).unwrap();
+        assert_eq!(sealed[2..10], 0u64.to_be_bytes());
+        assert_eq!(b.open(&sealed[2..]), Some(b"T 1 hello".to_vec()));
+    }
+
+    #[test]
+    fn keys_swapped_in_the_middle_show_in_the_fingerprint() {
+        let (mut a, mut b) = (Otr::new().unwrap(), Otr::new().unwrap());
+        let (mut to_a, mut to_b) = (Otr::new().unwrap(), Otr::new().unwrap());
+        a.accept(&to_a.offer()).unwrap();
+        to_a.accept(&a.offer()).unwrap();
+        b.accept(&to_b.offer()).unwrap();
+        to_b.accept(&b.offer()).unwrap();
+        assert_ne!(a.fingerprint(), b.fingerprint());
+    }
+}
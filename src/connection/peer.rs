@@ -1,28 +1,79 @@
-use std::net::{TcpListener, TcpStream};
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
-/// A Peer which holds the Stream to conenct them by and who it is.
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use x25519_dalek::PublicKey;
+
+use super::filter::AcceptFilter;
+use crate::protocol::codec::FrameReader;
+
+/// The serial the next Peer gets, so no two peers share a PeerId.
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
+
+/// A PeerId which names one peer, kept after it disconnects so events and later calls can refer to it.
+///
+/// # Fields
+/// `serial` - Which peer this is, never reused for another one.
+/// `addr` - The peer's address, shown when the PeerId is displayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerId {
+    serial: u64,
+    addr: SocketAddr,
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.addr);
+    }
+}
+
+/// A Peer which holds the Stream to conenct them by, who it is and the part of a frame it has sent so far.
+///
+/// # Fields
+/// `id` - The PeerId naming the peer.
+/// `stream` - The TcpStream to the peer.
+/// `who` - How the peer is shown, its address or "Server".
+/// `addr` - The peer's address, the other client's rather than the rendezvous server's when it relays for us.
+/// `connected_at` - When the peer connected.
+/// `nick` - The nick the peer announced, None until it does.
+/// `identity_key` - The public key the peer announced with its nick, None until it does or if it wasn't valid.
+/// `received` - The part of a frame received so far.
 pub struct Peer {
+    id: PeerId,
     stream: TcpStream,
     who: String,
+    addr: SocketAddr,
+    connected_at: Instant,
+    nick: Option<String>,
+    identity_key: Option<PublicKey>,
+    received: FrameReader,
 }
 
 impl Peer {
     /// Creates a new Option<Peer>, by grabbing one from given server.
     ///
+    /// Clients the filter doesn't allow are disconnected straight away.
+    ///
     /// # Arguments
     /// * `server` - A &TcpListener so we can accept a connection.
+    /// * `filter` - A &AcceptFilter of which IPs may connect.
     ///
     /// # Returns
-    ///  `Option<Peer>` - A peer if one was grabbed from the server TcpListener.
-    pub fn get_client(server: &TcpListener) -> Option<Peer> {
+    ///  `Option<Peer>` - A peer if one was grabbed from the server TcpListener, is allowed and could be made nonblocking.
+    pub fn get_client(server: &TcpListener, filter: &AcceptFilter) -> Option<Peer> {
         if let Ok((stream, addr)) = server.accept() {
-            stream
-                .set_nonblocking(true)
-                .expect("failed to initiate non-blocking");
-            return Some(Peer {
-                stream: stream,
-                who: format!("{}", addr),
-            });
+            if !filter.allows(&addr.ip()) {
+                return None;
+            }
+            if stream.set_nonblocking(true).is_err() {
+                return None;
+            }
+            return Some(Peer::new(stream, addr, format!("{}", addr)));
         }
 
         return None;
@@ -32,17 +83,53 @@ impl Peer {
     ///
     /// # Arguments
     /// * `stream` - A TcpStream to store to communicate witht he peer.
+    /// * `addr` - A SocketAddr of the peer.
     /// * `who` - A String that represents who the peer may be.
     ///
     /// # Returns
-    ///  `Peer` - the newly created a peer.
-    pub fn new(stream: TcpStream, who: String) -> Peer {
+    ///  `Peer` - the newly created a peer, connected as of now.
+    pub fn new(stream: TcpStream, addr: SocketAddr, who: String) -> Peer {
         return Peer {
+            id: PeerId {
+                serial: NEXT_SERIAL.fetch_add(1, Ordering::Relaxed),
+                addr: addr,
+            },
             stream: stream,
             who: who,
+            addr: addr,
+            connected_at: Instant::now(),
+            nick: None,
+            identity_key: None,
+            received: FrameReader::new(),
         };
     }
 
+    /// Records the nick and key the Peer announced in a Nick frame.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the nick.
+    /// * `key` - A &str of the base64 public key, one that isn't a valid key is left out.
+    pub fn set_identity(&mut self, nick: &str, key: &str) {
+        self.nick = Some(String::from(nick));
+        self.identity_key = STANDARD
+            .decode(key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .map(|b: [u8; 32]| PublicKey::from(b));
+    }
+
+    /// Accessor method for a Peer's PeerId.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `PeerId` - the PeerId naming the Peer.
+    pub fn id(&self) -> PeerId {
+        return self.id;
+    }
+
     /// Accessor method for a Peer's TcpStream.
     ///
     /// Called on a Peer.
@@ -53,6 +140,27 @@ impl Peer {
         return &self.stream;
     }
 
+    /// Reads the rest of the frame the Peer is sending.
+    ///
+    /// Called on a Peer, see `FrameReader::read_frame`.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    ///
+    /// # Returns
+    ///  `io::Result<Option<Vec<u8>>>` - the frame once all of it has arrived, None if the socket would block first.
+    pub fn read_frame(&mut self, msg_size: usize) -> io::Result<Option<Vec<u8>>> {
+        return self.received.read_frame(&self.stream, msg_size);
+    }
+
+    /// Gives back a frame from `read_frame` once it has been parsed, see `FrameReader::recycle`.
+    ///
+    /// # Arguments
+    /// * `buff` - A Vec<u8> of the frame.
+    pub fn recycle(&mut self, buff: Vec<u8>) {
+        self.received.recycle(buff);
+    }
+
     /// Accessor method for a Peer's TcpStream.
     ///
     /// Called on a Peer.
@@ -62,17 +170,54 @@ impl Peer {
     pub fn who(&self) -> &String {
         return &self.who;
     }
-}
 
-/// Clones a Peer by returning a new instance of one.
-impl Clone for Peer {
-    fn clone(&self) -> Peer {
-        Peer {
-            stream: self
-                .stream()
-                .try_clone()
-                .expect("Could not clone TcpStream."),
-            who: self.who().clone(),
-        }
+    /// Accessor method for a Peer's address.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `SocketAddr` - the Peer's address.
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    /// Accessor method for when a Peer connected.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `Instant` - when the Peer connected.
+    pub fn connected_at(&self) -> Instant {
+        return self.connected_at;
+    }
+
+    /// Accessor method for a Peer's nick.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `Option<&str>` - the nick the Peer announced, None if it hasn't.
+    pub fn nick(&self) -> Option<&str> {
+        return self.nick.as_deref();
+    }
+
+    /// Accessor method for a Peer's public key.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `Option<PublicKey>` - the key the Peer announced with its nick, None if it hasn't or it wasn't valid.
+    pub fn identity_key(&self) -> Option<PublicKey> {
+        return self.identity_key;
+    }
+
+    /// Accessor method for the IP address of a Peer.
+    ///
+    /// Called on a Peer.
+    ///
+    /// # Returns
+    ///  `Option<IpAddr>` - the Peer's IP, None if the socket is no longer connected.
+    pub fn ip(&self) -> Option<IpAddr> {
+        return self.stream.peer_addr().ok().map(|addr| addr.ip());
     }
 }
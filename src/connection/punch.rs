@@ -0,0 +1,178 @@
+use std::io::{self, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate socket2;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::protocol::{codec, Frame};
+
+/// How long to keep trying to reach the other peer directly before falling back to the rendezvous relay.
+const PUNCH_TIME: Duration = Duration::from_secs(5);
+
+/// How long a single direct connection attempt may take.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A Meeting which is how we ended up connected to the other peer in a room.
+///
+/// # Fields
+/// `stream` - The stream to the peer, or to the rendezvous server relaying for us.
+/// `peer` - The address the rendezvous server saw the peer connect from.
+/// `relayed` - true if hole punching failed and the rendezvous server passes our traffic on.
+pub struct Meeting {
+    pub stream: TcpStream,
+    pub peer: SocketAddr,
+    pub relayed: bool,
+}
+
+/// Makes a TCP socket that can share its local port with our other sockets, so the NAT mapping the
+/// rendezvous server saw is the one our direct connection attempts use.
+fn reusable_socket(addr: &SocketAddr) -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::for_address(*addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    return Ok(socket);
+}
+
+/// The any address of the same family as `addr`, on the given port.
+fn any_addr(addr: &SocketAddr, port: u16) -> SocketAddr {
+    let ip = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    return SocketAddr::new(ip, port);
+}
+
+/// Writes one frame with its length in front.
+fn write_frame(mut stream: &TcpStream, msg_size: usize, frame: &Frame) -> io::Result<()> {
+    return stream.write_all(&codec::encode(frame, msg_size));
+}
+
+/// Reads one frame of at most `msg_size` bytes, treating a malformed one as an error.
+fn read_frame(stream: &TcpStream, msg_size: usize) -> io::Result<Frame> {
+    let buff = codec::read_blocking(stream, msg_size)?;
+    return codec::decode(&buff).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()));
+}
+
+/// Meets the other peer in a room through a rendezvous server.
+///
+/// Waits until someone else joins the room, then tries a simultaneous TCP open to the address the server saw
+/// them connect from. If that doesn't work within a few seconds, asks the server to relay for us instead. The
+/// server can read relayed traffic.
+///
+/// # Arguments
+/// * `server` - A &str of the rendezvous server's host:port.
+/// * `room` - A &str of the room name both peers agreed on, a single word.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+///
+/// # Returns
+/// `io::Result<Meeting>` - how we are connected to the peer, or why we couldn't meet them.
+pub fn meet(server: &str, room: &str, msg_size: usize) -> io::Result<Meeting> {
+    let server = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "rendezvous server not found"))?;
+    let socket = reusable_socket(&server)?;
+    socket.bind(&any_addr(&server, 0).into())?;
+    socket.connect(&server.into())?;
+    let rendezvous: TcpStream = socket.into();
+    let port = rendezvous.local_addr()?.port();
+
+    write_frame(
+        &rendezvous,
+        msg_size,
+        &Frame::Meet {
+            room: String::from(room),
+        },
+    )?;
+    let (peer, listen) = match read_frame(&rendezvous, msg_size)? {
+        Frame::Punch { addr, listen } => match addr.parse::<SocketAddr>() {
+            Ok(peer) => (peer, listen),
+            Err(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("bad peer address {}", addr),
+                ))
+            }
+        },
+        Frame::Reject { reason } => return Err(io::Error::new(ErrorKind::Other, reason)),
+        frame => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected frame {}", frame.encode()),
+            ))
+        }
+    };
+
+    if let Some(stream) = punch(port, &peer, listen) {
+        // Closing our side tells the rendezvous server we don't need it any more.
+        return Ok(Meeting {
+            stream: stream,
+            peer: peer,
+            relayed: false,
+        });
+    }
+
+    write_frame(&rendezvous, msg_size, &Frame::Hello)?;
+    return Ok(Meeting {
+        stream: rendezvous,
+        peer: peer,
+        relayed: true,
+    });
+}
+
+/// Connects to the peer from our rendezvous port while they connect to us from theirs, so both NATs see
+/// outgoing traffic and let the other side's SYN in.
+///
+/// # Arguments
+/// * `port` - A u16 of the local port the rendezvous connection uses.
+/// * `peer` - A &SocketAddr of the peer's address as the rendezvous server saw it.
+/// * `listen` - A bool of whether to also accept the peer's connection, for when there is no NAT in the way
+/// and their SYN is refused before ours goes out.
+///
+/// # Returns
+/// `Option<TcpStream>` - the direct connection, or None if the peer couldn't be reached in time.
+fn punch(port: u16, peer: &SocketAddr, listen: bool) -> Option<TcpStream> {
+    let deadline = Instant::now() + PUNCH_TIME;
+    let listener = if listen {
+        reusable_socket(peer)
+            .and_then(|socket| {
+                socket.bind(&any_addr(peer, port).into())?;
+                socket.listen(1)?;
+                socket.set_nonblocking(true)?;
+                return Ok(socket);
+            })
+            .ok()
+    } else {
+        None
+    };
+
+    while Instant::now() < deadline {
+        if let Some(Ok((socket, from))) = listener.as_ref().map(|l| l.accept()) {
+            let from = from.as_socket().map(|a| a.ip());
+            if from == Some(peer.ip()) && socket.set_nonblocking(false).is_ok() {
+                return Some(socket.into());
+            }
+        }
+
+        let started = Instant::now();
+        let attempt = reusable_socket(peer).and_then(|socket| {
+            socket.bind(&any_addr(peer, port).into())?;
+            socket.connect_timeout(&(*peer).into(), ATTEMPT_TIMEOUT)?;
+            return Ok(socket);
+        });
+        match attempt {
+            Ok(socket) => return Some(socket.into()),
+            // Refused straight away means their SYN hasn't opened the way yet, don't spin.
+            Err(_) => thread::sleep(Duration::from_millis(200).saturating_sub(started.elapsed())),
+        }
+    }
+
+    return None;
+}
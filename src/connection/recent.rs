@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+/// How many message ids are remembered per peer.
+const RECENT_IDS: usize = 256;
+
+/// A RecentIds which remembers the ids of a peer's latest messages, so a message that arrives twice, e.g. when it
+/// was sent again because its ack was lost, is only passed on once.
+///
+/// # Fields
+/// `ids` - The ids seen, oldest first.
+pub struct RecentIds {
+    ids: VecDeque<u64>,
+}
+
+impl RecentIds {
+    /// Creates a new RecentIds that hasn't seen any ids.
+    ///
+    /// # Returns
+    /// `RecentIds` - the newly created ids.
+    pub fn new() -> RecentIds {
+        return RecentIds {
+            ids: VecDeque::new(),
+        };
+    }
+
+    /// Remembers a message id, forgetting the oldest once `RECENT_IDS` are remembered.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the message's id.
+    ///
+    /// # Returns
+    /// `bool` - false if the id was seen before and the message is a duplicate.
+    pub fn first_seen(&mut self, id: u64) -> bool {
+        if self.ids.contains(&id) {
+            return false;
+        }
+        if self.ids.len() == RECENT_IDS {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(id);
+        return true;
+    }
+
+    /// Forgets every id, for when a new peer takes over and starts its ids over.
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{escape, unescape};
+use crate::protocol::codec;
+
+/// A Recorder which writes every frame sent to and received from the peer to a file, with when, so `replay` can
+/// play the session back.
+///
+/// Each line is the ms since recording started, `<` for a received frame or `>` for a sent one, and the frame as it
+/// was on the wire without its length. Clones share the same file and clock.
+///
+/// # Fields
+/// `file` - The recording.
+/// `start` - When recording started.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Starts a recording, replacing the file if it already exists.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the file to record to.
+    ///
+    /// # Returns
+    /// `io::Result<Recorder>` - the recorder, or why the file could not be created.
+    pub fn create(path: &str) -> io::Result<Recorder> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        return Ok(Recorder {
+            file: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+        });
+    }
+
+    /// Records a frame read off the socket, even one that doesn't parse.
+    ///
+    /// # Arguments
+    /// * `buff` - A &[u8] of the frame without its length.
+    pub(super) fn received(&self, buff: &[u8]) {
+        self.write('<', buff);
+    }
+
+    /// Records a frame written to the socket.
+    ///
+    /// # Arguments
+    /// * `buff` - A &[u8] of the frame without its length.
+    pub(super) fn sent(&self, buff: &[u8]) {
+        self.write('>', buff);
+    }
+
+    /// Appends a frame to the recording, each in a single write so a crash can't leave half a line.
+    fn write(&self, direction: char, buff: &[u8]) {
+        let line = format!(
+            "{} {} {}\n",
+            self.start.elapsed().as_millis(),
+            direction,
+            escape(&String::from_utf8_lossy(buff))
+        );
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        // A full disk shouldn't take down the chat, the frame is only missing from the recording.
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+/// Plays a recording back as if it were the peer, on a local port a client connects to like any server.
+///
+/// The frames the recording received are sent at the times they were received, and whatever the client sends is
+/// read and dropped. The connection stays open after the last frame until the client closes it.
+///
+/// # Arguments
+/// * `path` - A &str of the recording.
+/// * `speed` - A f64 of how many times faster than recorded to play, 0 for as fast as possible.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+///
+/// # Returns
+/// `io::Result<String>` - the host:port to connect to, or why the recording could not be read.
+pub fn replay(path: &str, speed: f64, msg_size: usize) -> io::Result<String> {
+    let mut frames = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let mut parts = line.splitn(3, ' ');
+        if let (Some(ms), Some("<"), Some(msg)) = (parts.next(), parts.next(), parts.next()) {
+            if let Ok(ms) = ms.parse::<u64>() {
+                frames.push((Duration::from_millis(ms), unescape(msg)));
+            }
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || {
+        if let Ok((stream, _)) = listener.accept() {
+            play(stream, frames, speed, msg_size);
+        }
+    });
+    return Ok(addr.to_string());
+}
+
+/// Sends the recorded frames to the client that connected to `replay`, then waits for it to close.
+fn play(stream: TcpStream, frames: Vec<(Duration, String)>, speed: f64, msg_size: usize) {
+    // The client's frames are only read so it never blocks on a full socket.
+    let drain = match stream.try_clone() {
+        Ok(mut reader) => thread::spawn(move || {
+            let mut buff = [0; 4096];
+            while let Ok(n) = reader.read(&mut buff) {
+                if n == 0 {
+                    return;
+                }
+            }
+        }),
+        Err(_) => return,
+    };
+
+    let start = Instant::now();
+    let mut stream = stream;
+    for (at, msg) in frames {
+        if speed > 0.0 {
+            let due = at.div_f64(speed);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        if stream
+            .write_all(&codec::prefix(msg.as_bytes(), msg_size))
+            .is_err()
+        {
+            return;
+        }
+    }
+    let _ = drain.join();
+}
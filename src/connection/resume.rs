@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::invites::random_token;
+use super::{Connection, Peer};
+use crate::protocol::Frame;
+
+/// How long a client waits between tries to connect again.
+const RETRY_EVERY: Duration = Duration::from_secs(2);
+
+/// How long each try to connect again may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// ResumeTokens which let a client that lost its connection come back as the same session, without joining again.
+///
+/// Each client is given a token once it completes the handshake, which it can use once, while it is still connected
+/// or until `ttl` after its connection was lost. Clones share the same tokens.
+///
+/// # Fields
+/// `tokens` - Unused tokens mapped to when they expire, None while their client is still connected.
+/// `ttl` - How long a token can be used after its client's connection was lost.
+#[derive(Clone)]
+pub struct ResumeTokens {
+    tokens: Arc<Mutex<HashMap<String, Option<Instant>>>>,
+    ttl: Duration,
+}
+
+impl ResumeTokens {
+    /// Creates a new ResumeTokens with no tokens.
+    ///
+    /// # Arguments
+    /// * `ttl` - A Duration of how long a token can be used after its client's connection was lost.
+    ///
+    /// # Returns
+    ///  `ResumeTokens` - the newly created tokens.
+    pub fn new(ttl: Duration) -> ResumeTokens {
+        return ResumeTokens {
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            ttl: ttl,
+        };
+    }
+
+    /// Accessor method for how long tokens can be used after a connection is lost.
+    ///
+    /// # Returns
+    /// `Duration` - the token lifetime.
+    pub fn ttl(&self) -> Duration {
+        return self.ttl;
+    }
+
+    /// Makes a new random token for a client that is connected.
+    ///
+    /// # Returns
+    /// `io::Result<String>` - the token, or why no randomness could be read.
+    fn issue(&self) -> io::Result<String> {
+        let token = random_token()?;
+        self.tokens.lock().unwrap().insert(token.clone(), None);
+        return Ok(token);
+    }
+
+    /// Starts a token's time running out, for when its client's connection was lost.
+    ///
+    /// # Arguments
+    /// * `token` - A &str of the client's token.
+    fn lost(&self, token: &str) {
+        if let Some(expires) = self.tokens.lock().unwrap().get_mut(token) {
+            *expires = Some(Instant::now() + self.ttl);
+        }
+    }
+
+    /// Forgets a token, for when its client left on purpose or was dropped.
+    ///
+    /// # Arguments
+    /// * `token` - A &str of the client's token.
+    fn forget(&self, token: &str) {
+        self.tokens.lock().unwrap().remove(token);
+    }
+
+    /// Uses up a token, forgetting tokens that have expired.
+    ///
+    /// # Arguments
+    /// * `token` - A &str of the token the client sent.
+    ///
+    /// # Returns
+    /// `bool` - true if the token was valid, it can't be used again.
+    fn redeem(&self, token: &str) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = Instant::now();
+        tokens.retain(|_, expires| expires.map_or(true, |e| e > now));
+
+        return tokens.remove(token).is_some();
+    }
+}
+
+/// A Reconnect which is what a client needs to resume its session once its connection to the server is lost.
+///
+/// # Fields
+/// `addr` - The server's host:port.
+/// `nick` - The nick and public key announced again once connected.
+/// `token` - The token the server last gave us, None until it does or once the session can't be resumed.
+/// `ttl` - How long the server keeps the token once the connection is lost.
+/// `lost` - When the connection was lost, None until it is and again once the server took us back.
+/// `tried` - When we last tried to connect again.
+#[derive(Clone)]
+pub struct Reconnect {
+    addr: String,
+    nick: Option<(String, String)>,
+    token: Option<String>,
+    ttl: Duration,
+    lost: Option<Instant>,
+    tried: Option<Instant>,
+}
+
+impl Reconnect {
+    /// Creates a new Reconnect, which can't resume anything until the server gives us a token.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the server's host:port.
+    /// * `nick` - A Option<(String, String)> of the nick and public key to announce again.
+    ///
+    /// # Returns
+    ///  `Reconnect` - the newly created Reconnect.
+    pub fn new(addr: &str, nick: Option<(String, String)>) -> Reconnect {
+        return Reconnect {
+            addr: String::from(addr),
+            nick: nick,
+            token: None,
+            ttl: Duration::from_secs(0),
+            lost: None,
+            tried: None,
+        };
+    }
+
+    /// Accessor method for how long the server keeps our token once the connection is lost.
+    ///
+    /// # Returns
+    /// `Duration` - the time we have to resume the session.
+    pub fn ttl(&self) -> Duration {
+        return self.ttl;
+    }
+}
+
+/// Connects to a server without waiting longer than CONNECT_TIMEOUT for any of its addresses.
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(ErrorKind::NotFound, "no address to connect to");
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nonblocking(true)?;
+                return Ok(stream);
+            }
+            Err(err) => last = err,
+        }
+    }
+    return Err(last);
+}
+
+impl Connection {
+    /// Accessor method for the token the current client resumed its session with.
+    ///
+    /// # Returns
+    /// `Option<&str>` - the token the client used, None if it started a new session.
+    pub fn resumed(&self) -> Option<&str> {
+        return self.resumed.as_deref();
+    }
+
+    /// Accessor method for the token the current or last client can resume its session with.
+    ///
+    /// # Returns
+    /// `Option<&str>` - the token, None if the server doesn't give out tokens or the client didn't get one.
+    pub fn resume_token(&self) -> Option<&str> {
+        return self.resume_token.as_deref();
+    }
+
+    /// Accessor method for how long a client can resume its session once its connection is lost.
+    ///
+    /// # Returns
+    /// `Option<Duration>` - the time, None if the server doesn't let clients resume.
+    pub fn resume_ttl(&self) -> Option<Duration> {
+        return self.resume_tokens.as_ref().map(|t| t.ttl());
+    }
+
+    /// Accessor method for whether a client is trying to get its lost session back.
+    ///
+    /// # Returns
+    /// `bool` - true from when the connection was lost until the server takes us back or the token expires.
+    pub fn reconnecting(&self) -> bool {
+        return self.reconnect.as_ref().map_or(false, |r| r.lost.is_some());
+    }
+
+    /// Checks a Resume frame a client sent to start the handshake.
+    ///
+    /// Called on a connection server side, the token can't be used again either way.
+    ///
+    /// # Arguments
+    /// * `token` - A &str of the token the client sent.
+    ///
+    /// # Returns
+    /// `bool` - true if the client resumed its session.
+    pub(super) fn redeem_resume_token(&mut self, token: &str) -> bool {
+        match &self.resume_tokens {
+            Some(tokens) if tokens.redeem(token) => {
+                self.resumed = Some(String::from(token));
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Gives a client that completed the handshake a token to resume its session with, if the server gives them out.
+    ///
+    /// Called on a connection server side.
+    pub(super) fn issue_resume_token(&mut self) {
+        let tokens = match &self.resume_tokens {
+            Some(tokens) => tokens,
+            None => return,
+        };
+        // Without randomness the client just can't resume.
+        if let Ok(token) = tokens.issue() {
+            self.send_frame(&Frame::Resume {
+                token: token.clone(),
+                secs: Some(tokens.ttl().as_secs()),
+            });
+            self.resume_token = Some(token);
+        }
+    }
+
+    /// Ends the session with the peer, which can be resumed if the connection was lost but not if it left or was
+    /// dropped.
+    ///
+    /// Called on a connection, the relay calls it for clients it drops itself.
+    ///
+    /// # Arguments
+    /// * `lost` - A bool of whether the connection was lost rather than closed on purpose.
+    pub fn end_session(&mut self, lost: bool) {
+        if let (Some(tokens), Some(token)) = (&self.resume_tokens, &self.resume_token) {
+            if lost {
+                tokens.lost(token);
+            } else {
+                tokens.forget(token);
+            }
+        }
+        if let Some(reconnect) = &mut self.reconnect {
+            if !lost {
+                reconnect.token = None;
+            } else if reconnect.token.is_some() && reconnect.lost.is_none() {
+                reconnect.lost = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Remembers a token the server gave us to resume our session with.
+    ///
+    /// Called on a connection client side.
+    ///
+    /// # Arguments
+    /// * `token` - A String of the token.
+    /// * `secs` - A u64 of how long the server keeps it once the connection is lost.
+    ///
+    /// # Returns
+    /// `bool` - true if it came after connecting again, so the server has taken us back.
+    pub(super) fn keep_resume_token(&mut self, token: String, secs: u64) -> bool {
+        let reconnect = match &mut self.reconnect {
+            Some(reconnect) => reconnect,
+            None => return false,
+        };
+        reconnect.token = Some(token);
+        reconnect.ttl = Duration::from_secs(secs);
+        reconnect.tried = None;
+        return reconnect.lost.take().is_some();
+    }
+
+    /// Tries to connect to the server again after the connection was lost, every RETRY_EVERY until the token
+    /// expires.
+    ///
+    /// Called on a connection client side without a peer. Once connected the token is sent instead of Hello, then
+    /// our nick, the messages the server never acknowledged and the ones queued since.
+    ///
+    /// # Returns
+    /// `Option<bool>` - Some(true) once connected again, Some(false) once the token expired, None otherwise.
+    pub(super) fn try_resume(&mut self) -> Option<bool> {
+        let reconnect = self.reconnect.as_mut()?;
+        let (lost, token) = match (reconnect.lost, &reconnect.token) {
+            (Some(lost), Some(token)) => (lost, token.clone()),
+            _ => return None,
+        };
+        if lost.elapsed() >= reconnect.ttl {
+            reconnect.lost = None;
+            reconnect.token = None;
+            return Some(false);
+        }
+        if reconnect.tried.map_or(false, |t| t.elapsed() < RETRY_EVERY) {
+            return None;
+        }
+        reconnect.tried = Some(Instant::now());
+        let nick = reconnect.nick.clone();
+
+        let stream = connect(&reconnect.addr).ok()?;
+        self.socket_options.apply(&stream).ok()?;
+        let addr = stream.peer_addr().ok()?;
+        self.set_peer(Some(Peer::new(stream, addr, String::from("Server"))));
+        self.send_frame(&Frame::Resume {
+            token: token,
+            secs: None,
+        });
+        if let Some(otr) = &self.otr {
+            // A new session with the server, the queue is flushed once it started, sealed with its keys.
+            let key = otr.borrow_mut().offer();
+            self.send_frame(&Frame::Otr { key: key });
+            return Some(true);
+        }
+        if let Some((nick, key)) = nick {
+            self.send_frame(&Frame::Nick {
+                nick: nick,
+                key: key,
+            });
+        }
+        self.flush_queue();
+        return Some(true);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::protocol::Frame;
+
+/// How many sent messages wait for an ack at most, the oldest is forgotten after that.
+const MAX_PENDING: usize = 256;
+
+/// A Retransmit which sends messages again that the peer hasn't acknowledged.
+///
+/// # Fields
+/// `timeout` - How long to wait for an ack before sending a message again, zero never sends one again.
+/// `retries` - How many times a message is sent again before giving up on it.
+/// `pending` - The messages sent but not acknowledged yet, oldest first, kept even when they are never sent again
+/// to time their acks.
+#[derive(Clone)]
+pub struct Retransmit {
+    timeout: Duration,
+    retries: u32,
+    pending: VecDeque<Pending>,
+}
+
+/// A Pending message which was sent and is waiting for an ack.
+///
+/// # Fields
+/// `frame` - The message, a frame with a message id.
+/// `sent` - When it was last sent.
+/// `first_sent` - When it was first sent, for the round trip time.
+/// `tries` - How many times it has been sent again.
+#[derive(Clone)]
+struct Pending {
+    frame: Frame,
+    sent: Instant,
+    first_sent: Instant,
+    tries: u32,
+}
+
+impl Retransmit {
+    /// Creates a new Retransmit with nothing pending.
+    ///
+    /// # Arguments
+    /// * `timeout` - A Duration to wait for an ack, zero to never send a message again.
+    /// * `retries` - A u32 of how many times to send a message again.
+    ///
+    /// # Returns
+    /// `Retransmit` - the newly created retransmit.
+    pub fn new(timeout: Duration, retries: u32) -> Retransmit {
+        return Retransmit {
+            timeout: timeout,
+            retries: retries,
+            pending: VecDeque::new(),
+        };
+    }
+
+    /// Starts waiting for the ack of a message that was just sent.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame that was sent, only Text and Reply frames are acknowledged, others are ignored.
+    pub fn sent(&mut self, frame: &Frame) {
+        match frame {
+            Frame::Text { .. } | Frame::Reply { .. } => {}
+            _ => return,
+        }
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(Pending {
+            frame: frame.clone(),
+            sent: Instant::now(),
+            first_sent: Instant::now(),
+            tries: 0,
+        });
+    }
+
+    /// Stops waiting for a message the peer acknowledged.
+    ///
+    /// # Arguments
+    /// * `id` - A Option<u64> of the acknowledged message's id, None from peers that don't say which, which
+    /// acknowledges the oldest.
+    ///
+    /// # Returns
+    /// `Option<(u64, i64)>` - the message's id and how many ms after it was first sent it was acknowledged, None if
+    /// it wasn't pending.
+    pub fn acked(&mut self, id: Option<u64>) -> Option<(u64, i64)> {
+        let at = match id {
+            Some(id) => self
+                .pending
+                .iter()
+                .position(|p| p.frame.message_id() == Some(id))?,
+            None if self.pending.is_empty() => return None,
+            None => 0,
+        };
+        let pending = self.pending.remove(at)?;
+        let id = pending.frame.message_id()?;
+        return Some((id, pending.first_sent.elapsed().as_millis() as i64));
+    }
+
+    /// Takes every message still waiting for an ack, for sending to a peer that just connected ahead of anything
+    /// newer, and starts their timeouts over.
+    ///
+    /// # Returns
+    /// `Vec<Frame>` - the messages in the order they were first sent, which is the order of their ids, none if
+    /// messages are never sent again.
+    pub fn unacked(&mut self) -> Vec<Frame> {
+        if self.timeout == Duration::from_secs(0) {
+            self.pending.clear();
+            return Vec::new();
+        }
+        for pending in self.pending.iter_mut() {
+            pending.sent = Instant::now();
+        }
+        return self.pending.iter().map(|p| p.frame.clone()).collect();
+    }
+
+    /// Takes the messages that have waited too long for an ack.
+    ///
+    /// # Returns
+    /// `(Vec<Frame>, Vec<Frame>)` - the messages to send again, which are still waited for, and the ones that
+    /// have been sent `retries` times already and are given up on.
+    pub fn due(&mut self) -> (Vec<Frame>, Vec<Frame>) {
+        if self.timeout == Duration::from_secs(0) {
+            return (Vec::new(), Vec::new());
+        }
+        let mut again = Vec::new();
+        let mut given_up = Vec::new();
+        let mut waiting = VecDeque::new();
+        for mut pending in self.pending.drain(..) {
+            if pending.sent.elapsed() < self.timeout {
+                waiting.push_back(pending);
+            } else if pending.tries < self.retries {
+                pending.tries += 1;
+                pending.sent = Instant::now();
+                again.push(pending.frame.clone());
+                waiting.push_back(pending);
+            } else {
+                given_up.push(pending.frame);
+            }
+        }
+        self.pending = waiting;
+        return (again, given_up);
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use stopwatch::Stopwatch;
+
+use super::{Connection, NetStatus};
+use crate::protocol::Frame;
+
+/// A SharedConnection which is a handle to one Connection that a reader thread, a writer thread and the ui can all
+/// hold.
+///
+/// Clones share the Connection and its peer's socket, nothing is duplicated, and unlike the halves from `split`
+/// every clone sees the peer change. Each call locks the Connection only for as long as it takes, peers' sockets
+/// are nonblocking so a read never holds the lock waiting on the peer.
+///
+/// # Fields
+/// `con` - The Connection, locked by each call.
+#[derive(Clone)]
+pub struct SharedConnection {
+    con: Arc<Mutex<Connection>>,
+}
+
+impl SharedConnection {
+    /// Creates a new SharedConnection, see `Connection::shared`.
+    ///
+    /// # Arguments
+    /// * `con` - A Connection to share.
+    ///
+    /// # Returns
+    ///  `SharedConnection` - the handle.
+    pub(super) fn new(con: Connection) -> SharedConnection {
+        return SharedConnection {
+            con: Arc::new(Mutex::new(con)),
+        };
+    }
+
+    /// Locks the Connection, for anything the handle has no method for.
+    ///
+    /// Called on a SharedConnection, other clones wait until the guard is dropped so keep it short. A thread that
+    /// panicked while holding it doesn't keep the others from using the Connection.
+    ///
+    /// # Returns
+    /// `MutexGuard<Connection>` - the locked Connection.
+    pub fn lock(&self) -> MutexGuard<'_, Connection> {
+        return self.con.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Sends a message to the peer, see `Connection::send_message`.
+    ///
+    /// # Arguments
+    /// * `msg` - A String of the message to send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Empty if there was no current peer, or Failed with the socket error.
+    pub fn send_message(&self, msg: String) -> (String, Stopwatch) {
+        return self.lock().send_message(msg);
+    }
+
+    /// Sends a protocol frame to the peer, see `Connection::send_frame`.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame to encode and send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent or Empty if there was no current peer.
+    pub fn send_frame(&self, frame: &Frame) -> (String, Stopwatch) {
+        return self.lock().send_frame(frame);
+    }
+
+    /// Sends a protocol frame to the peer, or queues it if there is no peer, see `Connection::send_or_queue_frame`.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame to encode and send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, Queued, or Queue full if queueing is disabled.
+    pub fn send_or_queue_frame(&self, frame: &Frame) -> (String, Stopwatch) {
+        return self.lock().send_or_queue_frame(frame);
+    }
+
+    /// Receives a peer's message, see `Connection::receive_message`.
+    ///
+    /// # Returns
+    /// `String` - The received messaged, blocked, disconencted, timed out, banned, or empty depending on the situation.
+    pub fn receive_message(&self) -> String {
+        return self.lock().receive_message();
+    }
+
+    /// Receives a peer's frame, see `Connection::receive_frame`.
+    ///
+    /// # Returns
+    /// `Result<Frame, String>` - The received frame, or blocked, disconencted, timed out, banned, or empty depending
+    /// on the situation.
+    pub fn receive_frame(&self) -> Result<Frame, String> {
+        return self.lock().receive_frame();
+    }
+
+    /// Reports the current state of the connection, see `Connection::status`.
+    ///
+    /// # Returns
+    /// `NetStatus` - the connection's status.
+    pub fn status(&self) -> NetStatus {
+        return self.lock().status();
+    }
+
+    /// Accessor method for the message size, see `Connection::msg_size`.
+    ///
+    /// # Returns
+    /// `usize` - the most bytes a frame may have on the wire, not counting its length.
+    pub fn msg_size(&self) -> usize {
+        return self.lock().msg_size();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::connection::ConnectionBuilder;
+    use crate::protocol::{codec, Frame};
+
+    const MSG_SIZE: usize = 255;
+    const COUNT: u64 = 50;
+
+    #[test]
+    fn a_reader_and_a_writer_thread_share_one_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shared = ConnectionBuilder::new(MSG_SIZE)
+            .client(&addr)
+            .unwrap()
+            .shared();
+        // The peer sends back every frame it gets, Hello included.
+        let (mut peer, _) = listener.accept().unwrap();
+        thread::spawn(move || {
+            while let Ok(buff) = codec::read_blocking(&peer, MSG_SIZE) {
+                let frame = codec::decode(&buff).unwrap();
+                if peer.write_all(&codec::encode(&frame, MSG_SIZE)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let writer = shared.clone();
+        let sending = thread::spawn(move || {
+            for id in 0..COUNT {
+                let frame = Frame::Text {
+                    id: id,
+                    body: format!("message {}", id),
+                };
+                let (sent, _) = writer.send_frame(&frame);
+                assert!(sent.starts_with("Message sent"), "{}", sent);
+            }
+        });
+
+        let reader = shared.clone();
+        let received = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut ids = Vec::new();
+            while (ids.len() as u64) < COUNT && Instant::now() < deadline {
+                match reader.receive_frame() {
+                    Ok(Frame::Text { id, .. }) => ids.push(id),
+                    _ => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+            return ids;
+        });
+
+        sending.join().unwrap();
+        let ids = received.join().unwrap();
+        assert_eq!(ids, (0..COUNT).collect::<Vec<u64>>());
+        assert!(shared.status().peer.is_some());
+        assert_eq!(shared.msg_size(), MSG_SIZE);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::PeerId;
+
+/// A Simulation of a slow, lossy network, for trying reconnects, retransmits and acks locally.
+///
+/// Frames are delayed and dropped as they are received, so running both sides with the flag delays both ways.
+/// Frames are never reordered, like on a real TCP connection.
+///
+/// # Fields
+/// `latency` - How long each frame is held back.
+/// `jitter` - How much more or less than `latency` a frame may be held back, at random.
+/// `loss` - The chance a frame is dropped, from 0 to 1.
+/// `held` - The frames received but not due yet with when they are, oldest first.
+/// `peer` - The peer `held` came from, they are dropped once another peer connects.
+/// `rng` - The state of the xorshift generator deciding jitter and loss.
+#[derive(Clone)]
+pub struct Simulation {
+    latency: Duration,
+    jitter: Duration,
+    loss: f64,
+    held: VecDeque<(Instant, Vec<u8>)>,
+    peer: Option<PeerId>,
+    rng: u64,
+}
+
+impl Simulation {
+    /// Parses a Simulation from `--simulate`, e.g. `latency=200ms,jitter=50ms,loss=1%`.
+    ///
+    /// # Arguments
+    /// * `spec` - A &str of comma separated settings, each left out is 0. Times are in ms or s.
+    ///
+    /// # Returns
+    /// `Result<Simulation, String>` - the simulation, or which setting is wrong.
+    pub fn parse(spec: &str) -> Result<Simulation, String> {
+        let mut simulation = Simulation {
+            latency: Duration::from_secs(0),
+            jitter: Duration::from_secs(0),
+            loss: 0.0,
+            held: VecDeque::new(),
+            peer: None,
+            rng: seed(),
+        };
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let (name, value) = match setting.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(format!("\"{}\" should be name=value", setting)),
+            };
+            match name {
+                "latency" => simulation.latency = parse_time(value)?,
+                "jitter" => simulation.jitter = parse_time(value)?,
+                "loss" => simulation.loss = parse_loss(value)?,
+                _ => {
+                    return Err(format!(
+                        "unknown setting \"{}\", expected latency, jitter or loss",
+                        name
+                    ))
+                }
+            }
+        }
+        return Ok(simulation);
+    }
+
+    /// Passes a read through the simulation, holding back or dropping a frame that was just read.
+    ///
+    /// # Arguments
+    /// * `peer` - A PeerId of the peer that was read from.
+    /// * `read` - A io::Result<Option<Vec<u8>>> of what the read gave.
+    ///
+    /// # Returns
+    /// `io::Result<Option<Vec<u8>>>` - the oldest held frame if it is due, None if none is, or the read's error.
+    pub fn pass(
+        &mut self,
+        peer: PeerId,
+        read: io::Result<Option<Vec<u8>>>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if self.peer != Some(peer) {
+            self.peer = Some(peer);
+            self.held.clear();
+        }
+        if let Some(buff) = read? {
+            if self.next_f64() >= self.loss {
+                let jitter = self.jitter.as_secs_f64() * (self.next_f64() * 2.0 - 1.0);
+                let delay = (self.latency.as_secs_f64() + jitter).max(0.0);
+                let mut due = Instant::now() + Duration::from_secs_f64(delay);
+                // Jitter can't let a frame overtake the one before it.
+                if let Some((last, _)) = self.held.back() {
+                    due = due.max(*last);
+                }
+                self.held.push_back((due, buff));
+            }
+        }
+        match self.held.front() {
+            Some((due, _)) if *due <= Instant::now() => {
+                return Ok(self.held.pop_front().map(|(_, buff)| buff))
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    /// Draws the next random number from 0 up to 1.
+    fn next_f64(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        return (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+/// Seeds the generator from the clock, it only has to differ between runs.
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    // Xorshift never leaves zero.
+    return nanos | 1;
+}
+
+/// Parses a time like `200ms` or `2s`.
+fn parse_time(value: &str) -> Result<Duration, String> {
+    let (number, scale) = match value.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (value.strip_suffix('s').unwrap_or(value), 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => return Ok(Duration::from_secs_f64(n * scale)),
+        _ => return Err(format!("\"{}\" is not a time like 200ms or 2s", value)),
+    }
+}
+
+/// Parses a loss like `1%` or `0.01`.
+fn parse_loss(value: &str) -> Result<f64, String> {
+    let loss = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().map(|p| p / 100.0),
+        None => value.parse::<f64>(),
+    };
+    match loss {
+        Ok(loss) if (0.0..=1.0).contains(&loss) => return Ok(loss),
+        _ => return Err(format!("\"{}\" is not a loss like 1% or 0.01", value)),
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+extern crate socket2;
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::config::Args;
+
+/// SocketOptions which are the TCP options set on every peer's socket.
+///
+/// # Fields
+/// `nodelay` - Whether TCP_NODELAY is set so small messages go out straight away, on by default for chat.
+/// `keepalive` - How long the connection is idle before keepalive probes are sent, None leaves them off.
+/// `keepalive_interval` - How long between keepalive probes, None leaves it to the OS.
+/// `send_buffer` - The socket's send buffer size in bytes, None leaves it to the OS.
+/// `recv_buffer` - The socket's receive buffer size in bytes, None leaves it to the OS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+}
+
+/// Reads a flag where 0, the default, means not to set the option.
+fn optional(args: &Args, name: &str) -> Option<usize> {
+    return Some(args.usize_flag(name, 0)).filter(|n| *n > 0);
+}
+
+impl SocketOptions {
+    /// Creates the default SocketOptions, TCP_NODELAY and everything else left to the OS.
+    ///
+    /// # Returns
+    /// `SocketOptions` - the default options.
+    pub fn new() -> SocketOptions {
+        return SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            keepalive_interval: None,
+            send_buffer: None,
+            recv_buffer: None,
+        };
+    }
+
+    /// Reads SocketOptions from `--nodelay 0|1`, `--keepalive secs`, `--keepalive-interval secs`,
+    /// `--send-buffer bytes` and `--recv-buffer bytes`, exiting with an error if one is not a number.
+    ///
+    /// # Arguments
+    /// * `args` - A &Args of the command line.
+    ///
+    /// # Returns
+    /// `SocketOptions` - the options, defaults for any flag not given.
+    pub fn from_args(args: &Args) -> SocketOptions {
+        let secs = |name| optional(args, name).map(|s| Duration::from_secs(s as u64));
+        return SocketOptions {
+            nodelay: args.usize_flag("nodelay", 1) != 0,
+            keepalive: secs("keepalive"),
+            keepalive_interval: secs("keepalive-interval"),
+            send_buffer: optional(args, "send-buffer"),
+            recv_buffer: optional(args, "recv-buffer"),
+        };
+    }
+
+    /// Sets the options on a socket.
+    ///
+    /// Called on SocketOptions.
+    ///
+    /// # Arguments
+    /// * `stream` - A &TcpStream to set them on.
+    ///
+    /// # Returns
+    /// `io::Result<()>` - Ok once every option is set, or why one could not be.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+        socket.set_nodelay(self.nodelay)?;
+        match self.keepalive {
+            Some(time) => {
+                let mut keepalive = TcpKeepalive::new().with_time(time);
+                if let Some(interval) = self.keepalive_interval {
+                    keepalive = keepalive.with_interval(interval);
+                }
+                socket.set_tcp_keepalive(&keepalive)?;
+            }
+            None => socket.set_keepalive(false)?,
+        }
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+
+use stopwatch::Stopwatch;
+
+use super::{write_frame, Throttle};
+use crate::protocol::codec::{self, FrameReader};
+use crate::protocol::Frame;
+
+/// A ConnSender which is the writing half of a split Connection.
+///
+/// # Fields
+/// `msg_size` - The most bytes a message may have.
+/// `stream` - The peer's TcpStream, cloned from the Connection.
+/// `throttle` - The Connection's Throttle if it has one, shared so both halves count against the same limit.
+/// `buff` - The buffer messages are prefixed into, kept between sends.
+pub struct ConnSender {
+    msg_size: usize,
+    stream: TcpStream,
+    throttle: Option<Throttle>,
+    buff: RefCell<Vec<u8>>,
+}
+
+/// A ConnReceiver which is the reading half of a split Connection.
+///
+/// # Fields
+/// `msg_size` - The most bytes a message may have.
+/// `stream` - The peer's TcpStream, cloned from the Connection.
+/// `received` - The part of a frame received so far.
+pub struct ConnReceiver {
+    msg_size: usize,
+    stream: TcpStream,
+    received: FrameReader,
+}
+
+impl ConnSender {
+    /// Creates a new ConnSender.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize of the most bytes a message may have.
+    /// * `stream` - A TcpStream to write to.
+    /// * `throttle` - A Option<Throttle> limiting how fast messages are written.
+    ///
+    /// # Returns
+    ///  `ConnSender` - the newly created sender.
+    pub fn new(msg_size: usize, stream: TcpStream, throttle: Option<Throttle>) -> ConnSender {
+        return ConnSender {
+            msg_size: msg_size,
+            stream: stream,
+            throttle: throttle,
+            buff: RefCell::new(Vec::new()),
+        };
+    }
+
+    /// Sends a message to the peer.
+    ///
+    /// Called on a ConnSender, see `Connection::send_message`.
+    ///
+    /// # Arguments
+    /// * `msg` - A String of the message to send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, or Failed with the socket error.
+    pub fn send_message(&self, msg: String) -> (String, Stopwatch) {
+        return self.send_payload(msg.as_bytes());
+    }
+
+    /// Sends a protocol frame to the peer.
+    ///
+    /// Called on a ConnSender.
+    ///
+    /// # Arguments
+    /// * `frame` - A &Frame to encode and send to the peer.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, or Failed with the socket error.
+    pub fn send_frame(&self, frame: &Frame) -> (String, Stopwatch) {
+        return self.send_payload(&frame.to_bytes());
+    }
+
+    /// Sends a payload to the peer as one frame.
+    ///
+    /// Called on a ConnSender.
+    ///
+    /// # Arguments
+    /// * `payload` - A &[u8] of the bytes to send, cut to the message size.
+    ///
+    /// # Returns
+    /// `(String, Stopwatch)` - Message sent, or Failed with the socket error.
+    fn send_payload(&self, payload: &[u8]) -> (String, Stopwatch) {
+        let mut buff = self.buff.borrow_mut();
+        buff.clear();
+        codec::prefix_into(payload, self.msg_size, &mut buff);
+        if let Some(throttle) = &self.throttle {
+            throttle.take(buff.len());
+        }
+        let sent_time = Stopwatch::start_new();
+        if let Err(err) = write_frame(&self.stream, &buff) {
+            return (format!("Failed: {}", err), sent_time);
+        }
+        return (String::from("Message sent"), sent_time);
+    }
+
+    /// Sends a message to the peer that the peer's message has been received.
+    ///
+    /// Called on a ConnSender.
+    ///
+    /// # Arguments
+    /// * `id` - A u64 of the received message's id.
+    pub fn notify_message_received(&self, id: u64) {
+        self.send_frame(&Frame::Received { id: Some(id) });
+    }
+}
+
+impl ConnReceiver {
+    /// Creates a new ConnReceiver.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize of the most bytes a message may have.
+    /// * `stream` - A TcpStream to read from.
+    ///
+    /// # Returns
+    ///  `ConnReceiver` - the newly created receiver.
+    pub fn new(msg_size: usize, stream: TcpStream) -> ConnReceiver {
+        return ConnReceiver {
+            msg_size: msg_size,
+            stream: stream,
+            received: FrameReader::new(),
+        };
+    }
+
+    /// Receives a peer's message.
+    ///
+    /// Called on a ConnReceiver, see `receive_frame`.
+    ///
+    /// # Returns
+    /// `String` - The received messaged, blocked, disconencted, or empty if it was malformed.
+    pub fn receive_message(&mut self) -> String {
+        match self.receive_frame() {
+            Ok(frame) => return frame.encode(),
+            Err(status) => return status,
+        }
+    }
+
+    /// Receives a peer's frame.
+    ///
+    /// Called on a ConnReceiver, see `Connection::receive_frame`. The handshake and ban checks are left
+    /// to the Connection, so split only once the peer has completed the handshake.
+    ///
+    /// # Returns
+    /// `Result<Frame, String>` - The received frame, or blocked, disconencted, or empty if it was malformed.
+    pub fn receive_frame(&mut self) -> Result<Frame, String> {
+        match self.received.read_frame(&self.stream, self.msg_size) {
+            Ok(Some(buff)) => {
+                let frame = Frame::parse(&buff);
+                self.received.recycle(buff);
+                return frame.map_err(|_| String::from("Empty"));
+            }
+
+            Ok(None) => return Err(String::from("Blocked")),
+
+            // The reader skipped a frame that was too long, the next one is read from its start.
+            Err(ref err) if err.kind() == ErrorKind::InvalidData => {
+                return Err(String::from("Empty"))
+            }
+
+            Err(_) => return Err(String::from("Disconnected")),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A Throttle which limits how fast bytes are written using a token bucket.
+///
+/// Clones share the same bucket, so every copy of a Connection counts against one limit.
+#[derive(Clone)]
+pub struct Throttle {
+    limit_kbps: u32,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+/// The ThrottleStats of a Throttle, shown in the stats panel.
+///
+/// # Fields
+/// `limit_kbps` - The configured limit in kilobits per second.
+/// `rate_kbps` - How fast bytes were sent over the last second in kilobits per second.
+/// `usage` - `rate_kbps` as a fraction of `limit_kbps`.
+pub struct ThrottleStats {
+    pub limit_kbps: u32,
+    pub rate_kbps: f64,
+    pub usage: f64,
+}
+
+/// The shared state of a Throttle.
+///
+/// # Fields
+/// `bytes_per_sec` - How many tokens are added each second.
+/// `tokens` - How many bytes can be sent right now, negative when senders are waiting.
+/// `refilled` - When tokens were last added.
+/// `window_start` - When the current rate measurement window started.
+/// `window_bytes` - Bytes sent in the current window.
+/// `last_rate` - Bytes per second measured over the last full window.
+struct Bucket {
+    bytes_per_sec: f64,
+    tokens: f64,
+    refilled: Instant,
+    window_start: Instant,
+    window_bytes: u64,
+    last_rate: f64,
+}
+
+impl Throttle {
+    /// Creates a new Throttle given a limit.
+    ///
+    /// # Arguments
+    /// * `limit_kbps` - A u32 of the most kilobits per second to send.
+    ///
+    /// # Returns
+    ///  `Throttle` - the newly created throttle with a full bucket.
+    pub fn new(limit_kbps: u32) -> Throttle {
+        let bytes_per_sec = limit_kbps as f64 * 1000.0 / 8.0;
+        return Throttle {
+            limit_kbps: limit_kbps,
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_sec: bytes_per_sec,
+                tokens: bytes_per_sec,
+                refilled: Instant::now(),
+                window_start: Instant::now(),
+                window_bytes: 0,
+                last_rate: 0.0,
+            })),
+        };
+    }
+
+    /// Takes tokens for bytes about to be written, blocking until the bucket allows it.
+    ///
+    /// Called on a Throttle before every write.
+    ///
+    /// # Arguments
+    /// * `bytes` - A usize of how many bytes will be written.
+    pub fn take(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("Throttle lock poisoned.");
+            bucket.refill();
+            bucket.record(bytes);
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / bucket.bytes_per_sec)
+            } else {
+                Duration::from_secs(0)
+            }
+        };
+
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Reports the configured limit and current usage.
+    ///
+    /// Called on a Throttle.
+    ///
+    /// # Returns
+    /// `ThrottleStats` - the throttle's current stats.
+    pub fn stats(&self) -> ThrottleStats {
+        let mut bucket = self.bucket.lock().expect("Throttle lock poisoned.");
+        bucket.record(0);
+        let rate_kbps = bucket.last_rate * 8.0 / 1000.0;
+
+        return ThrottleStats {
+            limit_kbps: self.limit_kbps,
+            rate_kbps: rate_kbps,
+            usage: rate_kbps / self.limit_kbps.max(1) as f64,
+        };
+    }
+}
+
+impl Bucket {
+    /// Adds the tokens earned since the last refill, capped at one second's worth.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.bytes_per_sec;
+        self.tokens = (self.tokens + earned).min(self.bytes_per_sec);
+        self.refilled = now;
+    }
+
+    /// Counts sent bytes towards the rate measured over one second windows.
+    fn record(&mut self, bytes: usize) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Duration::from_secs(2) {
+            self.last_rate = 0.0;
+            self.window_start = Instant::now();
+            self.window_bytes = 0;
+        } else if elapsed >= Duration::from_secs(1) {
+            self.last_rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.window_start = Instant::now();
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes as u64;
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A Traffic which counts the bytes a Connection has sent and received.
+///
+/// Clones share the same counts, so the headless relay's clients add up to one total.
+#[derive(Clone, Default)]
+pub struct Traffic {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+impl Traffic {
+    /// Counts bytes written to a peer.
+    ///
+    /// # Arguments
+    /// * `bytes` - A usize of how many bytes were written.
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts bytes read from a peer.
+    ///
+    /// # Arguments
+    /// * `bytes` - A usize of how many bytes were read.
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accessor method for how many bytes were sent.
+    ///
+    /// # Returns
+    /// `u64` - the bytes sent.
+    pub fn sent(&self) -> u64 {
+        return self.sent.load(Ordering::Relaxed);
+    }
+
+    /// Accessor method for how many bytes were received.
+    ///
+    /// # Returns
+    /// `u64` - the bytes received.
+    pub fn received(&self) -> u64 {
+        return self.received.load(Ordering::Relaxed);
+    }
+}
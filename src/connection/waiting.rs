@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+
+use super::{turn_away, write_frame, Peer};
+use crate::protocol::{codec, Frame};
+
+/// A WaitingRoom which holds the clients that connected while the server had one, in the order they connected.
+///
+/// Each client is told its place in line with a Busy frame when it joins and again whenever it moves up, the first
+/// one becomes the server's peer once the current one leaves.
+///
+/// # Fields
+/// `clients` - The waiting clients, the next one first.
+/// `cap` - The most clients that may wait, the rest are turned away.
+pub struct WaitingRoom {
+    clients: VecDeque<Peer>,
+    cap: usize,
+}
+
+impl WaitingRoom {
+    /// Creates a new empty WaitingRoom given its size.
+    ///
+    /// # Arguments
+    /// * `cap` - A usize of the most clients that may wait, 0 turns every extra client away.
+    ///
+    /// # Returns
+    ///  `WaitingRoom` - the newly created waiting room.
+    pub fn new(cap: usize) -> WaitingRoom {
+        return WaitingRoom {
+            clients: VecDeque::new(),
+            cap: cap,
+        };
+    }
+
+    /// Accessor method for how many clients are waiting.
+    ///
+    /// # Returns
+    /// `usize` - the number of waiting clients.
+    pub fn len(&self) -> usize {
+        return self.clients.len();
+    }
+
+    /// Accessor method for how many clients may wait.
+    ///
+    /// # Returns
+    /// `usize` - the size of the waiting room.
+    pub fn cap(&self) -> usize {
+        return self.cap;
+    }
+
+    /// Lets a client wait for its turn, or turns it away if the room is full.
+    ///
+    /// Called on a WaitingRoom, the client is sent a Busy frame with its place in line either way, None if it was
+    /// turned away.
+    ///
+    /// # Arguments
+    /// * `client` - A Peer of the client that connected.
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    ///
+    /// # Returns
+    /// `bool` - true if the client is waiting, false if it was turned away.
+    pub fn join(&mut self, client: Peer, msg_size: usize) -> bool {
+        if self.clients.len() >= self.cap {
+            turn_away(client, &Frame::Busy { position: None }, msg_size);
+            return false;
+        }
+
+        let position = self.clients.len() + 1;
+        if !tell_position(&client, position, msg_size) {
+            return false;
+        }
+        self.clients.push_back(client);
+        return true;
+    }
+
+    /// Takes the client whose turn it is.
+    ///
+    /// Called on a WaitingRoom, clients that left while waiting are forgotten first and the ones still waiting are
+    /// told their new place in line.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    ///
+    /// # Returns
+    /// `Option<Peer>` - the next client, None if none is waiting.
+    pub fn next(&mut self, msg_size: usize) -> Option<Peer> {
+        self.prune(msg_size);
+        let next = self.clients.pop_front()?;
+        self.renumber(0, msg_size);
+        return Some(next);
+    }
+
+    /// Forgets the clients that left while waiting.
+    ///
+    /// Called on a WaitingRoom, the clients behind one that left are told their new place in line.
+    ///
+    /// # Arguments
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    pub fn prune(&mut self, msg_size: usize) {
+        let mut kept = VecDeque::with_capacity(self.clients.len());
+        let mut moved_from = None;
+
+        for client in self.clients.drain(..) {
+            // What a waiting client sent is left for its handshake, so one that sent something before it left is
+            // only seen to be gone once it is our peer and the handshake finds it closed.
+            let mut byte = [0; 1];
+            match client.stream().peek(&mut byte) {
+                Ok(0) => moved_from = moved_from.or(Some(kept.len())),
+                Ok(_) => kept.push_back(client),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => kept.push_back(client),
+                Err(_) => moved_from = moved_from.or(Some(kept.len())),
+            }
+        }
+        self.clients = kept;
+        if let Some(from) = moved_from {
+            self.renumber(from, msg_size);
+        }
+    }
+
+    /// Tells every client from the given index on its place in line, forgetting those that can't be told.
+    ///
+    /// # Arguments
+    /// * `from` - A usize of the index of the first client whose place changed.
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    fn renumber(&mut self, from: usize, msg_size: usize) {
+        let mut index = from;
+        while index < self.clients.len() {
+            if tell_position(&self.clients[index], index + 1, msg_size) {
+                index += 1;
+            } else {
+                self.clients.remove(index);
+            }
+        }
+    }
+}
+
+/// Sends a waiting client a Busy frame with its place in line.
+///
+/// # Arguments
+/// * `client` - A &Peer of the waiting client.
+/// * `position` - A usize of its place in line, 1 for next.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+///
+/// # Returns
+/// `bool` - true if the frame was sent, false if the client is gone.
+fn tell_position(client: &Peer, position: usize, msg_size: usize) -> bool {
+    let frame = Frame::Busy {
+        position: Some(position as u32),
+    };
+    return write_frame(client.stream(), &codec::encode(&frame, msg_size)).is_ok();
+}
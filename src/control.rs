@@ -0,0 +1,373 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+#[cfg(windows)]
+extern crate uds_windows;
+#[cfg(windows)]
+use uds_windows::{UnixListener, UnixStream};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::parse_age;
+
+/// How long a control client has to send its request once it connects.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many messages `history` and `search` return when no limit is given.
+const DEFAULT_LIMIT: usize = 50;
+
+/// The request was not valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The request was JSON but not a JSON-RPC request.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The request's method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The request's params were missing or of the wrong type.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was valid but could not be carried out, e.g. kicking a peer that is not connected.
+pub const COMMAND_FAILED: i64 = -32000;
+
+/// A ControlCommand which is sent over the control socket to administer a headless server.
+///
+/// # Variants
+/// `ListPeers` - List the connected clients, `list-peers`.
+/// `Kick` - Disconnect the client with the given address, `kick {"addr": ..}`.
+/// `Broadcast` - Send a message to every client as the server, `broadcast {"text": ..}`.
+/// `SetMotd` - Set the message sent to clients once they complete the handshake, `set-motd {"text": ..}`.
+/// An empty text clears it.
+/// `Stats` - Report the relay's counters, `stats`.
+/// `History` - Read the most recent stored messages, `history {"limit": .., "sender": ..}`, both optional.
+/// `Search` - Find stored messages containing some text, `search {"text": .., "limit": ..}`.
+/// `Invite` - Make a single-use token for an invite-only server, `invite`.
+/// `Purge` - Delete the stored and remembered messages older than an age like `7d`, `purge {"older_than": ..}`.
+/// `Shutdown` - Disconnect every client and stop the server, `shutdown`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlCommand {
+    ListPeers,
+    Kick(String),
+    Broadcast(String),
+    SetMotd(String),
+    Stats,
+    History(Option<String>, usize),
+    Search(String, usize),
+    Invite,
+    Purge(Duration),
+    Shutdown,
+}
+
+/// A PeerInfo which describes a connected client in the `list-peers` result.
+///
+/// # Fields
+/// `addr` - The client's address.
+/// `handshake_pending` - Whether the client has yet to complete the handshake.
+/// `nick` - The nick the client announced, None until it does.
+/// `connected_secs` - How long ago the client connected.
+/// `key` - The base64 public key the client announced with its nick, None until it does.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub addr: String,
+    pub handshake_pending: bool,
+    pub nick: Option<String>,
+    pub connected_secs: u64,
+    pub key: Option<String>,
+}
+
+/// The RelayStats of a headless server, the `stats` result.
+///
+/// # Fields
+/// `peers` - How many clients are connected, including ones still handshaking.
+/// `handshaking` - How many clients have yet to complete the handshake.
+/// `messages_relayed` - How many messages clients sent since the server started.
+/// `clients_dropped` - How many clients were dropped and banned for breaking the protocol.
+/// `handshake_failures` - How many of the dropped clients never completed the handshake.
+/// `reconnects` - How many clients connected from an IP that had connected before.
+/// `bytes_sent` - How many bytes were written to clients.
+/// `bytes_received` - How many bytes were read from clients.
+/// `uptime_secs` - How long the server has been running.
+/// `motd` - The current motd, None if there is none.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RelayStats {
+    pub peers: usize,
+    pub handshaking: usize,
+    pub messages_relayed: u64,
+    pub clients_dropped: u64,
+    pub handshake_failures: u64,
+    pub reconnects: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub uptime_secs: u64,
+    pub motd: Option<String>,
+}
+
+/// A RpcError which is returned to the control client instead of a result.
+///
+/// # Fields
+/// `code` - One of the JSON-RPC error codes above.
+/// `message` - What went wrong.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// The JSON-RPC response the server sends back, with either a result or an error.
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+/// The JSON-RPC request a control client sends, one per line.
+#[derive(Deserialize)]
+struct RpcRequest {
+    id: Value,
+    method: String,
+    params: Value,
+}
+
+/// A ControlSocket which is a Unix socket the headless server reads one JSON-RPC request per connection from.
+///
+/// # Fields
+/// `listener` - The nonblocking UnixListener.
+/// `path` - Where the socket lives, removed when the ControlSocket is dropped.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: String,
+}
+
+/// A ControlRequest which is a request read from a control client, answered with `reply` or `reply_error`.
+///
+/// # Fields
+/// `stream` - The control client's UnixStream.
+/// `id` - The request's id, echoed in the response.
+/// `command` - The parsed command, or why the request was invalid.
+pub struct ControlRequest {
+    stream: UnixStream,
+    id: Value,
+    pub command: Result<ControlCommand, RpcError>,
+}
+
+impl RpcError {
+    /// Creates a new RpcError.
+    ///
+    /// # Arguments
+    /// * `code` - A i64 JSON-RPC error code.
+    /// * `message` - A &str of what went wrong.
+    ///
+    /// # Returns
+    ///  `RpcError` - the newly created error.
+    pub fn new(code: i64, message: &str) -> RpcError {
+        return RpcError {
+            code: code,
+            message: String::from(message),
+        };
+    }
+}
+
+/// Parses a JSON-RPC request line into a ControlCommand.
+///
+/// # Arguments
+/// * `line` - A &str of the request without the trailing newline.
+///
+/// # Returns
+/// `(Value, Result<ControlCommand, RpcError>)` - the request's id, null if it had none, and the command or why
+/// the request was invalid.
+pub fn parse_control(line: &str) -> (Value, Result<ControlCommand, RpcError>) {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(err) => {
+            return (
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, &err.to_string())),
+            )
+        }
+    };
+    let request: RpcRequest = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(err) => {
+            return (
+                Value::Null,
+                Err(RpcError::new(INVALID_REQUEST, &err.to_string())),
+            )
+        }
+    };
+
+    let command = match request.method.as_str() {
+        "list-peers" => Ok(ControlCommand::ListPeers),
+        "stats" => Ok(ControlCommand::Stats),
+        "history" => {
+            let sender = request.params.get("sender").and_then(|v| v.as_str());
+            limit_param(&request.params)
+                .map(|limit| ControlCommand::History(sender.map(String::from), limit))
+        }
+        "search" => string_param(&request.params, "text")
+            .and_then(|text| non_empty(text, "text"))
+            .and_then(|text| {
+                limit_param(&request.params).map(|limit| ControlCommand::Search(text, limit))
+            }),
+        "invite" => Ok(ControlCommand::Invite),
+        "purge" => string_param(&request.params, "older_than").and_then(|age| {
+            parse_age(&age)
+                .map(ControlCommand::Purge)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, &e))
+        }),
+        "shutdown" => Ok(ControlCommand::Shutdown),
+        "kick" => string_param(&request.params, "addr").map(ControlCommand::Kick),
+        "broadcast" => string_param(&request.params, "text")
+            .and_then(|text| non_empty(text, "text"))
+            .map(ControlCommand::Broadcast),
+        "set-motd" => string_param(&request.params, "text").map(ControlCommand::SetMotd),
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("Unknown method {}", method),
+        )),
+    };
+
+    return (request.id, command);
+}
+
+/// Calls a method on a headless server's control socket.
+///
+/// # Arguments
+/// * `path` - A &str of the control socket.
+/// * `method` - A &str of the method to call.
+/// * `params` - A Value of the method's params.
+///
+/// # Returns
+/// `io::Result<Result<Value, RpcError>>` - the method's result or error, or why the server could not be reached.
+pub fn call(path: &str, method: &str, params: Value) -> io::Result<Result<Value, RpcError>> {
+    let mut stream = UnixStream::connect(path)?;
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response: RpcResponse = serde_json::from_str(&line)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    match response.error {
+        Some(error) => return Ok(Err(error)),
+        None => return Ok(Ok(response.result)),
+    }
+}
+
+/// Reads a string param by name.
+fn string_param(params: &Value, name: &str) -> Result<String, RpcError> {
+    match params.get(name).and_then(|v| v.as_str()) {
+        Some(value) => return Ok(String::from(value)),
+        None => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                &format!("Missing string param {}", name),
+            ))
+        }
+    }
+}
+
+/// Reads the optional limit param.
+fn limit_param(params: &Value) -> Result<usize, RpcError> {
+    match params.get("limit") {
+        None | Some(Value::Null) => return Ok(DEFAULT_LIMIT),
+        Some(limit) => match limit.as_u64() {
+            Some(limit) => return Ok(limit as usize),
+            None => return Err(RpcError::new(INVALID_PARAMS, "Param limit is not a number")),
+        },
+    }
+}
+
+/// Rejects an empty string param.
+fn non_empty(value: String, name: &str) -> Result<String, RpcError> {
+    if value.is_empty() {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            &format!("Param {} is empty", name),
+        ));
+    }
+    return Ok(value);
+}
+
+impl ControlSocket {
+    /// Binds a new ControlSocket, replacing a stale socket file left by a server that did not exit cleanly.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of where to create the socket.
+    ///
+    /// # Returns
+    /// `io::Result<ControlSocket>` - the bound socket, or why it could not be bound.
+    pub fn bind(path: &str) -> io::Result<ControlSocket> {
+        // Only remove the file if nothing answers on it, so we never steal a running server's socket.
+        if UnixStream::connect(path).is_err() {
+            let _ = fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+
+        return Ok(ControlSocket {
+            listener: listener,
+            path: String::from(path),
+        });
+    }
+
+    /// Reads a request from a waiting control client.
+    ///
+    /// Called on a ControlSocket, never blocks waiting for a client but waits up to READ_TIMEOUT for its request.
+    ///
+    /// # Returns
+    /// `Option<ControlRequest>` - the client's request, or None if no client was waiting or it sent nothing.
+    pub fn accept(&self) -> Option<ControlRequest> {
+        let (stream, _) = self.listener.accept().ok()?;
+        stream.set_nonblocking(false).ok()?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).ok()?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).ok()?;
+        let (id, command) = parse_control(line.trim_end());
+
+        return Some(ControlRequest {
+            stream: stream,
+            id: id,
+            command: command,
+        });
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl ControlRequest {
+    /// Answers the request with a result and closes the connection.
+    ///
+    /// Called on a ControlRequest, consumes it.
+    ///
+    /// # Arguments
+    /// * `result` - A Value of the method's result.
+    pub fn reply(self, result: Value) {
+        let id = self.id.clone();
+        self.respond(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    }
+
+    /// Answers the request with an error and closes the connection.
+    ///
+    /// Called on a ControlRequest, consumes it.
+    ///
+    /// # Arguments
+    /// * `error` - A RpcError of why the request failed.
+    pub fn reply_error(self, error: RpcError) {
+        let id = self.id.clone();
+        self.respond(json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+    }
+
+    /// Writes a response line.
+    fn respond(mut self, response: Value) {
+        // The control client may already have hung up, which is its loss.
+        let _ = writeln!(self.stream, "{}", response);
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+
+extern crate base64;
+extern crate chacha20poly1305;
+extern crate sha2;
+extern crate x25519_dalek;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::random::random;
+
+/// How many bytes of random nonce start each sealed message.
+const NONCE_LEN: usize = 12;
+
+/// KeyChange which is what learning a peer's key changed.
+///
+/// # Variants
+/// `New` - We had no key for the nick.
+/// `Unchanged` - We already had this key for the nick.
+/// `Changed` - The nick had a different key, it reconnected or someone is pretending to be them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyChange {
+    New,
+    Unchanged,
+    Changed,
+}
+
+/// A Keyring which holds our nick and key for this run, and the keys of the other clients on the relay.
+///
+/// Private messages are sealed with ChaCha20-Poly1305 under a key derived from an X25519 exchange between the
+/// two clients, so the relay passing them on can't read or change them. It could still hand out its own key
+/// for a nick, which is what comparing fingerprints catches.
+///
+/// # Fields
+/// `nick` - The nick we announced.
+/// `secret` - Our private key, made fresh each run.
+/// `public` - Our public key, announced with the nick.
+/// `peers` - Other clients' public keys by lowercased nick.
+pub struct Keyring {
+    nick: String,
+    secret: StaticSecret,
+    public: PublicKey,
+    peers: HashMap<String, PublicKey>,
+}
+
+/// Formats a short fingerprint of a public key for people to compare, e.g. `1a2b 3c4d 5e6f 7a8b`.
+fn fingerprint_of(key: &PublicKey) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    let groups: Vec<String> = digest[..8]
+        .chunks(2)
+        .map(|c| format!("{:02x}{:02x}", c[0], c[1]))
+        .collect();
+    return groups.join(" ");
+}
+
+impl Keyring {
+    /// Creates a new Keyring with a fresh key pair and no peers.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the nick we announce to the relay.
+    ///
+    /// # Returns
+    /// `io::Result<Keyring>` - the keyring, or why no randomness could be read.
+    pub fn new(nick: &str) -> io::Result<Keyring> {
+        let secret = StaticSecret::from(random::<32>()?);
+        let public = PublicKey::from(&secret);
+        return Ok(Keyring {
+            nick: String::from(nick),
+            secret: secret,
+            public: public,
+            peers: HashMap::new(),
+        });
+    }
+
+    /// Accessor method for our nick.
+    ///
+    /// # Returns
+    /// `&str` - the nick we announced.
+    pub fn nick(&self) -> &str {
+        return &self.nick;
+    }
+
+    /// Accessor method for our public key as it is sent in a Nick frame.
+    ///
+    /// # Returns
+    /// `String` - the base64 public key.
+    pub fn public_key(&self) -> String {
+        return STANDARD.encode(self.public.as_bytes());
+    }
+
+    /// Accessor method for our own key's fingerprint.
+    ///
+    /// # Returns
+    /// `String` - the fingerprint peers should see for us.
+    pub fn fingerprint(&self) -> String {
+        return fingerprint_of(&self.public);
+    }
+
+    /// Accessor method for a peer's key fingerprint.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the peer's nick.
+    ///
+    /// # Returns
+    /// `Option<String>` - the fingerprint, or None if we don't know the nick.
+    pub fn peer_fingerprint(&self, nick: &str) -> Option<String> {
+        return self.peers.get(&nick.to_lowercase()).map(fingerprint_of);
+    }
+
+    /// Remembers a peer's key from a Nick frame.
+    ///
+    /// # Arguments
+    /// * `nick` - A &str of the peer's nick.
+    /// * `key` - A &str of the base64 public key.
+    ///
+    /// # Returns
+    /// `Result<KeyChange, String>` - whether the key is new to us, or why it is not a valid key.
+    pub fn learn(&mut self, nick: &str, key: &str) -> Result<KeyChange, String> {
+        let bytes: [u8; 32] = STANDARD
+            .decode(key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| format!("{} sent a bad key", nick))?;
+        let key = PublicKey::from(bytes);
+
+        match self.peers.insert(nick.to_lowercase(), key) {
+            None => return Ok(KeyChange::New),
+            Some(old) if old == key => return Ok(KeyChange::Unchanged),
+            Some(_) => return Ok(KeyChange::Changed),
+        }
+    }
+
+    /// Makes the cipher shared with a peer, and the associated data that binds a message to who sent it to who.
+    fn cipher(
+        &self,
+        peer: &PublicKey,
+        sending: bool,
+    ) -> Result<(ChaCha20Poly1305, Vec<u8>), String> {
+        let shared = self.secret.diffie_hellman(peer);
+        if !shared.was_contributory() {
+            return Err(String::from("peer sent a weak key"));
+        }
+
+        // Both sides hash the keys in the same order so they derive the same cipher key.
+        let (low, high) = if self.public.as_bytes() < peer.as_bytes() {
+            (&self.public, peer)
+        } else {
+            (peer, &self.public)
+        };
+        let key = Sha256::new()
+            .chain_update(b"r2wc private message v1")
+            .chain_update(shared.as_bytes())
+            .chain_update(low.as_bytes())
+            .chain_update(high.as_bytes())
+            .finalize();
+
+        // Sender then receiver, so the relay can't bounce a message back to whoever sent it.
+        let (from, to) = if sending {
+            (&self.public, peer)
+        } else {
+            (peer, &self.public)
+        };
+        let aad = [from.as_bytes().as_slice(), to.as_bytes().as_slice()].concat();
+
+        return Ok((ChaCha20Poly1305::new(Key::from_slice(&key)), aad));
+    }
+
+    /// Encrypts a private message to a peer.
+    ///
+    /// # Arguments
+    /// * `to` - A &str of the peer's nick.
+    /// * `text` - A &str of the message.
+    ///
+    /// # Returns
+    /// `Result<String, String>` - the base64 nonce and ciphertext, or why it couldn't be sealed.
+    pub fn seal(&self, to: &str, text: &str) -> Result<String, String> {
+        let peer = self
+            .peers
+            .get(&to.to_lowercase())
+            .ok_or_else(|| format!("No key for {}, they aren't on this relay", to))?;
+        let (cipher, aad) = self.cipher(peer, true)?;
+        let nonce = random::<NONCE_LEN>().map_err(|e| e.to_string())?;
+        let sealed = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: text.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| String::from("could not encrypt"))?;
+
+        return Ok(STANDARD.encode([nonce.as_slice(), sealed.as_slice()].concat()));
+    }
+
+    /// Decrypts a private message from a peer.
+    ///
+    /// # Arguments
+    /// * `from` - A &str of the nick the relay says sent it.
+    /// * `data` - A &str of the base64 nonce and ciphertext.
+    ///
+    /// # Returns
+    /// `Result<String, String>` - the message, or why it could not be opened.
+    pub fn open(&self, from: &str, data: &str) -> Result<String, String> {
+        let peer = self
+            .peers
+            .get(&from.to_lowercase())
+            .ok_or_else(|| format!("No key for {}", from))?;
+        let data = STANDARD
+            .decode(data)
+            .map_err(|_| String::from("not base64"))?;
+        if data.len() < NONCE_LEN {
+            return Err(String::from("too short"));
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let (cipher, aad) = self.cipher(peer, false)?;
+        let text = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| String::from("it was not sealed for us or was tampered with"))?;
+
+        return String::from_utf8(text).map_err(|_| String::from("not text"));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::links::find_urls;
+
+/// A Format which is how part of a line is drawn.
+///
+/// # Variants
+/// `Bold` - Between `*`s.
+/// `Underline` - Between `_`s.
+/// `Code` - Between backticks, drawn in reverse.
+/// `Link` - A url.
+/// `Color` - A colour from syntax highlighting, drawn by the ratatui ui.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Bold,
+    Underline,
+    Code,
+    Link,
+    Color(u8, u8, u8),
+}
+
+/// The characters that start and end a span, and a backslash which takes the meaning away from the next one.
+const MARKERS: &[char] = &['*', '_', '`', '\\'];
+
+/// Whether a marker can start a span, which it can after the start or a non word character if the span doesn't
+/// start with a space. So `snake_case_names` are left alone.
+fn opens(before: Option<char>, after: Option<char>) -> bool {
+    return before.map_or(true, |c| !c.is_alphanumeric())
+        && after.map_or(false, |c| !c.is_whitespace());
+}
+
+/// Whether a marker can end a span, the other way round to `opens`.
+fn closes(before: Option<char>, after: Option<char>) -> bool {
+    return before.map_or(false, |c| !c.is_whitespace())
+        && after.map_or(true, |c| !c.is_alphanumeric());
+}
+
+/// Finds where the span started by the marker at `open` ends.
+///
+/// # Returns
+/// `Option<usize>` - the index in `chars` of the closing marker, None if the span is never closed.
+fn find_close(chars: &[char], open: usize) -> Option<usize> {
+    let marker = chars[open];
+    let mut i = open + 2;
+    while i < chars.len() {
+        if chars[i] == '\\' && marker != '`' {
+            i += 2;
+            continue;
+        }
+        if chars[i] == marker && closes(Some(chars[i - 1]), chars.get(i + 1).cloned()) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    return None;
+}
+
+/// Takes the markup out of part of a message with no urls in it, adding it to what is shown so far.
+fn parse_part(text: &str, shown: &mut String, spans: &mut Vec<(usize, usize, Format)>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut open: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let before = i.checked_sub(1).map(|b| chars[b]);
+        let after = chars.get(i + 1).cloned();
+        let in_code = open.map_or(false, |(close, _)| chars[close] == '`');
+        if c == '\\' && !in_code && after.map_or(false, |a| MARKERS.contains(&a)) {
+            shown.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+        match open {
+            Some((close, start)) if close == i => {
+                let format = match c {
+                    '*' => Format::Bold,
+                    '_' => Format::Underline,
+                    _ => Format::Code,
+                };
+                spans.push((start, shown.len(), format));
+                open = None;
+            }
+            None if MARKERS[..3].contains(&c) && opens(before, after) => {
+                match find_close(&chars, i) {
+                    Some(close) => open = Some((close, shown.len())),
+                    None => shown.push(c),
+                }
+            }
+            _ => shown.push(c),
+        }
+        i += 1;
+    }
+}
+
+/// Takes the markup out of a message, `*bold*`, `_underline_` and `` `code` ``. A backslash before one of
+/// `` *_`\ `` shows it as it is, and inside code everything is shown as it is. Spans don't nest, and urls are
+/// left as they are.
+///
+/// # Arguments
+/// * `text` - A &str of the message.
+///
+/// # Returns
+/// `(String, Vec<(usize, usize, Format)>)` - the message without its markup and the byte range of each span in
+/// it.
+pub fn parse(text: &str) -> (String, Vec<(usize, usize, Format)>) {
+    let mut shown = String::new();
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for (start, end) in find_urls(text) {
+        parse_part(&text[pos..start], &mut shown, &mut spans);
+        shown.push_str(&text[start..end]);
+        pos = end;
+    }
+    parse_part(&text[pos..], &mut shown, &mut spans);
+    return (shown, spans);
+}
+
+/// Splits part of a line where the spans covering it start and end.
+///
+/// # Arguments
+/// * `range` - A (usize, usize) of the byte range to split.
+/// * `spans` - A &[(usize, usize, Format)] of the byte ranges of the line's spans, which may overlap.
+///
+/// # Returns
+/// `Vec<(usize, usize, Vec<Format>)>` - the byte range of each piece, in order, and the formats it is drawn in.
+pub fn split(
+    range: (usize, usize),
+    spans: &[(usize, usize, Format)],
+) -> Vec<(usize, usize, Vec<Format>)> {
+    let (from, to) = range;
+    let mut cuts = vec![from, to];
+    for &(start, end, _) in spans {
+        cuts.extend([start, end].iter().filter(|&&c| from < c && c < to));
+    }
+    cuts.sort();
+    cuts.dedup();
+    return cuts
+        .windows(2)
+        .map(|w| {
+            let formats = spans
+                .iter()
+                .filter(|&&(start, end, _)| start <= w[0] && w[1] <= end)
+                .map(|&(_, _, format)| format)
+                .collect();
+            (w[0], w[1], formats)
+        })
+        .collect();
+}
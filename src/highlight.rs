@@ -0,0 +1,71 @@
+#[cfg(feature = "syntect")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "syntect")]
+use syntect::easy::HighlightLines;
+#[cfg(feature = "syntect")]
+use syntect::highlighting::ThemeSet;
+#[cfg(feature = "syntect")]
+use syntect::parsing::SyntaxSet;
+
+use crate::format::Format;
+
+/// The syntaxes and themes syntect knows, loaded the first time a code block is coloured.
+#[cfg(feature = "syntect")]
+static SYNTAXES: OnceLock<(SyntaxSet, ThemeSet)> = OnceLock::new();
+
+/// The syntect theme code is coloured with.
+#[cfg(feature = "syntect")]
+const THEME: &str = "base16-ocean.dark";
+
+/// Colours the lines of a code block by their syntax.
+///
+/// # Arguments
+/// * `lang` - A &str of the language, a name or file extension like `rust` or `rs`.
+/// * `lines` - A &[&str] of the block's lines.
+///
+/// # Returns
+/// `Vec<Vec<(usize, usize, Format)>>` - the coloured byte ranges of each line, none for a language syntect doesn't
+/// know.
+#[cfg(feature = "syntect")]
+pub fn highlight(lang: &str, lines: &[&str]) -> Vec<Vec<(usize, usize, Format)>> {
+    let (syntaxes, themes) = SYNTAXES.get_or_init(|| {
+        return (
+            SyntaxSet::load_defaults_nonewlines(),
+            ThemeSet::load_defaults(),
+        );
+    });
+    let (syntax, theme) = match (
+        syntaxes.find_syntax_by_token(lang),
+        themes.themes.get(THEME),
+    ) {
+        (Some(syntax), Some(theme)) if !lang.is_empty() => (syntax, theme),
+        _ => return vec![Vec::new(); lines.len()],
+    };
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    return lines
+        .iter()
+        .map(|line| {
+            let mut spans = Vec::new();
+            let mut pos = 0;
+            for (style, text) in highlighter
+                .highlight_line(line, syntaxes)
+                .unwrap_or_default()
+            {
+                let c = style.foreground;
+                spans.push((pos, pos + text.len(), Format::Color(c.r, c.g, c.b)));
+                pos += text.len();
+            }
+            spans
+        })
+        .collect();
+}
+
+/// Colours the lines of a code block by their syntax, which needs the `syntect` feature.
+///
+/// # Returns
+/// `Vec<Vec<(usize, usize, Format)>>` - no coloured ranges for any line.
+#[cfg(not(feature = "syntect"))]
+pub fn highlight(_lang: &str, lines: &[&str]) -> Vec<Vec<(usize, usize, Format)>> {
+    return vec![Vec::new(); lines.len()];
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use crate::term::Key;
+
+/// An Action which is something a key can be bound to with the config file's `keys`.
+///
+/// # Variants
+/// `Quit` - Leave the chat, `quit`.
+/// `Clear` - Clear the input line, `clear`.
+/// `Redraw` - Paint the whole screen again, `redraw`.
+/// `Complete` - Complete the command or name being typed, again for the next candidate, `complete`.
+/// `ScrollUp` - Scroll up a line, `scroll-up`.
+/// `ScrollDown` - Scroll down a line, `scroll-down`.
+/// `PageUp` - Scroll up a page, `page-up`.
+/// `PageDown` - Scroll down a page, `page-down`.
+/// `Top` - Scroll to the top, `top`.
+/// `Bottom` - Scroll to the bottom, `bottom`.
+/// `NextTab` - Switch to the next tab, `next-tab`.
+/// `PrevTab` - Switch to the previous tab, `prev-tab`.
+/// `Help` - Show the help, `help`.
+/// `CloseHelp` - Close the help, `close-help`.
+/// `TogglePeers` - Show or hide the peers panel, `peers`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    Clear,
+    Redraw,
+    Complete,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    NextTab,
+    PrevTab,
+    Help,
+    CloseHelp,
+    TogglePeers,
+}
+
+/// Every action by its name in the config file, with what it does and the keys it is bound to by default.
+const ACTIONS: &[(&str, Action, &str, &[Key])] = &[
+    (
+        "quit",
+        Action::Quit,
+        "quit",
+        &[Key::Ctrl('c'), Key::Ctrl('q')],
+    ),
+    (
+        "clear",
+        Action::Clear,
+        "clear the input line",
+        &[Key::Ctrl('u')],
+    ),
+    (
+        "redraw",
+        Action::Redraw,
+        "redraw the screen",
+        &[Key::Ctrl('l')],
+    ),
+    (
+        "complete",
+        Action::Complete,
+        "complete a command or name",
+        &[Key::Tab],
+    ),
+    (
+        "scroll-up",
+        Action::ScrollUp,
+        "scroll up a line",
+        &[Key::Up],
+    ),
+    (
+        "scroll-down",
+        Action::ScrollDown,
+        "scroll down a line",
+        &[Key::Down],
+    ),
+    (
+        "page-up",
+        Action::PageUp,
+        "scroll up a page",
+        &[Key::PageUp],
+    ),
+    (
+        "page-down",
+        Action::PageDown,
+        "scroll down a page",
+        &[Key::PageDown],
+    ),
+    ("top", Action::Top, "scroll to the top", &[Key::Home]),
+    (
+        "bottom",
+        Action::Bottom,
+        "scroll to the bottom",
+        &[Key::End],
+    ),
+    (
+        "next-tab",
+        Action::NextTab,
+        "switch to the next tab",
+        &[Key::Ctrl('n')],
+    ),
+    (
+        "prev-tab",
+        Action::PrevTab,
+        "switch to the previous tab",
+        &[Key::Ctrl('p')],
+    ),
+    ("help", Action::Help, "show this help", &[Key::Function(1)]),
+    (
+        "close-help",
+        Action::CloseHelp,
+        "close this help",
+        &[Key::Escape],
+    ),
+    (
+        "peers",
+        Action::TogglePeers,
+        "show or hide the peers panel",
+        &[Key::Function(2)],
+    ),
+];
+
+/// A Keymap which is which key does what, the defaults with the config file's `keys` on top.
+///
+/// # Fields
+/// `bindings` - Each bound key and its action.
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+/// Parses a key as it is written in the config file, e.g. `ctrl+q`, `f5`, `pageup`, `esc` or `q`.
+///
+/// # Returns
+/// `Result<Key, String>` - the key, or why it isn't one.
+fn parse_key(text: &str) -> Result<Key, String> {
+    let lower = text.trim().to_lowercase();
+    match lower.as_str() {
+        "esc" | "escape" => return Ok(Key::Escape),
+        "tab" => return Ok(Key::Tab),
+        "shift+tab" | "backtab" => return Ok(Key::BackTab),
+        "up" => return Ok(Key::Up),
+        "down" => return Ok(Key::Down),
+        "pageup" | "page-up" | "pgup" => return Ok(Key::PageUp),
+        "pagedown" | "page-down" | "pgdn" => return Ok(Key::PageDown),
+        "home" => return Ok(Key::Home),
+        "end" => return Ok(Key::End),
+        _ => (),
+    }
+
+    let mut chars = lower.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if !c.is_whitespace() {
+            return Ok(Key::Char(text.trim().chars().next().unwrap_or(c)));
+        }
+    }
+    if let Some(letter) = lower.strip_prefix("ctrl+") {
+        let mut chars = letter.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if c.is_ascii_lowercase() {
+                return Ok(Key::Ctrl(c));
+            }
+        }
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        if (1..=12).contains(&n) {
+            return Ok(Key::Function(n));
+        }
+    }
+    return Err(format!("unknown key \"{}\"", text));
+}
+
+/// How a key is written in the help.
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(c) => return c.to_string(),
+        Key::Ctrl(c) => return format!("Ctrl+{}", c.to_ascii_uppercase()),
+        Key::Function(n) => return format!("F{}", n),
+        Key::Escape => return String::from("Esc"),
+        Key::Tab => return String::from("Tab"),
+        Key::BackTab => return String::from("Shift+Tab"),
+        Key::Up => return String::from("Up"),
+        Key::Down => return String::from("Down"),
+        Key::PageUp => return String::from("Page Up"),
+        Key::PageDown => return String::from("Page Down"),
+        Key::Home => return String::from("Home"),
+        Key::End => return String::from("End"),
+        Key::Enter => return String::from("Enter"),
+        Key::Backspace => return String::from("Backspace"),
+        Key::Resize | Key::Quit | Key::Other => return String::new(),
+    }
+}
+
+impl Keymap {
+    /// Makes a Keymap from the config file's `keys`.
+    ///
+    /// An action in `keys` is bound to just the keys given, an empty list unbinds it, and a key given for one
+    /// action is taken away from any other action it is bound to by default.
+    ///
+    /// # Arguments
+    /// * `keys` - A &BTreeMap<String, Vec<String>> of action names and the keys to bind them to.
+    ///
+    /// # Returns
+    /// `Result<Keymap, String>` - the keymap, or which action or key is not valid.
+    pub fn new(keys: &BTreeMap<String, Vec<String>>) -> Result<Keymap, String> {
+        let mut bindings: Vec<(Key, Action)> = Vec::new();
+        for (name, texts) in keys {
+            let action = match ACTIONS.iter().find(|(n, ..)| n == name) {
+                Some((_, action, ..)) => *action,
+                None => return Err(format!("keys: unknown action \"{}\"", name)),
+            };
+            for text in texts {
+                let key = parse_key(text).map_err(|e| format!("keys: {}: {}", name, e))?;
+                if let Some((_, other)) = bindings.iter().find(|(k, _)| *k == key) {
+                    let other = ACTIONS.iter().find(|(_, a, ..)| a == other).unwrap();
+                    return Err(format!(
+                        "keys: {} is bound to both {} and {}",
+                        text, other.0, name
+                    ));
+                }
+                bindings.push((key, action));
+            }
+        }
+
+        for (name, action, _, defaults) in ACTIONS {
+            if keys.contains_key(*name) {
+                continue;
+            }
+            for key in defaults.iter() {
+                if !bindings.iter().any(|(k, _)| k == key) {
+                    bindings.push((*key, *action));
+                }
+            }
+        }
+
+        return Ok(Keymap { bindings: bindings });
+    }
+
+    /// Looks up what a key does.
+    ///
+    /// # Arguments
+    /// * `key` - A Key that was pressed.
+    ///
+    /// # Returns
+    /// `Option<Action>` - the key's action, or None if it isn't bound.
+    pub fn action(&self, key: Key) -> Option<Action> {
+        return self
+            .bindings
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, action)| *action);
+    }
+
+    /// Lists the keys for the help, in the order the actions are documented.
+    ///
+    /// # Returns
+    /// `Vec<(String, String)>` - each action's keys and what it does, for the actions that have keys.
+    pub fn help(&self) -> Vec<(String, String)> {
+        let mut help = vec![
+            (
+                key_name(Key::Enter),
+                String::from("send the line or run the command"),
+            ),
+            (
+                key_name(Key::Backspace),
+                String::from("delete the last character"),
+            ),
+            (
+                format!("{} v", key_name(Key::Escape)),
+                String::from("select messages, v marks a range, y copies, w saves"),
+            ),
+        ];
+        for (_, action, about, _) in ACTIONS {
+            let keys: Vec<String> = self
+                .bindings
+                .iter()
+                .filter(|(_, a)| a == action)
+                .map(|(k, _)| key_name(*k))
+                .collect();
+            if !keys.is_empty() {
+                help.push((keys.join(", "), String::from(*about)));
+            }
+        }
+        return help;
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// The program `/open` hands urls to.
+#[cfg(target_os = "macos")]
+const OPENER: &str = "open";
+#[cfg(windows)]
+const OPENER: &str = "explorer";
+#[cfg(not(any(target_os = "macos", windows)))]
+const OPENER: &str = "xdg-open";
+
+/// Finds the urls in some text.
+///
+/// A url starts with http://, https:// or www. and runs to the next whitespace, dropping trailing punctuation
+/// like a full stop or closing bracket.
+///
+/// # Arguments
+/// * `text` - A &str to search.
+///
+/// # Returns
+/// `Vec<(usize, usize)>` - the byte ranges of the urls, in order.
+pub fn find_urls(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+        if !c.is_whitespace() {
+            start = start.or(Some(i));
+            continue;
+        }
+        if let Some(s) = start.take() {
+            let word = &text[s..i];
+            let lower = word.to_ascii_lowercase();
+            let prefix = ["http://", "https://", "www."]
+                .iter()
+                .find(|p| lower.starts_with(*p));
+            let url = word.trim_end_matches(|c: char| ".,;:!?)]}'\"".contains(c));
+            match prefix {
+                Some(p) if url.len() > p.len() => ranges.push((s, s + url.len())),
+                _ => {}
+            }
+        }
+    }
+
+    return ranges;
+}
+
+/// Writes a number after each url in some text, so `/open` can refer to it.
+///
+/// # Arguments
+/// * `text` - A &str of the text.
+/// * `numbers` - A &[usize] of the numbers for the text's urls, in order.
+///
+/// # Returns
+/// `String` - the text with ` <n>` after each url.
+pub fn label_urls(text: &str, numbers: &[usize]) -> String {
+    let mut labelled = String::new();
+    let mut last = 0;
+
+    for ((_, end), n) in find_urls(text).into_iter().zip(numbers) {
+        labelled.push_str(&text[last..end]);
+        labelled.push_str(&format!(" <{}>", n));
+        last = end;
+    }
+    labelled.push_str(&text[last..]);
+
+    return labelled;
+}
+
+/// Opens a url in the desktop's browser without waiting for it.
+///
+/// The url is passed as a single argument, never through a shell.
+///
+/// # Arguments
+/// * `url` - A &str of the url, www. urls are opened as http.
+///
+/// # Returns
+/// `io::Result<()>` - Ok once the opener has started, or why it couldn't be.
+pub fn open_url(url: &str) -> io::Result<()> {
+    let url = if url.to_ascii_lowercase().starts_with("www.") {
+        format!("http://{}", url)
+    } else {
+        String::from(url)
+    };
+
+    let mut child = Command::new(OPENER)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap the opener once it exits so it doesn't linger as a zombie.
+    thread::spawn(move || child.wait());
+    return Ok(());
+}
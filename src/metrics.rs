@@ -0,0 +1,180 @@
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long a metrics scraper has to send its request once it connects.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The Counters a headless relay keeps, reported by `stats` and the metrics endpoint.
+///
+/// # Fields
+/// `messages_relayed` - How many messages clients have sent.
+/// `clients_dropped` - How many clients were dropped and banned for breaking the protocol.
+/// `handshake_failures` - How many of the dropped clients never completed the handshake.
+/// `reconnects` - How many clients connected from an IP that had connected before.
+#[derive(Clone, Debug, Default)]
+pub struct Counters {
+    pub messages_relayed: u64,
+    pub clients_dropped: u64,
+    pub handshake_failures: u64,
+    pub reconnects: u64,
+}
+
+/// A Snapshot of everything the metrics endpoint reports.
+///
+/// # Fields
+/// `peers` - How many clients are connected.
+/// `bytes_sent` - How many bytes were written to clients.
+/// `bytes_received` - How many bytes were read from clients.
+/// `counters` - The relay's Counters.
+pub struct Snapshot {
+    pub peers: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub counters: Counters,
+}
+
+/// A MetricsServer which answers Prometheus scrapes over plain HTTP.
+///
+/// # Fields
+/// `listener` - The nonblocking TcpListener scrapers connect to.
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    /// Binds a new MetricsServer.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the address to listen on, e.g. 127.0.0.1:9100.
+    ///
+    /// # Returns
+    /// `io::Result<MetricsServer>` - the bound server, or why it could not be bound.
+    pub fn bind(addr: &str) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        return Ok(MetricsServer { listener: listener });
+    }
+
+    /// Answers every waiting scrape.
+    ///
+    /// Called on a MetricsServer, never blocks waiting for a scraper but waits up to READ_TIMEOUT for its request.
+    ///
+    /// # Arguments
+    /// * `snapshot` - A &Snapshot of the values to report.
+    ///
+    /// # Returns
+    /// `bool` - true if a scraper was answered.
+    pub fn serve(&self, snapshot: &Snapshot) -> bool {
+        let mut served = false;
+
+        while let Ok((stream, _)) = self.listener.accept() {
+            // A scraper that hangs up or stalls only loses its own scrape.
+            let _ = respond(stream, snapshot);
+            served = true;
+        }
+
+        return served;
+    }
+}
+
+/// Reads one HTTP request and answers it, /metrics gets the metrics and anything else a 404.
+fn respond(mut stream: TcpStream, snapshot: &Snapshot) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buff = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buff)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buff[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split(' ').nth(1).unwrap_or("");
+    let (status, body) = if request.starts_with("GET ") && path == "/metrics" {
+        ("200 OK", render(snapshot))
+    } else {
+        ("404 Not Found", String::from("Not found, try /metrics\n"))
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    return stream.flush();
+}
+
+/// Renders a Snapshot in the Prometheus text exposition format.
+///
+/// # Arguments
+/// * `snapshot` - A &Snapshot of the values to report.
+///
+/// # Returns
+/// `String` - the metrics page.
+pub fn render(snapshot: &Snapshot) -> String {
+    let counters = &snapshot.counters;
+    let metrics: [(&str, &str, &str, u64); 7] = [
+        (
+            "r2wc_peers",
+            "gauge",
+            "Connected clients.",
+            snapshot.peers as u64,
+        ),
+        (
+            "r2wc_messages_relayed_total",
+            "counter",
+            "Messages sent by clients.",
+            counters.messages_relayed,
+        ),
+        (
+            "r2wc_bytes_sent_total",
+            "counter",
+            "Bytes written to clients.",
+            snapshot.bytes_sent,
+        ),
+        (
+            "r2wc_bytes_received_total",
+            "counter",
+            "Bytes read from clients.",
+            snapshot.bytes_received,
+        ),
+        (
+            "r2wc_handshake_failures_total",
+            "counter",
+            "Clients dropped before completing the handshake.",
+            counters.handshake_failures,
+        ),
+        (
+            "r2wc_clients_dropped_total",
+            "counter",
+            "Clients dropped and banned.",
+            counters.clients_dropped,
+        ),
+        (
+            "r2wc_reconnects_total",
+            "counter",
+            "Clients that connected from an IP seen before.",
+            counters.reconnects,
+        ),
+    ];
+
+    let mut page = String::new();
+    for (name, kind, help, value) in metrics.iter() {
+        // Writing to a String can't fail.
+        let _ = write!(
+            page,
+            "# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+            name, help, name, kind, name, value
+        );
+    }
+    return page;
+}
@@ -0,0 +1,178 @@
+use std::time::{Duration, Instant};
+
+/// How long a peer can go without sending anything before it shows as idle.
+const IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How long a peer that left stays in the list.
+const LEFT_FOR: Duration = Duration::from_secs(10 * 60);
+
+/// A Peer which is someone else in the chat.
+///
+/// # Fields
+/// `addr` - Who the peer is, the address the relay knows it by, or `Server`.
+/// `nick` - The nick the peer picked, None if it hasn't.
+/// `online` - Whether the peer is still connected.
+/// `away` - Whether the peer said it has gone away from the keyboard.
+/// `active` - When the peer last sent something, joined or left.
+struct Peer {
+    addr: String,
+    nick: Option<String>,
+    online: bool,
+    away: bool,
+    active: Instant,
+}
+
+/// Peers which is everyone else in the chat, kept up to date from connection events for the peers panel.
+///
+/// # Fields
+/// `peers` - The peers in the order they joined.
+pub struct Peers {
+    peers: Vec<Peer>,
+}
+
+/// Formats how long ago something was, in the largest whole unit.
+fn ago(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        return format!("{}s", secs);
+    }
+    if secs < 60 * 60 {
+        return format!("{}m", secs / 60);
+    }
+    return format!("{}h", secs / (60 * 60));
+}
+
+impl Peers {
+    /// Creates a new Peers with nobody in it.
+    ///
+    /// # Returns
+    /// `Peers` - the empty list.
+    pub fn new() -> Peers {
+        return Peers { peers: Vec::new() };
+    }
+
+    /// Adds a peer that connected, or marks it as back.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of who the peer is.
+    pub fn join(&mut self, addr: &str) {
+        self.peers
+            .retain(|p| p.online || p.active.elapsed() < LEFT_FOR);
+        match self.peers.iter_mut().find(|p| p.addr == addr) {
+            Some(peer) => {
+                peer.online = true;
+                peer.active = Instant::now();
+            }
+            None => self.peers.push(Peer {
+                addr: String::from(addr),
+                nick: None,
+                online: true,
+                away: false,
+                active: Instant::now(),
+            }),
+        }
+    }
+
+    /// Remembers the nick a peer picked.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of who the peer is.
+    /// * `nick` - A &str of the peer's nick.
+    pub fn set_nick(&mut self, addr: &str, nick: &str) {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr) {
+            peer.nick = Some(String::from(nick));
+        }
+    }
+
+    /// Remembers whether a peer is away from the keyboard.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of who the peer is.
+    /// * `away` - A bool of whether it is away.
+    pub fn set_away(&mut self, addr: &str, away: bool) {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr && p.online) {
+            if peer.away != away {
+                peer.away = away;
+                peer.active = Instant::now();
+            }
+        }
+    }
+
+    /// Marks a peer as gone.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of who the peer is.
+    pub fn leave(&mut self, addr: &str) {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr && p.online) {
+            peer.online = false;
+            peer.away = false;
+            peer.active = Instant::now();
+        }
+    }
+
+    /// Marks every peer as gone, for when the connection to them is lost.
+    pub fn leave_all(&mut self) {
+        for peer in self.peers.iter_mut().filter(|p| p.online) {
+            peer.online = false;
+            peer.away = false;
+            peer.active = Instant::now();
+        }
+    }
+
+    /// Forgets the peers that left, so the panel only lists who is still here.
+    pub fn forget_left(&mut self) {
+        self.peers.retain(|p| p.online);
+    }
+
+    /// Notes that a peer sent something.
+    ///
+    /// # Arguments
+    /// * `who` - A &str of the peer's address or nick.
+    pub fn active(&mut self, who: &str) {
+        let peer = self.peers.iter_mut().find(|p| {
+            p.online
+                && (p.addr == who
+                    || p.nick
+                        .as_ref()
+                        .map(|n| n.eq_ignore_ascii_case(who))
+                        .unwrap_or(false))
+        });
+        if let Some(peer) = peer {
+            peer.active = Instant::now();
+        }
+    }
+
+    /// The names of the peers still here, their nick or address as the panel shows them.
+    ///
+    /// # Returns
+    /// `Vec<String>` - the names in the order the peers joined.
+    pub fn names(&self) -> Vec<String> {
+        return self
+            .peers
+            .iter()
+            .filter(|p| p.online)
+            .map(|p| p.nick.clone().unwrap_or_else(|| p.addr.clone()))
+            .collect();
+    }
+
+    /// The lines of the peers panel, a name, whether the peer is here, idle, away or left, and for how long.
+    ///
+    /// # Returns
+    /// `Vec<String>` - the lines, starting with a heading.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![String::from("Peers")];
+        for peer in &self.peers {
+            let elapsed = peer.active.elapsed();
+            let presence = match (peer.online, elapsed < IDLE_AFTER) {
+                (false, _) if elapsed >= LEFT_FOR => continue,
+                (false, _) => "left",
+                (true, _) if peer.away => "away",
+                (true, true) => "here",
+                (true, false) => "idle",
+            };
+            let name = peer.nick.as_ref().unwrap_or(&peer.addr);
+            lines.push(format!("{} {} {}", name, presence, ago(elapsed)));
+        }
+        return lines;
+    }
+}
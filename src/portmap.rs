@@ -0,0 +1,219 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "upnp")]
+use igd_next::{search_gateway, PortMappingProtocol, SearchOptions};
+
+/// How long a mapping lasts before the router drops it, it is renewed at half this.
+const LEASE_SECS: u32 = 3600;
+
+/// How long to look for a UPnP router.
+#[cfg(feature = "upnp")]
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The port NAT-PMP routers listen on.
+const NAT_PMP_PORT: u16 = 5351;
+
+/// How many times a NAT-PMP request is sent before giving up, waiting twice as long each time.
+const NAT_PMP_TRIES: u32 = 4;
+
+/// A Router which forwarded a port for us.
+///
+/// # Variants
+/// `Upnp` - A UPnP internet gateway device, with the LAN address traffic is forwarded to.
+/// `NatPmp` - A NAT-PMP router at the given address.
+#[derive(Clone)]
+enum Router {
+    #[cfg(feature = "upnp")]
+    Upnp(igd_next::Gateway, SocketAddr),
+    NatPmp(Ipv4Addr),
+}
+
+/// A PortMapping which asks the router to forward a TCP port to us until it is dropped.
+///
+/// # Fields
+/// `router` - The router that forwards the port.
+/// `port` - The port, the same inside and outside.
+/// `external` - The address peers on the internet can connect to.
+/// `renewing` - Dropped to stop the thread that renews the lease.
+pub struct PortMapping {
+    router: Router,
+    port: u16,
+    external: SocketAddr,
+    renewing: Option<Sender<()>>,
+}
+
+impl PortMapping {
+    /// Asks the router to forward a port, trying UPnP and then NAT-PMP.
+    ///
+    /// UPnP is only tried when built with the `upnp` feature.
+    ///
+    /// # Arguments
+    /// * `port` - A u16 of the local port to forward, the external port is the same.
+    ///
+    /// # Returns
+    /// `Result<PortMapping, String>` - the mapping, or why each protocol failed.
+    pub fn open(port: u16) -> Result<PortMapping, String> {
+        #[cfg(feature = "upnp")]
+        let upnp = match map_upnp(port) {
+            Ok(mapping) => return Ok(mapping),
+            Err(err) => format!("UPnP: {}, ", err),
+        };
+        #[cfg(not(feature = "upnp"))]
+        let upnp = String::new();
+
+        return map_nat_pmp(port).map_err(|err| format!("{}NAT-PMP: {}", upnp, err));
+    }
+
+    /// Accessor method for the address peers on the internet can connect to.
+    ///
+    /// # Returns
+    /// `SocketAddr` - the router's external IP and the forwarded port.
+    pub fn external(&self) -> SocketAddr {
+        return self.external;
+    }
+
+    /// Renews the lease every half lease until the mapping is dropped.
+    fn keep_alive(mut self) -> PortMapping {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let router = self.router.clone();
+        let port = self.port;
+        thread::spawn(move || {
+            let half = Duration::from_secs(LEASE_SECS as u64 / 2);
+            while stopped.recv_timeout(half) == Err(RecvTimeoutError::Timeout) {
+                // A failed renewal is retried next time, the old lease is still good for a while.
+                let _ = request(&router, port, LEASE_SECS);
+            }
+        });
+        self.renewing = Some(stop);
+        return self;
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        self.renewing = None;
+        // If the router is gone the lease runs out on its own.
+        let _ = request(&self.router, self.port, 0);
+    }
+}
+
+/// Adds, renews, or with a zero lease removes, a mapping.
+fn request(router: &Router, port: u16, lease: u32) -> Result<(), String> {
+    match router {
+        #[cfg(feature = "upnp")]
+        Router::Upnp(gateway, local) => {
+            if lease == 0 {
+                return gateway
+                    .remove_port(PortMappingProtocol::TCP, port)
+                    .map_err(|e| e.to_string());
+            }
+            return gateway
+                .add_port(PortMappingProtocol::TCP, port, *local, lease, "r2wc")
+                .map_err(|e| e.to_string());
+        }
+        Router::NatPmp(gateway) => {
+            let mut msg = vec![0, 2, 0, 0];
+            msg.extend_from_slice(&port.to_be_bytes());
+            msg.extend_from_slice(&(if lease == 0 { 0 } else { port }).to_be_bytes());
+            msg.extend_from_slice(&lease.to_be_bytes());
+            return nat_pmp(*gateway, &msg, 16).map(|_| ());
+        }
+    }
+}
+
+/// Finds a UPnP router and asks it to forward the port to this machine.
+#[cfg(feature = "upnp")]
+fn map_upnp(port: u16) -> Result<PortMapping, String> {
+    let mut options = SearchOptions::default();
+    options.timeout = Some(SEARCH_TIMEOUT);
+    let gateway = search_gateway(options).map_err(|e| e.to_string())?;
+    let local = SocketAddr::new(local_ip(gateway.addr).map_err(|e| e.to_string())?, port);
+    let router = Router::Upnp(gateway.clone(), local);
+
+    request(&router, port, LEASE_SECS)?;
+    let ip = gateway.get_external_ip().map_err(|e| e.to_string())?;
+    let mapping = PortMapping {
+        router: router,
+        port: port,
+        external: SocketAddr::new(ip, port),
+        renewing: None,
+    };
+    return Ok(mapping.keep_alive());
+}
+
+/// Asks the default gateway to forward the port with NAT-PMP.
+fn map_nat_pmp(port: u16) -> Result<PortMapping, String> {
+    let gateway = default_gateway().ok_or("no default gateway found")?;
+    let router = Router::NatPmp(gateway);
+
+    let reply = nat_pmp(gateway, &[0, 0], 12)?;
+    let ip = Ipv4Addr::new(reply[8], reply[9], reply[10], reply[11]);
+    request(&router, port, LEASE_SECS)?;
+    let mapping = PortMapping {
+        router: router,
+        port: port,
+        external: SocketAddr::new(IpAddr::V4(ip), port),
+        renewing: None,
+    };
+    return Ok(mapping.keep_alive());
+}
+
+/// Sends a NAT-PMP request and waits for a successful reply of at least `len` bytes.
+fn nat_pmp(gateway: Ipv4Addr, msg: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket
+        .connect((gateway, NAT_PMP_PORT))
+        .map_err(|e| e.to_string())?;
+
+    let mut wait = Duration::from_millis(250);
+    let mut buff = [0; 16];
+    for _ in 0..NAT_PMP_TRIES {
+        socket.send(msg).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(wait))
+            .map_err(|e| e.to_string())?;
+        wait *= 2;
+
+        let n = match socket.recv(&mut buff) {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        // Replies echo the opcode plus 128, anything else is a stray packet.
+        if n < len || buff[0] != 0 || buff[1] != msg[1] + 128 {
+            continue;
+        }
+        let result = u16::from_be_bytes([buff[2], buff[3]]);
+        if result != 0 {
+            return Err(format!("router refused with result code {}", result));
+        }
+        return Ok(buff[..n].to_vec());
+    }
+
+    return Err(String::from("no reply from the router"));
+}
+
+/// Reads the IPv4 default gateway from the kernel's routing table, only available on Linux.
+fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() > 2 && fields[1] == "00000000" {
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            // The table is in host byte order, which is little endian on everything r2wc runs on.
+            return Some(Ipv4Addr::from(gateway.swap_bytes()));
+        }
+    }
+    return None;
+}
+
+/// Finds which of our addresses reaches the router, connecting a UDP socket sends nothing.
+#[cfg(feature = "upnp")]
+fn local_ip(router: SocketAddr) -> std::io::Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(router)?;
+    return Ok(socket.local_addr()?.ip());
+}
@@ -0,0 +1,733 @@
+use std::fmt;
+
+pub mod codec;
+
+/// How long an encoded frame can be, connections skip longer ones.
+const MAX_FRAME: usize = 255;
+
+/// A Frame which represents a single protocol message sent between peers.
+///
+/// Frames are encoded as a short tag followed by their fields separated by single spaces, e.g. `T 4 hello there`.
+/// They are text, apart from Binary whose data is passed on byte for byte.
+///
+/// # Variants
+/// `Text` - A chat message with the sender's id for it.
+/// `Edit` - Replaces the body of a previously sent message with the given id.
+/// `Delete` - Marks a previously sent message with the given id as deleted.
+/// `Received` - Acknowledges that a message was received, with its id, which older peers leave out.
+/// `Hello` - Sent by each side when a connection opens to complete the handshake.
+/// `Join` - Sent by a client instead of Hello to join an invite-only server with a single-use token.
+/// `Reject` - Tells the peer a frame it sent could not be parsed, and why.
+/// `Disconnect` - Tells the peer we are leaving, sent before the connection closes.
+/// `History` - A message sent before the client connected, with when (unix seconds) and who sent it.
+/// `Reply` - A chat message like Text that quotes the receiver's message with id `to`.
+/// `Relayed` - A chat message the headless relay passes on from one of its clients, with who sent it.
+/// `Meet` - Sent to a rendezvous server to wait for the other peer using the same room name.
+/// `Punch` - Sent by a rendezvous server with the address the other peer in the room connected from, and whether
+/// to also listen for them, which only one of the two does.
+/// `Nick` - Announces a client's nick and public key to the relay, which passes it on to the other clients.
+/// `Private` - An encrypted message for one client through the relay, `peer` is who it is for when sent to the
+/// relay and who sent it when the relay passes it on.
+/// `Presence` - Sent by the relay when another client joins, picks a nick, goes away, comes back or leaves, `peer`
+/// is its address.
+/// `System` - A notice from the relay for every client to show, like who joined or left and why.
+/// `Code` - One line of a code block, the `line`-th of `count`, in the language `lang` which is empty if not given.
+/// Every line of a block has the block's id, and they are not acknowledged.
+/// `Away` - Tells the peer we have gone away from the keyboard, or come back.
+/// `Resume` - Sent by a server after the handshake with a token that resumes the session for `secs` after the
+/// connection is lost, and by a client instead of Hello to resume with it, leaving out `secs`.
+/// `Ping` - Asks the peer for a Pong, with when it was sent in microseconds since the unix epoch.
+/// `Pong` - Answers a Ping with when it was sent, when the peer received it and when the peer answered, each by the
+/// clock of whoever took the time, so the latency and clock offset can be told apart.
+/// `Busy` - Sent by a server that has another client, with the client's place in line whenever it changes while it
+/// waits, or without one just before the connection closes if the waiting room is full.
+/// `Signature` - A detached signature for the sender's message with the given id, sent right after it and passed on
+/// by the relay like an edit.
+/// `Wiped` - Tells the peer we wiped our local history with `/wipe`, so whatever they keep is the only copy left.
+/// `Otr` - Our key for an off-the-record session, sent by each side after Hello when both are started with `--otr`.
+/// Every frame after the handshake is then sealed in a Binary frame.
+/// `Binary` - Raw bytes that are not read as text, e.g. encrypted data or part of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Text {
+        id: u64,
+        body: String,
+    },
+    Edit {
+        id: u64,
+        body: String,
+    },
+    Delete {
+        id: u64,
+    },
+    Received {
+        id: Option<u64>,
+    },
+    Hello,
+    Join {
+        token: String,
+    },
+    Reject {
+        reason: String,
+    },
+    Disconnect,
+    History {
+        time: i64,
+        sender: String,
+        body: String,
+    },
+    Reply {
+        id: u64,
+        to: u64,
+        body: String,
+    },
+    Relayed {
+        id: u64,
+        sender: String,
+        body: String,
+    },
+    Meet {
+        room: String,
+    },
+    Punch {
+        addr: String,
+        listen: bool,
+    },
+    Nick {
+        nick: String,
+        key: String,
+    },
+    Private {
+        id: u64,
+        peer: String,
+        data: String,
+    },
+    Presence {
+        peer: String,
+        online: bool,
+        away: bool,
+        nick: Option<String>,
+    },
+    System {
+        body: String,
+    },
+    Code {
+        id: u64,
+        line: u32,
+        count: u32,
+        lang: String,
+        text: String,
+    },
+    Away {
+        away: bool,
+    },
+    Resume {
+        token: String,
+        secs: Option<u64>,
+    },
+    Ping {
+        sent: i64,
+    },
+    Pong {
+        sent: i64,
+        received: i64,
+        replied: i64,
+    },
+    Busy {
+        position: Option<u32>,
+    },
+    Signature {
+        id: u64,
+        signature: String,
+    },
+    Wiped,
+    Otr {
+        key: String,
+    },
+    Binary {
+        data: Vec<u8>,
+    },
+}
+
+/// The tag of a Ping frame, which peers from before pings reject.
+pub const PING_TAG: &str = "I";
+
+/// How the reason of a Reject frame for an unknown tag starts, followed by the tag in quotes.
+///
+/// Peers tell which frames the other doesn't know by it, so it has to stay the same.
+const UNKNOWN_TAG: &str = "unknown tag ";
+
+/// A FrameError which explains why received bytes are not a valid Frame.
+///
+/// # Variants
+/// `Empty` - The message had no content.
+/// `UnknownTag` - The frame's tag is not one we know.
+/// `MissingField` - A required field was missing or empty.
+/// `BadId` - The id or time field was not a number.
+/// `TrailingData` - A frame that takes no fields was followed by more data.
+/// `TooLong` - The frame was over the given number of bytes a frame may have, so it was skipped unread.
+/// `Unsealed` - The frame was sent in the clear during an off-the-record session.
+/// `BadSeal` - The sealed frame was tampered with, replayed or sealed for another session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameError {
+    Empty,
+    UnknownTag(String),
+    MissingField(&'static str),
+    BadId(String),
+    TrailingData,
+    TooLong(usize),
+    Unsealed,
+    BadSeal,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Empty => return write!(f, "empty frame"),
+            FrameError::UnknownTag(tag) => return write!(f, "{}\"{}\"", UNKNOWN_TAG, tag),
+            FrameError::MissingField(field) => return write!(f, "missing {}", field),
+            FrameError::BadId(id) => return write!(f, "bad id \"{}\"", id),
+            FrameError::TrailingData => return write!(f, "unexpected fields"),
+            FrameError::TooLong(max) => return write!(f, "frame over {} bytes", max),
+            FrameError::Unsealed => return write!(f, "frame sent in the clear off the record"),
+            FrameError::BadSeal => return write!(f, "sealed frame failed its check"),
+        }
+    }
+}
+
+impl Frame {
+    /// Encodes a Frame into a String, for showing or storing it.
+    ///
+    /// Called on a Frame, the same as `to_bytes` except a Binary frame's data that isn't UTF-8 is replaced.
+    ///
+    /// # Returns
+    /// `String` - the encoded frame.
+    pub fn encode(&self) -> String {
+        match self {
+            Frame::Text { id, body } => return format!("T {} {}", id, body),
+            Frame::Edit { id, body } => return format!("E {} {}", id, body),
+            Frame::Delete { id } => return format!("D {}", id),
+            Frame::Received { id: Some(id) } => return format!("R {}", id),
+            Frame::Received { id: None } => return String::from("R"),
+            Frame::Hello => return String::from("H"),
+            Frame::Join { token } => return format!("J {}", token),
+            Frame::Reject { reason } => return format!("X {}", reason),
+            Frame::Disconnect => return String::from("Q"),
+            Frame::Wiped => return String::from("WP"),
+            Frame::History { time, sender, body } => {
+                return format!("B {} {} {}", time, sender, body)
+            }
+            Frame::Reply { id, to, body } => return format!("P {} {} {}", id, to, body),
+            Frame::Relayed { id, sender, body } => return format!("F {} {} {}", id, sender, body),
+            Frame::Meet { room } => return format!("M {}", room),
+            Frame::Punch { addr, listen } => return format!("A {} {}", addr, *listen as u8),
+            Frame::Nick { nick, key } => return format!("N {} {}", nick, key),
+            Frame::Private { id, peer, data } => return format!("W {} {} {}", id, peer, data),
+            Frame::Presence {
+                peer,
+                online,
+                away,
+                nick,
+            } => {
+                // Away is only sent for a client that is here, as a third state after left and here.
+                let state = if *online && *away { 2 } else { *online as u8 };
+                match nick {
+                    Some(nick) => return format!("L {} {} {}", peer, state, nick),
+                    None => return format!("L {} {}", peer, state),
+                }
+            }
+            Frame::System { body } => return format!("S {}", body),
+            Frame::Code {
+                id,
+                line,
+                count,
+                lang,
+                text,
+            } => {
+                let lang = if lang.is_empty() { "-" } else { lang };
+                return format!("C {} {}/{} {} {}", id, line, count, lang, text);
+            }
+            Frame::Away { away } => return format!("Z {}", *away as u8),
+            Frame::Resume { token, secs } => match secs {
+                Some(secs) => return format!("U {} {}", token, secs),
+                None => return format!("U {}", token),
+            },
+            Frame::Ping { sent } => return format!("{} {}", PING_TAG, sent),
+            Frame::Pong {
+                sent,
+                received,
+                replied,
+            } => return format!("O {} {} {}", sent, received, replied),
+            Frame::Busy {
+                position: Some(position),
+            } => return format!("K {}", position),
+            Frame::Busy { position: None } => return String::from("K"),
+            Frame::Signature { id, signature } => return format!("V {} {}", id, signature),
+            Frame::Otr { key } => return format!("Y {}", key),
+            Frame::Binary { data } => return format!("G {}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Encodes a Frame into the bytes that are written to the socket.
+    ///
+    /// Called on a Frame.
+    ///
+    /// # Returns
+    /// `Vec<u8>` - the encoded frame, a Binary frame's data as it is.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Frame::Binary { data } => {
+                let mut buff = Vec::with_capacity(2 + data.len());
+                buff.extend_from_slice(b"G ");
+                buff.extend_from_slice(data);
+                return buff;
+            }
+            _ => return self.encode().into_bytes(),
+        }
+    }
+
+    /// Whether a frame is short enough for a connection to pass on.
+    ///
+    /// Called on a Frame.
+    ///
+    /// # Returns
+    /// `bool` - true if the encoded frame is at most MAX_FRAME bytes.
+    pub fn fits(&self) -> bool {
+        return self.to_bytes().len() <= MAX_FRAME;
+    }
+
+    /// The tag a Reject frame says the peer doesn't know, telling apart a peer from before that frame was added.
+    ///
+    /// Called on a Frame, the reason is read back as the `FrameError::UnknownTag` every build rejects it with.
+    ///
+    /// # Returns
+    /// `Option<&str>` - the unknown tag, None for any other frame or reason.
+    pub fn unknown_tag(&self) -> Option<&str> {
+        match self {
+            Frame::Reject { reason } => {
+                return reason
+                    .strip_prefix(UNKNOWN_TAG)
+                    .and_then(|tag| tag.strip_prefix('"'))
+                    .and_then(|tag| tag.strip_suffix('"'))
+            }
+            _ => return None,
+        }
+    }
+
+    /// The id of the message a frame carries, for the frames that show up in the sender's chat.
+    ///
+    /// Called on a Frame.
+    ///
+    /// # Returns
+    /// `Option<u64>` - the id of a Text, Reply or Private frame, None for any other frame.
+    pub fn message_id(&self) -> Option<u64> {
+        match self {
+            Frame::Text { id, .. } | Frame::Reply { id, .. } | Frame::Private { id, .. } => {
+                return Some(*id)
+            }
+            _ => return None,
+        }
+    }
+
+    /// Decodes a Frame from a message read off the socket.
+    ///
+    /// # Arguments
+    /// * `msg` - A &str of the received message.
+    ///
+    /// # Returns
+    /// `Option<Frame>` - The decoded frame, or None if the message is not a valid frame.
+    pub fn decode(msg: &str) -> Option<Frame> {
+        return Frame::parse(msg.as_bytes()).ok();
+    }
+
+    /// Parses a Frame from the raw bytes of a message, never panicking on bad input.
+    ///
+    /// A Binary frame's data is kept byte for byte. Anything else is text, where invalid UTF-8 is replaced rather
+    /// than rejected and control characters are replaced so a peer can't send terminal escape sequences.
+    ///
+    /// # Arguments
+    /// * `buff` - A &[u8] of the received message.
+    ///
+    /// # Returns
+    /// `Result<Frame, FrameError>` - The parsed frame, or why it is not valid.
+    pub fn parse(buff: &[u8]) -> Result<Frame, FrameError> {
+        if buff.is_empty() {
+            return Err(FrameError::Empty);
+        }
+        // The data is the rest of the frame, so it is taken before anything is read as text.
+        if buff.starts_with(b"G ") {
+            return Ok(Frame::Binary {
+                data: buff[2..].to_vec(),
+            });
+        }
+
+        let msg = String::from_utf8_lossy(buff);
+        let mut parts = msg.splitn(3, ' ');
+        let tag = parts.next().unwrap_or("");
+        let id = parts.next();
+        let rest = parts.next();
+
+        match tag {
+            "T" | "E" => {
+                let id = parse_id(id)?;
+                let body = sanitize(rest.unwrap_or(""));
+                if tag == "T" {
+                    return Ok(Frame::Text { id: id, body: body });
+                }
+                if body.is_empty() {
+                    return Err(FrameError::MissingField("body"));
+                }
+                return Ok(Frame::Edit { id: id, body: body });
+            }
+            "D" => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                return Ok(Frame::Delete { id: parse_id(id)? });
+            }
+            "R" => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                let id = match id {
+                    Some(_) => Some(parse_id(id)?),
+                    None => None,
+                };
+                return Ok(Frame::Received { id: id });
+            }
+            "H" | "Q" | "WP" => {
+                if id.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                match tag {
+                    "H" => return Ok(Frame::Hello),
+                    "Q" => return Ok(Frame::Disconnect),
+                    _ => return Ok(Frame::Wiped),
+                }
+            }
+            "B" => {
+                let time = match id {
+                    Some(time) if !time.is_empty() => time
+                        .parse::<i64>()
+                        .map_err(|_| FrameError::BadId(sanitize(time)))?,
+                    _ => return Err(FrameError::MissingField("time")),
+                };
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let sender = sanitize(rest.next().unwrap_or(""));
+                if sender.is_empty() {
+                    return Err(FrameError::MissingField("sender"));
+                }
+                return Ok(Frame::History {
+                    time: time,
+                    sender: sender,
+                    body: sanitize(rest.next().unwrap_or("")),
+                });
+            }
+            "P" => {
+                let id = parse_id(id)?;
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let to = parse_id(rest.next())?;
+                return Ok(Frame::Reply {
+                    id: id,
+                    to: to,
+                    body: sanitize(rest.next().unwrap_or("")),
+                });
+            }
+            "F" => {
+                let id = parse_id(id)?;
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let sender = sanitize(rest.next().unwrap_or(""));
+                if sender.is_empty() {
+                    return Err(FrameError::MissingField("sender"));
+                }
+                return Ok(Frame::Relayed {
+                    id: id,
+                    sender: sender,
+                    body: sanitize(rest.next().unwrap_or("")),
+                });
+            }
+            "N" => {
+                let nick = match id {
+                    Some(nick) if !nick.is_empty() => sanitize(nick),
+                    _ => return Err(FrameError::MissingField("nick")),
+                };
+                match rest {
+                    Some(key) if !key.is_empty() && !key.contains(' ') => {
+                        return Ok(Frame::Nick {
+                            nick: nick,
+                            key: sanitize(key),
+                        })
+                    }
+                    Some(key) if !key.is_empty() => return Err(FrameError::TrailingData),
+                    _ => return Err(FrameError::MissingField("key")),
+                }
+            }
+            "W" => {
+                let id = parse_id(id)?;
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let peer = sanitize(rest.next().unwrap_or(""));
+                if peer.is_empty() {
+                    return Err(FrameError::MissingField("peer"));
+                }
+                let data = sanitize(rest.next().unwrap_or(""));
+                if data.is_empty() {
+                    return Err(FrameError::MissingField("data"));
+                }
+                return Ok(Frame::Private {
+                    id: id,
+                    peer: peer,
+                    data: data,
+                });
+            }
+            "A" => {
+                let addr = match id {
+                    Some(addr) if !addr.is_empty() => sanitize(addr),
+                    _ => return Err(FrameError::MissingField("addr")),
+                };
+                match rest {
+                    Some("0") | Some("1") => {
+                        return Ok(Frame::Punch {
+                            addr: addr,
+                            listen: rest == Some("1"),
+                        })
+                    }
+                    Some(_) => return Err(FrameError::TrailingData),
+                    None => return Err(FrameError::MissingField("listen")),
+                }
+            }
+            "L" => {
+                let peer = match id {
+                    Some(peer) if !peer.is_empty() => sanitize(peer),
+                    _ => return Err(FrameError::MissingField("peer")),
+                };
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let (online, away) = match rest.next() {
+                    Some("0") => (false, false),
+                    Some("1") => (true, false),
+                    Some("2") => (true, true),
+                    Some("") => return Err(FrameError::MissingField("online")),
+                    _ => return Err(FrameError::TrailingData),
+                };
+                let nick = match rest.next() {
+                    Some(nick) if !nick.is_empty() && !nick.contains(' ') => Some(sanitize(nick)),
+                    Some(_) => return Err(FrameError::TrailingData),
+                    None => None,
+                };
+                return Ok(Frame::Presence {
+                    peer: peer,
+                    online: online,
+                    away: away,
+                    nick: nick,
+                });
+            }
+            "J" | "M" | "Y" => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                let field = match (id, tag) {
+                    (Some(field), _) if !field.is_empty() => sanitize(field),
+                    (_, "J") => return Err(FrameError::MissingField("token")),
+                    (_, "Y") => return Err(FrameError::MissingField("key")),
+                    _ => return Err(FrameError::MissingField("room")),
+                };
+                match tag {
+                    "J" => return Ok(Frame::Join { token: field }),
+                    "Y" => return Ok(Frame::Otr { key: field }),
+                    _ => return Ok(Frame::Meet { room: field }),
+                }
+            }
+            "X" => {
+                let reason = match (id, rest) {
+                    (Some(first), Some(rest)) => format!("{} {}", first, rest),
+                    (Some(first), None) => String::from(first),
+                    _ => String::new(),
+                };
+                return Ok(Frame::Reject {
+                    reason: sanitize(&reason),
+                });
+            }
+            "S" => {
+                let body = match (id, rest) {
+                    (Some(first), Some(rest)) => format!("{} {}", first, rest),
+                    (Some(first), None) if !first.is_empty() => String::from(first),
+                    _ => return Err(FrameError::MissingField("body")),
+                };
+                return Ok(Frame::System {
+                    body: sanitize(&body),
+                });
+            }
+            "C" => {
+                let id = parse_id(id)?;
+                let mut rest = rest.unwrap_or("").splitn(3, ' ');
+                let place = rest.next().unwrap_or("");
+                let (line, count) = match place.split_once('/') {
+                    Some((line, count)) => (line.parse::<u32>(), count.parse::<u32>()),
+                    None if place.is_empty() => return Err(FrameError::MissingField("line")),
+                    None => return Err(FrameError::BadId(sanitize(place))),
+                };
+                let (line, count) = match (line, count) {
+                    (Ok(line), Ok(count)) if line < count => (line, count),
+                    _ => return Err(FrameError::BadId(sanitize(place))),
+                };
+                let lang = match rest.next() {
+                    Some("-") | None => String::new(),
+                    Some(lang) => sanitize(lang),
+                };
+                return Ok(Frame::Code {
+                    id: id,
+                    line: line,
+                    count: count,
+                    lang: lang,
+                    text: sanitize(rest.next().unwrap_or("")),
+                });
+            }
+            "Z" => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                match id {
+                    Some("0") => return Ok(Frame::Away { away: false }),
+                    Some("1") => return Ok(Frame::Away { away: true }),
+                    Some(away) if !away.is_empty() => {
+                        return Err(FrameError::BadId(sanitize(away)))
+                    }
+                    _ => return Err(FrameError::MissingField("away")),
+                }
+            }
+            "U" => {
+                let token = match id {
+                    Some(token) if !token.is_empty() => sanitize(token),
+                    _ => return Err(FrameError::MissingField("token")),
+                };
+                let secs = match rest {
+                    Some(secs) => Some(
+                        secs.parse::<u64>()
+                            .map_err(|_| FrameError::BadId(sanitize(secs)))?,
+                    ),
+                    None => None,
+                };
+                return Ok(Frame::Resume {
+                    token: token,
+                    secs: secs,
+                });
+            }
+            PING_TAG => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                return Ok(Frame::Ping {
+                    sent: parse_time(id, "sent")?,
+                });
+            }
+            "O" => {
+                let mut rest = rest.unwrap_or("").splitn(2, ' ');
+                let sent = parse_time(id, "sent")?;
+                let received = parse_time(rest.next(), "received")?;
+                let replied = parse_time(rest.next(), "replied")?;
+                return Ok(Frame::Pong {
+                    sent: sent,
+                    received: received,
+                    replied: replied,
+                });
+            }
+            "K" => {
+                if rest.is_some() {
+                    return Err(FrameError::TrailingData);
+                }
+                let position = match id {
+                    Some(position) => Some(
+                        position
+                            .parse::<u32>()
+                            .map_err(|_| FrameError::BadId(sanitize(position)))?,
+                    ),
+                    None => None,
+                };
+                return Ok(Frame::Busy { position: position });
+            }
+            "V" => {
+                let id = parse_id(id)?;
+                match rest {
+                    Some(signature) if !signature.is_empty() && !signature.contains(' ') => {
+                        return Ok(Frame::Signature {
+                            id: id,
+                            signature: sanitize(signature),
+                        })
+                    }
+                    Some(signature) if !signature.is_empty() => {
+                        return Err(FrameError::TrailingData)
+                    }
+                    _ => return Err(FrameError::MissingField("signature")),
+                }
+            }
+            // Without the space after the tag there is no data, not even an empty one.
+            "G" => return Err(FrameError::MissingField("data")),
+            _ => return Err(FrameError::UnknownTag(sanitize(tag))),
+        }
+    }
+}
+
+/// Splits a code block into the Code frames that carry it, one for each line.
+///
+/// # Arguments
+/// * `id` - A u64 of the block's id.
+/// * `lang` - A &str of the language it is in, empty if not given.
+/// * `lines` - A &[String] of its lines.
+///
+/// # Returns
+/// `(Vec<Frame>, usize)` - the frames, and how many lines were cut short because they didn't fit in a frame.
+pub fn code_frames(id: u64, lang: &str, lines: &[String]) -> (Vec<Frame>, usize) {
+    let mut cut = 0;
+    let mut frames = Vec::new();
+    for (i, text) in lines.iter().enumerate() {
+        let mut frame = Frame::Code {
+            id: id,
+            line: i as u32,
+            count: lines.len() as u32,
+            lang: String::from(lang),
+            text: text.clone(),
+        };
+        let over = frame.encode().len().saturating_sub(MAX_FRAME);
+        if let (true, Frame::Code { text, .. }) = (over > 0, &mut frame) {
+            let mut end = text.len().saturating_sub(over);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            cut += 1;
+        }
+        frames.push(frame);
+    }
+    return (frames, cut);
+}
+
+/// Parses a frame's id field.
+fn parse_id(id: Option<&str>) -> Result<u64, FrameError> {
+    match id {
+        Some(id) if !id.is_empty() => {
+            return id
+                .parse::<u64>()
+                .map_err(|_| FrameError::BadId(sanitize(id)));
+        }
+        _ => return Err(FrameError::MissingField("id")),
+    }
+}
+
+/// Parses a frame's timestamp field, in microseconds since the unix epoch.
+fn parse_time(time: Option<&str>, field: &'static str) -> Result<i64, FrameError> {
+    match time {
+        Some(time) if !time.is_empty() => {
+            return time
+                .parse::<i64>()
+                .map_err(|_| FrameError::BadId(sanitize(time)));
+        }
+        _ => return Err(FrameError::MissingField(field)),
+    }
+}
+
+/// Replaces control characters so received text is safe to draw.
+fn sanitize(text: &str) -> String {
+    return text
+        .chars()
+        .map(|c| if c.is_control() { '\u{FFFD}' } else { c })
+        .collect();
+}
@@ -0,0 +1,460 @@
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::str;
+
+use super::{Frame, FrameError};
+
+/// How many bytes the length in front of every frame takes, a big-endian u32.
+pub const HEADER: usize = 4;
+
+/// Puts the length in front of a message, which is how every frame goes on the wire.
+///
+/// # Arguments
+/// * `msg` - A &[u8] of the encoded frame.
+/// * `msg_size` - A usize of the most bytes a frame may have, longer messages are cut off.
+///
+/// # Returns
+/// `Vec<u8>` - the bytes to write.
+pub fn prefix(msg: &[u8], msg_size: usize) -> Vec<u8> {
+    let mut buff = Vec::with_capacity(HEADER + msg.len().min(msg_size));
+    prefix_into(msg, msg_size, &mut buff);
+    return buff;
+}
+
+/// Prefixes a message like `prefix`, appending it to a buffer that is kept between sends so sending doesn't
+/// allocate.
+///
+/// # Arguments
+/// * `msg` - A &[u8] of the encoded frame.
+/// * `msg_size` - A usize of the most bytes a frame may have, longer messages are cut off.
+/// * `buff` - A &mut Vec<u8> to append the prefixed message to.
+pub fn prefix_into(msg: &[u8], msg_size: usize, buff: &mut Vec<u8>) {
+    let len = cut(msg, msg_size);
+    buff.extend_from_slice(&(len as u32).to_be_bytes());
+    buff.extend_from_slice(&msg[..len]);
+}
+
+/// How much of a message fits in a frame.
+///
+/// # Arguments
+/// * `msg` - A &[u8] of the encoded frame.
+/// * `max` - A usize of the most bytes it may have.
+///
+/// # Returns
+/// `usize` - how many bytes to keep, text is cut between characters so what is left is still valid UTF-8.
+pub fn cut(msg: &[u8], max: usize) -> usize {
+    let mut len = msg.len().min(max);
+    if let Ok(text) = str::from_utf8(msg) {
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+    }
+    return len;
+}
+
+/// Encodes a Frame into the bytes written to the socket.
+///
+/// # Arguments
+/// * `frame` - A &Frame to encode.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+///
+/// # Returns
+/// `Vec<u8>` - the encoded frame, with its length in front.
+pub fn encode(frame: &Frame, msg_size: usize) -> Vec<u8> {
+    return prefix(&frame.to_bytes(), msg_size);
+}
+
+/// Decodes a Frame from the bytes of one frame read off the socket, see `Frame::parse`.
+///
+/// # Arguments
+/// * `buff` - A &[u8] of the frame without its length.
+///
+/// # Returns
+/// `Result<Frame, FrameError>` - the frame, or why it is not valid.
+pub fn decode(buff: &[u8]) -> Result<Frame, FrameError> {
+    return Frame::parse(buff);
+}
+
+/// Reads one frame from a blocking reader, for the short exchanges before a connection is handed to the network
+/// thread.
+///
+/// # Arguments
+/// * `reader` - A Read to read from, like a &TcpStream.
+/// * `msg_size` - A usize of the most bytes a frame may have.
+///
+/// # Returns
+/// `io::Result<Vec<u8>>` - the frame without its length, or the read error, InvalidData if it is too long.
+pub fn read_blocking<R: Read>(mut reader: R, msg_size: usize) -> io::Result<Vec<u8>> {
+    let mut header = [0; HEADER];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header) as usize;
+    if len > msg_size {
+        return Err(too_long(len, msg_size));
+    }
+    let mut buff = vec![0; len];
+    reader.read_exact(&mut buff)?;
+    return Ok(buff);
+}
+
+/// The error for a frame that is longer than the most a peer may send.
+fn too_long(len: usize, msg_size: usize) -> io::Error {
+    return io::Error::new(
+        ErrorKind::InvalidData,
+        format!("frame of {} bytes is over the {} byte limit", len, msg_size),
+    );
+}
+
+/// A FrameReader which collects a frame from a nonblocking reader across reads, so bytes that arrived before the
+/// reader would block are kept for the next read instead of being lost.
+///
+/// # Fields
+/// `header` - The length in front of the frame being read.
+/// `buff` - The frame being read.
+/// `filled` - How many bytes of the frame and its length have arrived.
+/// `spare` - A frame given back with `recycle`, read into next instead of allocating a new one.
+pub struct FrameReader {
+    header: [u8; HEADER],
+    buff: Vec<u8>,
+    filled: usize,
+    spare: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Creates a new FrameReader with no frame started.
+    ///
+    /// # Returns
+    ///  `FrameReader` - the newly created reader.
+    pub fn new() -> FrameReader {
+        return FrameReader {
+            header: [0; HEADER],
+            buff: Vec::new(),
+            filled: 0,
+            spare: Vec::new(),
+        };
+    }
+
+    /// Reads the rest of the current frame.
+    ///
+    /// Called on a FrameReader, only reads up to the end of the frame, so frames behind it are left in the socket
+    /// until they are asked for and a slow reader pushes back on the peer. A frame longer than `msg_size` is read
+    /// past without being kept, so the next read starts on the frame after it.
+    ///
+    /// # Arguments
+    /// * `reader` - A Read to read from, like a &TcpStream.
+    /// * `msg_size` - A usize of the most bytes a frame may have.
+    ///
+    /// # Returns
+    /// `io::Result<Option<Vec<u8>>>` - the frame once all of it has arrived, None if the reader would block first,
+    /// or the read error, UnexpectedEof if the peer closed the connection and InvalidData once a frame that is too
+    /// long has been skipped.
+    pub fn read_frame<R: Read>(
+        &mut self,
+        mut reader: R,
+        msg_size: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        while self.filled < HEADER {
+            match read_some(&mut reader, &mut self.header[self.filled..])? {
+                Some(n) => self.filled += n,
+                None => return Ok(None),
+            }
+        }
+
+        let len = u32::from_be_bytes(self.header) as usize;
+        let skip = len > msg_size;
+        if !skip {
+            self.buff.resize(len, 0);
+        }
+        let mut skipped = [0; 1024];
+        while self.filled < HEADER + len {
+            let at = self.filled - HEADER;
+            let into = if skip {
+                let left = (len - at).min(skipped.len());
+                &mut skipped[..left]
+            } else {
+                &mut self.buff[at..]
+            };
+            match read_some(&mut reader, into)? {
+                Some(n) => self.filled += n,
+                None => return Ok(None),
+            }
+        }
+
+        self.filled = 0;
+        if skip {
+            return Err(too_long(len, msg_size));
+        }
+        return Ok(Some(mem::replace(
+            &mut self.buff,
+            mem::take(&mut self.spare),
+        )));
+    }
+
+    /// Gives back a frame `read_frame` returned once it has been parsed, so the next frame is read into it.
+    ///
+    /// # Arguments
+    /// * `buff` - A Vec<u8> of the frame, its bytes are overwritten.
+    pub fn recycle(&mut self, buff: Vec<u8>) {
+        self.spare = buff;
+    }
+}
+
+/// Reads once from a nonblocking reader.
+///
+/// # Returns
+/// `io::Result<Option<usize>>` - how many bytes were read, None if it would block, or the read error,
+/// UnexpectedEof if the peer closed the connection.
+fn read_some<R: Read>(reader: &mut R, buff: &mut [u8]) -> io::Result<Option<usize>> {
+    match reader.read(buff) {
+        Ok(0) => {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "peer closed the connection",
+            ))
+        }
+        Ok(n) => return Ok(Some(n)),
+        Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+        Err(ref err) if err.kind() == ErrorKind::Interrupted => return Ok(Some(0)),
+        Err(err) => return Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self, ErrorKind, Read};
+
+    use proptest::prelude::*;
+
+    use super::{decode, encode, FrameReader};
+    use crate::protocol::{Frame, FrameError};
+
+    const MSG_SIZE: usize = 255;
+
+    /// A Read that hands out the bytes it was given in the chunks it was given them in, one chunk per read and
+    /// WouldBlock between chunks like a nonblocking socket.
+    struct Chunks {
+        chunks: VecDeque<Vec<u8>>,
+        blocked: bool,
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.blocked {
+                self.blocked = true;
+                return Err(io::Error::new(ErrorKind::WouldBlock, "no data yet"));
+            }
+            let chunk = match self.chunks.front_mut() {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.chunks.pop_front();
+                self.blocked = false;
+            }
+            return Ok(n);
+        }
+    }
+
+    /// Cuts bytes into chunks at the given points, which are taken modulo the length.
+    fn split(bytes: Vec<u8>, cuts: &[usize]) -> VecDeque<Vec<u8>> {
+        let mut cuts: Vec<usize> = cuts.iter().map(|c| c % (bytes.len() + 1)).collect();
+        cuts.push(bytes.len());
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut chunks = VecDeque::new();
+        let mut start = 0;
+        for cut in cuts {
+            if cut > start {
+                chunks.push_back(bytes[start..cut].to_vec());
+                start = cut;
+            }
+        }
+        return chunks;
+    }
+
+    /// A field that can't hold a space, like a nick or token.
+    fn word() -> impl Strategy<Value = String> {
+        return "[a-zA-Z0-9._:=+/-]{1,12}";
+    }
+
+    /// Free text, which can hold spaces but no control characters.
+    fn text() -> impl Strategy<Value = String> {
+        return "\\PC{0,40}";
+    }
+
+    /// Any frame that fits in MSG_SIZE bytes, with fields the parser keeps as they are.
+    fn frame() -> impl Strategy<Value = Frame> {
+        return prop_oneof![
+            (any::<u64>(), text()).prop_map(|(id, body)| Frame::Text { id: id, body: body }),
+            (any::<u64>(), "\\PC{1,40}").prop_map(|(id, body)| Frame::Edit { id: id, body: body }),
+            any::<u64>().prop_map(|id| Frame::Delete { id: id }),
+            any::<Option<u64>>().prop_map(|id| Frame::Received { id: id }),
+            Just(Frame::Hello),
+            word().prop_map(|token| Frame::Join { token: token }),
+            text().prop_map(|reason| Frame::Reject { reason: reason }),
+            Just(Frame::Disconnect),
+            Just(Frame::Wiped),
+            (any::<i64>(), word(), text()).prop_map(|(time, sender, body)| Frame::History {
+                time: time,
+                sender: sender,
+                body: body,
+            }),
+            (any::<u64>(), any::<u64>(), text()).prop_map(|(id, to, body)| Frame::Reply {
+                id: id,
+                to: to,
+                body: body,
+            }),
+            (any::<u64>(), word(), text()).prop_map(|(id, sender, body)| Frame::Relayed {
+                id: id,
+                sender: sender,
+                body: body,
+            }),
+            word().prop_map(|room| Frame::Meet { room: room }),
+            (word(), any::<bool>()).prop_map(|(addr, listen)| Frame::Punch {
+                addr: addr,
+                listen: listen,
+            }),
+            (word(), word()).prop_map(|(nick, key)| Frame::Nick {
+                nick: nick,
+                key: key
+            }),
+            (any::<u64>(), word(), "\\PC{1,40}").prop_map(|(id, peer, data)| Frame::Private {
+                id: id,
+                peer: peer,
+                data: data,
+            }),
+            (
+                word(),
+                any::<bool>(),
+                any::<bool>(),
+                proptest::option::of(word())
+            )
+                .prop_map(|(peer, online, away, nick)| Frame::Presence {
+                    peer: peer,
+                    online: online,
+                    away: online && away,
+                    nick: nick,
+                }),
+            "\\PC{1,40}".prop_map(|body| Frame::System { body: body }),
+            (any::<u64>(), 1..1000u32, "[a-z]{0,8}", text()).prop_flat_map(
+                |(id, count, lang, text)| (0..count).prop_map(move |line| Frame::Code {
+                    id: id,
+                    line: line,
+                    count: count,
+                    lang: lang.clone(),
+                    text: text.clone(),
+                })
+            ),
+            any::<bool>().prop_map(|away| Frame::Away { away: away }),
+            (word(), any::<Option<u64>>()).prop_map(|(token, secs)| Frame::Resume {
+                token: token,
+                secs: secs,
+            }),
+            any::<i64>().prop_map(|sent| Frame::Ping { sent: sent }),
+            any::<Option<u32>>().prop_map(|position| Frame::Busy { position: position }),
+            (any::<u64>(), "[A-Za-z0-9+/=]{1,160}").prop_map(|(id, signature)| Frame::Signature {
+                id: id,
+                signature: signature,
+            }),
+            "[A-Za-z0-9+/=]{1,44}".prop_map(|key| Frame::Otr { key: key }),
+            proptest::collection::vec(any::<u8>(), 0..200)
+                .prop_map(|data| Frame::Binary { data: data }),
+            (any::<i64>(), any::<i64>(), any::<i64>()).prop_map(|(sent, received, replied)| {
+                Frame::Pong {
+                    sent: sent,
+                    received: received,
+                    replied: replied,
+                }
+            }),
+        ];
+    }
+
+    proptest! {
+        /// Frames written back to back survive being read in chunks cut anywhere, several frames to a chunk or a
+        /// frame across several chunks.
+        #[test]
+        fn frames_survive_fragmentation_and_coalescing(
+            frames in proptest::collection::vec(frame(), 1..8),
+            cuts in proptest::collection::vec(any::<usize>(), 0..32),
+        ) {
+            let bytes: Vec<u8> = frames.iter().flat_map(|f| encode(f, MSG_SIZE)).collect();
+            let mut chunks = Chunks { chunks: split(bytes, &cuts), blocked: false };
+            let mut reader = FrameReader::new();
+            let mut decoded = Vec::new();
+            while decoded.len() < frames.len() {
+                if let Some(buff) = reader.read_frame(&mut chunks, MSG_SIZE).unwrap() {
+                    decoded.push(decode(&buff).unwrap());
+                    // The next frame overwrites this one, none of it may be left behind.
+                    reader.recycle(buff);
+                }
+            }
+            prop_assert_eq!(decoded, frames);
+        }
+
+        /// A frame over the limit is skipped whole and reported, and the frame after it is read as if it wasn't there.
+        #[test]
+        fn an_oversized_frame_is_skipped(
+            frame in frame(),
+            extra in 1..2000usize,
+            cuts in proptest::collection::vec(any::<usize>(), 0..32),
+        ) {
+            let len = MSG_SIZE + extra;
+            let mut bytes = (len as u32).to_be_bytes().to_vec();
+            // Bytes that would look like frames if they were read as one, with lengths of their own.
+            bytes.extend(encode(&frame, MSG_SIZE).into_iter().cycle().take(len));
+            bytes.extend(encode(&frame, MSG_SIZE));
+            let mut chunks = Chunks { chunks: split(bytes, &cuts), blocked: false };
+            let mut reader = FrameReader::new();
+            let mut rejected = false;
+            loop {
+                match reader.read_frame(&mut chunks, MSG_SIZE) {
+                    Ok(None) => continue,
+                    Ok(Some(buff)) => {
+                        prop_assert!(rejected, "read a frame before the oversized one was skipped");
+                        prop_assert_eq!(decode(&buff).unwrap(), frame);
+                        break;
+                    }
+                    Err(err) => {
+                        prop_assert_eq!(err.kind(), ErrorKind::InvalidData);
+                        prop_assert!(!rejected, "rejected twice");
+                        rejected = true;
+                    }
+                }
+            }
+        }
+
+        /// A peer is told which tag it sent that we don't know, and reads it back from the Reject.
+        #[test]
+        fn an_unknown_tag_is_read_back_from_its_reject(tag in "[A-Z]{1,2}") {
+            let reject = Frame::Reject { reason: FrameError::UnknownTag(tag.clone()).to_string() };
+            let received = decode(&encode(&reject, MSG_SIZE)[super::HEADER..]).unwrap();
+            prop_assert_eq!(received.unknown_tag(), Some(tag.as_str()));
+
+            let other = Frame::Reject { reason: FrameError::BadId(tag).to_string() };
+            prop_assert_eq!(other.unknown_tag(), None);
+        }
+
+        /// A connection that closes part way through a frame is an error, never a short frame.
+        #[test]
+        fn a_cut_off_frame_is_an_error(frame in frame(), keep in 0..MSG_SIZE) {
+            let mut bytes = encode(&frame, MSG_SIZE);
+            let keep = keep % bytes.len();
+            bytes.truncate(keep);
+            let mut chunks = Chunks { chunks: split(bytes, &[]), blocked: true };
+            let mut reader = FrameReader::new();
+            loop {
+                match reader.read_frame(&mut chunks, MSG_SIZE) {
+                    Ok(None) => continue,
+                    Ok(Some(_)) => prop_assert!(false, "read a frame from {} bytes", keep),
+                    Err(err) => {
+                        prop_assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+#[cfg(unix)]
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::io::Read;
+
+#[cfg(not(unix))]
+extern crate getrandom;
+
+/// Reads random bytes from the OS.
+#[cfg(unix)]
+pub fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    return Ok(bytes);
+}
+
+/// Reads random bytes from the OS, which has no /dev/urandom.
+#[cfg(not(unix))]
+pub fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes)?;
+    return Ok(bytes);
+}
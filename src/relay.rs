@@ -0,0 +1,1026 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::prelude::*;
+use serde_json::{json, Value};
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+use crate::connection::{
+    now_micros, AcceptFilter, Connection, PeerId, Reason, RecentIds, Throttle,
+};
+use crate::control::{
+    ControlCommand, ControlSocket, PeerInfo, RelayStats, RpcError, COMMAND_FAILED,
+};
+use crate::metrics::{Counters, MetricsServer, Snapshot};
+use crate::protocol::Frame;
+use crate::retention::{cutoff, Retention};
+use crate::settings::{RelaySettings, Settings};
+use crate::store::{Store, StoredMessage};
+use crate::transcript::Transcript;
+
+/// How long the relay sleeps when there was nothing to do.
+const IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// A Client which is connected to the relay.
+///
+/// # Fields
+/// `con` - The client's Connection.
+/// `id` - The PeerId of the client, which frames meant only for it are sent to.
+/// `who` - The client's address.
+/// `welcomed` - Whether the client was sent the motd after completing the handshake.
+/// `reconnect` - Whether the client's IP connected before, so it is sent the history it missed.
+/// `last_seen` - When the store last saw the client's IP, so it is sent only what it missed, None without a store.
+/// `nick` - The nick and public key the client announced, None until it does.
+/// `seen` - The ids of the client's latest messages, so one it sends twice is only relayed once.
+/// `away` - Whether the client said it has gone away from the keyboard.
+struct Client {
+    con: Connection,
+    id: PeerId,
+    who: String,
+    welcomed: bool,
+    reconnect: bool,
+    last_seen: Option<i64>,
+    nick: Option<(String, String)>,
+    seen: RecentIds,
+    away: bool,
+}
+
+/// A Parked session which is what the relay keeps of a client that lost its connection, for when it resumes.
+///
+/// # Fields
+/// `seen` - The ids of the client's latest messages, so one it sends again after resuming is only relayed once.
+/// `left` - When the connection was lost, so it is sent what it missed since.
+/// `until` - When its resume token expires and the session is forgotten.
+struct Parked {
+    seen: RecentIds,
+    left: i64,
+    until: Instant,
+}
+
+/// The longest nick a client may use.
+const MAX_NICK: usize = 16;
+
+/// A Relay which runs the server without a ui, passing messages between any number of clients.
+///
+/// # Fields
+/// `server` - The TcpListener clients connect to.
+/// `template` - A Connection without a peer, each client's Connection shares its settings, throttle and bans.
+/// `clients` - The connected clients, in the order they connected.
+/// `control` - A Option<ControlSocket> admin commands are read from.
+/// `transcript` - A Option<Transcript> to log to, None logs to stdout.
+/// `next_id` - The id of the next message the server itself sends.
+/// `motd` - A Option<String> sent to each client once it completes the handshake.
+/// `started` - When the relay started, for its uptime.
+/// `counters` - The relay's Counters.
+/// `seen` - Every IP a client has connected from, to count reconnects.
+/// `metrics` - A Option<MetricsServer> scrapes are answered on.
+/// `store` - A Option<Store> messages and peers are recorded in.
+/// `history` - The most recent messages, sent to reconnecting clients when there is no store.
+/// `history_len` - How many messages reconnecting clients are sent, 0 disables history.
+/// `live` - The allow and deny lists, send limit and client limit in use, which `configure` changes.
+/// `config` - The config file read again on SIGHUP, None if there is none.
+/// `parked` - The sessions of clients that lost their connection, by the token they can resume with.
+/// `retention` - The retention policy the store and history are kept to.
+pub struct Relay {
+    server: TcpListener,
+    template: Connection,
+    clients: Vec<Client>,
+    control: Option<ControlSocket>,
+    transcript: Option<Transcript>,
+    next_id: u64,
+    motd: Option<String>,
+    started: Instant,
+    counters: Counters,
+    seen: HashSet<IpAddr>,
+    metrics: Option<MetricsServer>,
+    store: Option<Store>,
+    history: VecDeque<StoredMessage>,
+    history_len: usize,
+    live: RelaySettings,
+    config: Option<PathBuf>,
+    parked: HashMap<String, Parked>,
+    retention: Retention,
+}
+
+impl Relay {
+    /// Creates a new Relay.
+    ///
+    /// # Arguments
+    /// * `template` - A Connection configured with the server's flags.
+    /// * `server` - A TcpListener to accept clients from.
+    /// * `control` - A Option<ControlSocket> to read admin commands from.
+    /// * `transcript` - A Option<Transcript> to log to, None logs to stdout.
+    /// * `metrics` - A Option<MetricsServer> to answer scrapes on.
+    /// * `store` - A Option<Store> to record messages and peers in.
+    ///
+    /// # Returns
+    ///  `Relay` - the newly created relay with no clients.
+    pub fn new(
+        template: Connection,
+        server: TcpListener,
+        control: Option<ControlSocket>,
+        transcript: Option<Transcript>,
+        metrics: Option<MetricsServer>,
+        store: Option<Store>,
+    ) -> Relay {
+        return Relay {
+            server: server,
+            template: template,
+            clients: Vec::new(),
+            control: control,
+            transcript: transcript,
+            next_id: 0,
+            motd: None,
+            started: Instant::now(),
+            counters: Counters::default(),
+            seen: HashSet::new(),
+            metrics: metrics,
+            store: store,
+            history: VecDeque::new(),
+            history_len: 20,
+            live: RelaySettings::default(),
+            config: None,
+            parked: HashMap::new(),
+            retention: Retention::new(None, None),
+        };
+    }
+
+    /// Sets how many recent messages reconnecting clients are sent.
+    ///
+    /// # Arguments
+    /// * `len` - A usize of how many messages to send, 0 disables history.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    /// Sets the retention policy the store and history are kept to, enforced every minute.
+    ///
+    /// # Arguments
+    /// * `max_messages` - A Option<usize> of the most messages kept, None to keep any number.
+    /// * `max_age` - A Option<Duration> of how old a message can get, None to keep messages however old.
+    pub fn set_retention(&mut self, max_messages: Option<usize>, max_age: Option<Duration>) {
+        self.retention.set_limits(max_messages, max_age);
+    }
+
+    /// Applies settings while the relay runs, anything left out keeps its current value.
+    ///
+    /// Nothing is changed if the allow or deny list is not valid.
+    ///
+    /// # Arguments
+    /// * `changes` - A &RelaySettings to apply.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or which range is not valid.
+    pub fn configure(&mut self, changes: &RelaySettings) -> Result<(), String> {
+        let allow = changes.allow.clone().or_else(|| self.live.allow.clone());
+        let deny = changes.deny.clone().or_else(|| self.live.deny.clone());
+        let filter = AcceptFilter::new(allow.as_deref(), deny.as_deref())?;
+        self.template.set_accept_filter(filter);
+        self.live.allow = allow;
+        self.live.deny = deny;
+
+        if let Some(max_kbps) = changes.max_kbps {
+            if self.live.max_kbps != Some(max_kbps) {
+                // Every client shares one limit, so they all get the new Throttle.
+                let throttle = if max_kbps > 0 {
+                    Some(Throttle::new(max_kbps))
+                } else {
+                    None
+                };
+                self.template.set_throttle(throttle.clone());
+                for client in self.clients.iter_mut() {
+                    client.con.set_throttle(throttle.clone());
+                }
+                self.live.max_kbps = Some(max_kbps);
+            }
+        }
+
+        if let Some(max_clients) = changes.max_clients {
+            // Clients already connected stay, only new ones are turned away.
+            self.live.max_clients = Some(max_clients);
+        }
+
+        if let Some(motd) = &changes.motd {
+            self.motd = if motd.is_empty() {
+                None
+            } else {
+                Some(motd.clone())
+            };
+        }
+        return Ok(());
+    }
+
+    /// Sets the config file whose relay settings are applied again on SIGHUP.
+    ///
+    /// # Arguments
+    /// * `path` - A Option<PathBuf> of the config file, None to ignore SIGHUP.
+    pub fn set_config_path(&mut self, path: Option<PathBuf>) {
+        self.config = path;
+    }
+
+    /// Reads the config file again and applies its relay settings, logging what happened.
+    fn reload(&mut self) {
+        let path = match &self.config {
+            Some(path) => path.clone(),
+            None => {
+                self.log("Got SIGHUP but there is no config file to reload");
+                return;
+            }
+        };
+        let applied = Settings::load(Some(path.clone())).and_then(|settings| {
+            self.set_retention(settings.max_messages(), settings.max_age());
+            return self.configure(&settings.relay.unwrap_or_default());
+        });
+        match applied {
+            Ok(()) => self.log(&format!("Reloaded config {}", path.display())),
+            Err(err) => self.log(&format!(
+                "Could not reload config {}, kept the old settings, {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+
+    /// Runs the relay until it is shut down over the control socket or gets SIGINT or SIGTERM.
+    ///
+    /// Called on a Relay, every client is sent a Disconnect frame before it returns. SIGHUP reloads the config file.
+    pub fn run(&mut self) {
+        // A flag rather than a signal iterator, which Windows doesn't have.
+        let stop = Arc::new(AtomicBool::new(false));
+        for signal in &[SIGINT, SIGTERM] {
+            if let Err(err) = flag::register(*signal, stop.clone()) {
+                println!("Error: Could not install signal handler, {}", err);
+                ::std::process::exit(0x0100);
+            }
+        }
+        let reload = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        {
+            if let Err(err) = flag::register(SIGHUP, reload.clone()) {
+                println!("Error: Could not install signal handler, {}", err);
+                ::std::process::exit(0x0100);
+            }
+        }
+
+        self.log("Waiting for clients...");
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            if reload.swap(false, Ordering::Relaxed) {
+                self.reload();
+            }
+
+            let mut busy = self.accept_clients();
+            busy |= self.receive();
+            match self.handle_control() {
+                Some(true) => break,
+                Some(false) => busy = true,
+                None => (),
+            }
+            if let Some(metrics) = &self.metrics {
+                busy |= metrics.serve(&self.snapshot());
+            }
+            if let Some((max_messages, before)) = self.retention.check() {
+                if let Err(err) = self.prune(max_messages, before) {
+                    self.log(&format!("Store error: {}", err));
+                }
+            }
+
+            if !busy {
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+
+        self.shutdown();
+    }
+
+    /// Writes a timestamped line to the transcript, or stdout if there is none.
+    fn log(&self, line: &str) {
+        let line = format!("{} {}", Local::now().format("%Y-%m-%d %H:%M:%S"), line);
+        match &self.transcript {
+            Some(transcript) => {
+                transcript.write_line(&line);
+                // Nobody is watching a headless server, so its log should never lag behind.
+                transcript.flush();
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    /// Records something in the store if there is one, logging rather than failing if it can't.
+    fn record<F>(&self, f: F)
+    where
+        F: FnOnce(&Store) -> Result<(), String>,
+    {
+        if let Some(store) = &self.store {
+            if let Err(err) = f(store) {
+                self.log(&format!("Store error: {}", err));
+            }
+        }
+    }
+
+    /// Accepts every waiting client, turning away those that would take the server over `max_clients`.
+    ///
+    /// # Returns
+    /// `bool` - true if a client connected.
+    fn accept_clients(&mut self) -> bool {
+        let mut accepted = false;
+
+        while let Some(con) = self.template.accept_new_client(&self.server) {
+            let id = match con.peer() {
+                Some(peer) => peer.id(),
+                None => continue,
+            };
+            let who = con.status().peer.unwrap_or_default();
+            let max_clients = self.live.max_clients.unwrap_or(0);
+            if max_clients > 0 && self.clients.len() >= max_clients {
+                self.log(&format!(
+                    "Client {} turned away, the server is full with {} clients",
+                    who,
+                    self.clients.len()
+                ));
+                con.refuse("The server is full, try again later");
+                continue;
+            }
+            let mut reconnect = match con.peer().and_then(|p| p.ip()) {
+                Some(ip) => !self.seen.insert(ip),
+                None => false,
+            };
+            if reconnect {
+                self.counters.reconnects += 1;
+            }
+            // Read before record_peer, which marks the IP as seen now.
+            let last_seen = match &self.store {
+                Some(store) => store.last_seen_ip(&who).unwrap_or(None),
+                None => None,
+            };
+            reconnect |= last_seen.is_some();
+            self.log(&format!("Client {} connected", who));
+            self.record(|store| store.record_peer(&who));
+            self.clients.push(Client {
+                con: con,
+                id: id,
+                who: who,
+                welcomed: false,
+                reconnect: reconnect,
+                last_seen: last_seen,
+                nick: None,
+                seen: RecentIds::new(),
+                away: false,
+            });
+            accepted = true;
+        }
+
+        return accepted;
+    }
+
+    /// Reads every client's waiting messages, relaying them to the other clients.
+    ///
+    /// # Returns
+    /// `bool` - true if any message was read.
+    fn receive(&mut self) -> bool {
+        let mut busy = false;
+        let mut gone = Vec::new();
+
+        for i in 0..self.clients.len() {
+            loop {
+                let pending = self.clients[i].con.handshake_pending();
+                let received = self.clients[i].con.receive_frame();
+                let who = self.clients[i].who.clone();
+                match received {
+                    Err(status) => match status.as_str() {
+                        "Blocked" => break,
+                        "Empty" => (),
+                        "Disconnected" => {
+                            self.log(&format!("Client {} disconnected", who));
+                            gone.push((i, Reason::Closed));
+                            break;
+                        }
+                        "Timed out" | "Banned" => {
+                            self.log(&format!("Client {} dropped and banned: {}", who, status));
+                            self.counters.clients_dropped += 1;
+                            if pending {
+                                self.counters.handshake_failures += 1;
+                            }
+                            let reason = if status == "Banned" {
+                                Reason::Banned
+                            } else {
+                                Reason::TimedOut
+                            };
+                            gone.push((i, reason));
+                            break;
+                        }
+                        _ => (),
+                    },
+                    Ok(frame) => match frame {
+                        Frame::Disconnect => {
+                            self.log(&format!("Client {} left", who));
+                            gone.push((i, Reason::Left));
+                            break;
+                        }
+                        Frame::Text { id, .. }
+                        | Frame::Reply { id, .. }
+                        | Frame::Private { id, .. }
+                            if !self.clients[i].seen.first_seen(id) =>
+                        {
+                            // Sent again because our ack was lost, ack it again but don't relay it twice.
+                            self.clients[i].con.notify_message_received(id);
+                        }
+                        frame @ Frame::Text { .. } | frame @ Frame::Reply { .. } => {
+                            self.counters.messages_relayed += 1;
+                            if let Frame::Text { id, body } | Frame::Reply { id, body, .. } = &frame
+                            {
+                                self.clients[i].con.notify_message_received(*id);
+                                self.log(&format!("{}: {}", who, body));
+                                self.record(|store| store.record_message(&who, *id, body));
+                                self.remember(&who, *id, body);
+                            }
+                            // Tell the other clients who sent it so they can tell clients apart.
+                            let frame = match frame {
+                                Frame::Text { id, body } => Frame::Relayed {
+                                    id: id,
+                                    sender: who.clone(),
+                                    body: body,
+                                },
+                                other => other,
+                            };
+                            self.broadcast(Some(i), &frame);
+                        }
+                        Frame::Nick { nick, key } => self.set_nick(i, nick, key),
+                        Frame::Private { id, peer, data } => {
+                            self.forward_private(i, id, &peer, data)
+                        }
+                        frame @ Frame::Edit { .. } => {
+                            if let Frame::Edit { id, body } = &frame {
+                                self.record(|store| store.edit_message(&who, *id, body));
+                                if let Some(m) = self.remembered(&who, *id) {
+                                    m.body = body.clone();
+                                    m.edited = true;
+                                }
+                            }
+                            self.broadcast(Some(i), &frame);
+                        }
+                        frame @ Frame::Delete { .. } => {
+                            if let Frame::Delete { id } = &frame {
+                                self.record(|store| store.delete_message(&who, *id));
+                                if let Some(m) = self.remembered(&who, *id) {
+                                    m.deleted = true;
+                                }
+                            }
+                            self.broadcast(Some(i), &frame);
+                        }
+                        // Only the other clients can check a signature, the relay just passes it on.
+                        frame @ Frame::Signature { .. } => self.broadcast(Some(i), &frame),
+                        Frame::Wiped => {
+                            let body = format!("{} wiped their history", name(&self.clients[i]));
+                            self.log(&body);
+                            self.announce(Some(i), body);
+                        }
+                        Frame::Away { away } => {
+                            self.clients[i].away = away;
+                            if self.clients[i].welcomed {
+                                self.broadcast(Some(i), &presence(&self.clients[i], true));
+                            }
+                        }
+                        Frame::Ping { sent } => {
+                            let received = now_micros();
+                            self.clients[i].con.send_frame(&Frame::Pong {
+                                sent: sent,
+                                received: received,
+                                replied: now_micros(),
+                            });
+                        }
+                        Frame::Code { line: 0, .. } => {
+                            // There is no relayed form that says who sent a block, so say so once per block.
+                            self.clients[i].con.send_frame(&Frame::Reject {
+                                reason: String::from("code blocks aren't relayed"),
+                            });
+                        }
+                        _ => (),
+                    },
+                }
+                busy = true;
+            }
+
+            if !self.clients[i].welcomed && !self.clients[i].con.handshake_pending() {
+                self.clients[i].welcomed = true;
+                let resumed = self.clients[i].con.resumed().map(String::from);
+                let parked = resumed.and_then(|token| self.parked.remove(&token));
+                let back = parked.is_some();
+                if let Some(parked) = parked {
+                    let client = &mut self.clients[i];
+                    client.seen = parked.seen;
+                    client.reconnect = true;
+                    client.last_seen = Some(parked.left);
+                    let who = client.who.clone();
+                    self.log(&format!("Client {} resumed its session", who));
+                }
+                if self.clients[i].reconnect {
+                    self.send_history(&self.clients[i]);
+                }
+                // Tell the newcomer who is here and who can get private messages, the others hear about its
+                // nick when it picks one.
+                for (j, other) in self.clients.iter().enumerate() {
+                    if j != i && other.welcomed {
+                        self.clients[i].con.send_frame(&presence(other, true));
+                    }
+                    if let Some((nick, key)) = &other.nick {
+                        self.clients[i].con.send_frame(&Frame::Nick {
+                            nick: nick.clone(),
+                            key: key.clone(),
+                        });
+                    }
+                }
+                self.broadcast(Some(i), &presence(&self.clients[i], true));
+                let how = if back { "is back" } else { "joined" };
+                self.announce(Some(i), format!("{} {}", name(&self.clients[i]), how));
+                if let Some(motd) = self.motd.clone() {
+                    let frame = self.server_text(motd);
+                    self.clients[i].con.send_frame(&frame);
+                }
+            }
+        }
+
+        for (i, reason) in gone.into_iter().rev() {
+            let client = self.clients.remove(i);
+            self.record(|store| store.record_peer_left(&client.who));
+            if client.welcomed {
+                self.broadcast(None, &presence(&client, false));
+                let body = match reason {
+                    Reason::Left => format!("{} left", name(&client)),
+                    reason => format!("{} left ({})", name(&client), reason),
+                };
+                self.announce(None, body);
+            }
+            if let (Reason::Closed, true) = (reason, client.welcomed) {
+                self.park(client);
+            }
+        }
+
+        return busy;
+    }
+
+    /// Gives a client the nick it asked for and tells the other clients its key, unless the nick is taken.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the client.
+    /// * `nick` - A String of the nick.
+    /// * `key` - A String of the client's public key, which the relay only passes on.
+    fn set_nick(&mut self, i: usize, nick: String, key: String) {
+        let valid = nick.len() <= MAX_NICK
+            && nick
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let taken = self.clients.iter().any(|c| match &c.nick {
+            Some((other, _)) => other.eq_ignore_ascii_case(&nick),
+            None => false,
+        });
+        let refused = if self.clients[i].nick.is_some() {
+            Some(String::from("Your nick can't be changed"))
+        } else if !valid {
+            Some(format!(
+                "Nick {} has to be at most {} letters, digits, - or _",
+                nick, MAX_NICK
+            ))
+        } else if taken {
+            Some(format!("Nick {} is taken", nick))
+        } else {
+            None
+        };
+        if let Some(reason) = refused {
+            self.clients[i]
+                .con
+                .send_frame(&Frame::Reject { reason: reason });
+            return;
+        }
+
+        self.log(&format!("Client {} is {}", self.clients[i].who, nick));
+        self.clients[i].con.set_peer_identity(&nick, &key);
+        let frame = Frame::Nick {
+            nick: nick.clone(),
+            key: key.clone(),
+        };
+        self.clients[i].nick = Some((nick, key));
+        self.broadcast(Some(i), &frame);
+        if self.clients[i].welcomed {
+            self.broadcast(Some(i), &presence(&self.clients[i], true));
+        }
+    }
+
+    /// Passes an encrypted message on to the client with the given nick, telling it which nick sent it.
+    ///
+    /// The relay can't read the message, so it is neither logged nor stored.
+    ///
+    /// # Arguments
+    /// * `i` - A usize of the sending client.
+    /// * `id` - A u64 of the sender's id for the message.
+    /// * `to` - A &str of the recipient's nick.
+    /// * `data` - A String of the sealed message.
+    fn forward_private(&mut self, i: usize, id: u64, to: &str, data: String) {
+        let from = match &self.clients[i].nick {
+            Some((nick, _)) => nick.clone(),
+            None => {
+                self.clients[i].con.send_frame(&Frame::Reject {
+                    reason: String::from("Pick a nick with --nick to send private messages"),
+                });
+                return;
+            }
+        };
+        let recipient = self
+            .clients
+            .iter()
+            .find(|c| match &c.nick {
+                Some((nick, _)) => nick.eq_ignore_ascii_case(to),
+                None => false,
+            })
+            .map(|c| c.id);
+
+        match recipient {
+            Some(peer) => {
+                self.send_to(
+                    peer,
+                    &Frame::Private {
+                        id: id,
+                        peer: from.clone(),
+                        data: data,
+                    },
+                );
+                self.clients[i].con.notify_message_received(id);
+                self.counters.messages_relayed += 1;
+                self.log(&format!("{} sent {} a private message", from, to));
+            }
+            None => {
+                self.clients[i].con.send_frame(&Frame::Reject {
+                    reason: format!("Nobody is called {}", to),
+                });
+            }
+        }
+    }
+
+    /// Sends a frame to every client that completed the handshake.
+    ///
+    /// # Arguments
+    /// * `from` - A Option<usize> of the client that sent the frame, who does not get it back.
+    /// * `frame` - A &Frame to send.
+    fn broadcast(&self, from: Option<usize>, frame: &Frame) {
+        let msg = frame.encode();
+        for (i, client) in self.clients.iter().enumerate() {
+            if Some(i) != from {
+                client.con.broadcast(msg.clone());
+            }
+        }
+    }
+
+    /// Tells every client that completed the handshake about something that happened on the relay.
+    ///
+    /// # Arguments
+    /// * `from` - A Option<usize> of the client it is about, who does not get it.
+    /// * `body` - A String of what happened.
+    fn announce(&self, from: Option<usize>, body: String) {
+        self.broadcast(from, &Frame::System { body: body });
+    }
+
+    /// Sends a frame to one client only.
+    ///
+    /// # Arguments
+    /// * `peer` - A PeerId of the client.
+    /// * `frame` - A &Frame to send.
+    ///
+    /// # Returns
+    /// `bool` - true if the client is still connected and the frame was sent.
+    fn send_to(&self, peer: PeerId, frame: &Frame) -> bool {
+        return match self.clients.iter().find(|c| c.id == peer) {
+            Some(client) => client
+                .con
+                .send_to(peer, frame.encode())
+                .0
+                .starts_with("Message sent"),
+            None => false,
+        };
+    }
+
+    /// Keeps the session of a client that lost its connection until its resume token expires, forgetting the
+    /// sessions that already expired.
+    fn park(&mut self, client: Client) {
+        let now = Instant::now();
+        self.parked.retain(|_, parked| parked.until > now);
+        let (token, ttl) = match (client.con.resume_token(), client.con.resume_ttl()) {
+            (Some(token), Some(ttl)) => (String::from(token), ttl),
+            _ => return,
+        };
+        self.parked.insert(
+            token,
+            Parked {
+                seen: client.seen,
+                left: Utc::now().timestamp(),
+                until: now + ttl,
+            },
+        );
+    }
+
+    /// Remembers a message for reconnecting clients.
+    fn remember(&mut self, sender: &str, id: u64, body: &str) {
+        if self.history_len == 0 {
+            return;
+        }
+        if self.history.len() >= self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(StoredMessage {
+            time: Utc::now().timestamp(),
+            sender: String::from(sender),
+            id: id,
+            body: String::from(body),
+            edited: false,
+            deleted: false,
+        });
+    }
+
+    /// Drops the remembered and stored messages past a retention limit, the oldest first.
+    ///
+    /// # Arguments
+    /// * `max` - A Option<usize> of the most messages to keep, None to keep any number.
+    /// * `before` - A Option<i64> unix time older messages are dropped before, None to keep them however old.
+    ///
+    /// # Returns
+    /// `Result<usize, String>` - how many stored messages were deleted, or remembered ones without a store, or
+    /// the store's error.
+    fn prune(&mut self, max: Option<usize>, before: Option<i64>) -> Result<usize, String> {
+        let len = self.history.len();
+        if let Some(before) = before {
+            self.history.retain(|m| m.time >= before);
+        }
+        if let Some(max) = max {
+            while self.history.len() > max {
+                self.history.pop_front();
+            }
+        }
+        return match &self.store {
+            Some(store) => store.prune(max, before),
+            None => Ok(len - self.history.len()),
+        };
+    }
+
+    /// Finds the latest remembered message from a sender with the given id.
+    fn remembered(&mut self, sender: &str, id: u64) -> Option<&mut StoredMessage> {
+        return self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|m| m.sender == sender && m.id == id);
+    }
+
+    /// Sends a client the messages it missed as History frames, read from the store if there is one.
+    ///
+    /// The store knows when the client was last here, even from before a restart, so it is sent only what came
+    /// since and told how much that was. Without a store it is sent the latest messages.
+    fn send_history(&self, client: &Client) {
+        if self.history_len == 0 {
+            return;
+        }
+
+        let read = match (&self.store, client.last_seen) {
+            (Some(store), Some(time)) => Some(store.since(time, self.history_len)),
+            (Some(store), None) => Some(store.recent(None, self.history_len)),
+            (None, _) => None,
+        };
+        let messages: Vec<StoredMessage> = match read {
+            Some(Ok(messages)) => messages,
+            Some(Err(err)) => {
+                self.log(&format!("Store error: {}", err));
+                return;
+            }
+            None => self
+                .history
+                .iter()
+                .filter(|m| client.last_seen.map_or(true, |time| m.time >= time))
+                .cloned()
+                .collect(),
+        };
+
+        let frames: Vec<Frame> = messages
+            .into_iter()
+            .filter(|m| !m.deleted)
+            .map(|m| Frame::History {
+                time: m.time,
+                sender: m.sender,
+                body: m.body,
+            })
+            .collect();
+        if client.last_seen.is_some() && !frames.is_empty() {
+            client.con.send_frame(&Frame::System {
+                body: match frames.len() {
+                    1 => String::from("1 message since you were last here"),
+                    n => format!("{} messages since you were last here", n),
+                },
+            });
+        }
+        client.con.send_frames(&frames);
+    }
+
+    /// Takes a Snapshot of the relay for the metrics endpoint.
+    fn snapshot(&self) -> Snapshot {
+        let traffic = self.template.traffic();
+        return Snapshot {
+            peers: self.clients.len(),
+            bytes_sent: traffic.sent(),
+            bytes_received: traffic.received(),
+            counters: self.counters.clone(),
+        };
+    }
+
+    /// Makes a Text frame from the server itself.
+    fn server_text(&mut self, body: String) -> Frame {
+        let frame = Frame::Text {
+            id: self.next_id,
+            body: body,
+        };
+        self.next_id += 1;
+        return frame;
+    }
+
+    /// Answers a waiting control request.
+    ///
+    /// # Returns
+    /// `Option<bool>` - None if there was no request, otherwise whether it asked the relay to shut down.
+    fn handle_control(&mut self) -> Option<bool> {
+        let request = self.control.as_ref()?.accept()?;
+
+        match request.command.clone() {
+            Ok(ControlCommand::ListPeers) => {
+                let peers: Vec<PeerInfo> = self
+                    .clients
+                    .iter()
+                    .map(|c| {
+                        let peer = c.con.peer();
+                        PeerInfo {
+                            addr: c.who.clone(),
+                            handshake_pending: c.con.handshake_pending(),
+                            nick: peer.and_then(|p| p.nick()).map(String::from),
+                            connected_secs: peer
+                                .map(|p| p.connected_at().elapsed().as_secs())
+                                .unwrap_or(0),
+                            key: peer
+                                .and_then(|p| p.identity_key())
+                                .map(|k| STANDARD.encode(k.as_bytes())),
+                        }
+                    })
+                    .collect();
+                request.reply(json!(peers));
+            }
+            Ok(ControlCommand::Kick(addr)) => {
+                match self.clients.iter().position(|c| c.who == addr) {
+                    Some(i) => {
+                        let mut client = self.clients.remove(i);
+                        client.con.send_frame(&Frame::Disconnect);
+                        client.con.end_session(false);
+                        self.record(|store| store.record_peer_left(&client.who));
+                        if client.welcomed {
+                            self.broadcast(None, &presence(&client, false));
+                            self.announce(None, format!("{} was kicked", name(&client)));
+                        }
+                        self.log(&format!("Client {} kicked", addr));
+                        request.reply(Value::Bool(true));
+                    }
+                    None => request
+                        .reply_error(RpcError::new(COMMAND_FAILED, &format!("No peer {}", addr))),
+                }
+            }
+            Ok(ControlCommand::Broadcast(text)) => {
+                self.log(&format!("Server: {}", text));
+                let frame = self.server_text(text);
+                if let Frame::Text { id, body } = &frame {
+                    self.record(|store| store.record_message("Server", *id, body));
+                    self.remember("Server", *id, body);
+                }
+                self.broadcast(None, &frame);
+                let sent_to = self
+                    .clients
+                    .iter()
+                    .filter(|c| !c.con.handshake_pending())
+                    .count();
+                request.reply(json!({ "sent_to": sent_to }));
+            }
+            Ok(ControlCommand::SetMotd(text)) => {
+                if text.is_empty() {
+                    self.motd = None;
+                    self.log("Motd cleared");
+                } else {
+                    self.log(&format!("Motd set to: {}", text));
+                    self.motd = Some(text);
+                }
+                request.reply(Value::Null);
+            }
+            Ok(ControlCommand::Stats) => {
+                let stats = RelayStats {
+                    peers: self.clients.len(),
+                    handshaking: self
+                        .clients
+                        .iter()
+                        .filter(|c| c.con.handshake_pending())
+                        .count(),
+                    messages_relayed: self.counters.messages_relayed,
+                    clients_dropped: self.counters.clients_dropped,
+                    handshake_failures: self.counters.handshake_failures,
+                    reconnects: self.counters.reconnects,
+                    bytes_sent: self.template.traffic().sent(),
+                    bytes_received: self.template.traffic().received(),
+                    uptime_secs: self.started.elapsed().as_secs(),
+                    motd: self.motd.clone(),
+                };
+                request.reply(json!(stats));
+            }
+            Ok(ControlCommand::History(sender, limit)) => match &self.store {
+                Some(store) => match store.recent(sender.as_ref().map(|s| s.as_str()), limit) {
+                    Ok(messages) => request.reply(json!(messages)),
+                    Err(err) => request.reply_error(RpcError::new(COMMAND_FAILED, &err)),
+                },
+                None => request.reply_error(no_store()),
+            },
+            Ok(ControlCommand::Search(text, limit)) => match &self.store {
+                Some(store) => match store.search(&text, limit) {
+                    Ok(messages) => request.reply(json!(messages)),
+                    Err(err) => request.reply_error(RpcError::new(COMMAND_FAILED, &err)),
+                },
+                None => request.reply_error(no_store()),
+            },
+            Ok(ControlCommand::Invite) => {
+                match self.template.invites().map(|i| (i.issue(), i.ttl())) {
+                    Some((Ok(token), ttl)) => {
+                        request.reply(json!({ "token": token, "expires_secs": ttl.as_secs() }))
+                    }
+                    Some((Err(err), _)) => {
+                        request.reply_error(RpcError::new(COMMAND_FAILED, &err.to_string()))
+                    }
+                    None => request.reply_error(RpcError::new(
+                        COMMAND_FAILED,
+                        "Invites are off, start the server with --invite-only",
+                    )),
+                }
+            }
+            Ok(ControlCommand::Purge(age)) => match self.prune(None, Some(cutoff(age))) {
+                Ok(purged) => {
+                    self.log(&format!("Purged {} messages", purged));
+                    request.reply(json!({ "purged": purged }));
+                }
+                Err(err) => request.reply_error(RpcError::new(COMMAND_FAILED, &err)),
+            },
+            Ok(ControlCommand::Shutdown) => {
+                request.reply(Value::Bool(true));
+                return Some(true);
+            }
+            Err(error) => request.reply_error(error),
+        }
+
+        return Some(false);
+    }
+
+    /// Tells every client the server is leaving.
+    fn shutdown(&mut self) {
+        for client in std::mem::take(&mut self.clients) {
+            client.con.send_frame(&Frame::Disconnect);
+            self.record(|store| store.record_peer_left(&client.who));
+        }
+        self.log("Shut down");
+    }
+}
+
+/// Makes the Presence frame telling the other clients a client is here, away or has left.
+fn presence(client: &Client, online: bool) -> Frame {
+    return Frame::Presence {
+        peer: client.who.clone(),
+        online: online,
+        away: client.away,
+        nick: client.nick.as_ref().map(|(nick, _)| nick.clone()),
+    };
+}
+
+/// How a client is named in announcements, its nick if it picked one, otherwise its address.
+fn name(client: &Client) -> String {
+    return match &client.nick {
+        Some((nick, _)) => nick.clone(),
+        None => client.who.clone(),
+    };
+}
+
+/// The error for history requests to a relay without a Store.
+fn no_store() -> RpcError {
+    return RpcError::new(
+        COMMAND_FAILED,
+        "No message store, start the server with --db path",
+    );
+}
@@ -0,0 +1,188 @@
+/// Rendezvous server file
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+extern crate chrono;
+use chrono::prelude::*;
+
+mod config;
+mod protocol;
+use self::config::Args;
+use self::protocol::{codec, Frame};
+
+const USAGE: &str = "Error: Usage ./r2wc-rendezvous [addr] [port]";
+
+/// The most bytes a frame may have, the limit both peers use.
+const MSG_SIZE: usize = 255;
+
+/// How long a new connection has to say which room it wants.
+const MEET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long paired peers have to either connect directly or ask us to relay.
+const DECIDE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Peers waiting for someone to join their room, with the address they connected from.
+type Rooms = Arc<Mutex<HashMap<String, (TcpStream, SocketAddr)>>>;
+
+/// Prints a timestamped line.
+fn log(line: &str) {
+    println!("{} {}", Local::now().format("%Y-%m-%d %H:%M:%S"), line);
+}
+
+/// Writes one frame with its length in front.
+fn write_frame(mut stream: &TcpStream, frame: &Frame) -> io::Result<()> {
+    return stream.write_all(&codec::encode(frame, MSG_SIZE));
+}
+
+/// Reads one frame of at most `MSG_SIZE` bytes, reading nothing past it so relayed traffic is left in the socket.
+fn read_frame(stream: &TcpStream) -> io::Result<Frame> {
+    let buff = codec::read_blocking(stream, MSG_SIZE)?;
+    return Frame::parse(&buff).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()));
+}
+
+/// Checks a waiting peer hasn't hung up, without taking anything out of the socket.
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let alive = match stream.peek(&mut [0]) {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(ref err) => err.kind() == ErrorKind::WouldBlock,
+    };
+    return alive && stream.set_nonblocking(false).is_ok();
+}
+
+/// Reads which room a new connection wants and either parks it there or pairs it with whoever is waiting.
+fn handle(stream: TcpStream, addr: SocketAddr, rooms: Rooms) {
+    let _ = stream.set_read_timeout(Some(MEET_TIMEOUT));
+    let room = match read_frame(&stream) {
+        Ok(Frame::Meet { room }) => room,
+        _ => {
+            let _ = write_frame(
+                &stream,
+                &Frame::Reject {
+                    reason: String::from("Expected a room"),
+                },
+            );
+            return;
+        }
+    };
+
+    let mut rooms = rooms.lock().unwrap();
+    let waiting = rooms.remove(&room);
+    match waiting {
+        Some((other, other_addr)) if is_alive(&other) => {
+            drop(rooms);
+            log(&format!("Pairing {} and {} in {}", other_addr, addr, room));
+            pair((other, other_addr), (stream, addr));
+        }
+        _ => {
+            log(&format!("{} is waiting in {}", addr, room));
+            rooms.insert(room, (stream, addr));
+        }
+    }
+}
+
+/// Tells two peers each other's address, then relays between them if they both ask because punching failed.
+fn pair(first: (TcpStream, SocketAddr), second: (TcpStream, SocketAddr)) {
+    let (first, first_addr) = first;
+    let (second, second_addr) = second;
+    // Only the peer that waited listens as well, so both can't end up with a different connection each.
+    let punch = |stream: &TcpStream, addr: SocketAddr, listen: bool| {
+        return write_frame(
+            stream,
+            &Frame::Punch {
+                addr: addr.to_string(),
+                listen: listen,
+            },
+        );
+    };
+    if punch(&first, second_addr, true).is_err() || punch(&second, first_addr, false).is_err() {
+        log(&format!(
+            "{} or {} left before pairing",
+            first_addr, second_addr
+        ));
+        return;
+    }
+
+    // A peer that connected directly just closes its connection to us, one that wants a relay sends Hello.
+    let wants_relay = |stream: &TcpStream| {
+        let _ = stream.set_read_timeout(Some(DECIDE_TIMEOUT));
+        return match read_frame(stream) {
+            Ok(Frame::Hello) => stream.set_read_timeout(None).is_ok(),
+            _ => false,
+        };
+    };
+    let first_relay = wants_relay(&first);
+    let second_relay = wants_relay(&second);
+    if !first_relay || !second_relay {
+        log(&format!(
+            "{} and {} connected directly",
+            first_addr, second_addr
+        ));
+        return;
+    }
+
+    log(&format!(
+        "Relaying between {} and {}",
+        first_addr, second_addr
+    ));
+    let copy = |from: &TcpStream, to: &TcpStream| -> io::Result<thread::JoinHandle<()>> {
+        let (mut from, mut to) = (from.try_clone()?, to.try_clone()?);
+        return Ok(thread::spawn(move || {
+            let _ = io::copy(&mut from, &mut to);
+            // Either side leaving ends the relay for both.
+            let _ = from.shutdown(Shutdown::Both);
+            let _ = to.shutdown(Shutdown::Both);
+        }));
+    };
+    match (copy(&first, &second), copy(&second, &first)) {
+        (Ok(there), Ok(back)) => {
+            let _ = there.join();
+            let _ = back.join();
+        }
+        _ => {
+            let _ = first.shutdown(Shutdown::Both);
+            let _ = second.shutdown(Shutdown::Both);
+        }
+    }
+    log(&format!(
+        "Stopped relaying between {} and {}",
+        first_addr, second_addr
+    ));
+}
+
+fn main() {
+    let args = Args::from_env().positional;
+    if args.len() != 2 {
+        println!("{}", USAGE);
+        ::std::process::exit(0x0100);
+    }
+    let listener = match TcpListener::bind(format!("{}:{}", args[0], args[1])) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Error: Could not listen, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    };
+
+    log("Waiting for peers...");
+    let rooms: Rooms = Arc::new(Mutex::new(HashMap::new()));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let addr = match stream.peer_addr() {
+            Ok(addr) => addr,
+            Err(_) => continue,
+        };
+        let rooms = rooms.clone();
+        thread::spawn(move || handle(stream, addr, rooms));
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+/// How often the retention policy is enforced.
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+/// A Retention which enforces the retention policy from the config file every CHECK_EVERY, dropping the messages
+/// past its limits.
+///
+/// # Fields
+/// `max_messages` - The most messages kept, None to keep any number.
+/// `max_age` - How old a message can get, None to keep messages however old.
+/// `checked` - When the policy was last enforced.
+pub struct Retention {
+    max_messages: Option<usize>,
+    max_age: Option<Duration>,
+    checked: Instant,
+}
+
+impl Retention {
+    /// Creates a new Retention, first enforced a CHECK_EVERY from now.
+    ///
+    /// # Arguments
+    /// * `max_messages` - A Option<usize> of the most messages kept, None to keep any number.
+    /// * `max_age` - A Option<Duration> of how old a message can get, None to keep messages however old.
+    ///
+    /// # Returns
+    /// `Retention` - the policy.
+    pub fn new(max_messages: Option<usize>, max_age: Option<Duration>) -> Retention {
+        return Retention {
+            max_messages: max_messages,
+            max_age: max_age,
+            checked: Instant::now(),
+        };
+    }
+
+    /// Changes the limits, for when the config file changes.
+    ///
+    /// # Arguments
+    /// * `max_messages` - A Option<usize> of the most messages kept, None to keep any number.
+    /// * `max_age` - A Option<Duration> of how old a message can get, None to keep messages however old.
+    pub fn set_limits(&mut self, max_messages: Option<usize>, max_age: Option<Duration>) {
+        self.max_messages = max_messages;
+        self.max_age = max_age;
+    }
+
+    /// Checks whether the policy is due to be enforced.
+    ///
+    /// # Returns
+    /// `Option<(Option<usize>, Option<i64>)>` - the most messages to keep and the unix time older messages are
+    /// dropped before, None if it isn't due yet or there are no limits.
+    pub fn check(&mut self) -> Option<(Option<usize>, Option<i64>)> {
+        if self.due() != Some(Duration::from_secs(0)) {
+            return None;
+        }
+        self.checked = Instant::now();
+        return Some((self.max_messages, self.max_age.map(cutoff)));
+    }
+
+    /// How long until the policy is due to be enforced, so the event loop wakes up in time.
+    ///
+    /// # Returns
+    /// `Option<Duration>` - the time left, None if there are no limits.
+    pub fn due(&self) -> Option<Duration> {
+        if self.max_messages.is_none() && self.max_age.is_none() {
+            return None;
+        }
+        return Some(CHECK_EVERY.saturating_sub(self.checked.elapsed()));
+    }
+}
+
+/// The unix time messages older than an age were sent before.
+///
+/// # Arguments
+/// * `age` - A Duration of how old the messages are.
+///
+/// # Returns
+/// `i64` - the time, in seconds since the unix epoch.
+pub fn cutoff(age: Duration) -> i64 {
+    return Utc::now().timestamp().saturating_sub(age.as_secs() as i64);
+}
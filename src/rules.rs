@@ -0,0 +1,179 @@
+use std::process::{Command, Stdio};
+use std::thread;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A Rule from the config file which decides what happens when a matching message arrives.
+///
+/// A rule with neither `sender`, `pattern` nor `mention` matches every message.
+///
+/// # Fields
+/// `sender` - Only match messages from this sender, e.g. Server, Client or a relayed address, ignoring case.
+/// `pattern` - Only match messages whose text matches this regex.
+/// `mention` - Only match messages that mention our nick, never true without one.
+/// `bell` - Ring the terminal bell.
+/// `notify` - Show a desktop notification.
+/// `urgent` - Ring the bell and notify even while do not disturb is on.
+/// `highlight` - Highlight the message in the chat.
+/// `hide` - Don't show the message at all.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub mention: bool,
+    pub bell: bool,
+    pub notify: bool,
+    pub urgent: bool,
+    pub highlight: bool,
+    pub hide: bool,
+}
+
+/// The Actions the first matching rule asks for, all false when no rule matches.
+///
+/// # Fields
+/// `bell` - Ring the terminal bell.
+/// `notify` - Show a desktop notification.
+/// `urgent` - Ring the bell and notify even while do not disturb is on.
+/// `highlight` - Highlight the message in the chat.
+/// `hide` - Don't show the message at all.
+/// `mention` - Whether the message mentions our nick, whatever the rules say.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Actions {
+    pub bell: bool,
+    pub notify: bool,
+    pub urgent: bool,
+    pub highlight: bool,
+    pub hide: bool,
+    pub mention: bool,
+}
+
+/// Rules which are the config file's rules with their patterns compiled.
+///
+/// # Fields
+/// `rules` - The rules and their compiled patterns, in the order they are checked.
+/// `mention` - Matches our nick as a whole word ignoring case, None without a nick.
+pub struct Rules {
+    rules: Vec<(Rule, Option<Regex>)>,
+    mention: Option<Regex>,
+}
+
+impl Rules {
+    /// Compiles a list of rules.
+    ///
+    /// # Arguments
+    /// * `rules` - A &[Rule] of the rules in the order they should be checked.
+    /// * `nick` - A Option<&str> of our nick, for telling which messages mention us.
+    ///
+    /// # Returns
+    /// `Result<Rules, String>` - the compiled rules, or which pattern is not a valid regex.
+    pub fn new(rules: &[Rule], nick: Option<&str>) -> Result<Rules, String> {
+        let mut compiled = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            let pattern = match &rule.pattern {
+                Some(pattern) => Some(
+                    Regex::new(pattern)
+                        .map_err(|e| format!("rule {}: bad pattern, {}", i + 1, e))?,
+                ),
+                None => None,
+            };
+            compiled.push((rule.clone(), pattern));
+        }
+
+        // Nicks can end in characters that aren't word characters, where \b would never match.
+        let mention = nick
+            .map(|nick| format!(r"(?i)(?:^|\W){}(?:$|\W)", regex::escape(nick)))
+            .map(|pattern| Regex::new(&pattern).map_err(|e| e.to_string()))
+            .transpose()?;
+
+        return Ok(Rules {
+            rules: compiled,
+            mention: mention,
+        });
+    }
+
+    /// Finds what should happen for an incoming message.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of who sent the message.
+    /// * `body` - A &str of the message's text.
+    ///
+    /// # Returns
+    /// `Actions` - what the first matching rule asks for.
+    pub fn check(&self, sender: &str, body: &str) -> Actions {
+        let mention = self
+            .mention
+            .as_ref()
+            .map(|m| m.is_match(body))
+            .unwrap_or(false);
+        let matching = self.rules.iter().find(|(rule, pattern)| {
+            let sender_matches = rule
+                .sender
+                .as_ref()
+                .map(|s| s.eq_ignore_ascii_case(sender))
+                .unwrap_or(true);
+            let body_matches = pattern.as_ref().map(|p| p.is_match(body)).unwrap_or(true);
+            sender_matches && body_matches && (mention || !rule.mention)
+        });
+
+        match matching {
+            Some((rule, _)) => {
+                return Actions {
+                    bell: rule.bell,
+                    notify: rule.notify,
+                    urgent: rule.urgent,
+                    highlight: rule.highlight,
+                    hide: rule.hide,
+                    mention: mention,
+                }
+            }
+            None => {
+                return Actions {
+                    mention: mention,
+                    ..Actions::default()
+                }
+            }
+        }
+    }
+}
+
+/// Shows a desktop notification without waiting for it, with `notify-send`, or `osascript` on macOS.
+///
+/// Failures are ignored, a missing notifier only means no popup.
+///
+/// # Arguments
+/// * `sender` - A &str of who sent the message, used as the title.
+/// * `body` - A &str of the message's text.
+pub fn notify_desktop(sender: &str, body: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"r2wc\" subtitle \"{}\"",
+            quote(body),
+            quote(sender)
+        ));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command
+            .arg("--app-name=r2wc")
+            .arg("--")
+            .arg(sender)
+            .arg(body);
+        command
+    };
+
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if let Ok(mut child) = child {
+        // Reap the notifier once it exits so it doesn't linger as a zombie.
+        thread::spawn(move || child.wait());
+    }
+}
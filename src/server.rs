@@ -1,268 +1,1079 @@
 /// Client UI file
-use std::net::TcpListener;
-use std::sync::mpsc::{self, RecvTimeoutError};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 extern crate chrono;
-use chrono::prelude::*;
 
-extern crate ncurses;
-use ncurses::*;
-use std::char;
+extern crate serde;
+extern crate serde_json;
+
+extern crate regex;
+
+extern crate qrcode;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+extern crate signal_hook;
 
 extern crate stopwatch;
-use stopwatch::Stopwatch;
 
+mod away;
+mod banner;
+mod chat;
+mod chatstats;
+mod clipboard;
+mod commands;
+mod common;
+mod complete;
+mod config;
 mod connection;
-use self::connection::Connection;
-
-/// Init ncurses
-fn init_ncurses() {
-    initscr();
-    raw();
-    keypad(stdscr(), true);
-    start_color();
-    init_pair(1, COLOR_GREEN, COLOR_BLACK);
-    init_pair(2, COLOR_BLUE, COLOR_BLACK);
-    init_pair(3, COLOR_WHITE, COLOR_BLACK);
-    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
-}
+mod control;
+mod format;
+mod highlight;
+mod keymap;
+mod links;
+mod metrics;
+mod peers;
+mod portmap;
+mod protocol;
+mod random;
+mod relay;
+mod retention;
+mod rules;
+mod settings;
+mod sign;
+mod spell;
+mod store;
+mod term;
+mod theme;
+mod transcript;
+mod ui;
+mod vault;
+mod watch;
+use self::away::AutoAway;
+use self::banner::banner;
+use self::chat::{Chat, REFRESH};
+use self::chatstats::chatstats;
+use self::commands::{contacts, help, outbox, parse_command, Command};
+use self::common::{
+    alert, check_signature, forward_signals, handle_selecting, install_panic_hook,
+    load_spell_checker, not_sent, open_journal, open_transcript, otr_started, save_settings,
+    say_goodbye, screen, send, send_code, send_left, send_signature, watch_settings, wipe_history,
+    UiEvent,
+};
+use self::complete::Completer;
+use self::config::{Args, Uri};
+use self::connection::{
+    set_port, AcceptFilter, Connection, ConnectionBuilder, Event, Invites, Journal, NetStatus,
+    OutgoingMsg, Reason, ResumeTokens,
+};
+use self::control::ControlSocket;
+use self::keymap::{Action, Keymap};
+use self::links::open_url;
+use self::metrics::MetricsServer;
+use self::peers::Peers;
+use self::portmap::PortMapping;
+use self::protocol::Frame;
+use self::relay::Relay;
+use self::retention::{cutoff, Retention};
+use self::rules::Rules;
+use self::settings::{RelaySettings, Settings};
+use self::sign::Signer;
+use self::store::Store;
+use self::term::Key;
+use self::transcript::{history, LastSession, Transcript};
+use self::ui::{ChatUi, Scroll, Ui};
+use self::vault::prompt_passphrase;
 
-/// Handle client messages.
-fn handle_client_message(
-    con: &Connection,
-    chat: &mut Vec<(std::string::String, bool)>,
-    msg: String,
-    sent_time: Stopwatch,
-) {
-    if msg == "Message Received." {
-        let time_in_ms = sent_time.elapsed_ms();
-        chat.push((
-            format!(
-                "Client {}: {} taking {}ms",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                msg,
-                time_in_ms
-            ),
-            true,
-        ));
-    } else if msg == "Disconnected" {
-        chat.push((
-            format!(
-                "Client {}: Disconnected",
-                Local::now().format("%Y-%m-%d %H:%M:%S")
-            ),
-            true,
-        ));
-        chat.push((String::from("Waiting for client..."), false));
-    } else if msg != "Empty" && msg != "Blocked" {
-        chat.push((
-            format!(
-                "Client {}: {}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                msg
-            ),
-            true,
-        ));
-        con.notify_message_received();
-    }
-}
+/// The most events handled in a row without drawing, so a flood of them can't keep the screen from updating.
+const MAX_BATCH: usize = 64;
 
-/// Handle chat logs.
-fn print_chat(chat: &mut Vec<(std::string::String, bool)>, max_y: usize, max_x: usize) {
-    while chat.len() >= (max_y + 1) {
-        chat.remove(0);
-    }
+/// What `/wipe` leaves alone, since it isn't history.
+const KEPT: &str = "contacts and their keys";
 
-    let mut chat_iter = chat.iter();
-    let mut ln = 0;
-    loop {
-        match chat_iter.next() {
-            Some((msg, client)) => {
-                mv(ln, 0);
-                clrtoeol();
-                if *client {
-                    attron(COLOR_PAIR(1));
-                } else {
-                    attron(COLOR_PAIR(2));
-                }
-                if msg.len() > max_x {
-                    let (mut first, mut next) = msg.split_at(max_x);
-                    printw(first);
-                    while next.len() > max_x {
-                        ln += 1;
-                        mv(ln, 0);
-                        let (f, n) = next.split_at(max_x);
-                        first = f;
-                        next = n;
-                        printw(first);
-                    }
-                    ln += 1;
-                    mv(ln, 0);
-                    printw(next);
-                } else {
-                    printw(msg);
+/// Handle network events.
+fn handle_network_event<U: Ui>(
+    ui: &mut U,
+    chat: &mut Chat,
+    status: &mut Option<NetStatus>,
+    peers: &mut Peers,
+    rules: &Rules,
+    settings: &Settings,
+    event: Event,
+) {
+    match event {
+        Event::PeerConnected(id) => {
+            peers.join(&id.to_string());
+            chat.push_status(format!("Client {} connected", id), false);
+        }
+        Event::Resumed(id) => {
+            chat.push_status(format!("Client {} resumed its session", id), false);
+        }
+        Event::Reconnecting(_) => (),
+        Event::PeerDisconnected(_, reason) => {
+            peers.leave_all();
+            let how = match reason {
+                Reason::Left | Reason::Closed => String::from("Disconnected"),
+                Reason::TimedOut => {
+                    String::from("Dropped and banned, it did not complete the handshake")
                 }
-                refresh();
-                ln += 1;
+                Reason::Banned => String::from("Dropped and banned, it broke the protocol"),
+            };
+            chat.push_status(format!("Client {}: {}", chat.stamp(), how), true);
+            chat.push_status(String::from("Waiting for client..."), false);
+        }
+        Event::Acknowledged(time_in_ms) => {
+            chat.push_status(
+                format!(
+                    "Client {}: Message Received. taking {}ms",
+                    chat.stamp(),
+                    time_in_ms
+                ),
+                true,
+            );
+        }
+        Event::Delivered(queued) => {
+            chat.push_status(format!("Delivered {} queued messages", queued), false);
+        }
+        Event::Status(s) => {
+            otr_started(chat, status, &s);
+            *status = Some(s);
+        }
+        Event::Frame(Frame::Text { id, body }) => {
+            client_active(status, peers);
+            if let Some(actions) = screen(settings, rules, "Client", &body) {
+                chat.push_remote(id, String::from("Client"), body.clone());
+                alert(ui, chat, &actions, "Client", &body);
+            }
+        }
+        Event::Frame(Frame::Reply { id, to, body }) => {
+            client_active(status, peers);
+            if let Some(actions) = screen(settings, rules, "Client", &body) {
+                chat.push_remote_reply(id, to, String::from("Client"), body.clone());
+                alert(ui, chat, &actions, "Client", &body);
             }
-            None => break,
         }
+        Event::Frame(Frame::Code {
+            id,
+            line,
+            count,
+            lang,
+            text,
+        }) => {
+            client_active(status, peers);
+            if !settings.is_ignored("Client") {
+                chat.push_code_line(id, String::from("Client"), line, count, lang, text);
+            }
+        }
+        Event::Frame(Frame::Away { away }) => {
+            if let Some(who) = status.as_ref().and_then(|s| s.peer.as_ref()) {
+                peers.set_away(who, away);
+            }
+        }
+        Event::Frame(Frame::Edit { id, body }) => {
+            chat.edit(id, true, body);
+        }
+        Event::Frame(Frame::Delete { id }) => {
+            chat.delete(id, true);
+        }
+        Event::Frame(Frame::Signature { id, signature }) => {
+            check_signature(chat, settings, "Client", None, id, &signature)
+        }
+        Event::Frame(Frame::Wiped) => {
+            chat.push_status(String::from("The client wiped their history"), true);
+        }
+        Event::NotSent(id, why) => not_sent(chat, id, why),
+        Event::Frame(Frame::Reject { reason }) => {
+            chat.push_status(format!("Peer rejected a message: {}", reason), false);
+        }
+        Event::Frame(_) => (),
     }
+}
 
-    while ln < (max_y as i32) - 1 {
-        mv(ln, 0);
-        clrtoeol();
-        ln += 1;
+/// Notes in the peers panel that the connected client sent something.
+fn client_active(status: &Option<NetStatus>, peers: &mut Peers) {
+    if let Some(who) = status.as_ref().and_then(|s| s.peer.as_ref()) {
+        peers.active(who);
     }
 }
 
-/// Check client is connected.
-fn client_check_handler(
-    con: &mut connection::Connection,
-    server: &TcpListener,
-    chat: &mut Vec<(std::string::String, bool)>,
-) {
-    match con.taken {
-        Some(taken_unwrapped) => {
-            if !taken_unwrapped {
-                con.await_client_timeout(&server);
-                let peer = con.get_peer();
-                match peer {
-                    Some(p) => {
-                        chat.push((format!("Client {} connected", p.who()), false));
-                    }
-                    None => (),
-                }
-            }
+/// The status bar above the input line.
+fn status_bar(status: &Option<NetStatus>) -> String {
+    let (peer, queued) = match status {
+        Some(s) => {
+            let peer = match &s.peer {
+                Some(who) if s.handshake_pending => format!("Handshaking with {}", who),
+                Some(who) => format!("Connected to {}", who),
+                None => String::from("Waiting for client"),
+            };
+            (peer, s.queued)
+        }
+        None => (String::from("Waiting for client"), 0),
+    };
+    return format!(" {} | Queued: {}", peer, queued);
+}
+
+/// The lines of the stats panel in the top right corner.
+fn stats(status: &Option<NetStatus>) -> Vec<String> {
+    let mut lines = vec![String::from("Stats")];
+    let queued = status.as_ref().map(|s| s.queued).unwrap_or(0);
+    lines.push(format!("Queued: {}", queued));
+    match status.as_ref().and_then(|s| s.throttle.as_ref()) {
+        Some(t) => lines.push(format!(
+            "Throttle: {:.1}/{} kbps ({:.0}%)",
+            t.rate_kbps,
+            t.limit_kbps,
+            t.usage * 100.0
+        )),
+        None => lines.push(String::from("Throttle: off")),
+    }
+    match status.as_ref().and_then(|s| s.clock.as_ref()) {
+        Some(c) => {
+            lines.push(format!(
+                "Latency: {:.1} ms one way ({:.1} ms rtt)",
+                c.one_way_ms, c.rtt_ms
+            ));
+            lines.push(format!("Clock offset: {:+.1} ms", c.offset_ms));
         }
-        None => return,
+        None => lines.push(String::from("Latency: measuring")),
     }
+    if let Some(fingerprint) = status.as_ref().and_then(|s| s.otr.as_ref()) {
+        lines.push(format!("Session: {}", fingerprint));
+    }
+
+    return lines;
 }
 
 /// Handles input.
-fn handle_input(
-    con: &Connection,
-    chat: &mut Vec<(std::string::String, bool)>,
-    input: Result<i32, RecvTimeoutError>,
+fn handle_input<U: Ui>(
+    ui: &mut U,
+    outgoing: &Sender<OutgoingMsg>,
+    chat: &mut Chat,
+    key: Key,
+    keymap: &Keymap,
     line: &mut String,
-    mut max_y: i32,
-    mut max_x: i32,
-    sent_time: &mut Stopwatch,
+    completer: &mut Completer,
+    peers: &mut Peers,
+    settings: &mut Settings,
+    invites: &Option<Invites>,
+    signer: &Option<Signer>,
+    journal: &Journal,
+    last_session: &mut Option<LastSession>,
 ) -> bool {
-    match input {
-        Ok(c) => {
-            match c {
-                // enter
-                0xA | 13 | KEY_ENTER => {
-                    if line == ":quit" {
-                        return true;
+    let escaped = ::std::mem::replace(&mut chat.escaped, key == Key::Escape);
+    if escaped && key == Key::Char('v') {
+        chat.select(true);
+        return false;
+    }
+    if chat.selecting() {
+        return handle_selecting(ui, chat, key);
+    }
+    let action = keymap.action(key);
+    if action != Some(Action::Complete) {
+        completer.reset();
+    }
+    if let Some(action) = action {
+        match action {
+            Action::Quit => return true,
+            Action::Clear => line.clear(),
+            Action::Redraw => ui.redraw(),
+            Action::Complete => completer.complete(line, &peers.names()),
+            Action::ScrollUp => ui.scroll(Scroll::Line, true),
+            Action::ScrollDown => ui.scroll(Scroll::Line, false),
+            Action::PageUp => ui.scroll(Scroll::Page, true),
+            Action::PageDown => ui.scroll(Scroll::Page, false),
+            Action::Top => ui.scroll(Scroll::All, true),
+            Action::Bottom => ui.scroll(Scroll::All, false),
+            Action::NextTab => ui.switch_tab(true),
+            Action::PrevTab => ui.switch_tab(false),
+            Action::Help => {
+                chat.close_overlays();
+                chat.show_help = true;
+            }
+            Action::CloseHelp => chat.close_overlays(),
+            Action::TogglePeers => chat.show_peers = !chat.show_peers,
+        }
+        return false;
+    }
+
+    match key {
+        Key::Enter if chat.drafting() => {
+            match parse_command(line) {
+                Some(Ok(Command::End)) => {
+                    if let Some((id, lang, lines)) = chat.finish_code() {
+                        send_code(outgoing, chat, id, &lang, &lines);
+                    }
+                }
+                Some(Ok(Command::Cancel)) => {
+                    chat.cancel_code();
+                }
+                _ => chat.add_code_line(line),
+            }
+            line.clear();
+        }
+        Key::Enter => {
+            match parse_command(line) {
+                Some(Ok(Command::Quit)) => return true,
+                Some(Ok(Command::Edit(id, text))) => {
+                    if chat.edit(id, false, text.clone()) {
+                        send(outgoing, Frame::Edit { id: id, body: text });
+                    } else {
+                        chat.push_status(format!("No message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::Reply(to, text))) => {
+                    let header = String::from("You");
+                    match chat.push_reply(to, header, text.clone()) {
+                        Some(id) => {
+                            send(
+                                outgoing,
+                                Frame::Reply {
+                                    id: id,
+                                    to: to,
+                                    body: text.clone(),
+                                },
+                            );
+                            send_signature(outgoing, chat, signer, id, &text);
+                        }
+                        None => chat.push_status(format!("No message with id {}", to), false),
+                    }
+                }
+                Some(Ok(Command::Resend(id))) => {
+                    if chat.clear_failed(id) {
+                        let _ = outgoing.send(OutgoingMsg::Resend(id));
+                    } else {
+                        chat.push_status(format!("No unsent message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::Delete(id))) => {
+                    if chat.delete(id, false) {
+                        send(outgoing, Frame::Delete { id: id });
+                    } else {
+                        chat.push_status(format!("No message with id {}", id), false);
+                    }
+                }
+                Some(Ok(Command::ToggleIds)) => chat.show_ids = !chat.show_ids,
+                Some(Ok(Command::ToggleStats)) => chat.show_stats = !chat.show_stats,
+                Some(Ok(Command::TogglePeers)) => chat.show_peers = !chat.show_peers,
+                Some(Ok(Command::Outbox)) => {
+                    for line in outbox(&journal.pending()) {
+                        chat.push_status(line, false);
                     }
-                    let (_, time) = con.send_message(line.clone());
-                    *sent_time = time;
-                    chat.push((
-                        format!(
-                            "You {}: {}",
-                            Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            line.clone()
-                        ),
+                }
+                Some(Ok(Command::Help)) => {
+                    chat.close_overlays();
+                    chat.show_help = true;
+                }
+                Some(Ok(Command::Chatstats)) => {
+                    chat.close_overlays();
+                    chat.show_chatstats = true;
+                }
+                Some(Ok(Command::History)) => {
+                    chat.close_overlays();
+                    chat.show_history = true;
+                }
+                Some(Ok(Command::Latest)) => ui.latest(),
+                Some(Ok(Command::Wipe(None))) => chat.push_status(
+                    format!(
+                        "This deletes the chat, the transcript, unsent messages, the peers that left \
+                         and the last session, and keeps {}. /wipe yes to go ahead, /wipe yes notify to \
+                         also tell the peer",
+                        KEPT
+                    ),
+                    false,
+                ),
+                Some(Ok(Command::Wipe(Some(notify)))) => {
+                    wipe_history(
+                        outgoing,
+                        chat,
+                        peers,
+                        journal,
+                        last_session,
+                        KEPT,
+                        notify,
+                    );
+                    ui.latest();
+                }
+                Some(Ok(Command::Purge(age))) => {
+                    let purged = chat.prune(None, Some(cutoff(age)));
+                    chat.push_status(
+                        format!("Dropped {} old messages, the transcript is kept", purged),
                         false,
-                    ));
-                    line.clear();
-                    mv(max_y, 3);
-                    clrtoeol();
+                    );
                 }
-                // backspace
-                0x7f | KEY_BACKSPACE => {
-                    &line.pop();
-                    mv(max_y, 3);
-                    clrtoeol();
+                Some(Ok(Command::Code(lang))) => {
+                    chat.start_code(String::from("You"), lang.unwrap_or_default());
                 }
-                // resize event
-                KEY_RESIZE => {
-                    clear();
-                    getmaxyx(stdscr(), &mut max_y, &mut max_x);
-                    max_y -= 1;
-                    max_x -= 1;
-                    mv(max_y, max_x);
-                    mv(max_y, (3 + line.len()) as i32);
+                Some(Ok(Command::Big(text))) => match banner(&text) {
+                    Ok(lines) => {
+                        let you = String::from("You");
+                        if let Some((id, lang, lines)) = chat.push_code(you, String::new(), &lines)
+                        {
+                            send_code(outgoing, chat, id, &lang, &lines);
+                        }
+                    }
+                    Err(e) => chat.push_status(e, false),
+                },
+                Some(Ok(Command::End)) | Some(Ok(Command::Cancel)) => chat.push_status(
+                    String::from("No code block is being written, /code starts one"),
+                    false,
+                ),
+                Some(Ok(Command::ToggleDnd)) => {
+                    chat.dnd = !chat.dnd;
+                    let state = if chat.dnd { "on" } else { "off" };
+                    chat.push_status(format!("Do not disturb is {}", state), false);
                 }
-
-                12 => return true,
-                // any other key
-                _ => {
-                    &line.push(char::from_u32(c as u32).unwrap());
-                    mv(max_y, 3);
-                    clrtoeol();
+                Some(Ok(Command::Ignore(None))) => {
+                    let ignored = if settings.ignore.is_empty() {
+                        String::from("Not ignoring anyone")
+                    } else {
+                        format!("Ignoring {}", settings.ignore.join(", "))
+                    };
+                    chat.push_status(ignored, false);
+                }
+                Some(Ok(Command::Ignore(Some(who)))) => {
+                    if settings.ignore(&who) {
+                        chat.push_status(format!("Ignoring {}", who), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("Already ignoring {}", who), false);
+                    }
+                }
+                Some(Ok(Command::Unignore(who))) => {
+                    if settings.unignore(&who) {
+                        chat.push_status(format!("No longer ignoring {}", who), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("Not ignoring {}", who), false);
+                    }
+                }
+                Some(Ok(Command::Contacts)) => {
+                    for line in contacts(&settings.contacts) {
+                        chat.push_status(line, false);
+                    }
+                }
+                Some(Ok(Command::AddContact(name, contact))) => {
+                    match settings.add_contact(&name, contact) {
+                        Ok(true) => chat.push_status(format!("Added @{} to contacts", name), false),
+                        Ok(false) => chat.push_status(format!("Updated @{}", name), false),
+                        Err(err) => chat.push_status(format!("Could not add @{}, {}", name, err), false),
+                    }
+                    save_settings(chat, settings);
+                }
+                Some(Ok(Command::RemoveContact(name))) => {
+                    if settings.remove_contact(&name).is_some() {
+                        chat.push_status(format!("Removed @{} from contacts", name), false);
+                        save_settings(chat, settings);
+                    } else {
+                        chat.push_status(format!("No contact @{}", name), false);
+                    }
+                }
+                Some(Ok(Command::Theme(None))) => {
+                    let names: Vec<String> = settings
+                        .theme_names()
+                        .into_iter()
+                        .map(|name| {
+                            if name == settings.theme_name() {
+                                format!("{} (current)", name)
+                            } else {
+                                String::from(name)
+                            }
+                        })
+                        .collect();
+                    chat.push_status(format!("Themes: {}", names.join(", ")), false);
+                }
+                Some(Ok(Command::Theme(Some(name)))) => match settings.find_theme(&name) {
+                    Some(theme) => {
+                        ui.set_theme(&theme);
+                        chat.push_status(format!("Switched to the {} theme", name), false);
+                        settings.theme = Some(name);
+                        save_settings(chat, settings);
+                    }
+                    None => chat.push_status(
+                        format!("There is no theme {}, /theme lists them", name),
+                        false,
+                    ),
+                },
+                Some(Ok(Command::Invite)) => match invites {
+                    Some(invites) => chat.push_status(describe_invite(invites), false),
+                    None => chat.push_status(
+                        String::from("Invites are off, start the server with --invite-only"),
+                        false,
+                    ),
+                },
+                Some(Ok(Command::Msg(..))) => chat.push_status(
+                    String::from("/msg is for clients of a headless server"),
+                    false,
+                ),
+                Some(Ok(Command::Open(n))) => match chat.url(n).cloned() {
+                    Some(url) => {
+                        if let Err(err) = open_url(&url) {
+                            chat.push_status(format!("Could not open {}, {}", url, err), false);
+                        }
+                    }
+                    None => chat.push_status(String::from("No such link"), false),
+                },
+                Some(Err(usage)) => chat.push_status(usage, false),
+                None => {
+                    let id = chat.push_local(String::from("You"), line.clone());
+                    send(
+                        outgoing,
+                        Frame::Text {
+                            id: id,
+                            body: line.clone(),
+                        },
+                    );
+                    send_signature(outgoing, chat, signer, id, line);
                 }
             }
+            line.clear();
+        }
+        Key::Backspace => {
+            line.pop();
+        }
+        Key::Resize => ui.resize(),
+
+        Key::Quit => return true,
+        Key::Char(c) => line.push(c),
+        _ => (),
+    }
+
+    return false;
+}
+
+/// Makes a new invite token and describes it for whoever hands it out.
+fn describe_invite(invites: &Invites) -> String {
+    match invites.issue() {
+        Ok(token) => {
+            return format!(
+                "Invite token: {} (single use, expires in {}s)",
+                token,
+                invites.ttl().as_secs()
+            )
         }
-        Err(_) => return false,
+        Err(err) => return format!("Could not make an invite, {}", err),
     }
+}
 
-    if line.len() + 3 > max_x as usize {
-        let (_, l) = line.split_at(line.len() + 3 - (max_x as usize));
-        printw(l);
+/// Asks the router to forward the listening port when `--map-port` is given.
+///
+/// # Returns
+/// `(Option<PortMapping>, Option<String>)` - the mapping, which has to live until the server exits, and what to tell the user.
+fn map_port(args: &Args, server: &TcpListener) -> (Option<PortMapping>, Option<String>) {
+    if !args.has_flag("map-port") {
+        return (None, None);
+    }
+    let port = match server.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(err) => return (None, Some(format!("Could not map port, {}", err))),
+    };
+    match PortMapping::open(port) {
+        Ok(mapping) => {
+            let mapped = format!("Router forwards {} to this server", mapping.external());
+            return (Some(mapping), Some(mapped));
+        }
+        Err(err) => {
+            return (
+                None,
+                Some(format!(
+                    "Could not map port {} on the router, {}",
+                    port, err
+                )),
+            )
+        }
+    }
+}
+
+/// Works out the link clients can connect with.
+///
+/// The router's address is used when the port is mapped. A server listening on every interface uses the
+/// address of the interface that has the default route, which is found without sending anything.
+///
+/// # Arguments
+/// * `server` - A &TcpListener the server listens on.
+/// * `mapping` - A &Option<PortMapping> of the router's forwarded port.
+/// * `invites` - A &Option<Invites> to put a new token in the link from, for an invite-only server.
+///
+/// # Returns
+/// `io::Result<Uri>` - the link, or why the listening address is unknown.
+fn server_uri(
+    server: &TcpListener,
+    mapping: &Option<PortMapping>,
+    invites: &Option<Invites>,
+) -> io::Result<Uri> {
+    let addr = match mapping {
+        Some(mapping) => mapping.external(),
+        None => server.local_addr()?,
+    };
+    let host = if addr.ip().is_unspecified() {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket
+            .connect("192.0.2.1:9")
+            .and_then(|_| socket.local_addr())
+            .map(|local| local.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
     } else {
-        printw(&line);
+        addr.ip()
+    };
+    let token = match invites {
+        Some(invites) => Some(invites.issue()?),
+        None => None,
+    };
+
+    return Ok(Uri {
+        host: host.to_string(),
+        port: addr.port(),
+        token: token,
+        nick: None,
+    });
+}
+
+/// Prints the server's link as a QR code for `--qr`, so a phone or another machine can pick it up.
+///
+/// # Returns
+/// `Option<String>` - the link, or None if it couldn't be worked out, which is printed instead.
+fn print_qr(
+    server: &TcpListener,
+    mapping: &Option<PortMapping>,
+    invites: &Option<Invites>,
+) -> Option<String> {
+    let uri = match server_uri(server, mapping, invites) {
+        Ok(uri) => uri.to_string(),
+        Err(err) => {
+            println!("Could not make a link to the server, {}", err);
+            return None;
+        }
+    };
+    match QrCode::new(uri.as_bytes()) {
+        // Terminals are usually light on dark, so swap the colours to get the dark on light scanners expect.
+        Ok(code) => println!(
+            "{}",
+            code.render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build()
+        ),
+        Err(err) => println!("Could not make a QR code, {}", err),
     }
+    println!("{}", uri);
 
-    return false;
+    return Some(uri);
+}
+
+/// Loads the config file given by `--config` and compiles its notification rules and keys, exiting if it is invalid.
+fn load_settings(args: &Args) -> (Settings, Rules, Keymap) {
+    let path = match args.flag("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Settings::default_path(),
+    };
+    let shown = path.clone().unwrap_or_default();
+    let loaded = Settings::load(path).and_then(|settings| {
+        let rules = Rules::new(&settings.rules, None)?;
+        let keymap = Keymap::new(&settings.keys)?;
+        return Ok((settings, rules, keymap));
+    });
+    match loaded {
+        Ok(loaded) => return loaded,
+        Err(err) => {
+            println!("Error: Could not load config {}, {}", shown.display(), err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Loads the config file again after it changed and applies it, telling the user whether that worked.
+///
+/// Our own saves, like `/ignore`, change nothing and are not mentioned.
+fn reload_settings<U: Ui>(
+    ui: &mut U,
+    args: &Args,
+    chat: &mut Chat,
+    outgoing: &Sender<OutgoingMsg>,
+    settings: &mut Settings,
+    rules: &mut Rules,
+    keymap: &mut Keymap,
+) {
+    let path = settings.path().cloned();
+    let shown = path.clone().unwrap_or_default();
+    let loaded = Settings::load(path).and_then(|settings| {
+        let rules = Rules::new(&settings.rules, None)?;
+        let keymap = Keymap::new(&settings.keys)?;
+        return Ok((settings, rules, keymap));
+    });
+    match loaded {
+        Ok((loaded, _, _)) if loaded == *settings => (),
+        Ok((loaded, new_rules, new_keymap)) => {
+            chat.set_time_format(loaded.time_format());
+            chat.set_relative_times(loaded.relative_times);
+            chat.set_formatting(!loaded.plain_text);
+            if loaded.theme() != settings.theme() {
+                ui.set_theme(&loaded.theme());
+            }
+            if loaded.max_kbps != settings.max_kbps {
+                let max_kbps = loaded
+                    .max_kbps
+                    .unwrap_or(args.usize_flag("max-kbps", 0) as u32);
+                let _ = outgoing.send(OutgoingMsg::Limit(max_kbps));
+            }
+            *settings = loaded;
+            *rules = new_rules;
+            *keymap = new_keymap;
+            chat.push_status(format!("Reloaded config {}", shown.display()), false);
+        }
+        Err(err) => chat.push_status(
+            format!(
+                "Could not reload config {}, kept the old settings, {}",
+                shown.display(),
+                err
+            ),
+            false,
+        ),
+    }
+}
+
+/// Asks for the passphrase `--encrypt-history` encrypts the transcript, journal and message store with, before the
+/// ui starts, exiting if it can't be read.
+fn history_passphrase(args: &Args) -> Option<String> {
+    let history = args.has_flag("log") || args.has_flag("journal") || args.has_flag("db");
+    if !args.has_flag("encrypt-history") || !history {
+        return None;
+    }
+    match prompt_passphrase("the history") {
+        Ok(passphrase) => return Some(passphrase),
+        Err(err) => {
+            println!("Error: Could not read the passphrase, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+}
+
+/// Runs the server without ncurses as a relay between any number of clients, see `Relay`.
+fn run_headless(
+    args: &Args,
+    con: Connection,
+    server: TcpListener,
+    transcript: Option<Transcript>,
+    passphrase: Option<&str>,
+) {
+    let control = match args.flag("control") {
+        Some(path) => match ControlSocket::bind(path) {
+            Ok(control) => Some(control),
+            Err(err) => {
+                println!("Error: Could not bind control socket {}, {}", path, err);
+                ::std::process::exit(0x0100);
+            }
+        },
+        None => None,
+    };
+    let metrics = match args.flag("metrics-addr") {
+        Some(addr) => match MetricsServer::bind(addr) {
+            Ok(metrics) => Some(metrics),
+            Err(err) => {
+                println!("Error: Could not listen for metrics on {}, {}", addr, err);
+                ::std::process::exit(0x0100);
+            }
+        },
+        None => None,
+    };
+
+    let store = match args.flag("db") {
+        Some(path) => match Store::open(path, passphrase) {
+            Ok(store) => Some(store),
+            Err(err) => {
+                println!("Error: Could not open database {}, {}", path, err);
+                ::std::process::exit(0x0100);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(invites) = con.invites() {
+        println!("{}", describe_invite(invites));
+    }
+
+    let mut relay = Relay::new(con, server, control, transcript, metrics, store);
+    relay.set_history_len(args.usize_flag("history", 20));
+    // The flags were checked when the connection was made, the config file's relay settings go over them.
+    let flags = RelaySettings {
+        motd: None,
+        max_kbps: Some(args.usize_flag("max-kbps", 0) as u32),
+        allow: args.flag("allow").cloned(),
+        deny: args.flag("deny").cloned(),
+        max_clients: Some(args.usize_flag("max-clients", 100)),
+    };
+    let (settings, _, _) = load_settings(args);
+    relay.set_retention(settings.max_messages(), settings.max_age());
+    let _ = relay.configure(&flags);
+    if let Err(err) = relay.configure(&settings.relay.clone().unwrap_or_default()) {
+        println!("Error: Bad allow or deny in the config file, {}", err);
+        ::std::process::exit(0x0100);
+    }
+    relay.set_config_path(settings.path().cloned());
+    relay.run();
 }
 
 fn main() {
-    let (mut con, server) = Connection::new_server_connection(255);
+    let args = Args::from_env();
+    let mut builder = ConnectionBuilder::from_args(255, &args)
+        .queue_cap(args.usize_flag("queue-cap", 50))
+        .handshake_timeout(Duration::from_secs(
+            args.usize_flag("handshake-timeout", 5) as u64
+        ))
+        .ban_duration(Duration::from_secs(args.usize_flag("ban-secs", 300) as u64))
+        .waiting_room(args.usize_flag("waiting-room", 8));
+    let passphrase = history_passphrase(&args);
+    let (journal, left) = open_journal(&args, passphrase.as_deref());
+    builder = builder.journal(journal.clone());
+    let resume_secs = args.usize_flag("resume-secs", 120) as u64;
+    if resume_secs > 0 {
+        builder = builder.resume(ResumeTokens::new(Duration::from_secs(resume_secs)));
+    }
+    match AcceptFilter::new(
+        args.flag("allow").map(|a| a.as_str()),
+        args.flag("deny").map(|d| d.as_str()),
+    ) {
+        Ok(filter) => builder = builder.accept_filter(filter),
+        Err(err) => {
+            println!("Error: Bad --allow or --deny, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    }
+    let signer = args.flag("sign-key").map(|key| Signer::new(key));
+    let invites = if args.has_flag("invite-only") {
+        let ttl = Duration::from_secs(args.usize_flag("invite-secs", 600) as u64);
+        Some(Invites::new(ttl))
+    } else {
+        None
+    };
+    if let Some(invites) = &invites {
+        builder = builder.invites(invites.clone());
+    }
+    // A headless server relays between its clients, which can't read each other's sealed frames.
+    if args.has_flag("headless") && args.has_flag("otr") {
+        println!("--otr is ignored by a headless server");
+        builder = builder.otr(false);
+    }
+    let (con, server) = match builder.server(&set_port()) {
+        Ok(con) => con,
+        Err(err) => {
+            println!("Error: Could not listen, {}", err);
+            ::std::process::exit(0x0100);
+        }
+    };
+    let mut last_session = args
+        .flag("log")
+        .and_then(|path| LastSession::read(path, passphrase.as_deref()));
+    let transcript = open_transcript(&args, passphrase.as_deref());
+    let (mapping, mapped) = map_port(&args, &server);
+    if args.has_flag("headless") {
+        if let Some(mapped) = &mapped {
+            println!("{}", mapped);
+        }
+        if args.has_flag("qr") {
+            print_qr(&server, &mapping, &invites);
+        }
+        run_headless(&args, con, server, transcript, passphrase.as_deref());
+        return;
+    }
+    let link = if args.has_flag("qr") {
+        let link = print_qr(&server, &mapping, &invites);
+        // The ui would draw over the code straight away, so wait until it has been scanned.
+        println!("Press Enter to start the chat.");
+        let _ = io::stdin().read_line(&mut String::new());
+        link
+    } else {
+        None
+    };
+    let (outgoing, events) = con.spawn_network(Some(server));
 
-    let mut chat: Vec<(String, bool)> = Vec::new();
+    let spell = load_spell_checker(&args);
+    let (mut settings, mut rules, mut keymap) = load_settings(&args);
+    let mut chat = Chat::new();
+    if let Some(transcript) = &transcript {
+        chat.set_transcript(transcript.clone());
+    }
     let mut line = String::new();
+    let mut status: Option<NetStatus> = None;
+    let mut peers = Peers::new();
+    let mut completer = Completer::new();
 
-    init_ncurses();
+    // Our handlers have to be in place before initscr, which otherwise installs its own that just exit.
+    let (tx, rx) = mpsc::channel::<UiEvent>();
+    forward_signals(tx.clone());
+    let mut ui = ChatUi::new(true);
+    install_panic_hook(ui.clone(), outgoing.clone(), transcript.clone());
 
-    let mut max_x = 0;
-    let mut max_y = 0;
-    getmaxyx(stdscr(), &mut max_y, &mut max_x);
-    max_y -= 1;
-    max_x -= 1;
+    chat.set_time_format(settings.time_format());
+    chat.set_relative_times(settings.relative_times);
+    chat.set_formatting(!settings.plain_text);
+    ui.set_theme(&settings.theme());
+    if let Some(max_kbps) = settings.max_kbps {
+        let _ = outgoing.send(OutgoingMsg::Limit(max_kbps));
+    }
+    watch_settings(&mut chat, &settings, tx.clone());
+    let started = Instant::now();
+    let mut away = AutoAway::new(settings.away_after());
+    let mut retention = Retention::new(settings.max_messages(), settings.max_age());
 
-    let (tx, rx) = mpsc::channel::<i32>();
+    let key_tx = tx.clone();
+    let mut keys = ui.clone();
     thread::spawn(move || loop {
-        let c = getch();
-        tx.send(c).unwrap();
+        let key = keys.read_key();
+        key_tx.send(UiEvent::Key(key)).unwrap();
+    });
+    thread::spawn(move || {
+        for event in events {
+            if tx.send(UiEvent::Net(event)).is_err() {
+                return;
+            }
+        }
     });
 
-    let mut sent_time = Stopwatch::start_new();
-    chat.push((String::from("Waiting for client..."), false));
+    if let Some(mapped) = mapped {
+        chat.push_status(mapped, false);
+    }
+    if let Some(link) = link {
+        chat.push_status(format!("Link: {}", link), false);
+    }
+    if let Some(invites) = &invites {
+        chat.push_status(describe_invite(invites), false);
+    }
+    if let Some(signer) = &signer {
+        chat.push_status(
+            format!("Signing messages with the gpg key {}", signer.key()),
+            false,
+        );
+    }
+    if args.has_flag("otr") {
+        chat.push_status(
+            String::from(
+                "Off the record, messages are sealed with keys only this session has once the peer's key arrives",
+            ),
+            false,
+        );
+    }
+    if let Some(last) = &last_session {
+        chat.push_status(last.describe(), false);
+    }
+    chat.push_status(String::from("Waiting for client..."), false);
+    send_left(&outgoing, &mut chat, &journal, left);
 
+    // An event that was already waiting once the last one was handled, and how many were handled since drawing.
+    let mut next: Option<UiEvent> = None;
+    let mut batched = 0;
     loop {
-        con.reject_other_clients(&server);
-
-        let msg = con.receive_message();
-        handle_client_message(&con, &mut chat, msg, sent_time);
-        print_chat(&mut chat, max_y as usize, max_x as usize);
-
-        mv(max_y, 0);
-        attron(COLOR_PAIR(3));
-        printw(">> ");
-        mv(max_y, (3 + line.len()) as i32);
-        refresh();
-
-        client_check_handler(&mut con, &server, &mut chat);
+        // Events that arrive together, like a burst of frames, are handled before drawing once.
+        if next.is_none() || batched >= MAX_BATCH {
+            let panel = if chat.show_stats {
+                Some(stats(&status))
+            } else {
+                None
+            };
+            ui.draw_chat(&mut chat);
+            let peers_panel = if chat.show_peers {
+                Some(peers.lines())
+            } else {
+                None
+            };
+            ui.draw_status(Some(&status_bar(&status)), panel.as_deref());
+            ui.draw_peers(peers_panel.as_deref());
+            let help_panel = if chat.show_help {
+                Some(help(&keymap.help()))
+            } else if chat.show_chatstats {
+                Some(chatstats(chat.messages(), started.elapsed()))
+            } else if chat.show_history {
+                Some(history(last_session.as_ref()))
+            } else {
+                None
+            };
+            ui.draw_help(help_panel.as_deref());
+            ui.draw_input(&line, &spell);
+            batched = 0;
+        }
 
-        let input = rx.recv_timeout(Duration::from_millis(100));
-        if handle_input(
-            &con,
-            &mut chat,
-            input,
-            &mut line,
-            max_y,
-            max_x,
-            &mut sent_time,
-        ) {
-            break;
+        let refresh = if chat.relative_times() {
+            Some(REFRESH)
+        } else {
+            None
+        };
+        let event = match next.take() {
+            Some(event) => Ok(event),
+            None => match [refresh, away.due(), retention.due()]
+                .iter()
+                .flatten()
+                .min()
+            {
+                Some(wait) => rx.recv_timeout(*wait),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            },
+        };
+        batched += 1;
+        match event {
+            // Nothing happened, but the relative times are due to be drawn again, we may be away now or old messages
+            // are due to be dropped.
+            Err(RecvTimeoutError::Timeout) => (),
+            Ok(UiEvent::Key(key)) => {
+                if let Some(frame) = away.key() {
+                    send(&outgoing, frame);
+                    chat.push_status(String::from("You are back"), false);
+                }
+                if handle_input(
+                    &mut ui,
+                    &outgoing,
+                    &mut chat,
+                    key,
+                    &keymap,
+                    &mut line,
+                    &mut completer,
+                    &mut peers,
+                    &mut settings,
+                    &invites,
+                    &signer,
+                    &journal,
+                    &mut last_session,
+                ) {
+                    break;
+                }
+            }
+            Ok(UiEvent::Net(event)) => handle_network_event(
+                &mut ui,
+                &mut chat,
+                &mut status,
+                &mut peers,
+                &rules,
+                &settings,
+                event,
+            ),
+            Ok(UiEvent::Reload) => reload_settings(
+                &mut ui,
+                &args,
+                &mut chat,
+                &outgoing,
+                &mut settings,
+                &mut rules,
+                &mut keymap,
+            ),
+            Ok(UiEvent::Quit) | Err(_) => break,
+        }
+        away.set_after(settings.away_after());
+        if let Some(frame) = away.check() {
+            send(&outgoing, frame);
+            chat.push_status(
+                String::from("You are shown as away, press a key to come back"),
+                false,
+            );
+        }
+        retention.set_limits(settings.max_messages(), settings.max_age());
+        if let Some((max_messages, before)) = retention.check() {
+            chat.prune(max_messages, before);
         }
+        next = rx.try_recv().ok();
     }
 
-    drop(server);
-    endwin();
+    say_goodbye(&outgoing, &transcript);
+    ui.end();
 }
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::chat::time_ago;
+use crate::config::Uri;
+use crate::settings::Settings;
+
+/// How many servers are remembered, the least recent is forgotten first.
+const MAX_SERVERS: usize = 10;
+
+/// A RecentServer which is a server we connected to, and when we last did.
+///
+/// # Fields
+/// `link` - The server's link, without the invite token since that only works once.
+/// `time` - When we last connected, in seconds since the unix epoch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecentServer {
+    pub link: String,
+    pub time: i64,
+}
+
+/// RecentServers which are the servers we connected to lately, newest first, kept next to the config file so a
+/// bare `r2wc-client` can offer them and `--last` can reconnect.
+///
+/// # Fields
+/// `servers` - The servers, newest first.
+/// `path` - Where they were loaded from and are saved to, None to never save.
+pub struct RecentServers {
+    servers: Vec<RecentServer>,
+    path: Option<PathBuf>,
+}
+
+impl RecentServers {
+    /// Where the recent servers are kept by default, `recent.json` next to the default config file.
+    ///
+    /// # Returns
+    /// `Option<PathBuf>` - the path, None when there is no home directory.
+    pub fn default_path() -> Option<PathBuf> {
+        return Some(Settings::default_path()?.with_file_name("recent.json"));
+    }
+
+    /// Loads the recent servers, a missing or unreadable file gives none.
+    ///
+    /// # Arguments
+    /// * `path` - A Option<PathBuf> of the file, None to remember nothing.
+    ///
+    /// # Returns
+    /// `RecentServers` - the servers, newest first.
+    pub fn load(path: Option<PathBuf>) -> RecentServers {
+        let servers = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        return RecentServers {
+            servers: servers,
+            path: path,
+        };
+    }
+
+    /// Accessor method for the servers.
+    ///
+    /// # Returns
+    /// `&[RecentServer]` - the servers, newest first.
+    pub fn servers(&self) -> &[RecentServer] {
+        return &self.servers;
+    }
+
+    /// Finds the link to a server we connected to.
+    ///
+    /// # Arguments
+    /// * `n` - A usize of which server, 0 for the most recent.
+    ///
+    /// # Returns
+    /// `Result<Uri, String>` - the link, or why there isn't one.
+    pub fn uri(&self, n: usize) -> Result<Uri, String> {
+        return match self.servers.get(n) {
+            Some(server) => Uri::parse(&server.link),
+            None if self.servers.is_empty() => Err(String::from("no server connected to yet")),
+            None => Err(format!("only {} servers connected to", self.servers.len())),
+        };
+    }
+
+    /// Remembers that we connected to a server now, moving it to the top.
+    ///
+    /// # Arguments
+    /// * `uri` - A &Uri of the server's link.
+    pub fn visited(&mut self, uri: &Uri) {
+        let link = Uri {
+            token: None,
+            ..uri.clone()
+        }
+        .to_string();
+        self.servers.retain(|server| server.link != link);
+        self.servers.insert(
+            0,
+            RecentServer {
+                link: link,
+                time: Local::now().timestamp(),
+            },
+        );
+        self.servers.truncate(MAX_SERVERS);
+    }
+
+    /// Writes the servers back to the file they were loaded from, creating its directory if needed.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or why the file could not be written.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(&self.servers).map_err(|e| e.to_string())?;
+        return fs::write(path, text + "\n").map_err(|e| e.to_string());
+    }
+
+    /// Lists the servers to pick from, numbered from 1.
+    ///
+    /// # Returns
+    /// `Vec<String>` - a line per server with how long ago we connected.
+    pub fn lines(&self) -> Vec<String> {
+        let now = Local::now().timestamp();
+        return self
+            .servers
+            .iter()
+            .enumerate()
+            .map(|(i, server)| {
+                format!(
+                    "{:>3}) {}  {}",
+                    i + 1,
+                    server.link,
+                    time_ago(server.time, now)
+                )
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{parse_age, Uri, URI_SCHEME};
+use crate::rules::Rule;
+use crate::theme::{Theme, BUILT_IN};
+
+/// How message times are shown when the config file doesn't say.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Settings which are read from the JSON config file given by `--config`.
+///
+/// # Fields
+/// `rules` - The notification rules, checked in order against every incoming message.
+/// `ignore` - The senders whose messages are never shown, changed with `/ignore` and `/unignore`.
+/// `keys` - The keys to bind to actions by name, see Keymap.
+/// `time_format` - How message times are shown, a chrono strftime format, DEFAULT_TIME_FORMAT if not given.
+/// `relative_times` - Whether messages show how long ago they were sent, like `5m ago`, instead.
+/// `plain_text` - Whether `*bold*`, `_underline_` and `` `code` `` in messages are shown as typed rather than drawn.
+/// `max_kbps` - The most kilobits per second to send, over `--max-kbps`, 0 for no limit.
+/// `away_after` - How many minutes without a key before the peer is told we are away, never if not given or 0.
+/// `theme` - The name of the theme the ui is drawn in, changed with `/theme`, the first of BUILT_IN if not given.
+/// `themes` - The user's own themes by name, a name here takes the place of a built in theme.
+/// `relay` - The headless server's settings that can change while it runs, applied again on SIGHUP.
+/// `retention` - How much history is kept, in the chat and a headless server's store and memory.
+/// `contacts` - The address book, where to reach people by name for `r2wc-client @name`, changed with `/contacts`.
+/// `path` - Where the settings were loaded from and are saved to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub rules: Vec<Rule>,
+    pub ignore: Vec<String>,
+    pub keys: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub relative_times: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub plain_text: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_kbps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub away_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub themes: BTreeMap<String, Theme>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<RelaySettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionSettings>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub contacts: BTreeMap<String, Contact>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+/// RelaySettings which a headless server applies when it starts and again whenever it gets SIGHUP, over the flags
+/// it was started with. A setting that is left out keeps its current value.
+///
+/// # Fields
+/// `motd` - The message sent to each client once it completes the handshake, empty clears it.
+/// `max_kbps` - The most kilobits per second to send across all clients, 0 for no limit.
+/// `allow` - The comma separated CIDRs that may connect, like `--allow`, empty allows everyone.
+/// `deny` - The comma separated CIDRs that may not connect, like `--deny`, empty denies no one.
+/// `max_clients` - The most clients connected at once, like `--max-clients`, 0 for no limit.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelaySettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_kbps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_clients: Option<usize>,
+}
+
+/// RetentionSettings which limit how much history is kept, enforced every minute and by `/purge`. A limit that is
+/// left out keeps everything.
+///
+/// # Fields
+/// `max_messages` - The most messages kept, the oldest are dropped first.
+/// `max_age` - How old a message can get before it is dropped, an age like `7d`, see `parse_age`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_messages: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<String>,
+}
+
+/// A Contact which is an address book entry, how to reach someone by name.
+///
+/// # Fields
+/// `addr` - Where they are, host:port or a whole r2wc:// link with its invite and nick.
+/// `key` - Their identity key as they gave it, like the fingerprint of the key they sign with, for checking it's them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    pub addr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Contact {
+    /// The link to connect to the contact with.
+    ///
+    /// # Returns
+    /// `Result<Uri, String>` - the link, or what is wrong with the address.
+    pub fn uri(&self) -> Result<Uri, String> {
+        if self.addr.contains("://") {
+            return Uri::parse(&self.addr);
+        }
+        return Uri::parse(&format!("{}{}", URI_SCHEME, self.addr));
+    }
+}
+
+impl Settings {
+    /// The config file used when `--config` is not given, ~/.config/r2wc/config.json.
+    ///
+    /// # Returns
+    /// `Option<PathBuf>` - the path, or None if there is no home directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        return Some(base.join("r2wc").join("config.json"));
+    }
+
+    /// Loads Settings from a config file, a missing file gives the defaults.
+    ///
+    /// # Arguments
+    /// * `path` - A Option<PathBuf> of the config file, None to use the defaults and never save.
+    ///
+    /// # Returns
+    /// `Result<Settings, String>` - the settings, or why the file could not be read.
+    pub fn load(path: Option<PathBuf>) -> Result<Settings, String> {
+        let mut settings = match path.as_ref().map(fs::read_to_string) {
+            Some(Ok(text)) => serde_json::from_str(&text).map_err(|e| e.to_string())?,
+            Some(Err(ref err)) if err.kind() == ErrorKind::NotFound => Settings::default(),
+            Some(Err(err)) => return Err(err.to_string()),
+            None => Settings::default(),
+        };
+        if let Some(format) = &settings.time_format {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("time_format \"{}\" is not a valid format", format));
+            }
+        }
+        if let Some(age) = settings.retention.as_ref().and_then(|r| r.max_age.as_ref()) {
+            parse_age(age).map_err(|e| format!("retention max_age {}", e))?;
+        }
+        if let Some(name) = &settings.theme {
+            if settings.find_theme(name).is_none() {
+                return Err(format!("there is no theme \"{}\"", name));
+            }
+        }
+        settings.path = path;
+
+        return Ok(settings);
+    }
+
+    /// Accessor method for how message times are shown.
+    ///
+    /// # Returns
+    /// `&str` - the strftime format, DEFAULT_TIME_FORMAT if the config file doesn't give one.
+    pub fn time_format(&self) -> &str {
+        return self.time_format.as_deref().unwrap_or(DEFAULT_TIME_FORMAT);
+    }
+
+    /// Accessor method for how long without a key before we are away.
+    ///
+    /// # Returns
+    /// `Option<Duration>` - the idle time, None if we are never marked away.
+    pub fn away_after(&self) -> Option<Duration> {
+        return self
+            .away_after
+            .filter(|&minutes| minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60));
+    }
+
+    /// Accessor method for the most messages kept.
+    ///
+    /// # Returns
+    /// `Option<usize>` - the limit, None to keep any number.
+    pub fn max_messages(&self) -> Option<usize> {
+        return self.retention.as_ref().and_then(|r| r.max_messages);
+    }
+
+    /// Accessor method for how old a message can get before it is dropped.
+    ///
+    /// # Returns
+    /// `Option<Duration>` - the age, None to keep messages however old, checked when the file is loaded.
+    pub fn max_age(&self) -> Option<Duration> {
+        return self
+            .retention
+            .as_ref()
+            .and_then(|r| r.max_age.as_ref())
+            .and_then(|age| parse_age(age).ok());
+    }
+
+    /// Finds a theme by name, the user's own before the built in ones.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the theme's name.
+    ///
+    /// # Returns
+    /// `Option<Theme>` - the theme, None if there is none by that name.
+    pub fn find_theme(&self, name: &str) -> Option<Theme> {
+        return self
+            .themes
+            .get(name)
+            .cloned()
+            .or_else(|| Theme::built_in(name));
+    }
+
+    /// Accessor method for the name of the theme the ui should be drawn in.
+    ///
+    /// # Returns
+    /// `&str` - the name given by `theme`, the first of BUILT_IN if not given.
+    pub fn theme_name(&self) -> &str {
+        return self.theme.as_deref().unwrap_or(BUILT_IN[0]);
+    }
+
+    /// The theme the ui should be drawn in.
+    ///
+    /// # Returns
+    /// `Theme` - the theme named by `theme_name`.
+    pub fn theme(&self) -> Theme {
+        return self.find_theme(self.theme_name()).unwrap_or_default();
+    }
+
+    /// The names of every theme `/theme` can switch to, the built in ones first.
+    ///
+    /// # Returns
+    /// `Vec<&str>` - the names.
+    pub fn theme_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = BUILT_IN.to_vec();
+        for name in self.themes.keys() {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        return names;
+    }
+
+    /// Accessor method for where the Settings were loaded from.
+    ///
+    /// # Returns
+    /// `Option<&PathBuf>` - the config file, None if there is none.
+    pub fn path(&self) -> Option<&PathBuf> {
+        return self.path.as_ref();
+    }
+
+    /// Writes the settings back to the file they were loaded from, creating its directory if needed.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or why the file could not be written.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Err(String::from("no config file, pass --config path")),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+
+        return fs::write(path, text + "\n").map_err(|e| e.to_string());
+    }
+
+    /// Adds a sender to the ignore list.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of the sender, an address or just an IP to ignore every port.
+    ///
+    /// # Returns
+    /// `bool` - false if the sender was already ignored.
+    pub fn ignore(&mut self, sender: &str) -> bool {
+        if self.ignore.iter().any(|s| s == sender) {
+            return false;
+        }
+        self.ignore.push(String::from(sender));
+        return true;
+    }
+
+    /// Removes a sender from the ignore list.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of the sender as it was ignored.
+    ///
+    /// # Returns
+    /// `bool` - false if the sender wasn't ignored.
+    pub fn unignore(&mut self, sender: &str) -> bool {
+        let before = self.ignore.len();
+        self.ignore.retain(|s| s != sender);
+        return self.ignore.len() != before;
+    }
+
+    /// Finds a contact in the address book.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the contact's name, matched ignoring case.
+    ///
+    /// # Returns
+    /// `Option<(&String, &Contact)>` - the name as it was saved and the contact, None if there is no such contact.
+    pub fn contact(&self, name: &str) -> Option<(&String, &Contact)> {
+        return self
+            .contacts
+            .iter()
+            .find(|(saved, _)| saved.eq_ignore_ascii_case(name));
+    }
+
+    /// Adds a contact to the address book, or changes where they are.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the contact's name.
+    /// * `contact` - A Contact of where they are.
+    ///
+    /// # Returns
+    /// `Result<bool, String>` - whether the contact is new, or what is wrong with the address.
+    pub fn add_contact(&mut self, name: &str, contact: Contact) -> Result<bool, String> {
+        contact.uri()?;
+        let new = self.remove_contact(name).is_none();
+        self.contacts.insert(String::from(name), contact);
+        return Ok(new);
+    }
+
+    /// Removes a contact from the address book.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of the contact's name, matched ignoring case.
+    ///
+    /// # Returns
+    /// `Option<Contact>` - the contact, None if there was no such contact.
+    pub fn remove_contact(&mut self, name: &str) -> Option<Contact> {
+        let saved = self.contact(name)?.0.clone();
+        return self.contacts.remove(&saved);
+    }
+
+    /// Checks whether messages from a sender should be dropped.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of who sent the message, matched whole or by its IP for addresses.
+    ///
+    /// # Returns
+    /// `bool` - true if the sender or its IP is on the ignore list.
+    pub fn is_ignored(&self, sender: &str) -> bool {
+        let ip = match sender.rfind(':') {
+            Some(i) if sender[i + 1..].chars().all(|c| c.is_ascii_digit()) => &sender[..i],
+            _ => sender,
+        };
+        return self
+            .ignore
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(sender) || s == ip);
+    }
+}
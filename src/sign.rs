@@ -0,0 +1,350 @@
+use std::convert::TryInto;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+extern crate base64;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// The OpenPGP packet tags of a signature and of literal data.
+const SIGNATURE_TAG: u8 = 2;
+const LITERAL_TAG: u8 = 11;
+
+/// A Signer which signs our messages with a key from the local GPG keyring, by running `gpg`.
+///
+/// The key has to be usable without a passphrase prompt, e.g. already unlocked in gpg-agent, since the prompt would
+/// fight the ui for the terminal.
+///
+/// # Fields
+/// `key` - The key id, fingerprint or user id `gpg --local-user` picks the key by.
+pub struct Signer {
+    key: String,
+}
+
+impl Signer {
+    /// Creates a new Signer for a key.
+    ///
+    /// # Arguments
+    /// * `key` - A &str of the key id, fingerprint or user id of the key to sign with.
+    ///
+    /// # Returns
+    ///  `Signer` - the newly created signer.
+    pub fn new(key: &str) -> Signer {
+        return Signer {
+            key: String::from(key),
+        };
+    }
+
+    /// Accessor method for the key messages are signed with.
+    ///
+    /// # Returns
+    /// `&str` - the key as it was given.
+    pub fn key(&self) -> &str {
+        return &self.key;
+    }
+
+    /// Makes a detached signature for a message.
+    ///
+    /// # Arguments
+    /// * `body` - A &str of the message as it is sent.
+    ///
+    /// # Returns
+    /// `Result<String, String>` - the base64 signature, or why gpg couldn't sign it.
+    pub fn sign(&self, body: &str) -> Result<String, String> {
+        let mut child = Command::new("gpg")
+            .args(&[
+                "--batch",
+                "--no-tty",
+                "--yes",
+                "--detach-sign",
+                "--local-user",
+            ])
+            .arg(&self.key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not run gpg, {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(body.as_bytes())
+                .map_err(|e| format!("could not hand gpg the message, {}", e))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("gpg failed, {}", e))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            let err = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "gpg could not sign with {}, {}",
+                self.key,
+                last_line(&err)
+            ));
+        }
+
+        return Ok(STANDARD.encode(&output.stdout));
+    }
+}
+
+/// A Signature which is a good signature, as gpg tells who made it.
+///
+/// # Fields
+/// `uid` - The user id of the key that made it.
+/// `fingerprint` - The fingerprint of the key that made it.
+/// `primary` - The fingerprint of that key's primary key, the same as `fingerprint` unless a subkey made it.
+/// `trusted` - Whether the local keyring trusts the key fully or ultimately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub uid: String,
+    pub fingerprint: String,
+    pub primary: String,
+    pub trusted: bool,
+}
+
+impl Signature {
+    /// Whether the signature was made with a key, as a contact gave it.
+    ///
+    /// Only fingerprints and key ids are compared, a user id doesn't pin a key since anyone can make one with any
+    /// user id.
+    ///
+    /// # Arguments
+    /// * `key` - A &str of the key's fingerprint or key id, spaces and a leading `0x` are ignored.
+    ///
+    /// # Returns
+    /// `bool` - true if the key or its primary key made the signature.
+    pub fn is_by(&self, key: &str) -> bool {
+        let key: String = key.split_whitespace().collect::<String>().to_uppercase();
+        let key = key.strip_prefix("0X").unwrap_or(&key);
+        if key.len() < 16 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return false;
+        }
+        return self.fingerprint.ends_with(key) || self.primary.ends_with(key);
+    }
+}
+
+/// Checks a peer's signature on a message against the keys in the local GPG keyring.
+///
+/// # Arguments
+/// * `body` - A &str of the message as it was received.
+/// * `signature` - A &str of the base64 detached signature sent with it.
+///
+/// # Returns
+/// `Result<Signature, String>` - who signed it, or why the signature can't be trusted.
+pub fn verify(body: &str, signature: &str) -> Result<Signature, String> {
+    let signature = STANDARD
+        .decode(signature)
+        .map_err(|_| String::from("the signature is not base64"))?;
+    let message = signed_message(&signature, body)?;
+    let output = Command::new("gpg")
+        .args(&["--batch", "--no-tty", "--status-fd", "1", "--verify"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(&message)?;
+            }
+            return child.wait_with_output();
+        })
+        .map_err(|e| format!("could not run gpg, {}", e))?;
+
+    return read_status(&String::from_utf8_lossy(&output.stdout));
+}
+
+/// Puts a detached signature and the message it signs together into one signed OpenPGP message, the signature
+/// packets followed by a literal data packet holding the message, so gpg can read both from stdin and nothing has
+/// to be written to disk.
+///
+/// # Arguments
+/// * `signature` - A &[u8] of the detached signature as gpg made it.
+/// * `body` - A &str of the message it is for.
+///
+/// # Returns
+/// `Result<Vec<u8>, String>` - the signed message, or why the signature isn't only signature packets, which could
+/// make gpg check something other than the message.
+fn signed_message(signature: &[u8], body: &str) -> Result<Vec<u8>, String> {
+    let mut rest = signature;
+    while !rest.is_empty() {
+        let (tag, len) = packet_header(rest).ok_or("the signature is not an OpenPGP packet")?;
+        if tag != SIGNATURE_TAG {
+            return Err(String::from("the signature holds more than a signature"));
+        }
+        rest = rest.get(len..).ok_or("the signature is cut short")?;
+    }
+    if signature.is_empty() {
+        return Err(String::from("the signature is empty"));
+    }
+
+    // Binary data, no file name and no date.
+    let mut literal = vec![b'b', 0, 0, 0, 0, 0];
+    literal.extend_from_slice(body.as_bytes());
+    let mut message = signature.to_vec();
+    message.push(0xC0 | LITERAL_TAG);
+    message.push(0xFF);
+    message.extend_from_slice(&(literal.len() as u32).to_be_bytes());
+    message.extend_from_slice(&literal);
+    return Ok(message);
+}
+
+/// Reads an OpenPGP packet's header, in either the old or the new format.
+///
+/// # Returns
+/// `Option<(u8, usize)>` - the packet's tag and its whole length with the header, None if it isn't a whole header
+/// or its length isn't given up front.
+fn packet_header(packet: &[u8]) -> Option<(u8, usize)> {
+    let first = *packet.first()?;
+    if first & 0x80 == 0 {
+        return None;
+    }
+    if first & 0x40 == 0 {
+        let tag = (first >> 2) & 0x0F;
+        let (size, len) = match first & 0x03 {
+            0 => (1, *packet.get(1)? as usize),
+            1 => (
+                2,
+                u16::from_be_bytes([*packet.get(1)?, *packet.get(2)?]) as usize,
+            ),
+            2 => (
+                4,
+                u32::from_be_bytes(packet.get(1..5)?.try_into().ok()?) as usize,
+            ),
+            _ => return None,
+        };
+        return Some((tag, 1 + size + len));
+    }
+    let tag = first & 0x3F;
+    let (size, len) = match *packet.get(1)? {
+        len @ 0..=191 => (1, len as usize),
+        192..=223 => (
+            2,
+            ((*packet.get(1)? as usize - 192) << 8) + *packet.get(2)? as usize + 192,
+        ),
+        255 => (
+            5,
+            u32::from_be_bytes(packet.get(2..6)?.try_into().ok()?) as usize,
+        ),
+        _ => return None,
+    };
+    return Some((tag, 1 + size + len));
+}
+
+/// Reads what gpg made of a signature from the lines it wrote to `--status-fd`.
+///
+/// # Arguments
+/// * `status` - A &str of gpg's status lines.
+///
+/// # Returns
+/// `Result<Signature, String>` - who signed it if the signature is good, or why it isn't.
+fn read_status(status: &str) -> Result<Signature, String> {
+    let mut good: Option<Signature> = None;
+    for line in status.lines() {
+        let mut parts = line.splitn(4, ' ');
+        if parts.next() != Some("[GNUPG:]") {
+            continue;
+        }
+        let keyword = parts.next().unwrap_or("");
+        let key = parts.next().unwrap_or("");
+        let uid = parts.next().unwrap_or(key);
+        match (keyword, good.as_mut()) {
+            ("GOODSIG", None) => {
+                good = Some(Signature {
+                    uid: String::from(uid),
+                    fingerprint: String::new(),
+                    primary: String::new(),
+                    trusted: false,
+                })
+            }
+            ("GOODSIG", Some(_)) => {
+                return Err(String::from("the message is signed more than once"))
+            }
+            // The primary key's fingerprint is the last field, when gpg gives it.
+            ("VALIDSIG", Some(good)) => {
+                good.fingerprint = String::from(key);
+                good.primary = String::from(line.rsplit(' ').next().unwrap_or(key));
+            }
+            ("TRUST_FULLY", Some(good)) | ("TRUST_ULTIMATE", Some(good)) => good.trusted = true,
+            ("TRUST_NEVER", Some(good)) => {
+                return Err(format!(
+                    "{}'s key is marked as never to be trusted",
+                    good.uid
+                ))
+            }
+            ("BADSIG", _) => {
+                return Err(format!(
+                    "the signature by {} doesn't match the message",
+                    uid
+                ))
+            }
+            ("EXPKEYSIG", _) => return Err(format!("{}'s key has expired", uid)),
+            ("REVKEYSIG", _) => return Err(format!("{}'s key has been revoked", uid)),
+            // The seventh field is why, 9 for a key we don't have.
+            ("ERRSIG", _) if line.split(' ').nth(7) == Some("9") => {
+                return Err(format!("no public key for {}, import it to check it", key))
+            }
+            ("ERRSIG", _) => return Err(format!("gpg could not check the signature by {}", key)),
+            _ => (),
+        }
+    }
+
+    return good.ok_or(String::from("gpg could not read the signature"));
+}
+
+/// The last non-empty line of gpg's error output, which says what went wrong.
+fn last_line(err: &str) -> &str {
+    return err
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("no reason given");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_status, signed_message, Signature};
+
+    #[test]
+    fn only_signature_packets_are_passed_to_gpg() {
+        // An old format signature packet and a new format one, with made up contents.
+        let signature = [0x88, 0x02, 0x04, 0x00, 0xC2, 0x01, 0x04];
+        let message = signed_message(&signature, "hi").unwrap();
+        assert_eq!(&message[..7], &signature);
+        assert_eq!(
+            &message[7..],
+            &[0xCB, 0xFF, 0, 0, 0, 8, b'b', 0, 0, 0, 0, 0, b'h', b'i']
+        );
+
+        // A literal data packet slipped in would be checked in place of the message.
+        let smuggled = [0xC2, 0x01, 0x04, 0xCB, 0x01, b'b'];
+        assert!(signed_message(&smuggled, "hi").is_err());
+        assert!(signed_message(&signature[..6], "hi").is_err());
+        assert!(signed_message(&[], "hi").is_err());
+    }
+
+    #[test]
+    fn gpg_status_says_who_signed() {
+        let status = "[GNUPG:] NEWSIG
+[GNUPG:] GOODSIG 0EFEBCA48FC802E3 Alice <alice@example.com>
+[GNUPG:] VALIDSIG 5D0A0EFEBCA48FC802E30EFEBCA48FC802E35D0A 2026-10-15 1792084308 0 4 0 22 8 00 A6ABA9DF8726D92451F0F7CD0EFEBCA48FC802E3
+[GNUPG:] TRUST_UNDEFINED 0 pgp
+";
+        let signature = read_status(status).unwrap();
+        assert_eq!(
+            signature,
+            Signature {
+                uid: String::from("Alice <alice@example.com>"),
+                fingerprint: String::from("5D0A0EFEBCA48FC802E30EFEBCA48FC802E35D0A"),
+                primary: String::from("A6ABA9DF8726D92451F0F7CD0EFEBCA48FC802E3"),
+                trusted: false,
+            }
+        );
+        assert!(signature.is_by("A6AB A9DF 8726 D924 51F0  F7CD 0EFE BCA4 8FC8 02E3"));
+        assert!(signature.is_by("0x0efebca48fc802e3"));
+        assert!(!signature.is_by("alice@example.com"));
+        assert!(!signature.is_by("8FC802E3"));
+
+        let never = status.replace("TRUST_UNDEFINED", "TRUST_NEVER");
+        assert!(read_status(&never).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+/// The word list used by `--spellcheck` when no `--dict` is given.
+pub const DEFAULT_DICT: &str = "/usr/share/dict/words";
+
+/// A SpellChecker which flags words that are not in a word list.
+///
+/// # Fields
+/// `words` - The known words, lowercased.
+pub struct SpellChecker {
+    words: HashSet<String>,
+}
+
+impl SpellChecker {
+    /// Loads a SpellChecker from a word list with one word per line, like /usr/share/dict/words.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the word list.
+    ///
+    /// # Returns
+    /// `io::Result<SpellChecker>` - the loaded checker, or why the word list could not be read.
+    pub fn load(path: &str) -> io::Result<SpellChecker> {
+        let words = fs::read_to_string(path)?
+            .lines()
+            .map(|w| w.trim().to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        return Ok(SpellChecker { words: words });
+    }
+
+    /// Finds the misspelled words in a line.
+    ///
+    /// Words are runs of letters and apostrophes. Single letters and anything mixed with digits, like ids or
+    /// times, are never flagged.
+    ///
+    /// # Arguments
+    /// * `line` - A &str of the line to check.
+    ///
+    /// # Returns
+    /// `Vec<(usize, usize)>` - the byte ranges of misspelled words, in order.
+    pub fn misspelled(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut start = None;
+
+        for (i, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+            if c.is_alphanumeric() || c == '\'' {
+                start = start.or(Some(i));
+                continue;
+            }
+            if let Some(s) = start.take() {
+                if !self.is_known(&line[s..i]) {
+                    ranges.push((s, i));
+                }
+            }
+        }
+
+        return ranges;
+    }
+
+    /// Checks a single word, allowing for possessives and surrounding quotes.
+    fn is_known(&self, word: &str) -> bool {
+        let word = word.trim_matches('\'');
+        if word.chars().count() < 2 || word.chars().any(|c| c.is_numeric()) {
+            return true;
+        }
+
+        let lower = word.to_lowercase();
+        return self.words.contains(&lower)
+            || lower
+                .strip_suffix("'s")
+                .map(|w| self.words.contains(w))
+                .unwrap_or(false);
+    }
+}
@@ -0,0 +1,462 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sqlite")]
+use chrono::Utc;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+#[cfg(feature = "sqlite")]
+use crate::vault::Vault;
+
+/// The tables and indexes of a message store, created when it is opened.
+///
+/// Deleted rows are overwritten with zeros, so purged messages aren't left behind in the file's free pages.
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+    PRAGMA secure_delete = ON;
+    CREATE TABLE IF NOT EXISTS peers (
+        addr TEXT PRIMARY KEY,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        connects INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        seq INTEGER PRIMARY KEY,
+        time INTEGER NOT NULL,
+        sender TEXT NOT NULL,
+        msg_id INTEGER NOT NULL,
+        body TEXT NOT NULL,
+        edited INTEGER NOT NULL DEFAULT 0,
+        deleted INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS messages_time ON messages (time);
+    CREATE INDEX IF NOT EXISTS messages_sender ON messages (sender, msg_id);
+    CREATE TABLE IF NOT EXISTS vault (
+        header TEXT NOT NULL
+    );
+";
+
+/// The columns of a StoredMessage, in the order `StoredMessage::from_row` reads them.
+#[cfg(feature = "sqlite")]
+const MESSAGE_COLUMNS: &str = "time, sender, msg_id, body, edited, deleted";
+
+/// A StoredMessage which is a message read back from the store.
+///
+/// # Fields
+/// `time` - When the message was sent, in seconds since the unix epoch.
+/// `sender` - Who sent it, a client's address or Server.
+/// `id` - The sender's id for the message.
+/// `body` - The message's current text.
+/// `edited` - Whether the message was edited.
+/// `deleted` - Whether the message was deleted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub time: i64,
+    pub sender: String,
+    pub id: u64,
+    pub body: String,
+    pub edited: bool,
+    pub deleted: bool,
+}
+
+/// A Store which keeps the headless server's messages and peers in SQLite.
+///
+/// Only available when built with the `sqlite` feature, otherwise `open` always fails.
+///
+/// # Fields
+/// `db` - The open database.
+/// `vault` - What message bodies are sealed with, None to keep them in the clear.
+#[cfg(feature = "sqlite")]
+pub struct Store {
+    db: Connection,
+    vault: Option<Vault>,
+}
+
+/// A Store which keeps the headless server's messages and peers in SQLite.
+///
+/// Built without the `sqlite` feature, so `open` always fails and a Store never exists.
+#[cfg(not(feature = "sqlite"))]
+pub enum Store {}
+
+#[cfg(feature = "sqlite")]
+impl StoredMessage {
+    /// Reads a StoredMessage from a row selected with MESSAGE_COLUMNS.
+    fn from_row(row: &Row) -> rusqlite::Result<StoredMessage> {
+        return Ok(StoredMessage {
+            time: row.get(0)?,
+            sender: row.get(1)?,
+            id: row.get::<_, i64>(2)? as u64,
+            body: row.get(3)?,
+            edited: row.get(4)?,
+            deleted: row.get(5)?,
+        });
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Store {
+    /// Opens a Store, creating the database and its tables if they don't exist.
+    ///
+    /// With a passphrase message bodies are sealed, see `Vault`, while who sent them and when stay readable so
+    /// they can still be queried. A new database keeps the vault's header in its vault table, an existing one has
+    /// to have been encrypted with the same passphrase from its first message.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the database file.
+    /// * `passphrase` - A Option<&str> to encrypt message bodies with, None to keep them in the clear.
+    ///
+    /// # Returns
+    /// `Result<Store, String>` - the opened store, or why it could not be opened.
+    pub fn open(path: &str, passphrase: Option<&str>) -> Result<Store, String> {
+        let db = Connection::open(path).map_err(|e| e.to_string())?;
+        db.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        let header: Option<String> = db
+            .query_row("SELECT header FROM vault", [], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let messages: i64 = db
+            .query_row("SELECT count(*) FROM messages", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+
+        let vault = match (passphrase, header) {
+            (Some(passphrase), Some(header)) => Some(Vault::unlock(passphrase, &header)?),
+            (Some(passphrase), None) if messages == 0 => {
+                let vault = Vault::create(passphrase).map_err(|e| e.to_string())?;
+                db.execute(
+                    "INSERT INTO vault (header) VALUES (?1)",
+                    params![vault.header()],
+                )
+                .map_err(|e| e.to_string())?;
+                Some(vault)
+            }
+            (Some(_), None) => {
+                return Err(String::from(
+                    "it already holds messages in the clear, encrypt a new one",
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(String::from(
+                    "it is encrypted, start with --encrypt-history",
+                ))
+            }
+            (None, None) => None,
+        };
+
+        return Ok(Store {
+            db: db,
+            vault: vault,
+        });
+    }
+
+    /// Seals a message body for the messages table, unchanged without a vault.
+    fn seal(&self, body: &str) -> String {
+        return match &self.vault {
+            Some(vault) => vault.seal(body),
+            None => String::from(body),
+        };
+    }
+
+    /// Records that a peer connected.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the peer's address.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or the database error.
+    pub fn record_peer(&self, addr: &str) -> Result<(), String> {
+        let now = Utc::now().timestamp();
+        self.db
+            .execute(
+                "INSERT INTO peers (addr, first_seen, last_seen, connects) VALUES (?1, ?2, ?2, 1)
+                 ON CONFLICT (addr) DO UPDATE SET last_seen = ?2, connects = connects + 1",
+                params![addr, now],
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    /// Records that a peer left, so it can be sent what it missed when it comes back, even after a restart.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the peer's address.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or the database error.
+    pub fn record_peer_left(&self, addr: &str) -> Result<(), String> {
+        self.db
+            .execute(
+                "UPDATE peers SET last_seen = ?2 WHERE addr = ?1",
+                params![addr, Utc::now().timestamp()],
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    /// Finds when a peer was last seen from an address's IP, when it last left or, if it never got to, connected.
+    ///
+    /// # Arguments
+    /// * `addr` - A &str of the peer's address, only its IP is compared.
+    ///
+    /// # Returns
+    /// `Result<Option<i64>, String>` - the unix time, None if the IP is not in the peers table, or the database
+    /// error.
+    pub fn last_seen_ip(&self, addr: &str) -> Result<Option<i64>, String> {
+        let prefix = &addr[..addr.rfind(':').map(|i| i + 1).unwrap_or(addr.len())];
+        return self
+            .db
+            .query_row(
+                "SELECT max(last_seen) FROM peers WHERE substr(addr, 1, length(?1)) = ?1",
+                params![prefix],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string());
+    }
+
+    /// Records a message.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of who sent the message.
+    /// * `id` - A u64 of the sender's id for the message.
+    /// * `body` - A &str of the message's text.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or the database error.
+    pub fn record_message(&self, sender: &str, id: u64, body: &str) -> Result<(), String> {
+        self.db
+            .execute(
+                "INSERT INTO messages (time, sender, msg_id, body) VALUES (?1, ?2, ?3, ?4)",
+                params![Utc::now().timestamp(), sender, id as i64, self.seal(body)],
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    /// Replaces the text of the sender's latest message with the given id.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of who sent the message.
+    /// * `id` - A u64 of the sender's id for the message.
+    /// * `body` - A &str of the new text.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or the database error.
+    pub fn edit_message(&self, sender: &str, id: u64, body: &str) -> Result<(), String> {
+        self.db
+            .execute(
+                "UPDATE messages SET body = ?3, edited = 1 WHERE seq =
+                 (SELECT max(seq) FROM messages WHERE sender = ?1 AND msg_id = ?2)",
+                params![sender, id as i64, self.seal(body)],
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    /// Marks the sender's latest message with the given id as deleted.
+    ///
+    /// # Arguments
+    /// * `sender` - A &str of who sent the message.
+    /// * `id` - A u64 of the sender's id for the message.
+    ///
+    /// # Returns
+    /// `Result<(), String>` - Ok, or the database error.
+    pub fn delete_message(&self, sender: &str, id: u64) -> Result<(), String> {
+        self.db
+            .execute(
+                "UPDATE messages SET deleted = 1 WHERE seq =
+                 (SELECT max(seq) FROM messages WHERE sender = ?1 AND msg_id = ?2)",
+                params![sender, id as i64],
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    /// Reads the most recent messages, oldest first.
+    ///
+    /// # Arguments
+    /// * `sender` - A Option<&str> to only read one sender's messages.
+    /// * `limit` - A usize of the most messages to read.
+    ///
+    /// # Returns
+    /// `Result<Vec<StoredMessage>, String>` - the messages, or the database error.
+    pub fn recent(&self, sender: Option<&str>, limit: usize) -> Result<Vec<StoredMessage>, String> {
+        let sql = format!(
+            "SELECT {} FROM messages WHERE ?1 IS NULL OR sender = ?1 ORDER BY seq DESC LIMIT ?2",
+            MESSAGE_COLUMNS
+        );
+        return self.query(&sql, params![sender, limit as i64]);
+    }
+
+    /// Reads the most recent messages sent since a time, oldest first.
+    ///
+    /// # Arguments
+    /// * `time` - A i64 unix time, messages from that second on are read.
+    /// * `limit` - A usize of the most messages to read.
+    ///
+    /// # Returns
+    /// `Result<Vec<StoredMessage>, String>` - the messages, or the database error.
+    pub fn since(&self, time: i64, limit: usize) -> Result<Vec<StoredMessage>, String> {
+        let sql = format!(
+            "SELECT {} FROM messages WHERE time >= ?1 ORDER BY seq DESC LIMIT ?2",
+            MESSAGE_COLUMNS
+        );
+        return self.query(&sql, params![time, limit as i64]);
+    }
+
+    /// Finds the most recent messages containing some text, oldest first.
+    ///
+    /// Sealed bodies can't be matched by SQLite, so with a vault every message is opened and matched here.
+    ///
+    /// # Arguments
+    /// * `text` - A &str to search for, matched case insensitively for ASCII.
+    /// * `limit` - A usize of the most messages to read.
+    ///
+    /// # Returns
+    /// `Result<Vec<StoredMessage>, String>` - the matching messages, or the database error.
+    pub fn search(&self, text: &str, limit: usize) -> Result<Vec<StoredMessage>, String> {
+        if self.vault.is_some() {
+            let sql = format!(
+                "SELECT {} FROM messages WHERE deleted = 0 ORDER BY seq DESC",
+                MESSAGE_COLUMNS
+            );
+            let text = text.to_ascii_lowercase();
+            let mut found = self.query(&sql, params![])?;
+            found.retain(|message| message.body.to_ascii_lowercase().contains(&text));
+            return Ok(found.split_off(found.len().saturating_sub(limit)));
+        }
+        let pattern = format!(
+            "%{}%",
+            text.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let sql = format!(
+            "SELECT {} FROM messages WHERE deleted = 0 AND body LIKE ?1 ESCAPE '\\'
+             ORDER BY seq DESC LIMIT ?2",
+            MESSAGE_COLUMNS
+        );
+        return self.query(&sql, params![pattern, limit as i64]);
+    }
+
+    /// Deletes the messages past the retention policy's limits, the oldest first.
+    ///
+    /// # Arguments
+    /// * `max` - A Option<usize> of the most messages to keep, None to keep any number.
+    /// * `before` - A Option<i64> unix time older messages are deleted before, None to keep them however old.
+    ///
+    /// # Returns
+    /// `Result<usize, String>` - how many messages were deleted, or the database error.
+    pub fn prune(&self, max: Option<usize>, before: Option<i64>) -> Result<usize, String> {
+        let mut deleted = 0;
+        if let Some(before) = before {
+            deleted += self
+                .db
+                .execute("DELETE FROM messages WHERE time < ?1", params![before])
+                .map_err(|e| e.to_string())?;
+        }
+        if let Some(max) = max {
+            deleted += self
+                .db
+                .execute(
+                    "DELETE FROM messages WHERE seq NOT IN
+                     (SELECT seq FROM messages ORDER BY seq DESC LIMIT ?1)",
+                    params![max as i64],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        return Ok(deleted);
+    }
+
+    /// Runs a query selecting MESSAGE_COLUMNS newest first, returning the rows oldest first.
+    fn query(
+        &self,
+        sql: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<StoredMessage>, String> {
+        let mut stmt = self.db.prepare(sql).map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params, StoredMessage::from_row)
+            .map_err(|e| e.to_string())?;
+
+        let mut messages = rows
+            .collect::<rusqlite::Result<Vec<StoredMessage>>>()
+            .map_err(|e| e.to_string())?;
+        if let Some(vault) = &self.vault {
+            for message in messages.iter_mut() {
+                message.body = vault.open(&message.body)?;
+            }
+        }
+        messages.reverse();
+        return Ok(messages);
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl Store {
+    /// Opens a Store, which always fails without the `sqlite` feature.
+    ///
+    /// # Arguments
+    /// * `_path` - A &str of the database file.
+    /// * `_passphrase` - A Option<&str> to encrypt message bodies with.
+    ///
+    /// # Returns
+    /// `Result<Store, String>` - why the store could not be opened.
+    pub fn open(_path: &str, _passphrase: Option<&str>) -> Result<Store, String> {
+        return Err(String::from(
+            "built without the sqlite feature, rebuild with --features sqlite",
+        ));
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn record_peer(&self, _addr: &str) -> Result<(), String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn record_peer_left(&self, _addr: &str) -> Result<(), String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn last_seen_ip(&self, _addr: &str) -> Result<Option<i64>, String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn record_message(&self, _sender: &str, _id: u64, _body: &str) -> Result<(), String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn edit_message(&self, _sender: &str, _id: u64, _body: &str) -> Result<(), String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn delete_message(&self, _sender: &str, _id: u64) -> Result<(), String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn recent(
+        &self,
+        _sender: Option<&str>,
+        _limit: usize,
+    ) -> Result<Vec<StoredMessage>, String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn since(&self, _time: i64, _limit: usize) -> Result<Vec<StoredMessage>, String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn prune(&self, _max: Option<usize>, _before: Option<i64>) -> Result<usize, String> {
+        match *self {}
+    }
+
+    /// Unreachable, a Store can't exist without the `sqlite` feature.
+    pub fn search(&self, _text: &str, _limit: usize) -> Result<Vec<StoredMessage>, String> {
+        match *self {}
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(feature = "crossterm")]
+mod cross;
+#[cfg(not(feature = "crossterm"))]
+mod curses;
+// The ratatui ui draws with its own widgets and only needs Key from here.
+#[cfg(feature = "crossterm")]
+#[cfg_attr(feature = "ratatui", allow(unused_imports))]
+pub use self::cross::{
+    attr_off, attr_on, beep, clear, clear_line, end, init, move_to, print, read_key, refresh,
+    set_color, set_theme, size,
+};
+#[cfg(not(feature = "crossterm"))]
+pub use self::curses::{
+    attr_off, attr_on, beep, clear, clear_line, end, init, move_to, print, read_key, refresh,
+    set_color, set_theme, size,
+};
+
+#[cfg(not(any(feature = "ncurses", feature = "crossterm")))]
+compile_error!("r2wc needs a terminal backend, build with the ncurses or crossterm feature");
+
+/// A Color which is what a piece of text is, each drawn in its own colours from the Theme given to `set_theme`.
+///
+/// # Variants
+/// `Remote` - The peer's messages, the theme's `remote`.
+/// `Local` - Our own messages, the theme's `local`.
+/// `Plain` - The input line, the theme's `input`.
+/// `Misspelled` - Misspelled words in the input line, the theme's `error`.
+/// `Failed` - The mark on our messages that were not sent, the theme's `error`.
+/// `Private` - Private messages, either way, the theme's `private`.
+/// `Mention` - Messages that mention our nick, the theme's `mention`.
+/// `System` - Status lines, the theme's `system`.
+/// `Bar` - The status bar, panels and help, the theme's `status_bar`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Remote,
+    Local,
+    Plain,
+    Misspelled,
+    Failed,
+    Private,
+    Mention,
+    System,
+    Bar,
+}
+
+/// An Attr which is a text attribute that can be turned on and off while drawing.
+///
+/// # Variants
+/// `Bold` - Bold text.
+/// `Dim` - Faint text, for quotes and deleted messages.
+/// `Underline` - Underlined text, for urls and misspellings.
+/// `Standout` - The terminal's best highlighting, for messages a rule highlights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attr {
+    Bold,
+    Dim,
+    Underline,
+    Standout,
+}
+
+/// A Key which is a key press the ui loop cares about, what most of them do is up to the Keymap.
+///
+/// # Variants
+/// `Char` - A character to add to the input line.
+/// `Enter` - Enter or return.
+/// `Backspace` - Backspace.
+/// `Resize` - The terminal was resized.
+/// `Quit` - The terminal went away, so there is nobody left to type.
+/// `Ctrl` - A letter with Ctrl held, raw mode delivers Ctrl+C as a key rather than a signal.
+/// `Function` - A function key, F1 is 1.
+/// `Escape` - Esc.
+/// `Tab` - Tab.
+/// `BackTab` - Shift+Tab.
+/// `Up` - The up arrow.
+/// `Down` - The down arrow.
+/// `PageUp` - Page Up.
+/// `PageDown` - Page Down.
+/// `Home` - Home.
+/// `End` - End.
+/// `Other` - Any other key, which is ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Resize,
+    Quit,
+    Ctrl(char),
+    Function(u8),
+    Escape,
+    Tab,
+    BackTab,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Other,
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+extern crate crossterm;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::queue;
+use crossterm::style::{self, Attribute, Colors, Print, SetAttribute, SetColors};
+use crossterm::terminal::{
+    self, disable_raw_mode, enable_raw_mode, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+};
+
+use super::{Attr, Color, Key};
+use crate::theme::{Depth, Pair, Shade, Theme};
+
+/// The Pen which is the colour and attributes text is drawn with.
+///
+/// Crossterm can't turn single attributes off the way ncurses does, turning off bold also turns off dim, so the
+/// whole pen is set again each time it changes.
+///
+/// # Fields
+/// `color` - The colour text is drawn in.
+/// `attrs` - The attributes that are on.
+struct Pen {
+    color: Color,
+    attrs: Vec<Attr>,
+}
+
+static PEN: Mutex<Pen> = Mutex::new(Pen {
+    color: Color::Plain,
+    attrs: Vec::new(),
+});
+
+/// The Theme given to `set_theme`, None until then for the default.
+static THEME: Mutex<Option<Theme>> = Mutex::new(None);
+
+/// The terminal colour for a Shade.
+fn shade(shade: Shade) -> style::Color {
+    match shade {
+        Shade::Default => return style::Color::Reset,
+        Shade::Black => return style::Color::Black,
+        Shade::Red => return style::Color::Red,
+        Shade::Green => return style::Color::Green,
+        Shade::Yellow => return style::Color::Yellow,
+        Shade::Blue => return style::Color::Blue,
+        Shade::Magenta => return style::Color::Magenta,
+        Shade::Cyan => return style::Color::Cyan,
+        Shade::White => return style::Color::White,
+        Shade::Indexed(index) => return style::Color::AnsiValue(index),
+        Shade::Rgb(r, g, b) => return style::Color::Rgb { r: r, g: g, b: b },
+    }
+}
+
+/// The terminal colours for a Color in the theme.
+fn colors(color: Color) -> Colors {
+    let theme = THEME.lock().unwrap();
+    let theme = theme.clone().unwrap_or_default();
+    let pair: &Pair = match color {
+        Color::Remote => &theme.remote,
+        Color::Local => &theme.local,
+        Color::Plain => &theme.input,
+        Color::Misspelled | Color::Failed => &theme.error,
+        Color::Private => &theme.private,
+        Color::Mention => &theme.mention,
+        Color::System => &theme.system,
+        Color::Bar => &theme.status_bar,
+    };
+    return Colors::new(shade(pair.fg), shade(pair.bg));
+}
+
+/// The terminal attribute for an Attr.
+fn attribute(attr: Attr) -> Attribute {
+    match attr {
+        Attr::Bold => return Attribute::Bold,
+        Attr::Dim => return Attribute::Dim,
+        Attr::Underline => return Attribute::Underlined,
+        Attr::Standout => return Attribute::Reverse,
+    }
+}
+
+/// Queues a command, drawing errors have nowhere to be shown so they are ignored like ncurses does.
+macro_rules! draw {
+    ($($command:expr),+) => {
+        let _ = queue!(io::stdout(), $($command),+);
+    };
+}
+
+/// Sets the terminal to draw with the pen.
+fn apply(pen: &Pen) {
+    draw!(SetAttribute(Attribute::Reset), SetColors(colors(pen.color)));
+    for attr in &pen.attrs {
+        draw!(SetAttribute(attribute(*attr)));
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen.
+pub fn init() {
+    let _ = enable_raw_mode();
+    draw!(EnterAlternateScreen, Hide, terminal::Clear(ClearType::All));
+    apply(&PEN.lock().unwrap());
+    refresh();
+}
+
+/// Restores the terminal.
+pub fn end() {
+    draw!(SetAttribute(Attribute::Reset), Show, LeaveAlternateScreen);
+    refresh();
+    let _ = disable_raw_mode();
+}
+
+/// The size of the terminal.
+///
+/// # Returns
+/// `(i32, i32)` - the number of rows and columns.
+pub fn size() -> (i32, i32) {
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    return (rows as i32, cols as i32);
+}
+
+/// Moves the cursor to a row and column.
+pub fn move_to(y: i32, x: i32) {
+    draw!(MoveTo(x.max(0) as u16, y.max(0) as u16));
+}
+
+/// Clears the whole screen.
+pub fn clear() {
+    draw!(terminal::Clear(ClearType::All));
+}
+
+/// Clears from the cursor to the end of its line.
+pub fn clear_line() {
+    draw!(terminal::Clear(ClearType::UntilNewLine));
+}
+
+/// Prints text at the cursor.
+pub fn print(text: &str) {
+    draw!(Print(text));
+}
+
+/// Draws text from now on in a colour.
+pub fn set_color(color: Color) {
+    let mut pen = PEN.lock().unwrap();
+    pen.color = color;
+    apply(&pen);
+}
+
+/// Draws in a theme's colours from now on, what is already on the screen keeps its colours until drawn again.
+pub fn set_theme(theme: &Theme) {
+    *THEME.lock().unwrap() = Some(theme.fit(Depth::from_env()));
+    apply(&PEN.lock().unwrap());
+}
+
+/// Turns an attribute on for text drawn from now on.
+pub fn attr_on(attr: Attr) {
+    let mut pen = PEN.lock().unwrap();
+    if !pen.attrs.contains(&attr) {
+        pen.attrs.push(attr);
+    }
+    apply(&pen);
+}
+
+/// Turns an attribute off for text drawn from now on.
+pub fn attr_off(attr: Attr) {
+    let mut pen = PEN.lock().unwrap();
+    pen.attrs.retain(|a| *a != attr);
+    apply(&pen);
+}
+
+/// Shows what has been drawn.
+pub fn refresh() {
+    let _ = io::stdout().flush();
+}
+
+/// Rings the terminal bell.
+pub fn beep() {
+    draw!(Print("\x07"));
+    refresh();
+}
+
+/// Waits for a key press.
+///
+/// # Returns
+/// `Key` - the key that was pressed.
+pub fn read_key() -> Key {
+    loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(Event::Resize(_, _)) => return Key::Resize,
+            Ok(_) => continue,
+            // The terminal is gone, so there is nobody left to type.
+            Err(_) => return Key::Quit,
+        };
+        // Windows reports releases as well as presses.
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Enter => return Key::Enter,
+            KeyCode::Backspace => return Key::Backspace,
+            KeyCode::Char(c) if ctrl && c.is_ascii_alphabetic() => {
+                return Key::Ctrl(c.to_ascii_lowercase())
+            }
+            KeyCode::Char(c) => return Key::Char(c),
+            KeyCode::F(n) => return Key::Function(n),
+            KeyCode::Esc => return Key::Escape,
+            KeyCode::Tab => return Key::Tab,
+            KeyCode::BackTab => return Key::BackTab,
+            KeyCode::Up => return Key::Up,
+            KeyCode::Down => return Key::Down,
+            KeyCode::PageUp => return Key::PageUp,
+            KeyCode::PageDown => return Key::PageDown,
+            KeyCode::Home => return Key::Home,
+            KeyCode::End => return Key::End,
+            _ => return Key::Other,
+        }
+    }
+}
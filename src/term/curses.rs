@@ -0,0 +1,190 @@
+use std::char;
+use std::ffi::CString;
+
+extern crate ncurses;
+use ncurses::*;
+
+use super::{Attr, Color, Key};
+use crate::theme::{Depth, Pair, Shade, Theme};
+
+/// The ncurses colour pair a Color is drawn with, set up by `set_theme`.
+fn pair(color: Color) -> i16 {
+    match color {
+        Color::Remote => return 1,
+        Color::Local => return 2,
+        Color::Plain => return 3,
+        Color::Misspelled | Color::Failed => return 4,
+        Color::Private => return 5,
+        Color::System => return 6,
+        Color::Bar => return 7,
+        Color::Mention => return 8,
+    }
+}
+
+/// The ncurses colour for a Shade, -1 is the terminal's own.
+fn shade(shade: Shade) -> i16 {
+    match shade {
+        Shade::Default => return -1,
+        Shade::Black => return COLOR_BLACK,
+        Shade::Red => return COLOR_RED,
+        Shade::Green => return COLOR_GREEN,
+        Shade::Yellow => return COLOR_YELLOW,
+        Shade::Blue => return COLOR_BLUE,
+        Shade::Magenta => return COLOR_MAGENTA,
+        Shade::Cyan => return COLOR_CYAN,
+        Shade::White => return COLOR_WHITE,
+        Shade::Indexed(index) => return index as i16,
+        // `set_theme` fits the theme to the terminal first, and ncurses has no colours by their red, green and blue.
+        Shade::Rgb(..) => return -1,
+    }
+}
+
+/// The ncurses attribute for an Attr.
+fn attr(attr: Attr) -> attr_t {
+    match attr {
+        Attr::Bold => return A_BOLD(),
+        Attr::Dim => return A_DIM(),
+        Attr::Underline => return A_UNDERLINE(),
+        Attr::Standout => return A_STANDOUT(),
+    }
+}
+
+/// Inits ncurses
+pub fn init() {
+    // Take the encoding from the environment, without it anything but ASCII is drawn as escapes. ncurses'
+    // own setlocale wrapper hands C a pointer to a string it has already dropped, so call it directly.
+    let from_env = CString::new("").unwrap();
+    unsafe {
+        ll::setlocale(LcCategory::all as i32, from_env.as_ptr());
+    }
+    initscr();
+    raw();
+    keypad(stdscr(), true);
+    start_color();
+    use_default_colors();
+    set_theme(&Theme::default());
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+}
+
+/// Restores the terminal.
+pub fn end() {
+    endwin();
+}
+
+/// The size of the terminal.
+///
+/// # Returns
+/// `(i32, i32)` - the number of rows and columns.
+pub fn size() -> (i32, i32) {
+    let mut max_y = 0;
+    let mut max_x = 0;
+    getmaxyx(stdscr(), &mut max_y, &mut max_x);
+    return (max_y, max_x);
+}
+
+/// Moves the cursor to a row and column.
+pub fn move_to(y: i32, x: i32) {
+    mv(y, x);
+}
+
+/// Clears the whole screen.
+pub fn clear() {
+    ncurses::clear();
+}
+
+/// Clears from the cursor to the end of its line.
+pub fn clear_line() {
+    clrtoeol();
+}
+
+/// Prints text at the cursor.
+pub fn print(text: &str) {
+    printw(text);
+}
+
+/// Draws text from now on in a colour.
+pub fn set_color(color: Color) {
+    attron(COLOR_PAIR(pair(color)));
+}
+
+/// Draws everything in a theme's colours from now on, including what is already on the screen.
+pub fn set_theme(theme: &Theme) {
+    // ncurses only draws colours from the palette, even on a terminal that could draw any.
+    let depth = match Depth::from_env() {
+        _ if COLORS() < 256 => Depth::Basic,
+        Depth::TrueColor => Depth::Indexed,
+        depth => depth,
+    };
+    let theme = theme.fit(depth);
+    let roles: [(Color, &Pair); 8] = [
+        (Color::Remote, &theme.remote),
+        (Color::Local, &theme.local),
+        (Color::Plain, &theme.input),
+        (Color::Failed, &theme.error),
+        (Color::Private, &theme.private),
+        (Color::System, &theme.system),
+        (Color::Bar, &theme.status_bar),
+        (Color::Mention, &theme.mention),
+    ];
+    for (color, colors) in roles.iter() {
+        init_pair(pair(*color), shade(colors.fg), shade(colors.bg));
+    }
+    // Cleared cells take the background, so the parts of the screen without text match the input line.
+    bkgd(COLOR_PAIR(pair(Color::Plain)));
+}
+
+/// Turns an attribute on for text drawn from now on.
+pub fn attr_on(a: Attr) {
+    attron(attr(a));
+}
+
+/// Turns an attribute off for text drawn from now on.
+pub fn attr_off(a: Attr) {
+    attroff(attr(a));
+}
+
+/// Shows what has been drawn.
+pub fn refresh() {
+    ncurses::refresh();
+}
+
+/// Rings the terminal bell.
+pub fn beep() {
+    ncurses::beep();
+}
+
+/// Waits for a key press.
+///
+/// # Returns
+/// `Key` - the key that was pressed.
+pub fn read_key() -> Key {
+    let c = getch();
+    match c {
+        // enter
+        0xA | 13 | KEY_ENTER => return Key::Enter,
+        // backspace
+        0x7f | KEY_BACKSPACE => return Key::Backspace,
+        // resize event
+        KEY_RESIZE => return Key::Resize,
+        9 => return Key::Tab,
+        KEY_BTAB => return Key::BackTab,
+        KEY_UP => return Key::Up,
+        KEY_DOWN => return Key::Down,
+        KEY_PPAGE => return Key::PageUp,
+        KEY_NPAGE => return Key::PageDown,
+        KEY_HOME => return Key::Home,
+        KEY_END => return Key::End,
+        // escape
+        27 => return Key::Escape,
+        // ctrl+a to ctrl+z, raw mode delivers ctrl+c as a key rather than SIGINT
+        1..=26 => return Key::Ctrl((b'a' + c as u8 - 1) as char),
+        // F1 to F12
+        _ if c > KEY_F0 && c <= KEY_F0 + 12 => return Key::Function((c - KEY_F0) as u8),
+        // getch gives ERR on failure and the other special keys from KEY_MIN on, neither is text
+        _ if c < 0 || c >= KEY_MIN => return Key::Other,
+        _ => match char::from_u32(c as u32) {
+            Some(c) => return Key::Char(c),
+            None => return Key::Other,
+        },
+    }
+}
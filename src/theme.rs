@@ -0,0 +1,343 @@
+use std::convert::TryFrom;
+use std::env;
+
+use serde::{Deserialize, Serialize};
+
+/// The themes that come with r2wc, the first is used when the config file doesn't pick one.
+pub const BUILT_IN: [&str; 3] = ["dark", "light", "solarized"];
+
+/// The basic colours in order, by name and roughly as xterm draws them.
+const BASIC: [(&str, Shade, (u8, u8, u8)); 8] = [
+    ("black", Shade::Black, (0, 0, 0)),
+    ("red", Shade::Red, (205, 0, 0)),
+    ("green", Shade::Green, (0, 205, 0)),
+    ("yellow", Shade::Yellow, (205, 205, 0)),
+    ("blue", Shade::Blue, (0, 0, 238)),
+    ("magenta", Shade::Magenta, (205, 0, 205)),
+    ("cyan", Shade::Cyan, (0, 205, 205)),
+    ("white", Shade::White, (229, 229, 229)),
+];
+
+/// The levels each channel of the 256 colour palette's 6x6x6 cube steps through.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// A Shade which is one of the terminal's colours.
+///
+/// In the config file a Shade is a name, a number from the 256 colour palette or `#rrggbb`.
+///
+/// # Variants
+/// `Default` - Whatever the terminal draws text or its background in.
+/// `Black`, `Red`, `Green`, `Yellow`, `Blue`, `Magenta`, `Cyan`, `White` - The eight basic colours, which most
+/// terminals let the user change.
+/// `Indexed` - A colour from the 256 colour palette.
+/// `Rgb` - A colour by its red, green and blue.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ShadeSpec", into = "ShadeSpec")]
+pub enum Shade {
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// A ShadeSpec which is how a Shade is written in the config file.
+///
+/// # Variants
+/// `Index` - A number from the 256 colour palette.
+/// `Name` - A colour's name or `#rrggbb`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ShadeSpec {
+    Index(u8),
+    Name(String),
+}
+
+/// A Depth which is how many colours the terminal can draw.
+///
+/// # Variants
+/// `Basic` - The eight basic colours.
+/// `Indexed` - The 256 colour palette.
+/// `TrueColor` - Any colour by its red, green and blue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Depth {
+    Basic,
+    Indexed,
+    TrueColor,
+}
+
+/// A Pair which is the colours a piece of text is drawn in.
+///
+/// # Fields
+/// `fg` - The text's colour.
+/// `bg` - The background's colour, the terminal's own if not given.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pair {
+    pub fg: Shade,
+    #[serde(default = "terminal_background")]
+    pub bg: Shade,
+}
+
+/// A Theme which is the colours the ui is drawn in, from the config file's `themes` or built in.
+///
+/// A theme in the config file that leaves a part out draws it the way `dark` does.
+///
+/// # Fields
+/// `local` - Our own messages.
+/// `remote` - The peer's messages.
+/// `system` - Status lines, like who joined and what a command did.
+/// `private` - Private messages, either way.
+/// `mention` - Messages that mention our nick.
+/// `error` - Misspelled words and the mark on messages that were not sent.
+/// `status_bar` - The status bar, panels and help.
+/// `input` - The input line.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub local: Pair,
+    pub remote: Pair,
+    pub system: Pair,
+    pub private: Pair,
+    pub mention: Pair,
+    pub error: Pair,
+    pub status_bar: Pair,
+    pub input: Pair,
+}
+
+/// The background a Pair gets when the config file leaves it out.
+fn terminal_background() -> Shade {
+    return Shade::Default;
+}
+
+/// Shorthand for a Pair in the built in themes.
+fn pair(fg: Shade, bg: Shade) -> Pair {
+    return Pair { fg: fg, bg: bg };
+}
+
+impl TryFrom<ShadeSpec> for Shade {
+    type Error = String;
+
+    fn try_from(spec: ShadeSpec) -> Result<Shade, String> {
+        let name = match spec {
+            ShadeSpec::Index(index) => return Ok(Shade::Indexed(index)),
+            ShadeSpec::Name(name) => name,
+        };
+        if name == "default" {
+            return Ok(Shade::Default);
+        }
+        if let Some((_, shade, _)) = BASIC.iter().find(|(n, _, _)| *n == name) {
+            return Ok(*shade);
+        }
+        let hex = match name.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => hex,
+            _ => {
+                return Err(format!(
+                    "\"{}\" is not a colour, use a name, 0 to 255 or #rrggbb",
+                    name
+                ))
+            }
+        };
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).unwrap_or(0);
+        return Ok(Shade::Rgb(channel(0), channel(2), channel(4)));
+    }
+}
+
+impl From<Shade> for ShadeSpec {
+    fn from(shade: Shade) -> ShadeSpec {
+        match shade {
+            Shade::Default => return ShadeSpec::Name(String::from("default")),
+            Shade::Indexed(index) => return ShadeSpec::Index(index),
+            Shade::Rgb(r, g, b) => return ShadeSpec::Name(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+            basic => {
+                let name = BASIC
+                    .iter()
+                    .find(|(_, s, _)| *s == basic)
+                    .map(|(n, _, _)| *n);
+                return ShadeSpec::Name(String::from(name.unwrap_or("default")));
+            }
+        }
+    }
+}
+
+/// How far apart two colours look, roughly.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    return d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2);
+}
+
+/// The index of the cube level nearest a channel.
+fn cube_level(channel: u8) -> usize {
+    return (0..CUBE.len())
+        .min_by_key(|&i| (CUBE[i] as i32 - channel as i32).abs())
+        .unwrap_or(0);
+}
+
+impl Shade {
+    /// The red, green and blue of a colour from the 256 colour palette, or of a basic colour.
+    fn rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Shade::Default => return None,
+            Shade::Rgb(r, g, b) => return Some((r, g, b)),
+            // The first 16 are the basic colours and their bright versions.
+            Shade::Indexed(index) if index < 16 => {
+                return BASIC.get(index as usize % 8).map(|b| b.2)
+            }
+            Shade::Indexed(index) if index < 232 => {
+                let index = index as usize - 16;
+                return Some((CUBE[index / 36], CUBE[index / 6 % 6], CUBE[index % 6]));
+            }
+            Shade::Indexed(index) => {
+                let grey = 8 + 10 * (index - 232);
+                return Some((grey, grey, grey));
+            }
+            basic => return BASIC.iter().find(|(_, s, _)| *s == basic).map(|b| b.2),
+        }
+    }
+
+    /// The nearest colour a terminal that draws `depth` colours has.
+    ///
+    /// # Arguments
+    /// * `depth` - A Depth of how many colours the terminal can draw.
+    ///
+    /// # Returns
+    /// `Shade` - the shade if the terminal has it, otherwise the colour nearest it that it has.
+    pub fn fit(self, depth: Depth) -> Shade {
+        let rgb = match (self, depth) {
+            (Shade::Rgb(r, g, b), _) => (r, g, b),
+            (Shade::Indexed(_), Depth::Basic) => self.rgb().unwrap_or((0, 0, 0)),
+            _ => return self,
+        };
+        match depth {
+            Depth::TrueColor => return self,
+            Depth::Indexed => {
+                let (r, g, b) = (cube_level(rgb.0), cube_level(rgb.1), cube_level(rgb.2));
+                let cube = Shade::Indexed((16 + 36 * r + 6 * g + b) as u8);
+                let level = ((rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3)
+                    .max(8)
+                    .min(238);
+                let grey = Shade::Indexed(232 + ((level - 8) / 10) as u8);
+                let near = |s: Shade| distance(s.rgb().unwrap_or((0, 0, 0)), rgb);
+                if near(grey) < near(cube) {
+                    return grey;
+                }
+                return cube;
+            }
+            Depth::Basic => {
+                let nearest = BASIC.iter().min_by_key(|(_, _, b)| distance(*b, rgb));
+                return nearest.map(|(_, s, _)| *s).unwrap_or(Shade::Default);
+            }
+        }
+    }
+}
+
+impl Depth {
+    /// Guesses how many colours the terminal can draw from `COLORTERM` and `TERM`.
+    ///
+    /// # Returns
+    /// `Depth` - TrueColor if `COLORTERM` says so, Indexed for a 256 colour `TERM`, otherwise Basic.
+    pub fn from_env() -> Depth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return Depth::TrueColor;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        if term.contains("256color") || term.contains("direct") {
+            return Depth::Indexed;
+        }
+        return Depth::Basic;
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        return Theme::dark();
+    }
+}
+
+impl Theme {
+    /// The dark theme, coloured text on black.
+    pub fn dark() -> Theme {
+        return Theme {
+            local: pair(Shade::Blue, Shade::Black),
+            remote: pair(Shade::Green, Shade::Black),
+            system: pair(Shade::Cyan, Shade::Black),
+            private: pair(Shade::Magenta, Shade::Black),
+            mention: pair(Shade::Yellow, Shade::Black),
+            error: pair(Shade::Red, Shade::Black),
+            status_bar: pair(Shade::Black, Shade::White),
+            input: pair(Shade::White, Shade::Black),
+        };
+    }
+
+    /// The light theme, coloured text on white.
+    pub fn light() -> Theme {
+        return Theme {
+            local: pair(Shade::Blue, Shade::White),
+            remote: pair(Shade::Green, Shade::White),
+            system: pair(Shade::Black, Shade::White),
+            private: pair(Shade::Magenta, Shade::White),
+            mention: pair(Shade::Red, Shade::White),
+            error: pair(Shade::Red, Shade::White),
+            status_bar: pair(Shade::White, Shade::Blue),
+            input: pair(Shade::Black, Shade::White),
+        };
+    }
+
+    /// The solarized dark theme, in solarized's own colours where the terminal can draw them.
+    pub fn solarized() -> Theme {
+        let base03 = Shade::Rgb(0x00, 0x2b, 0x36);
+        return Theme {
+            local: pair(Shade::Rgb(0x26, 0x8b, 0xd2), base03),
+            remote: pair(Shade::Rgb(0x85, 0x99, 0x00), base03),
+            system: pair(Shade::Rgb(0x2a, 0xa1, 0x98), base03),
+            private: pair(Shade::Rgb(0xd3, 0x36, 0x82), base03),
+            mention: pair(Shade::Rgb(0xb5, 0x89, 0x00), base03),
+            error: pair(Shade::Rgb(0xdc, 0x32, 0x2f), base03),
+            status_bar: pair(Shade::Rgb(0x93, 0xa1, 0xa1), Shade::Rgb(0x07, 0x36, 0x42)),
+            input: pair(Shade::Rgb(0x83, 0x94, 0x96), base03),
+        };
+    }
+
+    /// The theme with every colour the terminal can't draw swapped for the nearest one it can.
+    ///
+    /// # Arguments
+    /// * `depth` - A Depth of how many colours the terminal can draw.
+    ///
+    /// # Returns
+    /// `Theme` - the theme in colours the terminal has.
+    pub fn fit(&self, depth: Depth) -> Theme {
+        let fit = |p: &Pair| pair(p.fg.fit(depth), p.bg.fit(depth));
+        return Theme {
+            local: fit(&self.local),
+            remote: fit(&self.remote),
+            system: fit(&self.system),
+            private: fit(&self.private),
+            mention: fit(&self.mention),
+            error: fit(&self.error),
+            status_bar: fit(&self.status_bar),
+            input: fit(&self.input),
+        };
+    }
+
+    /// Finds a built in theme by name.
+    ///
+    /// # Arguments
+    /// * `name` - A &str of one of `BUILT_IN`.
+    ///
+    /// # Returns
+    /// `Option<Theme>` - the theme, None if there is no built in theme by that name.
+    pub fn built_in(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => return Some(Theme::dark()),
+            "light" => return Some(Theme::light()),
+            "solarized" => return Some(Theme::solarized()),
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use chrono::Local;
+
+use crate::chat::time_ago;
+use crate::vault::{is_header, Vault};
+
+/// A Transcript which appends every chat line to a log file.
+///
+/// Clones share the same file, so the panic hook can flush what the ui has written.
+///
+/// # Fields
+/// `path` - Where the log file is, for `wipe`.
+/// `writer` - The log file.
+/// `vault` - What every line is sealed with, None to write them in the clear.
+#[derive(Clone)]
+pub struct Transcript {
+    path: String,
+    writer: Arc<Mutex<BufWriter<File>>>,
+    vault: Option<Arc<Vault>>,
+}
+
+/// How many lines of the last session `/history` shows.
+const HISTORY_LINES: usize = 200;
+
+/// A LastSession which is what the transcript held before this run, so the last conversation is one command away.
+///
+/// # Fields
+/// `ended` - When the transcript was last written to, in seconds since the unix epoch.
+/// `lines` - Its last HISTORY_LINES lines, oldest first.
+pub struct LastSession {
+    pub ended: i64,
+    pub lines: Vec<String>,
+}
+
+impl LastSession {
+    /// Reads back the end of a transcript before this run appends to it.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the transcript's file.
+    /// * `passphrase` - A Option<&str> it is encrypted with, None if it was written in the clear.
+    ///
+    /// # Returns
+    /// `Option<LastSession>` - the last session, None if there is nothing to show or it can't be read.
+    pub fn read(path: &str, passphrase: Option<&str>) -> Option<LastSession> {
+        let ended = File::open(path)
+            .and_then(|file| file.metadata()?.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs() as i64;
+        let mut lines = read_lines(path, || {
+            passphrase.map(String::from).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, "the transcript is encrypted")
+            })
+        })
+        .ok()?;
+        if lines.is_empty() {
+            return None;
+        }
+        lines.drain(..lines.len().saturating_sub(HISTORY_LINES));
+        return Some(LastSession {
+            ended: ended,
+            lines: lines,
+        });
+    }
+
+    /// Describes when the last session ended, for the line shown on connect.
+    ///
+    /// # Returns
+    /// `String` - the line.
+    pub fn describe(&self) -> String {
+        return format!(
+            "Last session ended {}, /history to view",
+            time_ago(self.ended, Local::now().timestamp())
+        );
+    }
+}
+
+/// Makes the `/history` overlay, the end of the last session's transcript.
+///
+/// # Arguments
+/// * `last` - A Option<&LastSession> of the last session, None if there wasn't one.
+///
+/// # Returns
+/// `Vec<String>` - the overlay's lines.
+pub fn history(last: Option<&LastSession>) -> Vec<String> {
+    let last = match last {
+        Some(last) => last,
+        None => {
+            return vec![String::from(
+                "No earlier session, --log path keeps a transcript to show here",
+            )]
+        }
+    };
+    let mut lines = vec![format!("Last session, {} lines", last.lines.len())];
+    lines.extend(last.lines.iter().cloned());
+    return lines;
+}
+
+/// Overwrites a file with zeros and empties it, so what it held isn't left on the disk for undelete tools.
+///
+/// Filesystems that copy on write or journal data, and SSDs remapping blocks, may still keep older copies.
+///
+/// # Arguments
+/// * `file` - A &mut File opened for writing, not appending, left empty with its cursor at the start.
+///
+/// # Returns
+/// `io::Result<()>` - Ok, or why the file could not be overwritten.
+pub fn shred(file: &mut File) -> io::Result<()> {
+    let zeros = [0; 4096];
+    let mut left = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    while left > 0 {
+        let n = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    file.sync_all()?;
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    return file.sync_all();
+}
+
+/// Reads back every line of a transcript, opening them if it is encrypted.
+///
+/// # Arguments
+/// * `path` - A &str of the transcript's file.
+/// * `passphrase` - A FnOnce asked for the passphrase, only if the transcript turns out to be encrypted.
+///
+/// # Returns
+/// `io::Result<Vec<String>>` - the lines oldest first, or why they could not be read, e.g. the wrong passphrase.
+pub fn read_lines<F: FnOnce() -> io::Result<String>>(
+    path: &str,
+    passphrase: F,
+) -> io::Result<Vec<String>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let first = match lines.next() {
+        Some(first) => first?,
+        None => return Ok(Vec::new()),
+    };
+    if !is_header(&first) {
+        let mut read = vec![first];
+        for line in lines {
+            read.push(line?);
+        }
+        return Ok(read);
+    }
+
+    let vault = Vault::unlock(&passphrase()?, &first)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let mut read = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = vault.open(&line?).map_err(|e| {
+            io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 2, e))
+        })?;
+        read.push(line);
+    }
+    return Ok(read);
+}
+
+impl Transcript {
+    /// Opens a transcript, appending to the file if it already exists.
+    ///
+    /// With a passphrase every line is sealed, see `Vault`. A new file starts with the vault's header, an existing
+    /// one has to have been encrypted with the same passphrase. Without one, an encrypted file is refused.
+    ///
+    /// # Arguments
+    /// * `path` - A &str of the file to log to.
+    /// * `passphrase` - A Option<&str> to encrypt the transcript with, None to write it in the clear.
+    ///
+    /// # Returns
+    /// `io::Result<Transcript>` - the opened transcript, or why the file could not be opened.
+    pub fn open(path: &str, passphrase: Option<&str>) -> io::Result<Transcript> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        let mut header = String::new();
+        BufReader::new(writer.get_ref()).read_line(&mut header)?;
+        let vault = match passphrase {
+            Some(passphrase) => {
+                let vault = if header.is_empty() {
+                    let vault = Vault::create(passphrase)?;
+                    writeln!(writer, "{}", vault.header())?;
+                    writer.flush()?;
+                    vault
+                } else {
+                    Vault::unlock(passphrase, &header)
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+                };
+                Some(Arc::new(vault))
+            }
+            // Lines in the clear after sealed ones would leave the whole file unreadable.
+            None if is_header(header.trim_end()) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "it is encrypted, start with --encrypt-history",
+                ))
+            }
+            None => None,
+        };
+
+        return Ok(Transcript {
+            path: String::from(path),
+            writer: Arc::new(Mutex::new(writer)),
+            vault: vault,
+        });
+    }
+
+    /// Appends a line to the transcript.
+    ///
+    /// Called on a Transcript, the line is buffered until `flush` or the buffer fills.
+    ///
+    /// # Arguments
+    /// * `line` - A &str of the line to log.
+    pub fn write_line(&self, line: &str) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        // A full disk shouldn't take down the chat, the line is only lost from the log.
+        let _ = match &self.vault {
+            Some(vault) => writeln!(writer, "{}", vault.seal(line)),
+            None => writeln!(writer, "{}", line),
+        };
+    }
+
+    /// Shreds everything logged so far, see `shred`, and carries on logging to the emptied file.
+    ///
+    /// An encrypted transcript starts again with the vault's header, so it still opens with the same passphrase.
+    ///
+    /// # Returns
+    /// `io::Result<()>` - Ok, or why the file could not be wiped.
+    pub fn wipe(&self) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        // Buffered lines are written first so they are shredded with the rest rather than after it.
+        writer.flush()?;
+        // Appending always writes at the end, so the file is overwritten through a handle of its own.
+        shred(&mut OpenOptions::new().write(true).open(&self.path)?)?;
+        if let Some(vault) = &self.vault {
+            writeln!(writer, "{}", vault.header())?;
+            writer.flush()?;
+        }
+        return Ok(());
+    }
+
+    /// Writes any buffered lines to the file.
+    ///
+    /// Called on a Transcript, safe to call from a panic hook even if the lock was poisoned.
+    pub fn flush(&self) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writer.flush();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::chat::Chat;
+use crate::spell::SpellChecker;
+use crate::term::Key;
+use crate::theme::Theme;
+
+#[cfg(not(feature = "ratatui"))]
+mod terminal;
+#[cfg(feature = "ratatui")]
+mod tui;
+#[cfg(not(feature = "ratatui"))]
+pub use self::terminal::TermUi;
+#[cfg(feature = "ratatui")]
+pub use self::tui::TuiUi;
+
+/// The ui the binaries draw with, the ratatui one when it is built in.
+#[cfg(feature = "ratatui")]
+pub type ChatUi = TuiUi;
+#[cfg(not(feature = "ratatui"))]
+pub type ChatUi = TermUi;
+
+/// A Scroll which is how far to scroll.
+///
+/// # Variants
+/// `Line` - One row.
+/// `Page` - As many rows as fitted last time.
+/// `All` - To the top or bottom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scroll {
+    Line,
+    Page,
+    All,
+}
+
+/// A Ui which is somewhere the chat is shown and typed into, so the ui loops don't depend on how it is drawn.
+///
+/// Clones share the same screen, so one can wait for keys on its own thread while the ui loop draws with
+/// another, and the panic hook can restore the screen.
+pub trait Ui: Clone + Send + Sync + 'static {
+    /// Draws the chat log, dropping messages that no longer fit.
+    ///
+    /// # Arguments
+    /// * `chat` - A &mut Chat of the messages to draw.
+    fn draw_chat(&mut self, chat: &mut Chat);
+
+    /// Draws the status bar and the stats panel.
+    ///
+    /// # Arguments
+    /// * `bar` - A Option<&str> of the status bar above the input line, None if there isn't one.
+    /// * `stats` - A Option<&[String]> of the stats panel's lines, None while it is hidden.
+    fn draw_status(&mut self, bar: Option<&str>, stats: Option<&[String]>);
+
+    /// Draws the peers panel.
+    ///
+    /// # Arguments
+    /// * `peers` - A Option<&[String]> of the peers panel's lines, None while it is hidden.
+    fn draw_peers(&mut self, peers: Option<&[String]>);
+
+    /// Draws the help, or another overlay like `/chatstats`, over the chat.
+    ///
+    /// # Arguments
+    /// * `help` - A Option<&[String]> of the help's lines, None while it is closed.
+    fn draw_help(&mut self, help: Option<&[String]>);
+
+    /// Scrolls the help while it is open, otherwise the chat.
+    ///
+    /// # Arguments
+    /// * `by` - A Scroll of how far to scroll.
+    /// * `up` - A bool of whether to scroll towards the top.
+    fn scroll(&mut self, by: Scroll, up: bool);
+
+    /// Switches to another tab.
+    ///
+    /// # Arguments
+    /// * `forward` - A bool of whether to switch to the next tab rather than the previous one.
+    fn switch_tab(&mut self, forward: bool);
+
+    /// Shows the newest messages, switching to the chat and scrolling to its end.
+    fn latest(&mut self);
+
+    /// Draws the input line and shows everything drawn since the last call.
+    ///
+    /// # Arguments
+    /// * `line` - A &str of what has been typed so far.
+    /// * `spell` - A &Option<SpellChecker> to underline misspelled words with.
+    fn draw_input(&mut self, line: &str, spell: &Option<SpellChecker>);
+
+    /// Waits for a key press.
+    ///
+    /// # Returns
+    /// `Key` - the key that was pressed.
+    fn read_key(&mut self) -> Key;
+
+    /// Fits the ui to the screen again after a `Key::Resize`.
+    fn resize(&mut self);
+
+    /// Throws away what is on the screen so the next draw paints all of it again, for when something
+    /// else has written over the ui.
+    fn redraw(&mut self);
+
+    /// Draws the ui in a theme's colours from the next draw on.
+    ///
+    /// # Arguments
+    /// * `theme` - A &Theme of the colours to draw in.
+    fn set_theme(&mut self, theme: &Theme);
+
+    /// Gets the user's attention, for notification rules that ask for a bell.
+    fn beep(&mut self);
+
+    /// Puts the screen back the way it was before the ui started.
+    fn end(&mut self);
+}
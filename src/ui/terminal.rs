@@ -0,0 +1,332 @@
+use crate::chat::Chat;
+use crate::format::{self, Format};
+use crate::links::find_urls;
+use crate::spell::SpellChecker;
+use crate::term::{self, Attr, Color, Key};
+use crate::theme::Theme;
+
+use super::{Scroll, Ui};
+
+/// A TermUi which draws the chat in the terminal with whichever backend `term` was built with.
+///
+/// The chat fills the screen from the top, with an optional status bar and the input line at the bottom.
+///
+/// # Fields
+/// `max_y` - The last row, where the input line goes.
+/// `max_x` - The last column.
+/// `status_bar` - Whether the row above the input line is kept for a status bar.
+/// `stats_rows` - How many rows the stats panel took last time it was drawn, the peers panel goes below it.
+#[derive(Clone)]
+pub struct TermUi {
+    max_y: i32,
+    max_x: i32,
+    status_bar: bool,
+    stats_rows: i32,
+}
+
+/// The largest char boundary in `text` at or before `at`, so a line is never cut inside a character.
+fn floor_boundary(text: &str, at: usize) -> usize {
+    let mut at = at.min(text.len());
+    while !text.is_char_boundary(at) {
+        at -= 1;
+    }
+    return at;
+}
+
+/// The attributes a span is drawn with.
+fn attrs(format: Format) -> &'static [Attr] {
+    match format {
+        Format::Bold => return &[Attr::Bold],
+        Format::Underline => return &[Attr::Underline],
+        Format::Code => return &[Attr::Standout],
+        Format::Link => return &[Attr::Underline, Attr::Bold],
+        Format::Color(..) => return &[],
+    }
+}
+
+/// Prints part of a chat line, underlining the parts of it that are urls and formatting the rest of its spans.
+///
+/// # Arguments
+/// * `text` - A &str of the part to print.
+/// * `offset` - A usize of where the part starts in the line.
+/// * `spans` - A &[(usize, usize, Format)] of the byte ranges of the line's urls and formatted spans.
+/// * `highlight` - A bool of whether the whole line stands out, which the spans mustn't turn off.
+fn print_highlighted(text: &str, offset: usize, spans: &[(usize, usize, Format)], highlight: bool) {
+    for (start, end, formats) in format::split((offset, offset + text.len()), spans) {
+        let attrs: Vec<Attr> = formats
+            .into_iter()
+            .flat_map(|f| attrs(f).to_vec())
+            .collect();
+        for attr in &attrs {
+            term::attr_on(*attr);
+        }
+        term::print(&text[start - offset..end - offset]);
+        for attr in &attrs {
+            if !(highlight && *attr == Attr::Standout) {
+                term::attr_off(*attr);
+            }
+        }
+    }
+}
+
+/// Prints the input line from byte `start` on, underlining misspelled words in red.
+fn print_input(line: &str, start: usize, spell: &Option<SpellChecker>) {
+    let misspelled = spell
+        .as_ref()
+        .map(|s| s.misspelled(line))
+        .unwrap_or_default();
+
+    let mut pos = start;
+    for (from, to) in misspelled.into_iter().filter(|&(_, to)| to > start) {
+        let from = from.max(start);
+        term::print(&line[pos..from]);
+        term::set_color(Color::Misspelled);
+        term::attr_on(Attr::Underline);
+        term::print(&line[from..to]);
+        term::attr_off(Attr::Underline);
+        term::set_color(Color::Plain);
+        pos = to;
+    }
+    term::print(&line[pos..]);
+}
+
+impl TermUi {
+    /// Creates a new TermUi, taking over the terminal until `end` is called.
+    ///
+    /// # Arguments
+    /// * `status_bar` - A bool of whether to keep a row above the input line for a status bar.
+    ///
+    /// # Returns
+    /// `TermUi` - the ui, sized to the terminal.
+    pub fn new(status_bar: bool) -> TermUi {
+        term::init();
+        let (rows, cols) = term::size();
+        return TermUi {
+            max_y: rows - 1,
+            max_x: cols - 1,
+            status_bar: status_bar,
+            stats_rows: 0,
+        };
+    }
+
+    /// Draws a panel in the top right corner over the chat.
+    ///
+    /// # Arguments
+    /// * `top` - A i32 of the row the panel starts on.
+    /// * `lines` - A &[String] of the panel's lines.
+    fn draw_panel(&self, top: i32, lines: &[String]) {
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) + 2;
+        term::set_color(Color::Bar);
+        for (i, l) in lines.iter().enumerate() {
+            term::move_to(top + i as i32, self.max_x + 1 - width as i32);
+            term::print(&format!(" {:<w$} ", l, w = width - 2));
+        }
+    }
+}
+
+impl Ui for TermUi {
+    fn draw_chat(&mut self, chat: &mut Chat) {
+        let max_y = if self.status_bar {
+            self.max_y - 1
+        } else {
+            self.max_y
+        };
+        let max_x = self.max_x.max(1) as usize;
+        chat.truncate_front(max_y.max(0) as usize);
+        // Code blocks take a row for each line and two for their box, so drop old messages until they fit too.
+        let mut rows: usize = (0..chat.messages().len())
+            .map(|i| 1 + chat.code_height(i))
+            .sum();
+        while rows > max_y.max(0) as usize && chat.messages().len() > 1 {
+            rows -= 1 + chat.code_height(0);
+            chat.truncate_front(chat.messages().len() - 1);
+        }
+
+        let mut ln = 0;
+        for (i, message) in chat.messages().iter().enumerate() {
+            if let Some(day) = chat.day_change(i) {
+                let day: String = day.chars().take(max_x).collect();
+                term::move_to(ln, 0);
+                term::clear_line();
+                term::set_color(Color::System);
+                term::print(&day);
+                ln += 1;
+            }
+            let (msg, mut spans) = chat.line(i);
+            let msg = &msg;
+            if chat.is_selected(i) {
+                term::set_color(Color::Bar);
+            } else if message.mention {
+                term::set_color(Color::Mention);
+            } else if message.private {
+                term::set_color(Color::Private);
+            } else if message.is_status() {
+                term::set_color(Color::System);
+            } else if message.remote {
+                term::set_color(Color::Remote);
+            } else {
+                term::set_color(Color::Local);
+            }
+            if let Some(quote) = &message.quote {
+                let quote: String = format!("> {}", quote).chars().take(max_x).collect();
+                term::move_to(ln, 0);
+                term::clear_line();
+                term::attr_on(Attr::Dim);
+                term::print(&quote);
+                term::attr_off(Attr::Dim);
+                ln += 1;
+            }
+            term::move_to(ln, 0);
+            term::clear_line();
+            if message.deleted {
+                term::attr_on(Attr::Dim);
+            }
+            if message.highlight {
+                term::attr_on(Attr::Standout);
+            }
+            spans.extend(
+                find_urls(msg)
+                    .into_iter()
+                    .map(|(start, end)| (start, end, Format::Link)),
+            );
+            let mut offset = 0;
+            let mut rest = msg.as_str();
+            while rest.len() > max_x {
+                let cut = match floor_boundary(rest, max_x) {
+                    // Narrower than one character, print it anyway rather than never moving on.
+                    0 => rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1),
+                    cut => cut,
+                };
+                let (first, next) = rest.split_at(cut);
+                print_highlighted(first, offset, &spans, message.highlight);
+                offset += first.len();
+                rest = next;
+                ln += 1;
+                term::move_to(ln, 0);
+            }
+            print_highlighted(rest, offset, &spans, message.highlight);
+            if message.failed {
+                if rest.len() + 2 > max_x {
+                    ln += 1;
+                    term::move_to(ln, 0);
+                    term::clear_line();
+                } else {
+                    term::print(" ");
+                }
+                term::set_color(Color::Failed);
+                term::print("✗");
+            }
+            term::attr_off(Attr::Dim);
+            term::attr_off(Attr::Standout);
+            ln += 1;
+            for (row, spans) in chat.code_rows(i) {
+                let cut = row
+                    .char_indices()
+                    .nth(max_x)
+                    .map_or(row.len(), |(at, _)| at);
+                term::move_to(ln, 0);
+                term::clear_line();
+                print_highlighted(&row[..cut], 0, &spans, false);
+                ln += 1;
+            }
+        }
+
+        while ln < max_y - 1 {
+            term::move_to(ln, 0);
+            term::clear_line();
+            ln += 1;
+        }
+    }
+
+    fn draw_status(&mut self, bar: Option<&str>, stats: Option<&[String]>) {
+        if let (true, Some(bar)) = (self.status_bar, bar) {
+            let bar: String = bar.chars().take(self.max_x.max(0) as usize).collect();
+            term::move_to(self.max_y - 1, 0);
+            term::set_color(Color::Bar);
+            term::clear_line();
+            term::print(&bar);
+        }
+
+        self.stats_rows = 0;
+        if let Some(lines) = stats {
+            self.draw_panel(0, lines);
+            self.stats_rows = lines.len() as i32 + 1;
+        }
+    }
+
+    fn draw_peers(&mut self, peers: Option<&[String]>) {
+        if let Some(lines) = peers {
+            self.draw_panel(self.stats_rows, lines);
+        }
+    }
+
+    fn draw_help(&mut self, help: Option<&[String]>) {
+        let lines = match help {
+            Some(lines) => lines,
+            None => return,
+        };
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as i32 + 4;
+        let top = ((self.max_y - lines.len() as i32 - 2) / 2).max(0);
+        let left = ((self.max_x + 1 - width) / 2).max(0);
+        let blank = String::from("");
+
+        term::set_color(Color::Bar);
+        let framed = Some(&blank).into_iter().chain(lines).chain(Some(&blank));
+        for (i, l) in framed.enumerate() {
+            term::move_to(top + i as i32, left);
+            term::print(&format!("  {:<w$}  ", l, w = width as usize - 4));
+        }
+    }
+
+    /// Only as much of the chat as fits is kept, so there is nothing to scroll to.
+    fn scroll(&mut self, _by: Scroll, _up: bool) {}
+
+    /// There is only the chat.
+    fn switch_tab(&mut self, _forward: bool) {}
+
+    /// The newest messages are always shown.
+    fn latest(&mut self) {}
+
+    fn draw_input(&mut self, line: &str, spell: &Option<SpellChecker>) {
+        term::move_to(self.max_y, 0);
+        term::set_color(Color::Plain);
+        term::print(">> ");
+        term::clear_line();
+
+        // Keep the end of a long line in view.
+        let mut start = (line.len() + 3).saturating_sub(self.max_x.max(0) as usize);
+        while !line.is_char_boundary(start) {
+            start += 1;
+        }
+        print_input(line, start, spell);
+        term::refresh();
+    }
+
+    fn read_key(&mut self) -> Key {
+        return term::read_key();
+    }
+
+    fn resize(&mut self) {
+        term::clear();
+        let (rows, cols) = term::size();
+        self.max_y = rows - 1;
+        self.max_x = cols - 1;
+    }
+
+    fn redraw(&mut self) {
+        term::clear();
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        term::set_theme(theme);
+        term::clear();
+    }
+
+    fn beep(&mut self) {
+        term::beep();
+    }
+
+    fn end(&mut self) {
+        term::end();
+    }
+}
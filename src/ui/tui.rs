@@ -0,0 +1,812 @@
+use std::io::{self, Stdout, Write};
+use std::sync::{Arc, Mutex};
+
+extern crate ratatui;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::Show;
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, List, Paragraph, Tabs};
+use ratatui::{Frame, Terminal};
+
+use crate::chat::Chat;
+use crate::format::{self, Format};
+use crate::links::find_urls;
+use crate::spell::SpellChecker;
+use crate::term::{self, Key};
+use crate::theme::{Depth, Pair, Shade, Theme};
+
+use super::{Scroll, Ui};
+
+/// The tabs along the top, in order.
+const TABS: [&str; 2] = ["Chat", "Links"];
+
+/// How many messages are kept to scroll back through.
+const SCROLLBACK: usize = 1000;
+
+/// How many columns the sidebar takes, it is left out on terminals narrower than twice this.
+const SIDEBAR: u16 = 24;
+
+/// A TuiUi which draws the chat with ratatui widgets.
+///
+/// Tabs for the chat and the links the peer has sent run along the top. Below them the chat scrolls back, next
+/// to a sidebar with the stats and peers panels while either is shown, with an optional status bar and the input
+/// box at the bottom. Messages that come in while the chat is scrolled up or hidden are marked as new, and the
+/// marker stays where reading stopped until the next message after the chat has been read to its end.
+///
+/// # Fields
+/// `screen` - The Screen, shared with clones so any of them can draw.
+/// `status_bar` - Whether a status bar is drawn above the input box.
+#[derive(Clone)]
+pub struct TuiUi {
+    screen: Arc<Mutex<Screen>>,
+    status_bar: bool,
+}
+
+/// A Screen which is the terminal and everything last drawn on it.
+///
+/// # Fields
+/// `terminal` - The ratatui Terminal drawing to stdout.
+/// `view` - The View of what to draw.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    view: View,
+}
+
+/// A View which is what the ui shows, kept between calls so any of them can redraw the whole screen.
+///
+/// # Fields
+/// `chat` - The chat log's lines.
+/// `links` - The urls the peer has sent.
+/// `bar` - The status bar, None if there isn't one.
+/// `stats` - The stats panel's lines, None while it is hidden.
+/// `peers` - The peers panel's lines, None while it is hidden.
+/// `help` - The help's lines, None while it is closed.
+/// `help_scroll` - How many rows the help is scrolled down from its top.
+/// `help_page` - How many rows of the help the last draw fitted.
+/// `input` - What has been typed so far.
+/// `misspelled` - The byte ranges of the misspelled words in `input`.
+/// `tab` - Which of `TABS` is shown.
+/// `scroll` - How many rows the shown tab is scrolled up from its end.
+/// `page` - How many rows the last draw fitted, which is how far a page scrolls.
+/// `added` - How many messages the chat had ever had at its last draw, to tell which are new.
+/// `unread` - How many messages came in while the chat was scrolled up or hidden, 0 for none.
+/// `marker` - How many of the chat's last lines are below the new marker.
+/// `hold` - How many of the chat's last lines came in while it was scrolled up since the last draw, the view
+/// is scrolled past them so it stays still.
+/// `caught_up` - Whether the end of the chat has been shown since the marker was set, the next message to
+/// come in while away starts a new marker.
+/// `theme` - The Theme everything is drawn in.
+/// `depth` - How many colours the terminal can draw, syntax highlighting is fitted to it.
+struct View {
+    chat: Vec<Entry>,
+    links: Vec<String>,
+    bar: Option<String>,
+    stats: Option<Vec<String>>,
+    peers: Option<Vec<String>>,
+    help: Option<Vec<String>>,
+    help_scroll: usize,
+    help_page: usize,
+    input: String,
+    misspelled: Vec<(usize, usize)>,
+    tab: usize,
+    scroll: usize,
+    page: usize,
+    added: usize,
+    unread: usize,
+    marker: usize,
+    hold: usize,
+    caught_up: bool,
+    theme: Theme,
+    depth: Depth,
+}
+
+/// An Entry which is one line of a tab before it is wrapped.
+///
+/// # Fields
+/// `text` - The line.
+/// `marks` - The byte ranges of the parts to make stand out, the urls and formatted spans.
+/// `style` - The Style the line is drawn with.
+/// `failed` - Whether the line is one of our messages that was not sent, drawn with a red ✗ after it.
+/// `selected` - Whether the line is the selected message, which is kept in view.
+/// `cut` - Whether the line is cut off at the edge rather than wrapped, for the rows of a code block.
+#[derive(Clone)]
+struct Entry {
+    text: String,
+    marks: Vec<(usize, usize, Format)>,
+    style: Style,
+    failed: bool,
+    selected: bool,
+    cut: bool,
+}
+
+/// The ratatui colour for a Shade.
+fn shade(shade: Shade) -> Color {
+    match shade {
+        Shade::Default => return Color::Reset,
+        Shade::Black => return Color::Black,
+        Shade::Red => return Color::Red,
+        Shade::Green => return Color::Green,
+        Shade::Yellow => return Color::Yellow,
+        Shade::Blue => return Color::Blue,
+        Shade::Magenta => return Color::Magenta,
+        Shade::Cyan => return Color::Cyan,
+        Shade::White => return Color::White,
+        Shade::Indexed(index) => return Color::Indexed(index),
+        Shade::Rgb(r, g, b) => return Color::Rgb(r, g, b),
+    }
+}
+
+/// The Style text in a Pair's colours is drawn with.
+fn style(pair: &Pair) -> Style {
+    return Style::default().fg(shade(pair.fg)).bg(shade(pair.bg));
+}
+
+/// Splits a line into rows of at most `width` characters, breaking after a space where there is one.
+///
+/// # Returns
+/// `Vec<(usize, usize)>` - the byte range of each row.
+fn wrap(text: &str, width: usize) -> Vec<(usize, usize)> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = match text[start..].char_indices().nth(width) {
+            Some((i, _)) => start + i,
+            None => {
+                rows.push((start, text.len()));
+                return rows;
+            }
+        };
+        let cut = match text[start..end].rfind(' ') {
+            Some(i) if i > 0 => start + i + 1,
+            _ => end,
+        };
+        rows.push((start, cut));
+        start = cut;
+    }
+}
+
+/// Draws a style in a span's format as well.
+///
+/// # Arguments
+/// * `style` - A Style to draw the span in otherwise.
+/// * `format` - A Format of the span.
+/// * `depth` - A Depth of how many colours the terminal can draw.
+fn apply(style: Style, format: Format, depth: Depth) -> Style {
+    match format {
+        Format::Bold => return style.add_modifier(Modifier::BOLD),
+        Format::Underline => return style.add_modifier(Modifier::UNDERLINED),
+        Format::Code => return style.add_modifier(Modifier::REVERSED),
+        Format::Link => return style.add_modifier(Modifier::UNDERLINED | Modifier::BOLD),
+        Format::Color(r, g, b) => return style.fg(shade(Shade::Rgb(r, g, b).fit(depth))),
+    }
+}
+
+/// Turns part of a line into spans, drawing the marked parts of it in another style.
+///
+/// # Arguments
+/// * `text` - A &str of the whole line.
+/// * `range` - A (usize, usize) of the byte range to draw.
+/// * `marks` - A &[(usize, usize)] of the byte ranges to mark, in order.
+/// * `style` - A Style for the rest of the line.
+/// * `marked` - A Style for the marked parts.
+///
+/// # Returns
+/// `Vec<Span<'static>>` - the spans.
+fn spans(
+    text: &str,
+    range: (usize, usize),
+    marks: &[(usize, usize)],
+    style: Style,
+    marked: Style,
+) -> Vec<Span<'static>> {
+    let (mut pos, end) = range;
+    let mut spans = Vec::new();
+    for &(from, to) in marks {
+        let from = from.max(pos).min(end);
+        let to = to.min(end);
+        if from >= to {
+            continue;
+        }
+        spans.push(Span::styled(String::from(&text[pos..from]), style));
+        spans.push(Span::styled(String::from(&text[from..to]), marked));
+        pos = to;
+    }
+    spans.push(Span::styled(String::from(&text[pos..end]), style));
+    return spans;
+}
+
+/// Turns part of a chat line into spans, drawing the marked parts of it in their formats.
+///
+/// # Arguments
+/// * `text` - A &str of the whole line.
+/// * `range` - A (usize, usize) of the byte range to draw.
+/// * `marks` - A &[(usize, usize, Format)] of the byte ranges to mark.
+/// * `style` - A Style for the line, the marks are drawn in it with their formats on top.
+/// * `depth` - A Depth of how many colours the terminal can draw.
+///
+/// # Returns
+/// `Vec<Span<'static>>` - the spans.
+fn formatted(
+    text: &str,
+    range: (usize, usize),
+    marks: &[(usize, usize, Format)],
+    style: Style,
+    depth: Depth,
+) -> Vec<Span<'static>> {
+    return format::split(range, marks)
+        .into_iter()
+        .map(|(from, to, formats)| {
+            let style = formats
+                .into_iter()
+                .fold(style, |style, f| apply(style, f, depth));
+            Span::styled(String::from(&text[from..to]), style)
+        })
+        .collect();
+}
+
+impl View {
+    /// The lines of the shown tab.
+    fn entries(&self) -> Vec<Entry> {
+        match self.tab {
+            0 => return self.chat.clone(),
+            _ => {
+                return self
+                    .links
+                    .iter()
+                    .enumerate()
+                    .map(|(i, url)| {
+                        let prefix = format!("[{}] ", i + 1);
+                        Entry {
+                            marks: vec![(prefix.len(), prefix.len() + url.len(), Format::Link)],
+                            text: format!("{}{}", prefix, url),
+                            style: style(&self.theme.remote),
+                            failed: false,
+                            selected: false,
+                            cut: false,
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Draws everything.
+    fn render(&mut self, frame: &mut Frame) {
+        let bar_height = if self.bar.is_some() { 1 } else { 0 };
+        let [tabs, body, bar, input] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(bar_height),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Block::default().style(style(&self.theme.input)),
+            frame.area(),
+        );
+        frame.render_widget(
+            Tabs::new(TABS.iter().enumerate().map(|(i, name)| {
+                if i == 0 && self.tab != 0 && self.unread > 0 {
+                    format!("{} ({})", name, self.unread)
+                } else {
+                    String::from(*name)
+                }
+            }))
+            .select(self.tab)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            tabs,
+        );
+
+        let sidebar = self.peers.is_some() || self.stats.is_some();
+        if sidebar && body.width >= SIDEBAR * 2 {
+            let [main, side] =
+                Layout::horizontal([Constraint::Min(1), Constraint::Length(SIDEBAR)]).areas(body);
+            self.render_main(frame, main);
+            self.render_sidebar(frame, side);
+        } else {
+            self.render_main(frame, body);
+        }
+
+        if let Some(text) = &self.bar {
+            frame.render_widget(
+                Paragraph::new(text.as_str()).style(style(&self.theme.status_bar)),
+                bar,
+            );
+        }
+
+        self.render_input(frame, input);
+        self.render_help(frame, frame.area());
+    }
+
+    /// Draws the help or another overlay in a box in the middle of the area, scrolled, if it is open.
+    fn render_help(&mut self, frame: &mut Frame, area: Rect) {
+        let lines = match &self.help {
+            Some(lines) => lines,
+            None => return,
+        };
+        let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u16 + 4;
+        let height = (lines.len() as u16 + 2).min(area.height);
+        self.help_page = (height.saturating_sub(2) as usize).max(1);
+        self.help_scroll = self
+            .help_scroll
+            .min(lines.len().saturating_sub(self.help_page));
+        let [_, column, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(width),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+        let [_, popup, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(height),
+            Constraint::Fill(1),
+        ])
+        .areas(column);
+
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            List::new(
+                lines
+                    .iter()
+                    .skip(self.help_scroll)
+                    .map(|l| format!(" {}", l)),
+            )
+            .block(Block::default().borders(Borders::ALL).title(" Esc closes "))
+            .style(style(&self.theme.status_bar)),
+            popup,
+        );
+    }
+
+    /// Draws the shown tab, scrolled.
+    fn render_main(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL);
+        let inner = block.inner(area);
+        let entries = self.entries();
+        let marker = if self.tab == 0 && self.unread > 0 {
+            Some(entries.len().saturating_sub(self.marker))
+        } else {
+            None
+        };
+        let held = entries.len().saturating_sub(self.hold);
+        let mut held_from = 0;
+        let mut selected = None;
+        let mut lines = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            // The marker only lands on a held line when it came in with it.
+            if i == held {
+                held_from = lines.len();
+            }
+            if marker == Some(i) {
+                lines.push(Line::styled(
+                    format!("{:─^w$}", " new ", w = inner.width as usize),
+                    style(&self.theme.system),
+                ));
+            }
+            // The mark is wrapped with the text but drawn on its own.
+            let shown = if entry.failed {
+                format!("{} ✗", entry.text)
+            } else {
+                entry.text.clone()
+            };
+            let len = entry.text.len();
+            if entry.selected {
+                selected = Some(lines.len());
+            }
+            let rows = if entry.cut {
+                let width = inner.width as usize;
+                vec![(
+                    0,
+                    shown
+                        .char_indices()
+                        .nth(width)
+                        .map_or(shown.len(), |(at, _)| at),
+                )]
+            } else {
+                wrap(&shown, inner.width as usize)
+            };
+            for (from, to) in rows {
+                let mut row = formatted(
+                    &entry.text,
+                    (from.min(len), to.min(len)),
+                    &entry.marks,
+                    entry.style,
+                    self.depth,
+                );
+                if to > len {
+                    row.push(Span::styled(
+                        String::from(&shown[from.max(len)..to]),
+                        style(&self.theme.error),
+                    ));
+                }
+                lines.push(Line::from(row));
+            }
+        }
+
+        if self.hold > 0 && self.scroll > 0 {
+            self.scroll += lines.len() - held_from;
+        }
+        self.hold = 0;
+
+        let height = inner.height as usize;
+        self.page = height.max(1);
+        if let Some(first) = selected {
+            // Scrolled just far enough that the selected message's first row is in view.
+            let most = lines.len() - first - 1;
+            let least = (lines.len() - first).saturating_sub(height);
+            self.scroll = self.scroll.max(least).min(most);
+        }
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+        if self.tab == 0 && self.scroll == 0 {
+            self.caught_up = true;
+        }
+        let end = lines.len() - self.scroll;
+        let start = end.saturating_sub(height);
+
+        let title = match (self.scroll, marker.map(|_| self.unread)) {
+            (0, _) => format!(" {} ", TABS[self.tab]),
+            (scroll, Some(unread)) => {
+                format!(
+                    " {} (scrolled up {}, {} new) ",
+                    TABS[self.tab], scroll, unread
+                )
+            }
+            (scroll, None) => format!(" {} (scrolled up {}) ", TABS[self.tab], scroll),
+        };
+        frame.render_widget(block.title(title), area);
+        frame.render_widget(Paragraph::new(lines[start..end].to_vec()), inner);
+    }
+
+    /// Draws whichever of the stats and peers panels are shown, the stats on top.
+    fn render_sidebar(&mut self, frame: &mut Frame, area: Rect) {
+        let stats_height = match &self.stats {
+            Some(lines) => lines.len() as u16 + 2,
+            None => 0,
+        };
+        let [stats, peers] =
+            Layout::vertical([Constraint::Length(stats_height), Constraint::Min(0)]).areas(area);
+
+        for (lines, area) in [(&self.stats, stats), (&self.peers, peers)] {
+            if let Some(lines) = lines {
+                frame.render_widget(
+                    List::new(lines.iter().map(|l| l.as_str()))
+                        .block(Block::default().borders(Borders::ALL))
+                        .style(style(&self.theme.status_bar)),
+                    area,
+                );
+            }
+        }
+    }
+
+    /// Draws the input box with the cursor after the text, keeping the end of a long line in view.
+    fn render_input(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL);
+        let inner = block.inner(area);
+        let width = (inner.width as usize).max(1);
+
+        let count = self.input.chars().count();
+        let skip = (count + 1).saturating_sub(width);
+        let start = self
+            .input
+            .char_indices()
+            .nth(skip)
+            .map(|(i, _)| i)
+            .unwrap_or(self.input.len());
+        let text = spans(
+            &self.input,
+            (start, self.input.len()),
+            &self.misspelled,
+            style(&self.theme.input),
+            style(&self.theme.error).add_modifier(Modifier::UNDERLINED),
+        );
+
+        frame.render_widget(block, area);
+        frame.render_widget(Paragraph::new(Line::from(text)), inner);
+        frame.set_cursor_position(Position::new(inner.x + (count - skip) as u16, inner.y));
+    }
+}
+
+impl TuiUi {
+    /// Creates a new TuiUi, taking over the terminal until `end` is called.
+    ///
+    /// # Arguments
+    /// * `status_bar` - A bool of whether to draw a status bar above the input box.
+    ///
+    /// # Returns
+    /// `TuiUi` - the ui, drawing to stdout.
+    pub fn new(status_bar: bool) -> TuiUi {
+        let _ = enable_raw_mode();
+        let _ = execute!(io::stdout(), EnterAlternateScreen);
+        let terminal = match Terminal::new(CrosstermBackend::new(io::stdout())) {
+            Ok(terminal) => terminal,
+            Err(e) => {
+                let _ = execute!(io::stdout(), LeaveAlternateScreen);
+                let _ = disable_raw_mode();
+                println!("Error: can't draw on the terminal: {}", e);
+                ::std::process::exit(0x0100);
+            }
+        };
+
+        return TuiUi {
+            screen: Arc::new(Mutex::new(Screen {
+                terminal: terminal,
+                view: View {
+                    chat: Vec::new(),
+                    links: Vec::new(),
+                    bar: None,
+                    stats: None,
+                    peers: None,
+                    help: None,
+                    help_scroll: 0,
+                    help_page: 1,
+                    input: String::new(),
+                    misspelled: Vec::new(),
+                    tab: 0,
+                    scroll: 0,
+                    page: 1,
+                    added: 0,
+                    unread: 0,
+                    marker: 0,
+                    hold: 0,
+                    caught_up: false,
+                    theme: Theme::default(),
+                    depth: Depth::from_env(),
+                },
+            })),
+            status_bar: status_bar,
+        };
+    }
+
+    /// How many messages the chat had ever had at the last `draw_chat`.
+    fn added(&self) -> usize {
+        return self.screen.lock().map(|s| s.view.added).unwrap_or(0);
+    }
+
+    /// The Theme the ui is drawn in.
+    fn theme(&self) -> Theme {
+        return self
+            .screen
+            .lock()
+            .map(|s| s.view.theme.clone())
+            .unwrap_or_default();
+    }
+
+    /// Changes what the ui shows, it is drawn with the change on the next `draw_input`.
+    ///
+    /// # Arguments
+    /// * `change` - A FnOnce(&mut View) making the change.
+    fn update<F: FnOnce(&mut View)>(&self, change: F) {
+        if let Ok(mut screen) = self.screen.lock() {
+            change(&mut screen.view);
+        }
+    }
+}
+
+impl Ui for TuiUi {
+    fn draw_chat(&mut self, chat: &mut Chat) {
+        chat.truncate_front(SCROLLBACK);
+        let added = chat.added();
+        let first_new = chat.messages().len().saturating_sub(added - self.added());
+        let theme = self.theme();
+
+        let mut lines = Vec::new();
+        let mut fresh = 0;
+        for (i, message) in chat.messages().iter().enumerate() {
+            if i == first_new {
+                fresh = lines.len();
+            }
+            if let Some(day) = chat.day_change(i) {
+                lines.push(Entry {
+                    text: day,
+                    marks: Vec::new(),
+                    style: style(&theme.system),
+                    failed: false,
+                    selected: false,
+                    cut: false,
+                });
+            }
+            let mut style = style(if chat.is_selected(i) {
+                &theme.status_bar
+            } else if message.mention {
+                &theme.mention
+            } else if message.private {
+                &theme.private
+            } else if message.is_status() {
+                &theme.system
+            } else if message.remote {
+                &theme.remote
+            } else {
+                &theme.local
+            });
+            if let Some(quote) = &message.quote {
+                lines.push(Entry {
+                    text: format!("> {}", quote),
+                    marks: Vec::new(),
+                    style: style.add_modifier(Modifier::DIM),
+                    failed: false,
+                    selected: false,
+                    cut: false,
+                });
+            }
+            if message.deleted {
+                style = style.add_modifier(Modifier::DIM);
+            }
+            if message.highlight {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            let (text, mut marks) = chat.line(i);
+            marks.extend(
+                find_urls(&text)
+                    .into_iter()
+                    .map(|(from, to)| (from, to, Format::Link)),
+            );
+            lines.push(Entry {
+                marks: marks,
+                text: text,
+                style: style,
+                failed: message.failed,
+                selected: chat.is_selected(i),
+                cut: false,
+            });
+            for (row, marks) in chat.code_rows(i) {
+                lines.push(Entry {
+                    text: row,
+                    marks: marks,
+                    style: style,
+                    failed: false,
+                    selected: false,
+                    cut: true,
+                });
+            }
+        }
+
+        let fresh = if first_new < chat.messages().len() {
+            lines.len() - fresh
+        } else {
+            0
+        };
+        let links = chat.urls().clone();
+        self.update(|view| {
+            let new = added - view.added;
+            if new > 0 && (view.tab != 0 || view.scroll > 0) {
+                if view.caught_up {
+                    view.unread = 0;
+                    view.marker = 0;
+                    view.caught_up = false;
+                }
+                view.unread += new;
+                view.marker += fresh;
+                if view.tab == 0 {
+                    view.hold += fresh;
+                }
+            } else if new > 0 {
+                view.unread = 0;
+                view.marker = 0;
+            }
+            view.added = added;
+            view.chat = lines;
+            view.links = links;
+        });
+    }
+
+    fn draw_status(&mut self, bar: Option<&str>, stats: Option<&[String]>) {
+        let bar = bar.filter(|_| self.status_bar).map(String::from);
+        let stats = stats.map(|s| s.to_vec());
+        self.update(|view| {
+            view.bar = bar;
+            view.stats = stats;
+        });
+    }
+
+    fn draw_peers(&mut self, peers: Option<&[String]>) {
+        let peers = peers.map(|p| p.to_vec());
+        self.update(|view| view.peers = peers);
+    }
+
+    fn draw_help(&mut self, help: Option<&[String]>) {
+        let help = help.map(|h| h.to_vec());
+        self.update(|view| {
+            if view.help.is_none() {
+                view.help_scroll = 0;
+            }
+            view.help = help;
+        });
+    }
+
+    fn scroll(&mut self, by: Scroll, up: bool) {
+        self.update(|view| {
+            let (offset, page, from_top) = if view.help.is_some() {
+                (&mut view.help_scroll, view.help_page, true)
+            } else {
+                (&mut view.scroll, view.page, false)
+            };
+            // Too far is clamped when it next draws.
+            let rows = match by {
+                Scroll::Line => 1,
+                Scroll::Page => page,
+                Scroll::All => usize::MAX,
+            };
+            // The tabs count rows up from their end, the help down from its top.
+            *offset = if up != from_top {
+                offset.saturating_add(rows)
+            } else {
+                offset.saturating_sub(rows)
+            };
+        });
+    }
+
+    fn latest(&mut self) {
+        self.update(|view| {
+            view.tab = 0;
+            view.scroll = 0;
+        });
+    }
+
+    fn switch_tab(&mut self, forward: bool) {
+        self.update(|view| {
+            let step = if forward { 1 } else { TABS.len() - 1 };
+            view.tab = (view.tab + step) % TABS.len();
+            view.scroll = 0;
+        });
+    }
+
+    fn draw_input(&mut self, line: &str, spell: &Option<SpellChecker>) {
+        let misspelled = spell
+            .as_ref()
+            .map(|s| s.misspelled(line))
+            .unwrap_or_default();
+
+        if let Ok(mut screen) = self.screen.lock() {
+            let Screen { terminal, view } = &mut *screen;
+            view.input = String::from(line);
+            view.misspelled = misspelled;
+            let _ = terminal.draw(|frame| view.render(frame));
+        }
+    }
+
+    /// Waits without holding the screen, the ui loop needs it to draw.
+    fn read_key(&mut self) -> Key {
+        return term::read_key();
+    }
+
+    fn resize(&mut self) {
+        if let Ok(mut screen) = self.screen.lock() {
+            let _ = screen.terminal.autoresize();
+            let _ = screen.terminal.clear();
+        }
+    }
+
+    fn redraw(&mut self) {
+        if let Ok(mut screen) = self.screen.lock() {
+            let _ = screen.terminal.clear();
+        }
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        let depth = Depth::from_env();
+        let theme = theme.fit(depth);
+        self.update(|view| {
+            view.theme = theme;
+            view.depth = depth;
+        });
+    }
+
+    fn beep(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(b"\x07");
+        let _ = out.flush();
+    }
+
+    /// Doesn't touch the screen's lock, the panic hook calls this and the panic may have happened holding it.
+    fn end(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+        let _ = disable_raw_mode();
+    }
+}
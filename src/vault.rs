@@ -0,0 +1,285 @@
+use std::convert::TryInto;
+use std::io::{self, BufRead, Write};
+#[cfg(unix)]
+use std::process::Command;
+
+extern crate argon2;
+extern crate base64;
+extern crate chacha20poly1305;
+extern crate sha2;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::random::random;
+
+/// What a vault's header starts with, followed by the version, key derivation parameters, salt and check.
+const MAGIC: &str = "r2wc-vault";
+
+/// How many bytes the derived key has.
+const KEY_LEN: usize = 32;
+
+/// How many bytes of random salt a vault's key is derived with.
+const SALT_LEN: usize = 16;
+
+/// How many bytes of random nonce start each sealed line.
+const NONCE_LEN: usize = 12;
+
+/// What the header's check seals, so a wrong passphrase is caught before anything is written with it.
+const CHECK: &str = "r2wc";
+
+/// A Vault which seals the history kept on disk, transcripts and the message store, with a key derived from a
+/// passphrase.
+///
+/// Each line or message body is sealed on its own with ChaCha20-Poly1305 under a random nonce, so a file can be
+/// appended to without reading it back. The key is derived with Argon2id, whose parameters and salt are kept in the
+/// file's header, see `header`, next to a sealed check that tells a wrong passphrase from a damaged file.
+///
+/// # Fields
+/// `cipher` - The cipher keyed with the derived key.
+/// `params` - The Argon2id memory, time and parallelism costs the key was derived with.
+/// `salt` - The random salt the key was derived with.
+pub struct Vault {
+    cipher: ChaCha20Poly1305,
+    params: Params,
+    salt: [u8; SALT_LEN],
+}
+
+impl Vault {
+    /// Creates a new Vault for a file that has no history yet, with a fresh salt.
+    ///
+    /// # Arguments
+    /// * `passphrase` - A &str of the passphrase to derive the key from.
+    ///
+    /// # Returns
+    /// `io::Result<Vault>` - the vault, or why no randomness could be read.
+    pub fn create(passphrase: &str) -> io::Result<Vault> {
+        return Ok(Vault::derive(
+            passphrase,
+            Params::default(),
+            random::<SALT_LEN>()?,
+        ));
+    }
+
+    /// Unlocks the Vault of a file with the header it was written with.
+    ///
+    /// # Arguments
+    /// * `passphrase` - A &str of the passphrase to derive the key from.
+    /// * `header` - A &str of the file's header line, see `header`.
+    ///
+    /// # Returns
+    /// `Result<Vault, String>` - the vault, or why it can't be unlocked, e.g. the wrong passphrase.
+    pub fn unlock(passphrase: &str, header: &str) -> Result<Vault, String> {
+        let parts: Vec<&str> = header.trim_end().split(' ').collect();
+        let (params, salt, check) = match parts.as_slice() {
+            [MAGIC, "1", params, salt, check] => (params, salt, check),
+            [MAGIC, version, ..] => return Err(format!("unknown vault version {}", version)),
+            _ => return Err(String::from("not encrypted history")),
+        };
+        let params = parse_params(params).ok_or_else(|| String::from("damaged vault header"))?;
+        let salt = STANDARD
+            .decode(salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .ok_or_else(|| String::from("damaged vault header"))?;
+
+        let vault = Vault::derive(passphrase, params, salt);
+        if vault.open(check).ok().as_deref() != Some(CHECK) {
+            return Err(String::from("wrong passphrase"));
+        }
+        return Ok(vault);
+    }
+
+    /// Derives a Vault's key from a passphrase with Argon2id.
+    fn derive(passphrase: &str, params: Params, salt: [u8; SALT_LEN]) -> Vault {
+        let mut key = [0; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .expect("the salt and key lengths are fixed");
+        return Vault {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            params: params,
+            salt: salt,
+        };
+    }
+
+    /// Formats the header line a file sealed with this Vault starts with, read back by `unlock`.
+    ///
+    /// # Returns
+    /// `String` - the header, without a newline.
+    pub fn header(&self) -> String {
+        return format!(
+            "{} 1 m={},t={},p={} {} {}",
+            MAGIC,
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+            STANDARD.encode(self.salt),
+            self.seal(CHECK)
+        );
+    }
+
+    /// Seals a line of history.
+    ///
+    /// # Arguments
+    /// * `text` - A &str of the line.
+    ///
+    /// # Returns
+    /// `String` - the base64 nonce and ciphertext, with no newlines so it stays one line.
+    pub fn seal(&self, text: &str) -> String {
+        // Without randomness there's no safe nonce, and writing the line in the clear would defeat the point.
+        let nonce = random::<NONCE_LEN>().expect("no randomness to seal history with");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+                .expect("sealing can't fail"),
+        );
+        return STANDARD.encode(sealed);
+    }
+
+    /// Opens a line of history sealed with `seal`.
+    ///
+    /// # Arguments
+    /// * `sealed` - A &str of the sealed line.
+    ///
+    /// # Returns
+    /// `Result<String, String>` - the line, or why it can't be opened.
+    pub fn open(&self, sealed: &str) -> Result<String, String> {
+        let sealed = STANDARD
+            .decode(sealed.trim_end())
+            .map_err(|_| String::from("not a sealed line"))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(String::from("not a sealed line"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let text = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| String::from("sealed with another key or damaged"))?;
+        return String::from_utf8(text).map_err(|_| String::from("not text"));
+    }
+}
+
+/// Checks whether a file's first line is a vault's header, so the file is encrypted.
+///
+/// # Arguments
+/// * `line` - A &str of the line.
+///
+/// # Returns
+/// `bool` - whether it is a header, see `Vault::header`.
+pub fn is_header(line: &str) -> bool {
+    return line.split(' ').next() == Some(MAGIC);
+}
+
+/// Asks for the passphrase history is encrypted with on the terminal, before the ui takes it over.
+///
+/// # Arguments
+/// * `what` - A &str of what the passphrase is for, shown in the prompt.
+///
+/// # Returns
+/// `io::Result<String>` - the passphrase without its newline, or why it could not be read.
+pub fn prompt_passphrase(what: &str) -> io::Result<String> {
+    print!("Passphrase for {}: ", what);
+    io::stdout().flush()?;
+    let echo = set_echo(false);
+    let mut passphrase = String::new();
+    let read = io::stdin().lock().read_line(&mut passphrase);
+    if echo {
+        set_echo(true);
+        println!();
+    }
+    read?;
+
+    let passphrase = passphrase.trim_end_matches(&['\r', '\n'][..]);
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the passphrase is empty",
+        ));
+    }
+    return Ok(String::from(passphrase));
+}
+
+/// Turns the terminal's echo on or off with `stty`, so the passphrase isn't shown as it is typed.
+///
+/// # Returns
+/// `bool` - whether stty did it.
+#[cfg(unix)]
+fn set_echo(on: bool) -> bool {
+    return Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .status()
+        .map_or(false, |status| status.success());
+}
+
+/// Leaves the terminal's echo alone, there is no stty to turn it off with.
+#[cfg(not(unix))]
+fn set_echo(_on: bool) -> bool {
+    return false;
+}
+
+/// Reads the Argon2id parameters a header was written with, e.g. `m=19456,t=2,p=1`.
+fn parse_params(text: &str) -> Option<Params> {
+    let mut costs = [None; 3];
+    for part in text.split(',') {
+        let (name, value) = part.split_once('=')?;
+        let i = ["m", "t", "p"].iter().position(|n| *n == name)?;
+        costs[i] = Some(value.parse::<u32>().ok()?);
+    }
+    let [m, t, p] = costs;
+    return Params::new(m?, t?, p?, Some(KEY_LEN)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use super::{Vault, KEY_LEN, SALT_LEN};
+
+    /// A vault with the smallest costs, deriving with the real ones is slow in a test build.
+    fn quick(passphrase: &str) -> Vault {
+        let params = Params::new(Params::MIN_M_COST, 1, 1, Some(KEY_LEN)).unwrap();
+        return Vault::derive(passphrase, params, [7; SALT_LEN]);
+    }
+
+    #[test]
+    fn a_new_vault_keeps_its_kdf_parameters_in_the_header() {
+        let vault = Vault::create("hunter2").unwrap();
+        let header = vault.header();
+        assert!(header.starts_with("r2wc-vault 1 m=19456,t=2,p=1 "));
+        let line = vault.seal("hello");
+
+        let unlocked = Vault::unlock("hunter2", &header).unwrap();
+        assert_eq!(unlocked.open(&line).unwrap(), "hello");
+        assert!(Vault::unlock("hunter2", &header.replace("t=2", "t=3")).is_err());
+    }
+
+    #[test]
+    fn a_vault_unlocks_with_its_passphrase_only() {
+        let vault = quick("hunter2");
+        let header = vault.header();
+        let line = vault.seal("[12:00] Server: hi");
+        assert!(!line.contains("Server: hi") && !line.contains('\n'));
+
+        let unlocked = Vault::unlock("hunter2", &header).unwrap();
+        assert_eq!(unlocked.open(&line).unwrap(), "[12:00] Server: hi");
+        assert_eq!(
+            Vault::unlock("hunter3", &header).err().unwrap(),
+            "wrong passphrase"
+        );
+        assert!(Vault::unlock("hunter2", "[12:00] Server: hi").is_err());
+    }
+
+    #[test]
+    fn a_changed_line_does_not_open() {
+        let vault = quick("hunter2");
+        let mut line = vault.seal("hello").into_bytes();
+        let last = line.len() - 3;
+        line[last] = if line[last] == b'A' { b'B' } else { b'A' };
+        assert!(vault.open(&String::from_utf8(line).unwrap()).is_err());
+        assert!(quick("other").open(&vault.seal("hello")).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::prelude::*;
+
+use crate::chat::Chat;
+
+/// A Viewer which shows a past session's transcript for `--view`, read only, narrowed down by a search.
+///
+/// Lines that start like a message, a sender then a time in the time format the transcript was written with, are
+/// shown as messages so they are drawn like the live chat. Anything else, status lines, quotes and the lines of
+/// code blocks, is shown as it was written.
+///
+/// # Fields
+/// `lines` - The transcript's lines, oldest first.
+/// `time_format` - The strftime format the transcript's times were written in.
+pub struct Viewer {
+    lines: Vec<String>,
+    time_format: String,
+}
+
+impl Viewer {
+    /// Creates a new Viewer over a transcript's lines.
+    ///
+    /// # Arguments
+    /// * `lines` - A Vec<String> of the lines, oldest first, see `transcript::read_lines`.
+    /// * `time_format` - A &str of the strftime format the times were written in.
+    ///
+    /// # Returns
+    /// `Viewer` - the viewer.
+    pub fn new(lines: Vec<String>, time_format: &str) -> Viewer {
+        return Viewer {
+            lines: lines,
+            time_format: String::from(time_format),
+        };
+    }
+
+    /// Fills a chat with the lines that match a search, in place of what it held.
+    ///
+    /// # Arguments
+    /// * `chat` - A &mut Chat to show the lines in.
+    /// * `search` - A &str the lines have to contain, ignoring case, empty for every line.
+    ///
+    /// # Returns
+    /// `usize` - how many lines matched.
+    pub fn show(&self, chat: &mut Chat, search: &str) -> usize {
+        chat.prune(Some(0), None);
+        let search = search.to_lowercase();
+        let mut matched = 0;
+        for line in &self.lines {
+            if !line.to_lowercase().contains(&search) {
+                continue;
+            }
+            matched += 1;
+            match self.parse(line) {
+                Some((header, time, body)) => chat.push_history(time, header, body),
+                None => chat.push_status(line.clone(), false),
+            }
+        }
+        return matched;
+    }
+
+    /// Splits a line into who sent it, when and what they said, if it starts like a message.
+    fn parse(&self, line: &str) -> Option<(String, i64, String)> {
+        for (colon, _) in line.match_indices(": ") {
+            let lead = &line[..colon];
+            for (space, _) in lead.match_indices(' ').filter(|(space, _)| *space > 0) {
+                let time =
+                    match NaiveDateTime::parse_from_str(&lead[space + 1..], &self.time_format) {
+                        Ok(time) => time,
+                        Err(_) => continue,
+                    };
+                let time = Local.from_local_datetime(&time).earliest()?.timestamp();
+                return Some((
+                    String::from(&lead[..space]),
+                    time,
+                    String::from(&line[colon + 2..]),
+                ));
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Viewer;
+    use crate::chat::Chat;
+    use crate::settings::DEFAULT_TIME_FORMAT;
+
+    #[test]
+    fn messages_are_read_back_and_searched() {
+        let lines = vec![
+            String::from("Connected to 127.0.0.1:9000"),
+            String::from("Alice ✓ 2026-10-14 09:30:00: see you at 10: bring the notes"),
+            String::from("You 2026-10-14 09:31:05: Will do"),
+        ];
+        let viewer = Viewer::new(lines, DEFAULT_TIME_FORMAT);
+        let mut chat = Chat::new();
+
+        assert_eq!(viewer.show(&mut chat, ""), 3);
+        let messages = chat.messages();
+        assert!(messages[0].is_status());
+        assert_eq!(messages[0].body, "Connected to 127.0.0.1:9000");
+        assert_eq!(messages[1].header, "Alice ✓");
+        assert_eq!(messages[1].body, "see you at 10: bring the notes");
+        assert_eq!(chat.line(2).0, "You 2026-10-14 09:31:05: Will do");
+
+        assert_eq!(viewer.show(&mut chat, "will"), 1);
+        assert_eq!(chat.messages().len(), 1);
+        assert_eq!(chat.messages()[0].header, "You");
+    }
+}
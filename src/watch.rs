@@ -0,0 +1,61 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use notify::{Event, RecursiveMode, Watcher};
+
+/// How long to wait for an editor to finish saving before reporting a change, one save is often several events.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Watches a file for changes on a background thread.
+///
+/// The file's directory is watched rather than the file, so editors that save by writing a new file and renaming it
+/// over the old one are noticed too, and so is a file that doesn't exist yet.
+///
+/// # Arguments
+/// * `path` - A &Path of the file to watch.
+///
+/// # Returns
+/// `notify::Result<Receiver<()>>` - a receiver that gets a message after each change, or why the file can't be
+/// watched.
+pub fn watch(path: &Path) -> notify::Result<Receiver<()>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name: Option<OsString> = path.file_name().map(|n| n.to_os_string());
+    let (raw_tx, raw_rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(raw_tx)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    let (tx, rx) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        // Dropping the watcher stops it, so this thread keeps it for as long as anyone listens.
+        let _watcher = watcher;
+        while let Ok(event) = raw_rx.recv() {
+            let touched = match event {
+                Ok(event) => {
+                    !event.kind.is_access()
+                        && event
+                            .paths
+                            .iter()
+                            .any(|p| p.file_name().map(|n| n.to_os_string()) == name)
+                }
+                Err(_) => false,
+            };
+            if !touched {
+                continue;
+            }
+
+            thread::sleep(SETTLE_TIME);
+            while raw_rx.try_recv().is_ok() {}
+            if tx.send(()).is_err() {
+                return;
+            }
+        }
+    });
+
+    return Ok(rx);
+}
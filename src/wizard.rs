@@ -0,0 +1,145 @@
+use crate::config::Uri;
+use crate::term::Key;
+
+/// The form's fields, in the order Tab moves through them.
+const FIELDS: &[&str] = &["Host", "Port", "Nickname"];
+
+/// A Wizard which is the form a bare `r2wc-client` asks for the server with, when there's no recent server to pick.
+///
+/// The form is drawn over the chat like the help, and the field being filled in is typed into the input line.
+///
+/// # Fields
+/// `values` - What has been typed into the host, port and nickname.
+/// `focus` - Which of FIELDS is being filled in.
+/// `error` - What was wrong with the form the last time Enter was pressed.
+pub struct Wizard {
+    values: [String; 3],
+    focus: usize,
+    error: Option<String>,
+}
+
+impl Wizard {
+    /// Creates a new empty Wizard, filling in the host first.
+    ///
+    /// # Returns
+    /// `Wizard` - the form.
+    pub fn new() -> Wizard {
+        return Wizard {
+            values: Default::default(),
+            focus: 0,
+            error: None,
+        };
+    }
+
+    /// What has been typed into the field being filled in, for the input line.
+    ///
+    /// # Returns
+    /// `&str` - the field's text.
+    pub fn input(&self) -> &str {
+        return &self.values[self.focus];
+    }
+
+    /// Handles a key, Tab and the arrows move between fields and Enter connects.
+    ///
+    /// # Arguments
+    /// * `key` - A Key that was pressed.
+    ///
+    /// # Returns
+    /// `Option<Uri>` - the server to connect to once Enter was pressed on a valid form, otherwise None.
+    pub fn key(&mut self, key: Key) -> Option<Uri> {
+        match key {
+            Key::Tab | Key::Down => self.focus = (self.focus + 1) % FIELDS.len(),
+            Key::BackTab | Key::Up => self.focus = (self.focus + FIELDS.len() - 1) % FIELDS.len(),
+            Key::Enter => match self.uri() {
+                Ok(uri) => return Some(uri),
+                Err((field, err)) => {
+                    self.focus = field;
+                    self.error = Some(err);
+                }
+            },
+            Key::Char(c) => self.values[self.focus].push(c),
+            Key::Backspace => {
+                self.values[self.focus].pop();
+            }
+            _ => (),
+        }
+        return None;
+    }
+
+    /// Checks the form and makes the link it describes.
+    ///
+    /// # Returns
+    /// `Result<Uri, (usize, String)>` - the link, or which field is wrong and why.
+    fn uri(&self) -> Result<Uri, (usize, String)> {
+        let [host, port, nick] = &self.values;
+        let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err((0, String::from("The host is needed")));
+        }
+        let port = port
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| (1, String::from("The port has to be a number, 0-65535")))?;
+        let nick = nick.trim();
+        if nick.contains(char::is_whitespace) {
+            return Err((2, String::from("The nickname can't have spaces")));
+        }
+
+        return Ok(Uri {
+            host: String::from(host),
+            port: port,
+            token: None,
+            nick: Some(String::from(nick)).filter(|n| !n.is_empty()),
+        });
+    }
+
+    /// Lays out the form.
+    ///
+    /// # Returns
+    /// `Vec<String>` - the form's lines, the field being filled in marked with `>`.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![String::from("Connect to a server"), String::new()];
+        for (i, field) in FIELDS.iter().enumerate() {
+            let mark = if i == self.focus { ">" } else { " " };
+            lines.push(format!("{} {:<8}  {}", mark, field, self.values[i]));
+        }
+        lines.push(String::new());
+        if let Some(error) = &self.error {
+            lines.push(error.clone());
+        }
+        lines.push(String::from(
+            "Tab moves between fields, Enter connects, Esc gives up",
+        ));
+        return lines;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Wizard;
+    use crate::term::Key;
+
+    fn type_in(wizard: &mut Wizard, text: &str) {
+        for c in text.chars() {
+            assert!(wizard.key(Key::Char(c)).is_none());
+        }
+    }
+
+    #[test]
+    fn a_filled_in_form_connects() {
+        let mut wizard = Wizard::new();
+        type_in(&mut wizard, "example.com");
+        wizard.key(Key::Tab);
+        type_in(&mut wizard, "40x");
+        assert!(wizard.key(Key::Enter).is_none());
+        assert!(wizard.lines().iter().any(|l| l.starts_with("The port")));
+        wizard.key(Key::Backspace);
+        assert_eq!(wizard.input(), "40");
+
+        type_in(&mut wizard, "00");
+        wizard.key(Key::Down);
+        type_in(&mut wizard, "alice");
+        let uri = wizard.key(Key::Enter).unwrap();
+        assert_eq!(uri.to_string(), "r2wc://example.com:4000?nick=alice");
+    }
+}